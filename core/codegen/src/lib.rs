@@ -13,54 +13,265 @@
 extern crate proc_macro;
 extern crate syn;
 use darling::FromMeta;
-use proc_macro2::TokenStream;
+use proc_macro2::{Span, TokenStream};
 use quote::{quote, ToTokens};
 use syn::{
-    parse_macro_input, spanned::Spanned, token, AttributeArgs, Expr, ExprBlock, ExprClosure, Ident,
-    ItemFn, Meta, NestedMeta, Result, ReturnType, Type, TypePath,
+    parse::Parse,
+    parse_macro_input, parse_quote, parse_quote_spanned,
+    spanned::Spanned,
+    token,
+    visit_mut::{self, VisitMut},
+    Attribute, AttributeArgs, Data, DeriveInput, Expr, ExprBlock, ExprClosure, Fields, FnArg, GenericArgument, GenericParam,
+    Ident, ImplItem, Item, ItemFn, ItemImpl, ItemMod, ItemTrait, Lifetime, LifetimeDef, Lit, Meta, NestedMeta,
+    ParenthesizedGenericArguments, Pat, PathArguments, Receiver, Result, ReturnType, Signature,
+    Stmt, TraitItem, TraitItemMethod, Type, TypeBareFn, TypeImplTrait, TypeParamBound, TypePath,
+    TypeReference, Visibility, WherePredicate,
 };
 
 struct FormattedAttributes {
+    /// The name of the function in the metrics and logs.
+    name: String,
     ok_expr: TokenStream,
     err_expr: TokenStream,
-    ctx: String,
+    /// The expression of the context label, a string literal unless derived from the module.
+    ctx: TokenStream,
+    /// Whether the ctx is the default one, which depends on `INSTRUMENTED_DEFAULT_CTX`.
+    default_ctx: bool,
+    apdex_t: Option<f64>,
+    slo_ms: Option<u64>,
+    stream: bool,
+    ignore_err: Vec<String>,
+    err_label: ErrLabel,
+    cpu_time: bool,
+    count_allocs: bool,
+    debug_only: bool,
+    interarrival: bool,
+    track_last_called: bool,
+    deadline_ms: Option<u64>,
+    deadline_cancel: bool,
+    label_thread: bool,
+    chaos: bool,
+    location: bool,
+    track_recovery: bool,
+    /// The expression of the units of work of a call, or the error it doesn't parse with.
+    weight: Option<TokenStream>,
+    /// The path of the function classifying the returned values, or the error it doesn't parse
+    /// with.
+    classify: Option<TokenStream>,
+    local_metrics: bool,
+    hdr: bool,
+    histogram: Option<HistogramMode>,
+    /// The path of the function labelling the errors, or the error it doesn't parse with.
+    err_labels: Option<TokenStream>,
+    /// The expression of the size class of a call, or the error it doesn't parse with.
+    size_class: Option<TokenStream>,
+    max_rate: Option<u32>,
+    /// The expression of the error returned by the calls over `max_rate`, or the error it doesn't
+    /// parse with.
+    rate_limit_err: Option<TokenStream>,
+    max_inflight: Option<u32>,
+    /// The expression of the error returned by the calls over `max_inflight`, or the error it
+    /// doesn't parse with.
+    overload_err: Option<TokenStream>,
+    return_timing: bool,
+    percentiles: bool,
+    phases: bool,
+    main: bool,
+}
+
+/// Where the durations of a function are observed, overriding
+/// `instrumented::native_histogram::set_histogram_mode`.
+#[derive(Clone, Copy, PartialEq)]
+enum HistogramMode {
+    Classic,
+    Native,
+    Both,
+}
+
+impl FromMeta for HistogramMode {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        match value {
+            "classic" => Ok(HistogramMode::Classic),
+            "native" => Ok(HistogramMode::Native),
+            "both" => Ok(HistogramMode::Both),
+            _ => Err(darling::Error::unknown_value(value)),
+        }
+    }
+}
+
+/// How the `err` label of the error counter is derived from an error.
+#[derive(Clone, Copy, PartialEq)]
+enum ErrLabel {
+    /// The `Debug` representation of the error.
+    Debug,
+    /// The `Debug` representation of the `io::ErrorKind` of the error, or of the error it wraps.
+    IoKind,
+    /// The class of an error implementing `InstrumentedErrorClass`.
+    Class,
+}
+
+impl FromMeta for ErrLabel {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        match value {
+            "debug" => Ok(ErrLabel::Debug),
+            "io_kind" => Ok(ErrLabel::IoKind),
+            "class" => Ok(ErrLabel::Class),
+            _ => Err(darling::Error::unknown_value(value)),
+        }
+    }
 }
 
 impl FormattedAttributes {
     pub fn parse_attributes(
         attr: &[NestedMeta],
+        function_name: &str,
         fmt_default: &str,
         ctx_default: &str,
+        opaque: bool,
     ) -> darling::Result<Self> {
-        Options::from_list(attr)
-            .map(|opts| Self::get_ok_err_streams(&opts, fmt_default, ctx_default))
+        let opts = Options::from_list(attr)?;
+        if !cfg!(feature = "logging")
+            && cfg!(feature = "deny-log-levels")
+            && (opts.ok_log().is_some() || opts.err_log().is_some())
+        {
+            return Err(darling::Error::custom(
+                "log levels require the `logging` feature of `instrumented`",
+            ));
+        }
+        Ok(Self::get_ok_err_streams(
+            &opts,
+            function_name,
+            fmt_default,
+            ctx_default,
+            opaque,
+        ))
     }
 
-    fn get_ok_err_streams(att: &Options, fmt_default: &str, ctx_default: &str) -> Self {
-        let ok_log = att.ok_log();
-        let err_log = att.err_log();
+    /// When `opaque` is set, the returned value can't be formatted (it's an `impl Trait`), and
+    /// `fmt` is logged without arguments.
+    fn get_ok_err_streams(
+        att: &Options,
+        function_name: &str,
+        fmt_default: &str,
+        ctx_default: &str,
+        opaque: bool,
+    ) -> Self {
+        // Without the `logging` feature, the levels are ignored and nothing is logged.
+        let logging = cfg!(feature = "logging");
+        let ok_log = att.ok_log().filter(|_| logging);
+        let err_log = att.err_log().filter(|_| logging);
         let fmt = att.fmt().unwrap_or(fmt_default);
-        let ctx = att.ctx().unwrap_or(ctx_default).to_string();
+        let ctx = match att.ctx() {
+            Some(ctx) => quote!(#ctx),
+            None if att.named.ctx_from_module => module_ctx(),
+            None => default_ctx(ctx_default),
+        };
+        // With `location`, the logs end with a `location=file:line` field.
+        let (fmt, location) = if att.named.location {
+            (
+                quote!(concat!(#fmt, " location={}")),
+                quote!(, __INSTRUMENTED_LOCATION),
+            )
+        } else {
+            (quote!(#fmt), quote!())
+        };
 
         let ok_expr = match ok_log {
+            Some(loglevel) if opaque => {
+                let log_token = ctx_log_level(get_logger_token(loglevel), &ctx);
+                quote! {log::log!(#log_token, #fmt #location);}
+            }
             Some(loglevel) => {
-                let log_token = get_logger_token(&loglevel);
-                quote! {log::log!(#log_token, #fmt, result);}
+                let log_token = ctx_log_level(get_logger_token(loglevel), &ctx);
+                quote! {log::log!(#log_token, #fmt, result #location);}
             }
-            None => quote! {()},
+            None => quote! {},
         };
 
-        let err_expr = match err_log {
-            Some(loglevel) => {
-                let log_token = get_logger_token(&loglevel);
-                quote! {log::log!(#log_token, #fmt, err);}
+        let err_expr = match (err_log, att.named.log_rate_limit) {
+            (Some(loglevel), Some(limit)) => {
+                let log_token = ctx_log_level(get_logger_token(loglevel), &ctx);
+                quote! {
+                    if let Some(suppressed) = ::instrumented::err_log_permit(#function_name, #limit, #log_token) {
+                        if suppressed > 0 {
+                            log::log!(
+                                #log_token,
+                                "{}() => suppressed {} similar errors",
+                                #function_name,
+                                ::instrumented::format_count(suppressed)
+                            );
+                        }
+                        log::log!(#log_token, #fmt, err #location);
+                    }
+                }
             }
-            None => quote! {()},
+            (Some(loglevel), None) => {
+                let log_token = ctx_log_level(get_logger_token(loglevel), &ctx);
+                quote! {log::log!(#log_token, #fmt, err #location);}
+            }
+            (None, _) => quote! {},
         };
         FormattedAttributes {
+            name: function_name.to_string(),
             ok_expr,
             err_expr,
             ctx,
+            default_ctx: att.ctx().is_none() && !att.named.ctx_from_module,
+            apdex_t: att.named.apdex_t,
+            slo_ms: att.named.slo_ms,
+            stream: att.named.stream,
+            ignore_err: att.ignore_err(),
+            err_label: att.named.err_label.unwrap_or(ErrLabel::Debug),
+            cpu_time: att.named.cpu_time,
+            count_allocs: att.named.count_allocs,
+            debug_only: att.named.debug_only,
+            interarrival: att.named.interarrival,
+            track_last_called: att.named.track_last_called,
+            deadline_ms: att.named.deadline_ms,
+            deadline_cancel: att.named.deadline_cancel,
+            label_thread: att.named.label_thread,
+            chaos: att.named.chaos,
+            location: att.named.location,
+            track_recovery: att.named.track_recovery,
+            weight: att
+                .named
+                .weight
+                .as_ref()
+                .map(|weight| parse_lit_str::<Expr>(weight, "weight")),
+            classify: att
+                .named
+                .classify
+                .as_ref()
+                .map(|classify| parse_lit_str::<syn::Path>(classify, "classify")),
+            local_metrics: att.named.local_metrics,
+            hdr: att.named.hdr,
+            histogram: att.named.histogram,
+            err_labels: att
+                .named
+                .err_labels
+                .as_ref()
+                .map(|err_labels| parse_lit_str::<syn::Path>(err_labels, "err_labels")),
+            size_class: att
+                .named
+                .size_class
+                .as_ref()
+                .map(|size_class| parse_lit_str::<Expr>(size_class, "size_class")),
+            max_rate: att.named.max_rate,
+            rate_limit_err: att
+                .named
+                .rate_limit_err
+                .as_ref()
+                .map(|rate_limit_err| parse_lit_str::<Expr>(rate_limit_err, "rate_limit_err")),
+            max_inflight: att.named.max_inflight,
+            overload_err: att
+                .named
+                .overload_err
+                .as_ref()
+                .map(|overload_err| parse_lit_str::<Expr>(overload_err, "overload_err")),
+            return_timing: att.named.return_timing,
+            percentiles: att.named.percentiles,
+            phases: att.named.phases,
+            main: att.named.main,
         }
     }
 }
@@ -72,6 +283,40 @@ struct NamedOptions {
     err: Option<Ident>,
     fmt: Option<String>,
     ctx: Option<String>,
+    ctx_from_module: bool,
+    apdex_t: Option<f64>,
+    slo_ms: Option<u64>,
+    stream: bool,
+    ignore_err: Option<String>,
+    err_label: Option<ErrLabel>,
+    log_rate_limit: Option<u32>,
+    cpu_time: bool,
+    count_allocs: bool,
+    debug_only: bool,
+    interarrival: bool,
+    track_last_called: bool,
+    deadline_ms: Option<u64>,
+    deadline_cancel: bool,
+    label_thread: bool,
+    chaos: bool,
+    location: bool,
+    test_namespace: Option<bool>,
+    track_recovery: bool,
+    weight: Option<Lit>,
+    classify: Option<Lit>,
+    local_metrics: bool,
+    hdr: bool,
+    histogram: Option<HistogramMode>,
+    err_labels: Option<Lit>,
+    size_class: Option<Lit>,
+    max_rate: Option<u32>,
+    rate_limit_err: Option<Lit>,
+    max_inflight: Option<u32>,
+    overload_err: Option<Lit>,
+    return_timing: bool,
+    percentiles: bool,
+    phases: bool,
+    main: bool,
 }
 
 struct Options {
@@ -102,6 +347,17 @@ impl Options {
     pub fn ctx(&self) -> Option<&str> {
         self.named.ctx.as_ref().map(String::as_str)
     }
+
+    pub fn ignore_err(&self) -> Vec<String> {
+        self.named
+            .ignore_err
+            .iter()
+            .flat_map(|names| names.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect()
+    }
 }
 
 impl FromMeta for Options {
@@ -153,6 +409,79 @@ fn check_if_return_result(f: &ItemFn) -> bool {
     false
 }
 
+/// Returns `T` if a function returns `Pin<Box<dyn Future<Output = T>>>`, which is the shape
+/// `async-trait` gives to `async fn`s before `#[instrument]` gets to see them.
+fn boxed_future_output(f: &ItemFn) -> Option<&Type> {
+    fn single_type_arg<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+        let segment = match ty {
+            Type::Path(path) => path.path.segments.last()?,
+            _ => return None,
+        };
+        if segment.ident != name {
+            return None;
+        }
+        match &segment.arguments {
+            PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    let ty = match &f.sig.output {
+        ReturnType::Type(_, ty) => ty.as_ref(),
+        ReturnType::Default => return None,
+    };
+    let trait_object = match single_type_arg(single_type_arg(ty, "Pin")?, "Box")? {
+        Type::TraitObject(trait_object) => trait_object,
+        _ => return None,
+    };
+    trait_object.bounds.iter().find_map(|bound| {
+        let segment = match bound {
+            TypeParamBound::Trait(bound) => bound.path.segments.last()?,
+            _ => return None,
+        };
+        if segment.ident != "Future" {
+            return None;
+        }
+        match &segment.arguments {
+            PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+                GenericArgument::Binding(binding) if binding.ident == "Output" => Some(&binding.ty),
+                _ => None,
+            }),
+            _ => None,
+        }
+    })
+}
+
+/// Check if a function returns `impl Stream<Item = Result<..>>`.
+fn check_if_stream_item_result(f: &ItemFn) -> bool {
+    if let ReturnType::Type(_, t) = &f.sig.output {
+        if let Type::ImplTrait(impl_trait) = t.as_ref() {
+            for bound in &impl_trait.bounds {
+                if let TypeParamBound::Trait(bound) = bound {
+                    let args = match bound.path.segments.last().map(|s| &s.arguments) {
+                        Some(PathArguments::AngleBracketed(args)) => args,
+                        _ => continue,
+                    };
+                    for arg in &args.args {
+                        if let GenericArgument::Binding(binding) = arg {
+                            if binding.ident == "Item" {
+                                if let Type::Path(path) = &binding.ty {
+                                    return is_result_type(path);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    false
+}
+
 fn get_logger_token(att: &Ident) -> TokenStream {
     // Capitalize the first letter.
     let attr_str = att.to_string().to_lowercase();
@@ -162,6 +491,69 @@ fn get_logger_token(att: &Ident) -> TokenStream {
     quote!(log::Level::#att_str)
 }
 
+/// The context label of the module a function is defined in, the last segment of its path.
+fn module_ctx() -> TokenStream {
+    quote!(::instrumented::module_ctx(module_path!()))
+}
+
+/// The context label of the functions without `ctx`: `fallback`, unless overridden for the whole
+/// crate by the `INSTRUMENTED_DEFAULT_CTX` environment variable at build time, either with a
+/// label, or with `module` to derive it from the module of each function, or `package` to use the
+/// name of the package.
+fn default_ctx(fallback: &str) -> TokenStream {
+    match std::env::var("INSTRUMENTED_DEFAULT_CTX") {
+        Ok(ref ctx) if ctx == "module" => module_ctx(),
+        Ok(ref ctx) if ctx == "package" => {
+            let package = std::env::var("CARGO_PKG_NAME").unwrap_or_else(|_| fallback.to_string());
+            quote!(#package)
+        }
+        Ok(ref ctx) if !ctx.is_empty() => quote!(#ctx),
+        _ => quote!(#fallback),
+    }
+}
+
+/// The `crate` label of a function, the name of the package being built, read like `package`
+/// above. Only exported when enabled with `instrumented::set_crate_label`.
+fn crate_name() -> TokenStream {
+    let package = std::env::var("CARGO_PKG_NAME").unwrap_or_default();
+    quote!(#package)
+}
+
+/// With the `ctx-log-level` feature, the level is looked up at runtime instead, so that it can be
+/// overridden by context with `instrumented::set_ctx_log_level`.
+fn ctx_log_level(log_token: TokenStream, ctx: &TokenStream) -> TokenStream {
+    if cfg!(feature = "ctx-log-level") {
+        quote!({
+            static __INSTRUMENTED_LOG_LEVEL: ::instrumented::CtxLogLevel =
+                ::instrumented::CtxLogLevel::new();
+            __INSTRUMENTED_LOG_LEVEL.level(#ctx, #log_token)
+        })
+    } else {
+        log_token
+    }
+}
+
+/// Check if a function returns `impl Trait`, whose values can't be formatted.
+fn check_if_return_impl_trait(f: &ItemFn) -> bool {
+    if let ReturnType::Type(_, t) = &f.sig.output {
+        if let Type::ImplTrait(_) = t.as_ref() {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn check_if_return_never(f: &ItemFn) -> bool {
+    if let ReturnType::Type(_, t) = &f.sig.output {
+        if let Type::Never(_) = t.as_ref() {
+            return true;
+        }
+    }
+
+    false
+}
+
 fn make_closure(original: &ItemFn) -> ExprClosure {
     let body = Box::new(Expr::Block(ExprBlock {
         attrs: Default::default(),
@@ -190,63 +582,1095 @@ fn replace_function_headers(original: ItemFn, new: &mut ItemFn) {
     new.block = block;
 }
 
+/// Parses the code in the string value of an option, keeping the spans of the string literal so
+/// that type errors point at it.
+fn parse_lit_str<T: Parse + ToTokens>(value: &Lit, option: &str) -> TokenStream {
+    match value {
+        Lit::Str(code) => match code.parse::<T>() {
+            Ok(code) => code.into_token_stream(),
+            Err(err) => err.to_compile_error(),
+        },
+        _ => syn::Error::new(value.span(), format!("`{}` must be a string", option))
+            .to_compile_error(),
+    }
+}
+
+/// Inserts the statements run first on every call of an instrumented function.
+fn add_preamble(new: &mut ItemFn, expressions: &FormattedAttributes, flags: &[&str]) {
+    let name = &expressions.name;
+    if cfg!(feature = "debug-introspection") {
+        add_introspection(new, name, &expressions.ctx, flags);
+    }
+    if expressions.interarrival {
+        add_interarrival(new, name, &expressions.ctx);
+    }
+    if let Some(size_class) = &expressions.size_class {
+        new.block.stmts.insert(
+            0,
+            parse_quote!(let __instrumented_size_class: &'static str = #size_class;),
+        );
+    }
+    if let Some(weight) = &expressions.weight {
+        let ctx = &expressions.ctx;
+        let krate = crate_name();
+        new.block.stmts.insert(
+            0,
+            parse_quote!(::instrumented::inc_work_units_in_crate(#name, #ctx, #krate, #weight);),
+        );
+    }
+    if expressions.label_thread {
+        let ctx = &expressions.ctx;
+        let krate = crate_name();
+        new.block.stmts.insert(
+            0,
+            parse_quote!(::instrumented::inc_thread_counter_in_crate(#name, #ctx, #krate);),
+        );
+    }
+    if expressions.track_last_called {
+        let ctx = &expressions.ctx;
+        let krate = crate_name();
+        new.block.stmts.insert(
+            0,
+            parse_quote!(::instrumented::set_last_called_in_crate(#name, #ctx, #krate);),
+        );
+    }
+    if let Some(max) = expressions.max_inflight {
+        add_inflight_limit(new, name, &expressions.ctx, max);
+    }
+    if let Some(rate) = expressions.max_rate {
+        add_rate_limit(new, name, &expressions.ctx, rate, expressions.rate_limit_err.is_some());
+    }
+    if expressions.location {
+        add_location(new, name, &expressions.ctx);
+    }
+    if expressions.default_ctx {
+        add_default_ctx_tracking(new);
+    }
+    let help = doc_help(&new.attrs);
+    add_registration(new, name, &expressions.ctx, help);
+}
+
+/// Reads `INSTRUMENTED_DEFAULT_CTX` with `option_env!` in the generated code too, as the variable
+/// read by the macro isn't tracked by rustc: this way, changing it rebuilds the crate, and the
+/// functions are expanded with the new default ctx rather than keeping stale labels.
+fn add_default_ctx_tracking(new: &mut ItemFn) {
+    new.block.stmts.insert(
+        0,
+        parse_quote!(const _: ::std::option::Option<&str> = option_env!("INSTRUMENTED_DEFAULT_CTX");),
+    );
+}
+
+/// Records the function and what was generated for it at program startup, for
+/// `instrumented::introspection`.
+fn add_introspection(new: &mut ItemFn, name: &str, ctx: &TokenStream, flags: &[&str]) {
+    let introspection: Stmt = parse_quote_spanned! {new.sig.ident.span()=>
+        #[used]
+        #[cfg_attr(
+            any(target_os = "linux", target_os = "android", target_os = "freebsd"),
+            link_section = ".init_array"
+        )]
+        #[cfg_attr(any(target_os = "macos", target_os = "ios"), link_section = "__DATA,__mod_init_func")]
+        #[cfg_attr(windows, link_section = ".CRT$XCU")]
+        static __INSTRUMENTED_INTROSPECTION: extern "C" fn() = {
+            extern "C" fn __instrumented_introspect() {
+                static __INSTRUMENTED_RECORD: ::instrumented::introspection::FunctionRecord =
+                    ::instrumented::introspection::FunctionRecord {
+                        name: #name,
+                        ctx: #ctx,
+                        module: module_path!(),
+                        file: file!(),
+                        line: line!(),
+                        flags: &[#(#flags),*],
+                    };
+                ::instrumented::introspection::register(&__INSTRUMENTED_RECORD);
+            }
+            __instrumented_introspect
+        };
+    };
+    new.block.stmts.insert(0, introspection);
+}
+
+/// The names of the metrics and behaviours generated for a function, for introspection.
+fn introspection_flags(
+    expressions: &FormattedAttributes,
+    returns_result: bool,
+    is_async: bool,
+) -> Vec<&'static str> {
+    let mut flags = vec!["calls", "timer"];
+    let optional = [
+        (returns_result, "errors"),
+        (is_async, "async"),
+        (expressions.stream, "stream"),
+        (!expressions.ok_expr.is_empty(), "log_ok"),
+        (!expressions.err_expr.is_empty(), "log_err"),
+        (!expressions.ignore_err.is_empty(), "ignore_err"),
+        (expressions.apdex_t.is_some(), "apdex"),
+        (expressions.slo_ms.is_some(), "slo"),
+        (expressions.cpu_time, "cpu_time"),
+        (expressions.count_allocs, "count_allocs"),
+        (expressions.interarrival, "interarrival"),
+        (expressions.track_last_called, "track_last_called"),
+        (expressions.deadline_ms.is_some(), "deadline"),
+        (expressions.deadline_cancel, "deadline_cancel"),
+        (expressions.label_thread, "label_thread"),
+        (expressions.chaos, "chaos"),
+        (expressions.location, "location"),
+        (expressions.track_recovery, "track_recovery"),
+        (expressions.weight.is_some(), "weight"),
+        (expressions.classify.is_some(), "classify"),
+        (expressions.local_metrics, "local_metrics"),
+        (expressions.hdr, "hdr"),
+        (expressions.histogram.is_some(), "histogram"),
+        (expressions.err_labels.is_some(), "err_labels"),
+        (expressions.size_class.is_some(), "size_class"),
+        (expressions.max_rate.is_some(), "max_rate"),
+        (expressions.max_inflight.is_some(), "max_inflight"),
+        (expressions.return_timing, "return_timing"),
+        (expressions.percentiles, "percentiles"),
+        (expressions.phases, "phases"),
+        (expressions.main, "main"),
+    ];
+    flags.extend(
+        optional
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, flag)| *flag),
+    );
+    flags
+}
+
+/// Takes a token from the rate limiter of the function, in a static of the function. When the
+/// calls over the limit return an error, whether the call is one of them is kept for the body.
+fn add_rate_limit(new: &mut ItemFn, name: &str, ctx: &TokenStream, rate: u32, returns_err: bool) {
+    let krate = crate_name();
+    let permit = quote! {
+        static __INSTRUMENTED_RATE_LIMITER: ::instrumented::rate_limit::RateLimiter =
+            ::instrumented::rate_limit::RateLimiter::new();
+        ::instrumented::rate_limit::permit_in_crate(#name, #ctx, #krate, &__INSTRUMENTED_RATE_LIMITER, #rate)
+    };
+    let rate_limit: Stmt = if returns_err {
+        parse_quote!(let __instrumented_rate_limited = !{ #permit };)
+    } else {
+        parse_quote!({ #permit; })
+    };
+    new.block.stmts.insert(0, rate_limit);
+}
+
+/// Takes a slot from the inflight limiter of the function, in a static of the function. The
+/// permit, if any, is kept for the body, which returns the overload error without one.
+fn add_inflight_limit(new: &mut ItemFn, name: &str, ctx: &TokenStream, max: u32) {
+    let krate = crate_name();
+    let inflight_limit: Stmt = parse_quote! {
+        let __instrumented_inflight_permit = {
+            static __INSTRUMENTED_INFLIGHT_LIMITER: ::instrumented::load_shed::InflightLimiter =
+                ::instrumented::load_shed::InflightLimiter::new();
+            ::instrumented::load_shed::acquire_in_crate(#name, #ctx, #krate, &__INSTRUMENTED_INFLIGHT_LIMITER, #max)
+        };
+    };
+    new.block.stmts.insert(0, inflight_limit);
+}
+
+/// Captures the location of the function in a constant, and exports it on the first call.
+fn add_location(new: &mut ItemFn, name: &str, ctx: &TokenStream) {
+    let location: Stmt = parse_quote_spanned! {new.sig.ident.span()=>
+        const __INSTRUMENTED_LOCATION: ::instrumented::Location =
+            ::instrumented::Location::new(file!(), line!());
+    };
+    let export: Stmt = parse_quote! {
+        {
+            static __INSTRUMENTED_LOCATION_EXPORTED: ::std::sync::Once = ::std::sync::Once::new();
+            __INSTRUMENTED_LOCATION_EXPORTED.call_once(|| {
+                ::instrumented::register_location(#name, #ctx, &__INSTRUMENTED_LOCATION)
+            });
+        }
+    };
+    new.block.stmts.insert(0, export);
+    new.block.stmts.insert(0, location);
+}
+
+/// Observes the time since the previous call, stored in a static of the function.
+fn add_interarrival(new: &mut ItemFn, name: &str, ctx: &TokenStream) {
+    let krate = crate_name();
+    let interarrival: Stmt = parse_quote! {
+        {
+            static __INSTRUMENTED_LAST_CALL: ::std::sync::atomic::AtomicU64 =
+                ::std::sync::atomic::AtomicU64::new(0);
+            ::instrumented::observe_interarrival_in_crate(#name, #ctx, #krate, &__INSTRUMENTED_LAST_CALL);
+        }
+    };
+    new.block.stmts.insert(0, interarrival);
+}
+
+/// Registers the function with `instrumented` at program startup, by placing a constructor in the
+/// platform's initializer section, so it can be listed before it is first called. The first line
+/// of its doc comment, if any, is registered for `function_info`.
+fn add_registration(new: &mut ItemFn, name: &str, ctx: &TokenStream, help: Option<String>) {
+    let help = help.map(|help| {
+        quote!(::instrumented::register_function_help(#name, #ctx, #help);)
+    });
+    let registration: Stmt = parse_quote! {
+        #[used]
+        #[cfg_attr(
+            any(target_os = "linux", target_os = "android", target_os = "freebsd"),
+            link_section = ".init_array"
+        )]
+        #[cfg_attr(any(target_os = "macos", target_os = "ios"), link_section = "__DATA,__mod_init_func")]
+        #[cfg_attr(windows, link_section = ".CRT$XCU")]
+        static __INSTRUMENTED_REGISTRATION: extern "C" fn() = {
+            extern "C" fn __instrumented_register() {
+                ::instrumented::register_function(#name, #ctx);
+                #help
+            }
+            __instrumented_register
+        };
+    };
+    new.block.stmts.insert(0, registration);
+}
+
+/// The maximum length in characters of the `help` label of `function_info`.
+const MAX_HELP_LEN: usize = 100;
+
+/// The first line of a doc comment, trimmed, with its whitespace collapsed and without its final
+/// period, truncated to `MAX_HELP_LEN` characters.
+fn doc_help(attrs: &[Attribute]) -> Option<String> {
+    let line = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(doc)) => match doc.lit {
+                Lit::Str(doc) => Some(doc.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .flat_map(|doc| {
+            doc.lines()
+                .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+                .collect::<Vec<_>>()
+        })
+        .find(|line| !line.is_empty())?;
+    Some(line.trim_end_matches('.').chars().take(MAX_HELP_LEN).collect())
+}
+
+/// Makes every elided lifetime in the inputs of an `async fn` explicit, so that the returned
+/// `impl Future` can be bound by all of them.
+struct ExplicitLifetimes {
+    future_lifetime: Lifetime,
+    lifetimes: Vec<Lifetime>,
+}
+
+impl ExplicitLifetimes {
+    fn next_lifetime(&mut self, span: Span) -> Lifetime {
+        let lifetime = Lifetime::new(
+            &format!("'__instrumented_life{}", self.lifetimes.len()),
+            span,
+        );
+        self.lifetimes.push(lifetime.clone());
+        lifetime
+    }
+}
+
+impl VisitMut for ExplicitLifetimes {
+    fn visit_receiver_mut(&mut self, receiver: &mut Receiver) {
+        if let Some((and_token, lifetime)) = &mut receiver.reference {
+            if lifetime.is_none() {
+                *lifetime = Some(self.next_lifetime(and_token.span));
+            }
+        }
+        visit_mut::visit_receiver_mut(self, receiver);
+    }
+
+    fn visit_type_reference_mut(&mut self, reference: &mut TypeReference) {
+        if reference.lifetime.is_none() {
+            reference.lifetime = Some(self.next_lifetime(reference.and_token.span));
+        }
+        visit_mut::visit_type_reference_mut(self, reference);
+    }
+
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if lifetime.ident == "_" {
+            *lifetime = self.next_lifetime(lifetime.span());
+        }
+    }
+
+    fn visit_type_impl_trait_mut(&mut self, impl_trait: &mut TypeImplTrait) {
+        impl_trait
+            .bounds
+            .push(TypeParamBound::Lifetime(self.future_lifetime.clone()));
+        visit_mut::visit_type_impl_trait_mut(self, impl_trait);
+    }
+
+    // Elided lifetimes in `fn(&T)` and `Fn(&T)` are higher-ranked, leave them alone.
+    fn visit_type_bare_fn_mut(&mut self, _: &mut TypeBareFn) {}
+
+    fn visit_parenthesized_generic_arguments_mut(&mut self, _: &mut ParenthesizedGenericArguments) {
+    }
+}
+
+/// Rewrites `async fn f(..) -> T` into `fn f(..) -> impl Future<Output = T>`, so that the
+/// instrumented future can be constructed at call time.
+fn desugar_async_signature(sig: &mut Signature) {
+    let future_lifetime = Lifetime::new("'__instrumented", Span::call_site());
+    let mut visitor = ExplicitLifetimes {
+        future_lifetime: future_lifetime.clone(),
+        lifetimes: Vec::new(),
+    };
+    for input in sig.inputs.iter_mut() {
+        visitor.visit_fn_arg_mut(input);
+    }
+
+    let mut bounds: Vec<WherePredicate> = Vec::new();
+    let mut lifetimes: Vec<GenericParam> = Vec::new();
+    let mut others: Vec<GenericParam> = Vec::new();
+    for param in sig.generics.params.iter() {
+        match param {
+            GenericParam::Lifetime(def) => {
+                let lifetime = &def.lifetime;
+                bounds.push(parse_quote!(#lifetime: #future_lifetime));
+                lifetimes.push(param.clone());
+            }
+            GenericParam::Type(ty) => {
+                let ident = &ty.ident;
+                bounds.push(parse_quote!(#ident: #future_lifetime));
+                others.push(param.clone());
+            }
+            GenericParam::Const(_) => others.push(param.clone()),
+        }
+    }
+    for lifetime in visitor.lifetimes {
+        bounds.push(parse_quote!(#lifetime: #future_lifetime));
+        lifetimes.push(GenericParam::Lifetime(LifetimeDef::new(lifetime)));
+    }
+    if let Some(FnArg::Receiver(_)) = sig.inputs.first() {
+        bounds.push(parse_quote!(Self: #future_lifetime));
+    }
+    lifetimes.push(GenericParam::Lifetime(LifetimeDef::new(
+        future_lifetime.clone(),
+    )));
+    sig.generics.params = lifetimes.into_iter().chain(others).collect();
+    sig.generics.make_where_clause().predicates.extend(bounds);
+
+    let output = match &sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+    sig.output = parse_quote! {
+        -> impl ::std::future::Future<Output = #output> + #future_lifetime
+    };
+    sig.asyncness = None;
+}
+
+fn observe_apdex(
+    apdex_t: Option<f64>,
+    function_name: &str,
+    ctx: &TokenStream,
+    is_err: TokenStream,
+) -> TokenStream {
+    let krate = crate_name();
+    match apdex_t {
+        Some(threshold) => quote! {
+            ::instrumented::observe_apdex_in_crate(#function_name, #ctx, #krate, #threshold, __instrumented_elapsed, #is_err);
+        },
+        None => quote! {},
+    }
+}
+
+/// Counts a call towards the latency SLO of `slo_ms`, if any: it's good if it didn't fail and
+/// completed within the threshold.
+fn observe_slo(
+    slo_ms: Option<u64>,
+    function_name: &str,
+    ctx: &TokenStream,
+    is_err: TokenStream,
+) -> TokenStream {
+    let krate = crate_name();
+    match slo_ms {
+        Some(slo_ms) => {
+            let threshold = slo_ms as f64 / 1000.0;
+            quote! {
+                ::instrumented::observe_slo_in_crate(#function_name, #ctx, #krate, #threshold, __instrumented_elapsed, #is_err);
+            }
+        }
+        None => quote! {},
+    }
+}
+
 #[allow(unused)]
 fn generate_function(
-    closure: &ExprClosure,
+    invoke: &TokenStream,
     expressions: &FormattedAttributes,
     result: bool,
+    is_async: bool,
     function_name: String,
-    ctx: &str,
+    ctx: &TokenStream,
 ) -> Result<ItemFn> {
     let FormattedAttributes {
         ok_expr,
         err_expr,
         ctx,
+        apdex_t,
+        slo_ms,
+        ignore_err,
+        err_label,
+        cpu_time,
+        count_allocs,
+        chaos,
+        track_recovery,
+        classify,
+        local_metrics,
+        hdr,
+        histogram,
+        err_labels,
+        size_class,
+        rate_limit_err,
+        overload_err,
+        return_timing,
+        percentiles,
+        phases,
+        ..
     } = expressions;
+    let krate = crate_name();
+    let enter_phases = enter_phases(*phases, &function_name, ctx);
+    let observe_duration = if *local_metrics {
+        quote!(::instrumented::observe_duration_local_in_crate(
+            #function_name,
+            #ctx,
+            #krate,
+            __instrumented_start
+        ))
+    } else if *hdr {
+        quote!(::instrumented::hdr::observe_duration_for(
+            #function_name,
+            #ctx,
+            __instrumented_start
+        ))
+    } else if let Some(histogram) = histogram {
+        let mode = match histogram {
+            HistogramMode::Classic => quote!(Classic),
+            HistogramMode::Native => quote!(Native),
+            HistogramMode::Both => quote!(Both),
+        };
+        quote! {
+            ::instrumented::native_histogram::observe_duration_in_crate(
+                #function_name,
+                #ctx,
+                #krate,
+                __instrumented_start,
+                ::instrumented::native_histogram::HistogramMode::#mode,
+            )
+        }
+    } else {
+        quote!(::instrumented::observe_duration_in_crate(
+            #function_name,
+            #ctx,
+            #krate,
+            __instrumented_start
+        ))
+    };
+    // The size class is evaluated by the preamble, before the arguments are moved.
+    let observe_size_class = if size_class.is_some() {
+        quote! {
+            ::instrumented::observe_size_class_in_crate(
+                #function_name,
+                #ctx,
+                #krate,
+                __instrumented_size_class,
+                __instrumented_elapsed,
+            );
+        }
+    } else {
+        quote!()
+    };
+    // With `hdr`, the percentiles are read from the HDR histogram of the function instead.
+    let observe_percentiles = if !*percentiles {
+        quote!()
+    } else if *hdr {
+        quote!(::instrumented::percentiles::track_hdr_for(#function_name, #ctx);)
+    } else {
+        quote!(::instrumented::percentiles::observe_for(#function_name, #ctx, __instrumented_elapsed);)
+    };
+    // The inflight gauge of async functions is maintained by `InstrumentedFuture`.
+    let (inc_inflight, dec_inflight) = if is_async {
+        (quote!(), quote!())
+    } else {
+        (
+            quote!(::instrumented::inc_inflight_in_crate(#function_name, #ctx, #krate);),
+            quote!(::instrumented::dec_inflight_in_crate(#function_name, #ctx, #krate);),
+        )
+    };
+    let (cpu_start, cpu_observe) = if *cpu_time {
+        (
+            quote!(let __instrumented_cpu_start = ::instrumented::thread_cpu_time();),
+            quote!(::instrumented::observe_cpu_time_in_crate(#function_name, #ctx, #krate, __instrumented_cpu_start);),
+        )
+    } else {
+        (quote!(), quote!())
+    };
+    let (allocs_start, allocs_observe) = if *count_allocs {
+        (
+            quote!(let __instrumented_body_allocs = ::instrumented::alloc::alloc_snapshot();),
+            quote!(::instrumented::alloc::observe_allocs_for(#function_name, #ctx, __instrumented_body_allocs);),
+        )
+    } else {
+        (quote!(), quote!())
+    };
+    // The classifier borrows the returned value, or the `Ok` value of a `Result`.
+    let classify = match classify {
+        Some(classify) => quote! {
+            ::instrumented::inc_result_class_in_crate(#function_name, #ctx, #krate, #classify(&result));
+        },
+        None => quote!(),
+    };
+    let inject_latency = if *chaos {
+        quote!(::instrumented::chaos::inject_latency_for(#function_name);)
+    } else {
+        quote!()
+    };
+    // The calls over `max_rate` return before being counted, the async ones from their future.
+    let rate_limited = match rate_limit_err {
+        Some(rate_limit_err) if !is_async => quote! {
+            if __instrumented_rate_limited {
+                return Err(#rate_limit_err);
+            }
+        },
+        _ => quote!(),
+    };
+    // The calls over `max_inflight` too, keeping their slot until they return.
+    let load_shed = match overload_err {
+        Some(overload_err) if !is_async => quote! {
+            let __instrumented_inflight_permit = match __instrumented_inflight_permit {
+                Some(permit) => permit,
+                None => return Err(#overload_err),
+            };
+        },
+        _ => quote!(),
+    };
+    // With `return_timing`, the value is returned with the duration of the call.
+    let returned = if *return_timing {
+        quote! {
+            ::instrumented::Timed::new(
+                result,
+                ::std::time::Duration::from_secs_f64(__instrumented_elapsed),
+            )
+        }
+    } else {
+        quote!(result)
+    };
     let code = if result {
+        // Injected errors are returned without running the function.
+        let (inject_err, invoke) = if *chaos {
+            (
+                quote! {
+                    let __instrumented_injected = ::instrumented::chaos::injected_error(#function_name);
+                    let __instrumented_is_injected = __instrumented_injected.is_some();
+                },
+                quote! {
+                    (match __instrumented_injected {
+                        Some(err) => Err(err),
+                        None => #invoke,
+                    })
+                },
+            )
+        } else {
+            (quote!(), quote!(#invoke))
+        };
+        let count_err = if *chaos {
+            quote! {
+                if __instrumented_is_injected {
+                    ::instrumented::chaos::inc_injected_error_counter_in_crate(#function_name, #ctx, #krate, __instrumented_err);
+                } else {
+                    ::instrumented::inc_error_counter_in_crate(#function_name, #ctx, #krate, __instrumented_err);
+                }
+            }
+        } else {
+            quote!(::instrumented::inc_error_counter_in_crate(#function_name, #ctx, #krate, __instrumented_err);)
+        };
+        // Ignored errors don't start failure episodes, nor end them.
+        let (recovery_state, recovery_ok, recovery_err) = if *track_recovery {
+            (
+                quote! {
+                    static __INSTRUMENTED_FAILING_SINCE: ::std::sync::atomic::AtomicU64 =
+                        ::std::sync::atomic::AtomicU64::new(0);
+                },
+                quote!(::instrumented::observe_recovery_in_crate(#function_name, #ctx, #krate, &__INSTRUMENTED_FAILING_SINCE, true);),
+                quote! {
+                    if !__instrumented_ignored {
+                        ::instrumented::observe_recovery_in_crate(#function_name, #ctx, #krate, &__INSTRUMENTED_FAILING_SINCE, false);
+                    }
+                },
+            )
+        } else {
+            (quote!(), quote!(), quote!())
+        };
+        let apdex_ok = observe_apdex(*apdex_t, &function_name, ctx, quote!(false));
+        let apdex_err = observe_apdex(
+            *apdex_t,
+            &function_name,
+            ctx,
+            quote!(!__instrumented_ignored),
+        );
+        let slo_ok = observe_slo(*slo_ms, &function_name, ctx, quote!(false));
+        let slo_err = observe_slo(*slo_ms, &function_name, ctx, quote!(!__instrumented_ignored));
+        let err_label_expr = match err_label {
+            ErrLabel::Debug => quote!(format!("{:?}", err)),
+            ErrLabel::IoKind => quote! {
+                ::instrumented::io_kind_label(&err).unwrap_or_else(|| format!("{:?}", err))
+            },
+            ErrLabel::Class => quote! {
+                ::instrumented::InstrumentedErrorClass::class(&err).to_string()
+            },
+        };
+        let err_detail = match (err_labels, err_label) {
+            (Some(err_labels), _) => quote! {
+                ::instrumented::inc_error_detail_for(#function_name, #ctx, #err_labels(&err));
+            },
+            (None, ErrLabel::Class) => quote! {
+                ::instrumented::inc_error_detail_for(
+                    #function_name,
+                    #ctx,
+                    ::instrumented::error_class_labels(&err),
+                );
+            },
+            (None, _) => quote!(),
+        };
+        let ignored = if ignore_err.is_empty() {
+            quote!(false)
+        } else {
+            quote! {
+                ::instrumented::is_ignored_err(
+                    &__instrumented_err,
+                    ::instrumented::type_name_of(&err),
+                    &[#(#ignore_err),*],
+                )
+            }
+        };
+        // Only emitted when errors are logged, as an empty `if` trips lints.
+        let log_err = if err_expr.is_empty() {
+            quote!()
+        } else {
+            quote! {
+                if !__instrumented_ignored {
+                    #err_expr
+                }
+            }
+        };
         quote! {
             fn temp() {
-                ::instrumented::inc_called_counter_for(#function_name, #ctx);
-                ::instrumented::inc_inflight_for(#function_name, #ctx);
-                let timer = ::instrumented::get_timer_for(#function_name, #ctx);
-                (#closure)()
+                #recovery_state
+                #rate_limited
+                #load_shed
+                ::instrumented::inc_called_counter_in_crate(#function_name, #ctx, #krate);
+                #inc_inflight
+                let __instrumented_start = ::instrumented::Instant::now();
+                #enter_phases
+                #cpu_start
+                #allocs_start
+                #inject_latency
+                #inject_err
+                #invoke
                     .map(|result| {
-                        #ok_expr;
-                        ::instrumented::dec_inflight_for(#function_name, #ctx);
-                        result
+                        #cpu_observe
+                        #allocs_observe
+                        #ok_expr
+                        let __instrumented_elapsed =
+                            #observe_duration;
+                        #observe_size_class
+                        #observe_percentiles
+                        #apdex_ok
+                        #slo_ok
+                        #recovery_ok
+                        #classify
+                        ::instrumented::notify_observers(#function_name, #ctx, __instrumented_elapsed, None);
+                        #dec_inflight
+                        #returned
                     })
                     .map_err(|err| {
-                        #err_expr;
-                        ::instrumented::inc_error_counter_for(#function_name, #ctx, format!("{:?}", err));
-                        ::instrumented::dec_inflight_for(#function_name, #ctx);
+                        #cpu_observe
+                        #allocs_observe
+                        let __instrumented_err = #err_label_expr;
+                        let __instrumented_ignored = #ignored;
+                        #log_err
+                        let __instrumented_elapsed =
+                            #observe_duration;
+                        #observe_size_class
+                        #observe_percentiles
+                        #apdex_err
+                        #slo_err
+                        #recovery_err
+                        ::instrumented::notify_observers(
+                            #function_name,
+                            #ctx,
+                            __instrumented_elapsed,
+                            if __instrumented_ignored { None } else { Some(&__instrumented_err) },
+                        );
+                        if !__instrumented_ignored {
+                            #count_err
+                            #err_detail
+                        }
+                        #dec_inflight
                         err
                     })
             }
         }
     } else {
+        let apdex = observe_apdex(*apdex_t, &function_name, ctx, quote!(false));
+        let slo = observe_slo(*slo_ms, &function_name, ctx, quote!(false));
         quote! {
             fn temp() {
-                ::instrumented::inc_called_counter_for(#function_name, #ctx);
-                ::instrumented::inc_inflight_for(#function_name, #ctx);
-                let timer = ::instrumented::get_timer_for(#function_name, #ctx);
-                let result = (#closure)();
-                #ok_expr;
-                ::instrumented::dec_inflight_for(#function_name, #ctx);
-                result
+                ::instrumented::inc_called_counter_in_crate(#function_name, #ctx, #krate);
+                #inc_inflight
+                let __instrumented_start = ::instrumented::Instant::now();
+                #enter_phases
+                #cpu_start
+                #allocs_start
+                #inject_latency
+                let result = #invoke;
+                #cpu_observe
+                #allocs_observe
+                #ok_expr
+                let __instrumented_elapsed =
+                    #observe_duration;
+                #observe_size_class
+                #observe_percentiles
+                #apdex
+                #slo
+                #classify
+                ::instrumented::notify_observers(#function_name, #ctx, __instrumented_elapsed, None);
+                #dec_inflight
+                #returned
             }
         }
     };
 
+    // The allocations of the whole call, instrumentation included, are excluded from the
+    // allocations of the calling function, by a guard so that the calls returning early are too.
+    if *count_allocs {
+        let mut function: ItemFn = syn::parse2(code)?;
+        let block = &function.block;
+        function.block = parse_quote!({
+            let __instrumented_allocs = ::instrumented::alloc::attribute_allocs_on_drop();
+            #block
+        });
+        return Ok(function);
+    }
+
     syn::parse2(code)
 }
 
+/// Wraps the generated body of an async function so that the original body runs inside an
+/// `InstrumentedFuture` created when the function is called. When `boxed` is set, the original
+/// body already evaluates to a boxed future (as produced by `async-trait`), and the generated
+/// body boxes its future in turn.
+fn generate_async_function(
+    original: &ItemFn,
+    expressions: &FormattedAttributes,
+    result: bool,
+    boxed: bool,
+    function_name: String,
+) -> Result<ItemFn> {
+    let ctx = &expressions.ctx;
+    let krate = crate_name();
+    let block = &original.block;
+    let inner = generate_function(
+        &quote!(__instrumented_future.await),
+        expressions,
+        result,
+        true,
+        function_name.clone(),
+        ctx,
+    )?;
+    let inner_block = &inner.block;
+    // The future holds the slot of the call until it completes or is dropped.
+    let inner_block = match &expressions.overload_err {
+        Some(overload_err) => quote!({
+            let __instrumented_inflight_permit = match __instrumented_inflight_permit {
+                Some(permit) => permit,
+                None => return Err(#overload_err),
+            };
+            #inner_block
+        }),
+        None => quote!(#inner_block),
+    };
+    let inner_block = match &expressions.rate_limit_err {
+        Some(rate_limit_err) => quote!({
+            if __instrumented_rate_limited {
+                return Err(#rate_limit_err);
+            }
+            #inner_block
+        }),
+        None => inner_block,
+    };
+    let (future, body) = if boxed {
+        (quote!(#block), quote!(Box::pin(async move #inner_block)))
+    } else {
+        (quote!(async move #block), quote!(async move #inner_block))
+    };
+
+    let deadline = match expressions.deadline_ms {
+        Some(ms) if expressions.deadline_cancel => quote! {
+            let __instrumented_future = ::instrumented::CancelAtDeadline::new_in_crate(
+                #function_name,
+                #ctx,
+                #krate,
+                ::std::time::Duration::from_millis(#ms),
+                __instrumented_future,
+            );
+        },
+        Some(ms) => quote! {
+            let __instrumented_future = ::instrumented::DeadlineFuture::new_in_crate(
+                #function_name,
+                #ctx,
+                #krate,
+                ::std::time::Duration::from_millis(#ms),
+                __instrumented_future,
+            );
+        },
+        None => quote!(),
+    };
+
+    syn::parse2(quote! {
+        fn temp() {
+            let __instrumented_future =
+                ::instrumented::InstrumentedFuture::new_in_crate(#function_name, #ctx, #krate, #future);
+            #deadline
+            #body
+        }
+    })
+}
+
+/// Instruments a function returning a stream: the call itself is counted and timed as usual,
+/// and the returned stream is wrapped in an `InstrumentedStream`.
+fn generate_stream_function(
+    closure: &ExprClosure,
+    expressions: &FormattedAttributes,
+    item_is_result: bool,
+    function_name: String,
+) -> Result<ItemFn> {
+    let ctx = &expressions.ctx;
+    let krate = crate_name();
+    let classify = if item_is_result {
+        quote!(::instrumented::result_item_error)
+    } else {
+        quote!(::instrumented::never_item_error)
+    };
+
+    syn::parse2(quote! {
+        fn temp() {
+            ::instrumented::inc_called_counter_in_crate(#function_name, #ctx, #krate);
+            let __instrumented_start = ::instrumented::Instant::now();
+            let stream = (#closure)();
+            ::instrumented::observe_duration_in_crate(#function_name, #ctx, #krate, __instrumented_start);
+            ::instrumented::InstrumentedStream::new_in_crate(
+                #function_name,
+                #ctx,
+                #krate,
+                __instrumented_start,
+                stream,
+                #classify,
+            )
+        }
+    })
+}
+
+/// Instruments a function returning `!`: the call is counted, but as it never returns, nothing
+/// else is recorded.
+fn generate_never_function(
+    original: &ItemFn,
+    expressions: &FormattedAttributes,
+    function_name: String,
+) -> Result<ItemFn> {
+    let ctx = &expressions.ctx;
+    let krate = crate_name();
+    let enter_phases = enter_phases(expressions.phases, &function_name, ctx);
+    let block = &original.block;
+
+    syn::parse2(quote! {
+        fn temp() {
+            ::instrumented::inc_called_counter_in_crate(#function_name, #ctx, #krate);
+            #enter_phases
+            #block
+        }
+    })
+}
+
+/// With `phases`, makes the function the frame of the `phase!` blocks of its body, until it
+/// returns or unwinds.
+fn enter_phases(phases: bool, function_name: &str, ctx: &TokenStream) -> TokenStream {
+    if phases {
+        quote!(let __instrumented_frame = ::instrumented::phase::enter_for(#function_name, #ctx);)
+    } else {
+        quote!()
+    }
+}
+
 /// Instruments a function.
 ///
+/// The leading level, e.g. `INFO`, and the `ok` and `err` levels are the levels the results and
+/// errors are logged at, with the `log` crate. Without the `logging` feature of `instrumented`,
+/// they are ignored, or rejected with `deny-log-levels`.
+///
 /// # Optional arguments
-/// * `ctx` - Specify a context label (defaults to `default`)
+/// * `ctx` - Specify a context label (defaults to `default`, or to the `INSTRUMENTED_DEFAULT_CTX`
+///   environment variable at build time: a label, `module` or `package`, as below)
+/// * `ctx_from_module` - Without `ctx`, labels the function with the last segment of the path of
+///   its module, e.g. `billing` for `my_app::billing::charge`
 /// * `fmt` - Provide a formatting string (defaults to `"() => {:?}`)
+/// * `apdex_t` - Apdex threshold in seconds; calls are counted as satisfied (`<= T`), tolerating
+///   (`<= 4T`) or frustrated (slower, or returned an error)
+/// * `slo_ms` - Latency SLO threshold in milliseconds, e.g. `slo_ms = 200`: every call is counted
+///   in `function_slo_total`, and the calls that completed within the threshold without an error
+///   in `function_slo_good_total`, for burn-rate alerts against a target ratio. Ignored errors
+///   are judged by their duration. Not supported on streams.
+/// * `stream` - The function returns `impl Stream`; the stream is wrapped to record the time to
+///   the first item, the time until completion, the number of items, and `Err` items as errors.
+///   The returned stream isn't logged.
+/// * `ignore_err` - Comma separated list of error variant or type names (e.g. `"NotFound,
+///   WouldBlock"`) that are part of normal control flow: matching errors are neither logged nor
+///   counted as errors, although the call is still counted and timed
+/// * `err_label` - How the `err` label of `function_error_total` is derived: `"debug"` (the
+///   default) uses the `Debug` representation of the error, `"io_kind"` uses the
+///   `io::ErrorKind` of errors that are or wrap an `io::Error` (through `Error::source`), and
+///   falls back to `Debug` otherwise. `"io_kind"` requires the error to implement
+///   `std::error::Error + 'static`. `"class"` uses the class of errors implementing
+///   `instrumented::InstrumentedErrorClass`, e.g. derived, and counts them in
+///   `function_error_detail_total` with bounded `class` and `retryable` labels, so it can't be
+///   combined with `err_labels`. `ignore_err` then matches the class.
+/// * `log_rate_limit` - Maximum number of errors logged per 10 seconds (a token bucket, keyed by
+///   function name). The number of suppressed errors is logged in a summary line, before the next
+///   error logged, or when the metrics are gathered, e.g. scraped, if that's earlier. Metrics are
+///   never throttled.
+/// * `cpu_time` - Also records the CPU time consumed by the calling thread during the call, as
+///   `function_cpu_seconds`. Reading the thread CPU clock costs a syscall per call, so this is
+///   opt-in. Only supported on synchronous functions, and a no-op on platforms other than unix
+///   and Windows.
+/// * `count_allocs` - Also records the number of allocations and allocated bytes of the call, as
+///   `function_allocations_total` and `function_allocated_bytes_total`. Requires the `alloc`
+///   feature and `instrumented::alloc::CountingAllocator` to be the global allocator. Allocations
+///   of nested functions that also count allocations are only counted by the innermost one. Only
+///   supported on synchronous functions.
+/// * `interarrival` - Also records the time between consecutive calls of the function, as
+///   `function_interarrival_seconds`, to tell how bursty the calls are. The first call isn't
+///   observed.
+/// * `track_last_called` - Also sets `function_last_called_timestamp_seconds` to the current unix
+///   time on every call, e.g. to alert when a scheduled job hasn't run recently.
+/// * `deadline_ms` - Deadline of calls of an async function, in milliseconds from the call. Calls
+///   still running when it elapses are counted in `function_deadline_exceeded_total`, and keep
+///   running.
+/// * `deadline_cancel` - With `deadline_ms`, calls still running at the deadline are cancelled
+///   instead, and return `Err(instrumented::DeadlineExceeded.into())`: the function must return a
+///   `Result` whose error type implements `From<instrumented::DeadlineExceeded>`.
+/// * `label_thread` - Also counts calls by the name of the calling thread, as
+///   `function_called_by_thread_total` with a `thread` label (`unnamed` for unnamed threads).
+///   Thread names are sanitized and truncated to 32 characters.
+/// * `chaos` - Lets `instrumented::chaos` inject errors and latency into a fraction of the calls,
+///   for resilience testing. Functions without it are never affected. Injected errors are counted
+///   with the `injected="true"` label of `function_error_total`. Only supported on synchronous
+///   functions.
+/// * `location` - Captures the source file and line of the function with `file!()` and
+///   `line!()`, appends them to its logs as a `location=file:line` field, and exports them as the
+///   `location` label of `function_location_info`. Off by default, as paths add noise to labels;
+///   a common prefix can be stripped with `instrumented::set_location_prefix`.
+/// * `track_recovery` - Also records how long the function stays broken, from the first error of
+///   a failure episode to the next success, as `function_recovery_seconds`. Episodes that never
+///   recover aren't observed, and ignored errors neither start nor end an episode. Only supported
+///   on functions returning a `Result`.
+/// * `weight` - An expression of the units of work of a call, e.g. `weight = "items.len() as
+///   u64"` for a function processing a batch of `items`, added to `function_work_units_total`.
+///   It's evaluated with the arguments before the body runs, and must be a `u64`.
+/// * `classify` - The path of a function classifying the returned values, e.g. `classify =
+///   "crate::metrics::classify_hit"` to tell cache hits from misses. It's called with a reference
+///   to the returned value, or to the `Ok` value of a `Result`, and returns a `&'static str` class
+///   counted in `function_result_class_total` with a `class` label. Errors aren't classified. Not
+///   supported on streams.
+/// * `local_metrics` - Buffers the durations of the calls in a thread-local buffer, observed into
+///   `function_time_seconds` every `instrumented::set_local_metrics_batch` calls of the thread
+///   (64 by default), when the thread gathers the metrics or exits, or on
+///   `instrumented::flush_local_metrics`. This cuts the overhead of functions called millions of
+///   times a second, while the histograms of other threads lag behind by up to a batch. Only
+///   supported on synchronous functions.
+/// * `hdr` - Records the durations of the calls into an in-process HDR histogram instead of
+///   `function_time_seconds`, for functions running in microseconds or less, whose durations the
+///   buckets of the histogram quantize poorly. The p50, p90, p99 and p999 are exported in
+///   `function_time_hdr_seconds` with a `quantile` label, and the number of calls in
+///   `function_time_hdr_count`. Requires the `hdr` feature of `instrumented`, see
+///   `instrumented::hdr`. Not supported on streams, nor with `local_metrics`.
+/// * `histogram` - Where the durations of the calls are observed: `"classic"` in the buckets of
+///   `function_time_seconds`, `"native"` in a Prometheus native histogram, or `"both"`, see
+///   `instrumented::native_histogram`. Defaults to `set_histogram_mode`, itself classic by
+///   default. Not supported on streams, nor with `local_metrics` or `hdr`.
+/// * `size_class` - An expression of the size class of a call, e.g. `size_class =
+///   "bucket_len(items.len())"`, to see the latency by input size. It's evaluated with the
+///   arguments before the body runs, and must be a `&'static str` such as `"small"` or `"large"`,
+///   added as the `size_class` label of `function_time_by_size_class_seconds`, next to the
+///   duration observed in `function_time_seconds`. Keep the classes few, as each is a histogram.
+///   Not supported on streams.
+/// * `percentiles` - Exports the p50, p90 and p99 durations of the calls as the gauges
+///   `function_time_p50_seconds`, `function_time_p90_seconds` and `function_time_p99_seconds`,
+///   for dashboards that can't run `histogram_quantile`. They're process-local approximations,
+///   computed at gather time from a reservoir of 1024 durations sampled uniformly since the
+///   start, or from the HDR histogram with `hdr`, see `instrumented::percentiles`. Not supported
+///   on streams.
+/// * `phases` - Times the `instrumented::phase!` blocks of the body in `function_phase_seconds`,
+///   with the name and ctx of the function and the `phase` label, see `instrumented::phase`, and
+///   its `instrumented::ffi_section` calls in `function_ffi_seconds`. Only supported on
+///   synchronous functions.
+/// * `err_labels` - The path of a function labelling the errors, e.g. `err_labels =
+///   "crate::obs::classify"`, for errors with more than one dimension. It's called with a
+///   reference to the error, and returns a `Vec<(&'static str, String)>` of label names and
+///   values, such as `[("kind", "timeout"), ("retryable", "true")]`, counted in
+///   `function_error_detail_total`. The label names of a function are fixed by its first error:
+///   errors labelled with other names are logged once and not counted. Ignored errors aren't
+///   labelled. Only supported on functions returning a `Result`.
+/// * `max_rate` - The maximum number of calls per second, e.g. `max_rate = 1000`, enforced by a
+///   token bucket holding a second of calls. The calls over the limit, or over
+///   `instrumented::rate_limit::set_global_max_rate`, are counted in
+///   `function_rate_limited_total` instead of as calls. Unless `rate_limit_err` is set, they still
+///   run, as the limit can only be recorded.
+/// * `rate_limit_err` - With `max_rate`, an expression of the error returned by the calls over
+///   the limit instead of running the function, e.g. `rate_limit_err =
+///   "MyError::TooManyRequests"`. Only supported on functions returning a `Result`.
+/// * `max_inflight` - The maximum number of calls running at once, e.g. `max_inflight = 64`.
+///   The calls over the limit return `overload_err` without running the function, and are
+///   counted in `function_load_shed_total` instead of as calls. The calls of async functions
+///   hold their slot until their future completes or is dropped. Requires `overload_err`.
+/// * `overload_err` - With `max_inflight`, an expression of the error returned by the calls over
+///   the limit, e.g. `overload_err = "MyError::Overloaded"`. Only supported on functions
+///   returning a `Result`.
+/// * `return_timing` - **Changes the return type** of the function from `T` to
+///   `instrumented::Timed<T>`, or from `Result<T, E>` to `Result<Timed<T>, E>`, so that callers
+///   can read the duration of the call, as observed in `function_time_seconds`, e.g. for a
+///   response header. `Timed` derefs to the value, and has `duration()` and `into_inner()`. The
+///   documentation of the function says so too. Only supported on synchronous functions.
+/// * `main` - Shuts the metrics down when the function returns, after its call is recorded, see
+///   `instrumented::lifecycle`. Meant for `fn main`, and only supported on synchronous functions
+///   without arguments.
+/// * `test_namespace` - In test builds, records the function as `test_<name>`, so that the metrics
+///   of test helpers don't show up under the names of production functions. Enabled by default
+///   for the functions with a `#[test]` or `#[bench]` attribute after `#[instrument]`, which
+///   `test_namespace = false` disables.
+/// * `debug_only` - Only instruments the function in builds with `debug_assertions` enabled; in
+///   release builds the function is left as is, without any overhead.
 ///
-/// # Example
+/// # `impl Trait` and `!`
+/// The value returned by a function returning `impl Trait` can't be formatted, so `fmt` is
+/// logged without arguments (defaults to `"name() => <impl Trait>"`). Calls of functions returning
+/// `!` are counted, but as they never return, nothing else is recorded.
+///
+/// # `unsafe` and `extern` functions
+/// The signature and attributes of the function, including `unsafe`, the ABI and `#[no_mangle]`,
+/// are kept as is, and only the body is wrapped. Variadic functions aren't supported.
+///
+/// # Async functions
+/// `async fn`s are rewritten into functions returning `impl Future`, so that the time between
+/// calling the function and the future first being polled can be recorded separately (as
+/// `function_poll_delay_seconds`) from the time spent running it (`function_time_seconds`).
+///
+/// Methods of `#[async_trait]` traits are supported as well. Place `#[instrument]` on the method
+/// itself, below `#[async_trait]` on the impl: `async-trait` rewrites the method first into one
+/// returning `Pin<Box<dyn Future>>`, which `#[instrument]` recognizes and wraps so that the
+/// measured duration covers the awaited body.
+///
+/// # Example
 /// ```rust
 /// extern crate instrumented;
 /// extern crate log;
@@ -268,35 +1692,1037 @@ pub fn instrument(
 ) -> proc_macro::TokenStream {
     let attr = parse_macro_input!(attr as AttributeArgs);
     let original_fn: ItemFn = parse_macro_input!(item as ItemFn);
-    let fmt_default = original_fn.sig.ident.to_string() + "() => {:?}";
-    let ctx_default = "default";
-    let parsed_attributes =
-        match FormattedAttributes::parse_attributes(&attr, &fmt_default, &ctx_default) {
-            Ok(val) => val,
-            Err(err) => {
-                return err.write_errors().into();
+    expand(&attr, original_fn).into()
+}
+
+/// Instruments all the functions of an inline module, as if each had `#[instrument]` with the
+/// same arguments, under the name `module::function`.
+///
+/// # Optional arguments
+/// The arguments of `#[instrument]`, and:
+/// * `recursive` - Also instruments the functions of nested modules, under the name
+///   `module::nested::function`. With `ctx_from_module`, their context label is `nested`.
+///
+/// Functions marked `#[instrument(skip)]` are left as is, and so are the functions with their own
+/// `#[instrument]` attribute, and methods. Modules declared as `mod name;` aren't supported, as the
+/// macro can't see their items.
+///
+/// # Example
+/// ```rust
+/// extern crate instrumented;
+/// extern crate log;
+/// use instrumented::instrument_mod;
+///
+/// #[instrument_mod(INFO, ctx = "parser")]
+/// mod parser {
+///     // Instrumented as `parser::parse`.
+///     pub fn parse(input: &str) -> usize {
+///         input.len()
+///     }
+///
+///     #[instrument(skip)]
+///     pub fn is_blank(input: &str) -> bool {
+///         input.trim().is_empty()
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn instrument_mod(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let attr = parse_macro_input!(attr as AttributeArgs);
+    let module: ItemMod = parse_macro_input!(item as ItemMod);
+    expand_mod(attr, module).into()
+}
+
+/// Generates an instrumented wrapper of a trait, `Instrumented<Trait>`, implementing the trait by
+/// delegating to the wrapped implementation, with each method instrumented as if it had
+/// `#[instrument]` with the same arguments, under the name of the method. The trait itself is
+/// left as is.
+///
+/// This instruments the implementations of a trait that can't be annotated, e.g. behind a
+/// `Box<dyn Trait>`. As a macro can't see the items of a trait from its name, the attribute goes
+/// on the trait definition.
+///
+/// # Optional arguments
+/// The arguments of `#[instrument]`. Methods marked `#[instrument(skip)]` are delegated without
+/// being instrumented.
+///
+/// The methods must take `self`, `&self` or `&mut self`, or no receiver, and can't return `Self`.
+/// Async methods are supported along with `#[async_trait]`, placed after `#[instrument_trait]`.
+///
+/// # Example
+/// ```rust
+/// extern crate instrumented;
+/// extern crate log;
+/// use instrumented::instrument_trait;
+///
+/// #[instrument_trait(INFO, ctx = "storage")]
+/// trait Store {
+///     fn get(&self, key: &str) -> Result<String, String>;
+/// }
+///
+/// struct MemoryStore;
+///
+/// impl Store for MemoryStore {
+///     fn get(&self, key: &str) -> Result<String, String> {
+///         Err(format!("{} not found", key))
+///     }
+/// }
+///
+/// let store: Box<dyn Store> = Box::new(InstrumentedStore::new(MemoryStore));
+/// assert!(store.get("key").is_err());
+/// ```
+#[proc_macro_attribute]
+pub fn instrument_trait(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let attr = parse_macro_input!(attr as AttributeArgs);
+    let item: ItemTrait = parse_macro_input!(item as ItemTrait);
+    expand_trait(&attr, item).into()
+}
+
+/// The arguments of `#[instrument_drop]`.
+#[derive(Default, FromMeta)]
+#[darling(default)]
+struct DropOptions {
+    name: Option<String>,
+    ctx: Option<String>,
+}
+
+/// Counts and times the body of a `Drop` implementation, like `instrumented::timed_drop`, under
+/// the name `Type::drop`. The inflight gauge is decremented even if the drop panics.
+///
+/// The attribute goes on the `impl Drop` block, as an attribute on the type can't see its `Drop`
+/// implementation. Only the body of `drop` is timed, as the fields are dropped after it returns:
+/// wrap the value with `instrumented::timed_drop` to time the drop of the fields too.
+///
+/// # Optional arguments
+/// * `ctx` - Specify a context label (defaults to `default`, or `INSTRUMENTED_DEFAULT_CTX`)
+/// * `name` - Specify the name label (defaults to `Type::drop`)
+///
+/// # Example
+/// ```rust
+/// extern crate instrumented;
+/// use instrumented::instrument_drop;
+///
+/// struct Journal;
+///
+/// #[instrument_drop(ctx = "io")]
+/// impl Drop for Journal {
+///     fn drop(&mut self) {
+///         // e.g. sync the journal to disk
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn instrument_drop(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let attr = parse_macro_input!(attr as AttributeArgs);
+    let item: ItemImpl = parse_macro_input!(item as ItemImpl);
+    expand_drop(&attr, item).into()
+}
+
+fn expand_drop(attr: &[NestedMeta], mut item: ItemImpl) -> TokenStream {
+    let options = match DropOptions::from_list(attr) {
+        Ok(options) => options,
+        Err(err) => return err.write_errors(),
+    };
+    let is_drop = item.trait_.as_ref().is_some_and(|(_, path, _)| {
+        path.segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Drop")
+    });
+    if !is_drop {
+        return syn::Error::new(
+            item.impl_token.span(),
+            "`#[instrument_drop]` can only be used on `impl Drop` blocks",
+        )
+        .to_compile_error();
+    }
+    let name = options.name.unwrap_or_else(|| {
+        let ty = match &*item.self_ty {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .map_or_else(String::new, |segment| segment.ident.to_string()),
+            ty => ty.to_token_stream().to_string(),
+        };
+        format!("{}::drop", ty)
+    });
+    let ctx = match options.ctx {
+        Some(ctx) => quote!(#ctx),
+        None => default_ctx("default"),
+    };
+    let krate = crate_name();
+    for impl_item in &mut item.items {
+        if let ImplItem::Method(method) = impl_item {
+            if method.sig.ident == "drop" {
+                let timer: Stmt = parse_quote! {
+                    let __instrumented_drop = ::instrumented::DropTimer::start_in_crate(#name, #ctx, #krate);
+                };
+                method.block.stmts.insert(0, timer);
+            }
+        }
+    }
+    item.into_token_stream()
+}
+
+/// The arguments of `#[instrumented(...)]` on a variant deriving `InstrumentedErrorClass`.
+#[derive(Default, FromMeta)]
+#[darling(default)]
+struct ClassOptions {
+    class: Option<String>,
+    retryable: bool,
+}
+
+/// Derives `instrumented::InstrumentedErrorClass` for an error enum, for `err_label = "class"`.
+///
+/// Each variant is classified by its `#[instrumented(class = "...", retryable)]` attribute. The
+/// class defaults to the name of the variant in snake case, and variants are only retryable with
+/// `retryable`.
+///
+/// # Example
+/// ```rust
+/// extern crate instrumented;
+/// use instrumented::InstrumentedErrorClass;
+///
+/// #[derive(Debug, InstrumentedErrorClass)]
+/// enum FetchError {
+///     #[instrumented(class = "timeout", retryable)]
+///     TimedOut,
+///     #[instrumented(class = "client")]
+///     BadRequest(String),
+///     NotFound { path: String },
+/// }
+///
+/// assert_eq!(FetchError::TimedOut.class(), "timeout");
+/// assert!(FetchError::TimedOut.retryable());
+/// assert_eq!(FetchError::NotFound { path: "/".into() }.class(), "not_found");
+/// ```
+#[proc_macro_derive(InstrumentedErrorClass, attributes(instrumented))]
+pub fn derive_error_class(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_error_class(input).into()
+}
+
+fn expand_error_class(input: DeriveInput) -> TokenStream {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return syn::Error::new(
+                input.ident.span(),
+                "`InstrumentedErrorClass` can only be derived for enums",
+            )
+            .to_compile_error()
+        }
+    };
+    let mut class_arms = vec![];
+    let mut retryable_arms = vec![];
+    for variant in &data.variants {
+        let mut options = ClassOptions::default();
+        for attr in &variant.attrs {
+            if !attr.path.is_ident("instrumented") {
+                continue;
+            }
+            let parsed = match attr.parse_meta() {
+                Ok(Meta::List(list)) => {
+                    let nested = list.nested.into_iter().collect::<Vec<_>>();
+                    ClassOptions::from_list(&nested).map_err(|err| err.write_errors())
+                }
+                Ok(meta) => Err(syn::Error::new(
+                    meta.span(),
+                    "expected `#[instrumented(class = \"...\", retryable)]`",
+                )
+                .to_compile_error()),
+                Err(err) => Err(err.to_compile_error()),
+            };
+            match parsed {
+                Ok(parsed) => options = parsed,
+                Err(err) => return err,
             }
+        }
+        let ident = &variant.ident;
+        let class = options
+            .class
+            .unwrap_or_else(|| snake_case(&ident.to_string()));
+        if class.is_empty() {
+            return syn::Error::new(ident.span(), "the class of an error can't be empty")
+                .to_compile_error();
+        }
+        let retryable = options.retryable;
+        let pattern = match &variant.fields {
+            Fields::Named(_) => quote!(Self::#ident { .. }),
+            Fields::Unnamed(_) => quote!(Self::#ident(..)),
+            Fields::Unit => quote!(Self::#ident),
         };
+        class_arms.push(quote!(#pattern => #class,));
+        retryable_arms.push(quote!(#pattern => #retryable,));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::instrumented::InstrumentedErrorClass for #name #ty_generics #where_clause {
+            fn class(&self) -> &'static str {
+                match *self {
+                    #(#class_arms)*
+                }
+            }
 
-    let closure = make_closure(&original_fn);
+            fn retryable(&self) -> bool {
+                match *self {
+                    #(#retryable_arms)*
+                }
+            }
+        }
+    }
+}
+
+/// `TimedOut` to `timed_out`, and `HTTPError` to `http_error`: a run of capitals is one word.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let after_lower = i > 0 && !chars[i - 1].is_uppercase();
+            let ends_run = i > 0
+                && chars[i - 1].is_uppercase()
+                && chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if after_lower || ends_run {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+fn is_recursive_flag(arg: &NestedMeta) -> bool {
+    if let NestedMeta::Meta(Meta::Path(path)) = arg {
+        return path.is_ident("recursive");
+    }
+    false
+}
+
+fn is_instrument_attr(attr: &Attribute) -> bool {
+    attr.path
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == "instrument")
+}
+
+/// Whether the attribute is `#[instrument(skip)]`, which excludes a function from
+/// `#[instrument_mod]`.
+fn is_instrument_skip(attr: &Attribute) -> bool {
+    if !is_instrument_attr(attr) {
+        return false;
+    }
+    if let Ok(Meta::List(list)) = attr.parse_meta() {
+        if let Some(NestedMeta::Meta(Meta::Path(path))) = list.nested.first() {
+            return list.nested.len() == 1 && path.is_ident("skip");
+        }
+    }
+    false
+}
+
+fn expand_mod(attr: AttributeArgs, module: ItemMod) -> TokenStream {
+    let (recursive, attr): (Vec<_>, Vec<_>) = attr.into_iter().partition(is_recursive_flag);
+    match instrument_mod_items(&attr, !recursive.is_empty(), module, "") {
+        Ok(module) => module.into_token_stream(),
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+fn instrument_mod_items(
+    attr: &[NestedMeta],
+    recursive: bool,
+    mut module: ItemMod,
+    prefix: &str,
+) -> Result<ItemMod> {
+    let (brace, items) = match module.content.take() {
+        Some(content) => content,
+        None => {
+            return Err(syn::Error::new(
+                module.ident.span(),
+                "`#[instrument_mod]` can only be used on inline modules, as it can't see the \
+                 items of `mod name;`",
+            ))
+        }
+    };
+    let prefix = format!("{}{}::", prefix, module.ident);
+    let mut instrumented = Vec::with_capacity(items.len());
+    for item in items {
+        instrumented.push(match item {
+            Item::Fn(mut function) => {
+                let attrs = function.attrs.len();
+                function.attrs.retain(|attr| !is_instrument_skip(attr));
+                if function.attrs.len() != attrs || function.attrs.iter().any(is_instrument_attr) {
+                    Item::Fn(function)
+                } else {
+                    let name = format!("{}{}", prefix, function.sig.ident);
+                    Item::Verbatim(expand_as(attr, function, &name))
+                }
+            }
+            Item::Mod(nested) if recursive => {
+                Item::Mod(instrument_mod_items(attr, recursive, nested, &prefix)?)
+            }
+            item => item,
+        });
+    }
+    module.content = Some((brace, instrumented));
+    Ok(module)
+}
+
+fn is_async_trait_attr(attr: &Attribute) -> bool {
+    attr.path
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == "async_trait")
+}
+
+fn expand_trait(attr: &[NestedMeta], mut item: ItemTrait) -> TokenStream {
+    match trait_wrapper(attr, &mut item) {
+        Ok(wrapper) => quote!(#item #wrapper),
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+/// Generates the wrapper of `#[instrument_trait]`, removing the `#[instrument(skip)]` attributes
+/// from the methods of the trait.
+fn trait_wrapper(attr: &[NestedMeta], item: &mut ItemTrait) -> Result<TokenStream> {
+    let trait_ident = &item.ident;
+    let wrapper = Ident::new(&format!("Instrumented{}", trait_ident), trait_ident.span());
+    // The wrapped type, named after any type parameter of the trait.
+    let inner = if item.generics.type_params().any(|param| param.ident == "T") {
+        Ident::new("Inner", Span::call_site())
+    } else {
+        Ident::new("T", Span::call_site())
+    };
+    let (_, ty_generics, where_clause) = item.generics.split_for_impl();
+    let trait_path = quote!(#trait_ident #ty_generics);
+    let async_trait: Vec<_> = item
+        .attrs
+        .iter()
+        .filter(|attr| is_async_trait_attr(attr))
+        .collect();
+    // The futures of `#[async_trait]` borrow the wrapper across awaits, and are `Send` unless
+    // declared with `#[async_trait(?Send)]`.
+    let send = async_trait
+        .iter()
+        .any(|attr| !attr.tokens.to_string().replace(' ', "").contains("?Send"));
+    let mut generics = item.generics.clone();
+    generics.params.push(if send {
+        parse_quote!(#inner: #trait_path + ::std::marker::Send + ::std::marker::Sync)
+    } else {
+        parse_quote!(#inner: #trait_path)
+    });
+    let (impl_generics, _, _) = generics.split_for_impl();
+
+    let mut items = Vec::with_capacity(item.items.len());
+    for trait_item in &mut item.items {
+        items.push(match trait_item {
+            TraitItem::Method(method) => {
+                let skip = method.attrs.iter().any(is_instrument_skip);
+                method.attrs.retain(|attr| !is_instrument_skip(attr));
+                let delegate = delegate_method(method, &inner, &trait_path)?;
+                if skip {
+                    delegate.into_token_stream()
+                } else {
+                    let name = method.sig.ident.to_string();
+                    expand_as(attr, delegate, &name)
+                }
+            }
+            TraitItem::Type(ty) => {
+                let ident = &ty.ident;
+                quote!(type #ident = <#inner as #trait_path>::#ident;)
+            }
+            TraitItem::Const(constant) => {
+                let ident = &constant.ident;
+                let ty = &constant.ty;
+                quote!(const #ident: #ty = <#inner as #trait_path>::#ident;)
+            }
+            other => {
+                return Err(syn::Error::new(
+                    other.span(),
+                    "`#[instrument_trait]` can only delegate methods, types and constants",
+                ))
+            }
+        });
+    }
+    let vis = &item.vis;
+    let doc = format!(
+        "Instruments every method of `{}`, by delegating to the wrapped implementation.",
+        trait_ident
+    );
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Debug, Clone)]
+        #vis struct #wrapper<#inner> {
+            inner: #inner,
+        }
+
+        #[allow(dead_code)]
+        impl<#inner> #wrapper<#inner> {
+            /// Wraps an implementation.
+            #[must_use]
+            #vis fn new(inner: #inner) -> Self {
+                #wrapper { inner }
+            }
+
+            /// Returns the wrapped implementation.
+            #[must_use]
+            #vis fn inner(&self) -> &#inner {
+                &self.inner
+            }
+
+            /// Unwraps the implementation.
+            #[must_use]
+            #vis fn into_inner(self) -> #inner {
+                self.inner
+            }
+        }
+
+        #(#async_trait)*
+        impl #impl_generics #trait_path for #wrapper<#inner> #where_clause {
+            #(#items)*
+        }
+    })
+}
+
+/// Builds a method delegating to the implementation of `method` by the wrapped `inner`.
+fn delegate_method(
+    method: &TraitItemMethod,
+    inner: &Ident,
+    trait_path: &TokenStream,
+) -> Result<ItemFn> {
+    let mut sig = method.sig.clone();
+    let mut receiver = None;
+    let mut args = Vec::new();
+    for (i, input) in sig.inputs.iter_mut().enumerate() {
+        match input {
+            FnArg::Receiver(arg) => {
+                receiver = Some(match (&arg.reference, &arg.mutability) {
+                    (Some(_), Some(_)) => quote!(&mut self.inner),
+                    (Some(_), None) => quote!(&self.inner),
+                    (None, _) => quote!(self.inner),
+                });
+                if arg.reference.is_none() {
+                    arg.mutability = None;
+                }
+            }
+            FnArg::Typed(arg) => {
+                let ident = match &*arg.pat {
+                    Pat::Ident(pat) if pat.ident == "self" => {
+                        return Err(syn::Error::new(
+                            pat.span(),
+                            "`#[instrument_trait]` can only delegate methods taking `self`, \
+                             `&self` or `&mut self`",
+                        ))
+                    }
+                    Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none() => {
+                        pat.ident.clone()
+                    }
+                    pat => Ident::new(&format!("arg{}", i), pat.span()),
+                };
+                arg.pat = parse_quote!(#ident);
+                args.push(ident);
+            }
+        }
+    }
+    let ident = &sig.ident;
+    let receiver = receiver.into_iter();
+    // The type and const parameters are passed explicitly, as they can't always be inferred from
+    // the arguments. The lifetimes can't be, when late bound.
+    let params: Vec<&Ident> = sig
+        .generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(ty) => Some(&ty.ident),
+            GenericParam::Const(constant) => Some(&constant.ident),
+            GenericParam::Lifetime(_) => None,
+        })
+        .collect();
+    let turbofish = if params.is_empty() {
+        quote!()
+    } else {
+        quote!(::<#(#params),*>)
+    };
+    let mut call =
+        quote!(<#inner as #trait_path>::#ident #turbofish(#(#receiver,)* #(#args),*));
+    if sig.asyncness.is_some() {
+        call = quote!(#call.await);
+    }
+    let attrs = method
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("cfg"))
+        .cloned()
+        .collect();
+    Ok(ItemFn {
+        attrs,
+        vis: Visibility::Inherited,
+        sig,
+        block: Box::new(parse_quote!({ #call })),
+    })
+}
+
+fn expand(attr: &[NestedMeta], original_fn: ItemFn) -> TokenStream {
+    let name = original_fn.sig.ident.to_string();
+    expand_as(attr, original_fn, &name)
+}
+
+/// Instruments `original_fn` under the given name, or under `test_<name>` in test builds when it
+/// goes in the test namespace.
+fn expand_as(attr: &[NestedMeta], original_fn: ItemFn, name: &str) -> TokenStream {
+    let test_namespace = Options::from_list(attr)
+        .ok()
+        .and_then(|options| options.named.test_namespace)
+        .unwrap_or_else(|| is_test_only(&original_fn));
+    if !test_namespace {
+        return expand_named(attr, original_fn, name);
+    }
+    let test = expand_named(attr, original_fn.clone(), &format!("test_{}", name));
+    let production = expand_named(attr, original_fn, name);
+    let mut expansion = with_cfg(test, quote!(test));
+    expansion.extend(with_cfg(production, quote!(not(test))));
+    expansion
+}
+
+/// Whether the function only exists in test builds, as a test or a benchmark. `#[cfg(test)]`
+/// attributes are evaluated before the expansion, so test helpers need `test_namespace`.
+fn is_test_only(original_fn: &ItemFn) -> bool {
+    original_fn.attrs.iter().any(|attr| {
+        attr.path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "test" || segment.ident == "bench")
+    })
+}
+
+/// Applies `#[cfg(#predicate)]` to every item of an expansion.
+fn with_cfg(expansion: TokenStream, predicate: TokenStream) -> TokenStream {
+    match syn::parse2::<syn::File>(expansion.clone()) {
+        Ok(file) => file
+            .items
+            .into_iter()
+            .map(|item| quote!(#[cfg(#predicate)] #item))
+            .collect(),
+        Err(_) => expansion,
+    }
+}
+
+/// Instruments `original_fn` under the given name, in every build.
+fn expand_named(attr: &[NestedMeta], original_fn: ItemFn, name: &str) -> TokenStream {
+    let opaque = check_if_return_impl_trait(&original_fn);
+    let fmt_default = if opaque {
+        name.to_string() + "() => <impl Trait>"
+    } else {
+        name.to_string() + "() => {:?}"
+    };
+    let ctx_default = "default";
+    let parsed_attributes = match FormattedAttributes::parse_attributes(
+        attr,
+        name,
+        &fmt_default,
+        ctx_default,
+        opaque,
+    ) {
+        Ok(val) => val,
+        Err(err) => {
+            return err.write_errors();
+        }
+    };
+
+    // The original function is kept as is in release builds.
+    if parsed_attributes.debug_only {
+        let instrumented = instrument_function(&parsed_attributes, original_fn.clone());
+        return quote! {
+            #[cfg(debug_assertions)]
+            #instrumented
+            #[cfg(not(debug_assertions))]
+            #original_fn
+        };
+    }
+
+    instrument_function(&parsed_attributes, original_fn)
+}
+
+fn instrument_function(
+    parsed_attributes: &FormattedAttributes,
+    original_fn: ItemFn,
+) -> TokenStream {
+    if let Some(variadic) = &original_fn.sig.variadic {
+        return syn::Error::new(
+            variadic.span(),
+            "`#[instrument]` can't be used on variadic functions",
+        )
+        .to_compile_error();
+    }
     let is_result = check_if_return_result(&original_fn);
+    let is_async =
+        original_fn.sig.asyncness.is_some() || boxed_future_output(&original_fn).is_some();
+    if (parsed_attributes.cpu_time || parsed_attributes.count_allocs)
+        && (parsed_attributes.stream || is_async)
+    {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`cpu_time` and `count_allocs` can only be used on synchronous functions",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.chaos && (parsed_attributes.stream || is_async) {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`chaos` can only be used on synchronous functions",
+        )
+        .to_compile_error();
+    }
+    if let Some(slo_ms) = parsed_attributes.slo_ms {
+        let message = if slo_ms == 0 {
+            Some("`slo_ms` must be positive")
+        } else if parsed_attributes.stream || check_if_return_never(&original_fn) {
+            Some("`slo_ms` can only be used on functions returning a value")
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            return syn::Error::new(original_fn.sig.ident.span(), message).to_compile_error();
+        }
+    }
+    if parsed_attributes.phases && (parsed_attributes.stream || is_async) {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`phases` can only be used on synchronous functions",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.local_metrics && (parsed_attributes.stream || is_async) {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`local_metrics` can only be used on synchronous functions",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.histogram.is_some()
+        && (parsed_attributes.stream || parsed_attributes.local_metrics || parsed_attributes.hdr)
+    {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`histogram` can't be used on streams, nor with `local_metrics` or `hdr`",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.hdr {
+        let message = if !cfg!(feature = "hdr") {
+            Some("`hdr` requires the `hdr` feature of `instrumented`")
+        } else if parsed_attributes.stream {
+            Some("`hdr` can't be used on streams")
+        } else if parsed_attributes.local_metrics {
+            Some("`hdr` can't be combined with `local_metrics`")
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            return syn::Error::new(original_fn.sig.ident.span(), message).to_compile_error();
+        }
+    }
+    if parsed_attributes.deadline_ms.is_some() && !is_async {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`deadline_ms` can only be used on async functions",
+        )
+        .to_compile_error();
+    }
+    let returns_result = is_result
+        || match boxed_future_output(&original_fn) {
+            Some(Type::Path(path)) => is_result_type(path),
+            _ => false,
+        };
+    if parsed_attributes.track_recovery && (parsed_attributes.stream || !returns_result) {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`track_recovery` can only be used on functions returning a `Result`",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.err_labels.is_some() && parsed_attributes.err_label == ErrLabel::Class {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`err_label = \"class\"` can't be combined with `err_labels`",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.err_labels.is_some() && (parsed_attributes.stream || !returns_result) {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`err_labels` can only be used on functions returning a `Result`",
+        )
+        .to_compile_error();
+    }
+    let flags = introspection_flags(
+        parsed_attributes,
+        returns_result || (parsed_attributes.stream && check_if_stream_item_result(&original_fn)),
+        is_async,
+    );
+    if parsed_attributes.classify.is_some()
+        && (parsed_attributes.stream || check_if_return_never(&original_fn))
+    {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`classify` can only be used on functions returning a value",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.size_class.is_some()
+        && (parsed_attributes.stream || check_if_return_never(&original_fn))
+    {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`size_class` can only be used on functions returning a value",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.percentiles
+        && (parsed_attributes.stream || check_if_return_never(&original_fn))
+    {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`percentiles` can only be used on functions returning a value",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.return_timing
+        && (parsed_attributes.stream || is_async || check_if_return_never(&original_fn))
+    {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`return_timing` can only be used on synchronous functions returning a value",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.main
+        && (parsed_attributes.stream
+            || is_async
+            || !original_fn.sig.inputs.is_empty()
+            || check_if_return_never(&original_fn))
+    {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`main` can only be used on synchronous functions without arguments",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.max_rate == Some(0) {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`max_rate` must be at least 1 call per second",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.rate_limit_err.is_some() {
+        let message = if parsed_attributes.max_rate.is_none() {
+            Some("`rate_limit_err` requires `max_rate`")
+        } else if parsed_attributes.stream || !returns_result {
+            Some("`rate_limit_err` can only be used on functions returning a `Result`")
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            return syn::Error::new(original_fn.sig.ident.span(), message).to_compile_error();
+        }
+    }
+    if parsed_attributes.max_inflight == Some(0) {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`max_inflight` must be at least 1 call",
+        )
+        .to_compile_error();
+    }
+    let message = match (
+        parsed_attributes.max_inflight,
+        &parsed_attributes.overload_err,
+    ) {
+        (Some(_), None) => Some("`max_inflight` requires `overload_err`"),
+        (None, Some(_)) => Some("`overload_err` requires `max_inflight`"),
+        (Some(_), Some(_)) if parsed_attributes.stream || !returns_result => {
+            Some("`max_inflight` can only be used on functions returning a `Result`")
+        }
+        _ => None,
+    };
+    if let Some(message) = message {
+        return syn::Error::new(original_fn.sig.ident.span(), message).to_compile_error();
+    }
+    if parsed_attributes.deadline_cancel && parsed_attributes.deadline_ms.is_none() {
+        return syn::Error::new(
+            original_fn.sig.ident.span(),
+            "`deadline_cancel` requires `deadline_ms`",
+        )
+        .to_compile_error();
+    }
+    if parsed_attributes.stream {
+        if let Some(asyncness) = original_fn.sig.asyncness {
+            return syn::Error::new(
+                asyncness.span(),
+                "`stream` can't be used on async functions",
+            )
+            .to_compile_error();
+        }
+        let closure = make_closure(&original_fn);
+        let mut new_fn = generate_stream_function(
+            &closure,
+            parsed_attributes,
+            check_if_stream_item_result(&original_fn),
+            parsed_attributes.name.clone(),
+        )
+        .expect("Failed Generating Function");
+        replace_function_headers(original_fn, &mut new_fn);
+        add_preamble(&mut new_fn, parsed_attributes, &flags);
+        return new_fn.into_token_stream();
+    }
+    if let Some(output) = boxed_future_output(&original_fn) {
+        let is_result = match output {
+            Type::Path(path) => is_result_type(path),
+            _ => false,
+        };
+        let mut new_fn = generate_async_function(
+            &original_fn,
+            parsed_attributes,
+            is_result,
+            true,
+            parsed_attributes.name.clone(),
+        )
+        .expect("Failed Generating Function");
+        replace_function_headers(original_fn, &mut new_fn);
+        add_preamble(&mut new_fn, parsed_attributes, &flags);
+        return new_fn.into_token_stream();
+    }
+    if original_fn.sig.asyncness.is_some() {
+        let mut new_fn = generate_async_function(
+            &original_fn,
+            parsed_attributes,
+            is_result,
+            false,
+            parsed_attributes.name.clone(),
+        )
+        .expect("Failed Generating Function");
+        let mut original_fn = original_fn;
+        desugar_async_signature(&mut original_fn.sig);
+        replace_function_headers(original_fn, &mut new_fn);
+        add_preamble(&mut new_fn, parsed_attributes, &flags);
+        return new_fn.into_token_stream();
+    }
+
+    if check_if_return_never(&original_fn) {
+        let mut new_fn = generate_never_function(
+            &original_fn,
+            parsed_attributes,
+            parsed_attributes.name.clone(),
+        )
+        .expect("Failed Generating Function");
+        replace_function_headers(original_fn, &mut new_fn);
+        add_preamble(&mut new_fn, parsed_attributes, &flags);
+        return new_fn.into_token_stream();
+    }
+
+    let closure = make_closure(&original_fn);
     let mut new_fn = generate_function(
-        &closure,
-        &parsed_attributes,
+        &quote!((#closure)()),
+        parsed_attributes,
         is_result,
-        original_fn.sig.ident.to_string(),
+        false,
+        parsed_attributes.name.clone(),
         &parsed_attributes.ctx,
     )
     .expect("Failed Generating Function");
     replace_function_headers(original_fn, &mut new_fn);
-    new_fn.into_token_stream().into()
+    if parsed_attributes.return_timing {
+        if let Err(err) = return_timed(&mut new_fn, is_result) {
+            return err.to_compile_error();
+        }
+    }
+    add_preamble(&mut new_fn, parsed_attributes, &flags);
+    if parsed_attributes.main {
+        add_main_returned(&mut new_fn);
+    }
+    new_fn.into_token_stream()
+}
+
+/// Calls `instrumented::lifecycle::main_returned` once the call of `main` is recorded, so the
+/// metrics are shut down, and pushed, with it.
+fn add_main_returned(new: &mut ItemFn) {
+    let block = &new.block;
+    new.block = parse_quote!({
+        let __instrumented_result = (|| #block)();
+        ::instrumented::lifecycle::main_returned();
+        __instrumented_result
+    });
+}
+
+/// Changes the return type of a function with `return_timing` from `T` to
+/// `instrumented::Timed<T>`, or from `Result<T, E>` to `Result<Timed<T>, E>`, and says so in its
+/// documentation.
+fn return_timed(function: &mut ItemFn, is_result: bool) -> Result<()> {
+    let (arrow, ty) = match &function.sig.output {
+        ReturnType::Type(arrow, ty) => (*arrow, (**ty).clone()),
+        ReturnType::Default => (Default::default(), parse_quote!(())),
+    };
+    let ty = if is_result {
+        let mut ty = ty;
+        let ok = match &mut ty {
+            Type::Path(path) => match &mut path.path.segments.last_mut().unwrap().arguments {
+                PathArguments::AngleBracketed(arguments) => {
+                    arguments.args.iter_mut().find_map(|argument| match argument {
+                        GenericArgument::Type(ok) => Some(ok),
+                        _ => None,
+                    })
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        match ok {
+            Some(ok) => *ok = parse_quote!(::instrumented::Timed<#ok>),
+            None => {
+                return Err(syn::Error::new(
+                    ty.span(),
+                    "`return_timing` needs the `Ok` type of the `Result`, which this alias hides",
+                ))
+            }
+        }
+        ty
+    } else {
+        parse_quote!(::instrumented::Timed<#ty>)
+    };
+    function.sig.output = ReturnType::Type(arrow, Box::new(ty));
+    let doc = if is_result {
+        " **Instrumented with `return_timing`**: the `Ok` value is wrapped in an \
+         `instrumented::Timed`, with the duration of the call."
+    } else {
+        " **Instrumented with `return_timing`**: the value is returned in an \
+         `instrumented::Timed`, with the duration of the call."
+    };
+    function.attrs.push(parse_quote!(#[doc = ""]));
+    function.attrs.push(parse_quote!(#[doc = #doc]));
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use syn::parse_quote;
+    use syn::{parse_quote, AttributeArgs, DeriveInput, ItemFn, ItemImpl, ItemMod, ItemTrait};
 
-    use super::is_result_type;
+    use super::{
+        doc_help, expand, expand_drop, expand_error_class, expand_mod, expand_trait,
+        is_result_type, snake_case, MAX_HELP_LEN,
+    };
+    use quote::{quote, ToTokens};
 
     #[test]
     fn result_type() {
@@ -304,4 +2730,558 @@ mod tests {
         assert!(is_result_type(&parse_quote!(std::result::Result<T, E>)));
         assert!(is_result_type(&parse_quote!(fmt::Result)));
     }
+
+    #[test]
+    fn instrument_mod_rejects_out_of_line_modules() {
+        let module: ItemMod = parse_quote!(
+            mod parser;
+        );
+        let expanded = expand_mod(vec![], module).to_string();
+        assert!(expanded.contains("compile_error"), "{}", expanded);
+        assert!(expanded.contains("inline modules"), "{}", expanded);
+    }
+
+    #[test]
+    fn tests_go_in_the_test_namespace() {
+        let original: ItemFn = parse_quote!(
+            #[test]
+            fn it_works() {}
+        );
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let expanded = expand(&attr, original).to_string();
+        assert!(expanded.contains("# [cfg (test)]"), "{}", expanded);
+        assert!(expanded.contains("\"test_it_works\""), "{}", expanded);
+        assert!(expanded.contains("# [cfg (not (test))]"), "{}", expanded);
+    }
+
+    #[test]
+    fn registers_the_first_doc_line() {
+        let original: ItemFn = parse_quote!(
+            ///
+            /// Fetches   a user
+            /// by id.
+            fn get_user(id: u64) -> u64 {
+                id
+            }
+        );
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let expanded = expand(&attr, original).to_string();
+        assert!(
+            expanded.contains("register_function_help (\"get_user\" , \"default\" , \"Fetches a user\")"),
+            "{}",
+            expanded
+        );
+
+        let long: ItemFn = parse_quote!(
+            #[doc = "The quick brown fox jumps over the lazy dog, the quick brown fox jumps over the lazy dog again and again."]
+            fn undocumented() {}
+        );
+        let help = doc_help(&long.attrs).unwrap();
+        assert_eq!(help.chars().count(), MAX_HELP_LEN);
+        assert!(help.starts_with("The quick brown fox"));
+
+        let undocumented: ItemFn = parse_quote!(
+            fn undocumented() {}
+        );
+        assert_eq!(doc_help(&undocumented.attrs), None);
+    }
+
+    #[test]
+    fn tracks_the_default_ctx_variable() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let original: ItemFn = parse_quote!(
+            fn get_user() {}
+        );
+        let expanded = expand(&attr, original).to_string();
+        assert!(
+            expanded.contains("option_env ! (\"INSTRUMENTED_DEFAULT_CTX\")"),
+            "{}",
+            expanded
+        );
+
+        let attr: AttributeArgs = vec![parse_quote!(INFO), parse_quote!(ctx = "users")];
+        let original: ItemFn = parse_quote!(
+            fn get_user() {}
+        );
+        let expanded = expand(&attr, original).to_string();
+        assert!(!expanded.contains("INSTRUMENTED_DEFAULT_CTX"), "{}", expanded);
+    }
+
+    #[test]
+    fn shuts_down_when_main_returns() {
+        let main: ItemFn = parse_quote!(
+            fn main() -> Result<(), std::io::Error> {
+                Ok(())
+            }
+        );
+        let attr: AttributeArgs = vec![parse_quote!(INFO), parse_quote!(main)];
+        let expanded = expand(&attr, main).to_string();
+        assert!(
+            expanded.contains("lifecycle :: main_returned ()"),
+            "{}",
+            expanded
+        );
+
+        let run: ItemFn = parse_quote!(
+            fn run(config: Config) {}
+        );
+        let expanded = expand(&attr, run).to_string();
+        assert!(
+            expanded.contains("`main` can only be used on synchronous functions without arguments"),
+            "{}",
+            expanded
+        );
+    }
+
+    #[test]
+    fn only_shuts_down_in_the_flagged_main() {
+        // Without the flag, e.g. a helper of a submodule named `main`.
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let main: ItemFn = parse_quote!(
+            fn main() {}
+        );
+        let expanded = expand(&attr, main).to_string();
+        assert!(!expanded.contains("main_returned"), "{}", expanded);
+
+        // Nor for the functions of an instrumented module.
+        let module: ItemMod = parse_quote! {
+            mod cli {
+                pub fn main() {}
+            }
+        };
+        let expanded = expand_mod(attr, module).to_string();
+        assert!(!expanded.contains("main_returned"), "{}", expanded);
+    }
+
+    /// The registration static inserted at the start of every instrumented function, followed by
+    /// the tracking of `INSTRUMENTED_DEFAULT_CTX` for the default ctx.
+    fn registration(name: &str, ctx: &str) -> proc_macro2::TokenStream {
+        let tracking = if ctx == "default" {
+            quote!(const _: ::std::option::Option<&str> = option_env!("INSTRUMENTED_DEFAULT_CTX");)
+        } else {
+            quote!()
+        };
+        quote! {
+            #[used]
+            #[cfg_attr(
+                any(target_os = "linux", target_os = "android", target_os = "freebsd"),
+                link_section = ".init_array"
+            )]
+            #[cfg_attr(any(target_os = "macos", target_os = "ios"), link_section = "__DATA,__mod_init_func")]
+            #[cfg_attr(windows, link_section = ".CRT$XCU")]
+            static __INSTRUMENTED_REGISTRATION: extern "C" fn() = {
+                extern "C" fn __instrumented_register() {
+                    ::instrumented::register_function(#name, #ctx);
+                }
+                __instrumented_register
+            };
+            #tracking
+        }
+    }
+
+    /// Compares the expansions after reparsing, so that both are printed the same way.
+    fn assert_expands_to(actual: proc_macro2::TokenStream, expected: proc_macro2::TokenStream) {
+        let actual: ItemFn = syn::parse2(actual).unwrap();
+        let expected: ItemFn = syn::parse2(expected).unwrap();
+        assert_eq!(
+            actual.into_token_stream().to_string(),
+            expected.into_token_stream().to_string()
+        );
+    }
+
+    #[test]
+    fn expands_impl_trait_without_formatting_the_value() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let item: ItemFn = parse_quote! {
+            fn make_iter(n: u32) -> impl Iterator<Item = u32> {
+                0..n
+            }
+        };
+        let registration = registration("make_iter", "default");
+        let expected = quote! {
+            fn make_iter(n: u32) -> impl Iterator<Item = u32> {
+                #registration
+                ::instrumented::inc_called_counter_in_crate("make_iter", "default", "instrumented-codegen");
+                ::instrumented::inc_inflight_in_crate("make_iter", "default", "instrumented-codegen");
+                let __instrumented_start = ::instrumented::Instant::now();
+                let result = (move || { 0..n })();
+                log::log!(log::Level::Info, "make_iter() => <impl Trait>");
+                let __instrumented_elapsed =
+                    ::instrumented::observe_duration_in_crate("make_iter", "default", "instrumented-codegen", __instrumented_start);
+                ::instrumented::notify_observers("make_iter", "default", __instrumented_elapsed, None);
+                ::instrumented::dec_inflight_in_crate("make_iter", "default", "instrumented-codegen");
+                result
+            }
+        };
+        assert_expands_to(expand(&attr, item), expected);
+    }
+
+    #[test]
+    fn expands_never_returning_function() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let item: ItemFn = parse_quote! {
+            fn diverges() -> ! {
+                panic!("boom")
+            }
+        };
+        let registration = registration("diverges", "default");
+        let expected = quote! {
+            fn diverges() -> ! {
+                #registration
+                ::instrumented::inc_called_counter_in_crate("diverges", "default", "instrumented-codegen");
+                {
+                    panic!("boom")
+                }
+            }
+        };
+        assert_expands_to(expand(&attr, item), expected);
+    }
+
+    #[test]
+    fn expands_unsized_generic_function() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let item: ItemFn = parse_quote! {
+            fn process<T: ?Sized + AsRef<str>>(x: &T) -> usize {
+                x.as_ref().len()
+            }
+        };
+        let registration = registration("process", "default");
+        let expected = quote! {
+            fn process<T: ?Sized + AsRef<str>>(x: &T) -> usize {
+                #registration
+                ::instrumented::inc_called_counter_in_crate("process", "default", "instrumented-codegen");
+                ::instrumented::inc_inflight_in_crate("process", "default", "instrumented-codegen");
+                let __instrumented_start = ::instrumented::Instant::now();
+                let result = (move || { x.as_ref().len() })();
+                log::log!(log::Level::Info, "process() => {:?}", result);
+                let __instrumented_elapsed =
+                    ::instrumented::observe_duration_in_crate("process", "default", "instrumented-codegen", __instrumented_start);
+                ::instrumented::notify_observers("process", "default", __instrumented_elapsed, None);
+                ::instrumented::dec_inflight_in_crate("process", "default", "instrumented-codegen");
+                result
+            }
+        };
+        assert_expands_to(expand(&attr, item), expected);
+    }
+
+    /// The signature of an expanded function.
+    fn signature(expanded: proc_macro2::TokenStream) -> String {
+        let function: ItemFn = syn::parse2(expanded).unwrap();
+        function.sig.into_token_stream().to_string()
+    }
+
+    #[test]
+    fn keeps_higher_ranked_bounds() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let item: ItemFn = parse_quote! {
+            fn apply<F>(f: F, g: impl for<'a> Fn(&'a str) -> &'a str) -> usize
+            where
+                F: for<'a> Fn(&'a str) -> &'a str,
+            {
+                g(f("key")).len()
+            }
+        };
+        let expected = item.sig.to_token_stream().to_string();
+        assert_eq!(signature(expand(&attr, item)), expected);
+
+        let item: ItemFn = parse_quote! {
+            async fn apply<F>(f: F, key: &str) -> usize
+            where
+                for<'a> F: Fn(&'a str) -> &'a str,
+            {
+                f(key).len()
+            }
+        };
+        let expected: ItemFn = parse_quote! {
+            fn apply<'__instrumented_life0, '__instrumented, F>(f: F, key: &'__instrumented_life0 str)
+                -> impl ::std::future::Future<Output = usize> + '__instrumented
+            where
+                for<'a> F: Fn(&'a str) -> &'a str,
+                F: '__instrumented,
+                '__instrumented_life0: '__instrumented
+            {}
+        };
+        assert_eq!(
+            signature(expand(&attr, item)),
+            expected.sig.into_token_stream().to_string()
+        );
+    }
+
+    #[test]
+    fn keeps_const_generics() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let item: ItemFn = parse_quote! {
+            fn chunk<const N: usize>() -> [u8; N] {
+                [0; N]
+            }
+        };
+        let expected = item.sig.to_token_stream().to_string();
+        assert_eq!(signature(expand(&attr, item)), expected);
+
+        let item: ItemFn = parse_quote! {
+            async fn fill<T: ?Sized + ToOwned, const N: usize>(value: &T) -> Vec<T::Owned> {
+                (0..N).map(|_| value.to_owned()).collect()
+            }
+        };
+        let expected: ItemFn = parse_quote! {
+            fn fill<'__instrumented_life0, '__instrumented, T: ?Sized + ToOwned, const N: usize>(
+                value: &'__instrumented_life0 T
+            ) -> impl ::std::future::Future<Output = Vec<T::Owned> > + '__instrumented
+            where
+                T: '__instrumented,
+                '__instrumented_life0: '__instrumented
+            {}
+        };
+        assert_eq!(
+            signature(expand(&attr, item)),
+            expected.sig.into_token_stream().to_string()
+        );
+    }
+
+    #[test]
+    fn keeps_default_type_parameters() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let item: ItemFn = parse_quote! {
+            #[allow(invalid_type_param_default)]
+            fn parse<T: std::str::FromStr = u32>(value: &str) -> Option<T> {
+                value.parse().ok()
+            }
+        };
+        let expected = item.sig.to_token_stream().to_string();
+        assert_eq!(signature(expand(&attr, item)), expected);
+
+        // The methods are delegated with their type and const parameters, which can't all be
+        // inferred.
+        let item: ItemTrait = parse_quote! {
+            pub trait Store<K: ?Sized = str> {
+                fn chunk<V: Default, const N: usize>(&self, key: &K) -> usize;
+            }
+        };
+        let expanded = expand_trait(&attr, item).to_string();
+        let impl_header = "impl < K : ? Sized , T : Store < K > > Store < K > for InstrumentedStore < T >";
+        assert!(expanded.contains(impl_header), "{}", expanded);
+        let call = "< T as Store < K > > :: chunk :: < V , N > (& self . inner , key)";
+        assert!(expanded.contains(call), "{}", expanded);
+    }
+
+    #[test]
+    fn rejects_variadic_function() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let item: ItemFn = parse_quote! {
+            unsafe extern "C" fn printf(format: *const u8, ...) -> i32 {
+                0
+            }
+        };
+        let expanded = expand(&attr, item).to_string();
+        assert!(
+            expanded.contains("can't be used on variadic functions"),
+            "{}",
+            expanded
+        );
+    }
+
+    #[test]
+    fn rejects_weight_that_isnt_a_string() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO), parse_quote!(weight = 3)];
+        let item: ItemFn = parse_quote! {
+            fn flush(items: &[u32]) {}
+        };
+        let expanded = expand(&attr, item).to_string();
+        assert!(expanded.contains("`weight` must be a string"), "{}", expanded);
+    }
+
+    #[test]
+    #[cfg(not(feature = "hdr"))]
+    fn rejects_hdr_without_the_feature() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO), parse_quote!(hdr)];
+        let item: ItemFn = parse_quote! {
+            fn dot(a: f32, b: f32) -> f32 {
+                a * b
+            }
+        };
+        let expanded = expand(&attr, item).to_string();
+        assert!(
+            expanded.contains("`hdr` requires the `hdr` feature of `instrumented`"),
+            "{}",
+            expanded
+        );
+    }
+
+    #[test]
+    fn rejects_err_labels_without_result() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO), parse_quote!(err_labels = "labels")];
+        let item: ItemFn = parse_quote! {
+            fn count(items: &[u32]) -> usize {
+                items.len()
+            }
+        };
+        let expanded = expand(&attr, item).to_string();
+        assert!(
+            expanded.contains("`err_labels` can only be used on functions returning a `Result`"),
+            "{}",
+            expanded
+        );
+    }
+
+    #[test]
+    fn derives_error_classes() {
+        let input: DeriveInput = parse_quote! {
+            enum FetchError {
+                #[instrumented(class = "timeout", retryable)]
+                TimedOut,
+                BadStatus(u16),
+            }
+        };
+        let expanded = expand_error_class(input).to_string();
+        assert!(expanded.contains("Self :: TimedOut => \"timeout\""), "{}", expanded);
+        assert!(expanded.contains("Self :: TimedOut => true"), "{}", expanded);
+        assert!(expanded.contains("Self :: BadStatus (..) => \"bad_status\""), "{}", expanded);
+        assert!(expanded.contains("Self :: BadStatus (..) => false"), "{}", expanded);
+
+        let input: DeriveInput = parse_quote! {
+            struct FetchError;
+        };
+        let expanded = expand_error_class(input).to_string();
+        assert!(expanded.contains("can only be derived for enums"), "{}", expanded);
+    }
+
+    #[test]
+    fn error_classes_keep_acronyms_together() {
+        assert_eq!(snake_case("TimedOut"), "timed_out");
+        assert_eq!(snake_case("HTTPError"), "http_error");
+        assert_eq!(snake_case("IOError"), "io_error");
+        assert_eq!(snake_case("BadHTTP"), "bad_http");
+        assert_eq!(snake_case("Http2Error"), "http2_error");
+        assert_eq!(snake_case("TLS"), "tls");
+
+        let input: DeriveInput = parse_quote! {
+            enum FetchError {
+                HTTPError(u16),
+            }
+        };
+        let expanded = expand_error_class(input).to_string();
+        assert!(
+            expanded.contains("Self :: HTTPError (..) => \"http_error\""),
+            "{}",
+            expanded
+        );
+    }
+
+    #[test]
+    fn rejects_rate_limit_err_without_max_rate() {
+        let attr: AttributeArgs = vec![
+            parse_quote!(INFO),
+            parse_quote!(rate_limit_err = "Busy"),
+        ];
+        let item: ItemFn = parse_quote! {
+            fn lookup(key: u32) -> Result<u32, Busy> {
+                Ok(key)
+            }
+        };
+        let expanded = expand(&attr, item).to_string();
+        assert!(
+            expanded.contains("`rate_limit_err` requires `max_rate`"),
+            "{}",
+            expanded
+        );
+    }
+
+    #[test]
+    fn return_timing_wraps_the_ok_type() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO), parse_quote!(return_timing)];
+        let item: ItemFn = parse_quote! {
+            fn lookup(key: u32) -> io::Result<u32> {
+                Ok(key)
+            }
+        };
+        let expanded: ItemFn = syn::parse2(expand(&attr, item)).unwrap();
+        let expected: syn::ReturnType = parse_quote!(-> io::Result<::instrumented::Timed<u32>>);
+        assert_eq!(
+            expanded.sig.output.to_token_stream().to_string(),
+            expected.to_token_stream().to_string()
+        );
+
+        let item: ItemFn = parse_quote! {
+            async fn lookup(key: u32) -> u32 {
+                key
+            }
+        };
+        let expanded = expand(&attr, item).to_string();
+        assert!(
+            expanded.contains("`return_timing` can only be used on synchronous functions"),
+            "{}",
+            expanded
+        );
+    }
+
+    #[test]
+    fn rejects_max_inflight_without_overload_err() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO), parse_quote!(max_inflight = 8)];
+        let item: ItemFn = parse_quote! {
+            fn lookup(key: u32) -> Result<u32, Busy> {
+                Ok(key)
+            }
+        };
+        let expanded = expand(&attr, item).to_string();
+        assert!(
+            expanded.contains("`max_inflight` requires `overload_err`"),
+            "{}",
+            expanded
+        );
+    }
+
+    #[test]
+    fn instrument_trait_skips_methods() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let item: ItemTrait = parse_quote! {
+            pub trait Store {
+                fn get(&self, key: &str) -> Option<String>;
+                #[instrument(skip)]
+                fn size(&self) -> usize;
+            }
+        };
+        let expanded = syn::parse2::<syn::File>(expand_trait(&attr, item)).unwrap();
+        assert_eq!(expanded.items.len(), 4);
+        let expanded = expanded.into_token_stream().to_string();
+        assert!(!expanded.contains("skip"), "{}", expanded);
+        assert!(expanded.contains("pub struct InstrumentedStore < T >"));
+        let size = "fn size (& self) -> usize { < T as Store > :: size (& self . inner ,) }";
+        assert!(expanded.contains(size), "{}", expanded);
+    }
+
+    #[test]
+    fn instrument_trait_rejects_boxed_self() {
+        let attr: AttributeArgs = vec![parse_quote!(INFO)];
+        let item: ItemTrait = parse_quote! {
+            trait Store {
+                fn close(self: Box<Self>);
+            }
+        };
+        let expanded = expand_trait(&attr, item).to_string();
+        assert!(expanded.contains("can only delegate methods taking"), "{}", expanded);
+    }
+
+    #[test]
+    fn instrument_drop_names_the_type() {
+        let attr: AttributeArgs = vec![parse_quote!(ctx = "io")];
+        let item: ItemImpl = parse_quote! {
+            impl Drop for Journal {
+                fn drop(&mut self) {}
+            }
+        };
+        let expanded = expand_drop(&attr, item).to_string();
+        assert!(
+            expanded.contains("DropTimer :: start_in_crate (\"Journal::drop\" , \"io\" , \"instrumented-codegen\")"),
+            "{}",
+            expanded
+        );
+
+        let item: ItemImpl = parse_quote! {
+            impl Journal {
+                fn drop(&mut self) {}
+            }
+        };
+        let expanded = expand_drop(&attr, item).to_string();
+        assert!(expanded.contains("can only be used on `impl Drop` blocks"), "{}", expanded);
+    }
 }
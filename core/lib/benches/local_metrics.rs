@@ -0,0 +1,54 @@
+//! Compares the overhead of an instrumented function observing every call into the histogram
+//! with one buffering its durations with `local_metrics`, from several threads at once.
+//!
+//! Run with `cargo bench --bench local_metrics`.
+use instrumented::instrument;
+use std::thread;
+use std::time::Instant;
+
+const THREADS: u32 = 8;
+const CALLS: u32 = 200_000;
+
+#[instrument(INFO, ctx = "bench")]
+fn shared(n: u32) -> u32 {
+    n.wrapping_mul(31)
+}
+
+#[instrument(INFO, ctx = "bench", local_metrics)]
+fn local(n: u32) -> u32 {
+    n.wrapping_mul(31)
+}
+
+/// Returns the mean nanoseconds per call of `f`, called `CALLS` times by each of `THREADS`.
+fn per_call(f: fn(u32) -> u32) -> f64 {
+    let start = Instant::now();
+    let workers = (0..THREADS)
+        .map(|_| {
+            thread::spawn(move || {
+                let mut acc = 0u32;
+                for n in 0..CALLS {
+                    acc = acc.wrapping_add(f(n));
+                }
+                acc
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+    (elapsed.as_secs() as f64 * 1e9 + f64::from(elapsed.subsec_nanos()))
+        / f64::from(THREADS * CALLS)
+}
+
+fn main() {
+    // Warms up the series and the thread-local buffers.
+    per_call(shared);
+    per_call(local);
+
+    let shared = per_call(shared);
+    let local = per_call(local);
+    println!("observed every call:   {:>8.1} ns/call", shared);
+    println!("local_metrics batches: {:>8.1} ns/call", local);
+    println!("speedup:               {:>8.2}x", shared / local);
+}
@@ -0,0 +1,115 @@
+//! Aliases of the metrics prefix and of ctx values, to rename them without breaking dashboards
+//! overnight.
+//!
+//! While an alias is set, every gathered series is also exported under its old name, with the
+//! same value, until the alias is removed:
+//!
+//! ```rust,no_run
+//! // METRICS_PREFIX=billing, previously `payments`, and the `invoice` ctx previously `bill`.
+//! let config = instrumented::Config::new("127.0.0.1:5000")
+//!     .alias_prefix("payments")
+//!     .alias_ctx("bill", "invoice");
+//! instrumented::init_with_config(config);
+//! ```
+//!
+//! The series of the new ctx are copied under the old ctx, then the families under the current
+//! prefix are copied under the old prefix, so both the old prefix and the old ctx are exported
+//! together. An alias never replaces a series that's still recorded under its old name. The
+//! buckets of native histograms are only exported under the new names.
+use crate::poison::RwLockExt;
+use crate::prometheus::proto::{Metric, MetricFamily};
+use std::sync::RwLock;
+
+#[derive(Default)]
+struct Aliases {
+    prefix: Option<String>,
+    ctxs: Vec<(String, String)>,
+}
+
+lazy_static! {
+    static ref ALIASES: RwLock<Aliases> = RwLock::new(Aliases::default());
+}
+
+/// Also exports the families under the `METRICS_PREFIX` with the `old` prefix instead, or without
+/// a prefix if `old` is empty. Replaces the previous prefix alias.
+pub fn set_alias_prefix(old: &str) {
+    ALIASES.write_or_recover().prefix = Some(old.to_string());
+}
+
+/// Also exports the series whose `ctx` label is `new` with the `old` ctx instead.
+pub fn add_alias_ctx(old: &str, new: &str) {
+    let mut aliases = ALIASES.write_or_recover();
+    let alias = (old.to_string(), new.to_string());
+    if !aliases.ctxs.contains(&alias) {
+        aliases.ctxs.push(alias);
+    }
+}
+
+/// Removes the prefix and ctx aliases, once the dashboards use the new names.
+pub fn clear_aliases() {
+    *ALIASES.write_or_recover() = Aliases::default();
+}
+
+/// Adds the aliased copies of the families.
+pub(crate) fn apply(families: &mut Vec<MetricFamily>) {
+    let aliases = ALIASES.read_or_recover();
+    for (old, new) in &aliases.ctxs {
+        for family in families.iter_mut() {
+            alias_ctx(family, old, new);
+        }
+    }
+    if let Some(old) = &aliases.prefix {
+        let prefix = match &*crate::METRICS_PREFIX {
+            Some(prefix) => format!("{}_", prefix),
+            None => String::new(),
+        };
+        let old = if old.is_empty() {
+            String::new()
+        } else {
+            format!("{}_", old)
+        };
+        if old == prefix {
+            return;
+        }
+        let copies: Vec<_> = families
+            .iter()
+            .filter_map(|family| {
+                let name = family.get_name().strip_prefix(prefix.as_str())?;
+                let name = format!("{}{}", old, name);
+                if families.iter().any(|other| other.get_name() == name) {
+                    return None;
+                }
+                let mut copy = family.clone();
+                copy.set_name(name);
+                Some(copy)
+            })
+            .collect();
+        families.extend(copies);
+    }
+}
+
+fn alias_ctx(family: &mut MetricFamily, old: &str, new: &str) {
+    let copies: Vec<Metric> = family
+        .get_metric()
+        .iter()
+        .filter(|metric| crate::functions::label(metric, "ctx") == new)
+        .map(|metric| {
+            let mut copy = metric.clone();
+            for pair in copy.mut_label().iter_mut() {
+                if pair.get_name() == "ctx" {
+                    pair.set_value(old.to_string());
+                }
+            }
+            copy
+        })
+        .filter(|copy| {
+            !family
+                .get_metric()
+                .iter()
+                .any(|metric| metric.get_label() == copy.get_label())
+        })
+        .collect();
+    for copy in copies {
+        family.mut_metric().push(copy);
+    }
+}
@@ -0,0 +1,178 @@
+//! Allocation counting, for the `count_allocs` attribute.
+//!
+//! Allocations are only counted when the `alloc` feature is enabled and `CountingAllocator` is
+//! installed as the global allocator:
+//!
+//! ```rust,ignore
+//! #[global_allocator]
+//! static ALLOCATOR: instrumented::alloc::CountingAllocator = instrumented::alloc::CountingAllocator;
+//! ```
+//!
+//! Allocations made by nested functions that also count allocations are only attributed to the
+//! innermost one, so that the counts of a function are its own, like self time.
+#[cfg(feature = "alloc")]
+use std::alloc::{GlobalAlloc, Layout, System};
+#[cfg(feature = "alloc")]
+use std::cell::Cell;
+
+#[cfg(feature = "alloc")]
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static ALLOCATED_BYTES: Cell<u64> = const { Cell::new(0) };
+    // Allocations already attributed to nested functions.
+    static ATTRIBUTED_ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static ATTRIBUTED_BYTES: Cell<u64> = const { Cell::new(0) };
+}
+
+#[cfg(feature = "alloc")]
+lazy_static! {
+    static ref FUNC_ALLOCATIONS: crate::prometheus::IntCounterVec = {
+        let counter_opts = crate::prometheus::Opts::new(
+            "function_allocations_total",
+            "Number of allocations made by function calls",
+        );
+        let counter =
+            crate::prometheus::IntCounterVec::new(counter_opts, &["type", "name", "ctx"]).unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_ALLOCATED_BYTES: crate::prometheus::IntCounterVec = {
+        let counter_opts = crate::prometheus::Opts::new(
+            "function_allocated_bytes_total",
+            "Number of bytes allocated by function calls",
+        );
+        let counter =
+            crate::prometheus::IntCounterVec::new(counter_opts, &["type", "name", "ctx"]).unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+#[cfg(all(feature = "alloc", feature = "exporter"))]
+pub(crate) fn reset_metrics() {
+    FUNC_ALLOCATIONS.reset();
+    FUNC_ALLOCATED_BYTES.reset();
+}
+
+/// A global allocator wrapping the system allocator, counting the allocations of each thread.
+#[cfg(feature = "alloc")]
+pub struct CountingAllocator;
+
+#[cfg(feature = "alloc")]
+fn count(size: usize) {
+    // The thread locals are unavailable while a thread is being torn down.
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+    let _ = ALLOCATED_BYTES.try_with(|bytes| bytes.set(bytes.get() + size as u64));
+}
+
+#[cfg(feature = "alloc")]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// The allocation counters of the current thread at the start of a call.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+pub struct AllocSnapshot {
+    allocations: u64,
+    bytes: u64,
+    attributed_allocations: u64,
+    attributed_bytes: u64,
+}
+
+#[doc(hidden)]
+#[cfg(feature = "alloc")]
+pub fn alloc_snapshot() -> AllocSnapshot {
+    AllocSnapshot {
+        allocations: ALLOCATIONS.with(Cell::get),
+        bytes: ALLOCATED_BYTES.with(Cell::get),
+        attributed_allocations: ATTRIBUTED_ALLOCATIONS.with(Cell::get),
+        attributed_bytes: ATTRIBUTED_BYTES.with(Cell::get),
+    }
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "alloc"))]
+pub fn alloc_snapshot() -> AllocSnapshot {
+    AllocSnapshot::default()
+}
+
+/// Records the allocations made by the current thread since `start`, excluding the ones of nested
+/// functions.
+#[doc(hidden)]
+#[cfg(feature = "alloc")]
+pub fn observe_allocs_for(name: &'static str, ctx: &'static str, start: AllocSnapshot) {
+    let end = alloc_snapshot();
+    let allocations = (end.allocations - start.allocations)
+        - (end.attributed_allocations - start.attributed_allocations);
+    let bytes = (end.bytes - start.bytes) - (end.attributed_bytes - start.attributed_bytes);
+
+    if crate::is_enabled() {
+        FUNC_ALLOCATIONS
+            .with_label_values(&["func_call", name, ctx])
+            .inc_by(allocations as i64);
+        FUNC_ALLOCATED_BYTES
+            .with_label_values(&["func_call", name, ctx])
+            .inc_by(bytes as i64);
+    }
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "alloc"))]
+pub fn observe_allocs_for(_name: &'static str, _ctx: &'static str, _start: AllocSnapshot) {}
+
+/// Marks every allocation made by the current thread since `start` as attributed, so that they
+/// aren't counted again by the calling function. `start` is taken before any instrumentation code
+/// runs, so the allocations of the instrumentation itself are excluded as well.
+#[doc(hidden)]
+#[cfg(feature = "alloc")]
+pub fn attribute_allocs_since(start: AllocSnapshot) {
+    let end = alloc_snapshot();
+    ATTRIBUTED_ALLOCATIONS.with(|attributed| {
+        attributed.set(start.attributed_allocations + (end.allocations - start.allocations))
+    });
+    ATTRIBUTED_BYTES
+        .with(|attributed| attributed.set(start.attributed_bytes + (end.bytes - start.bytes)));
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "alloc"))]
+pub fn attribute_allocs_since(_start: AllocSnapshot) {}
+
+/// Attributes the allocations made by the current thread since it was created when dropped, so
+/// that a call returning early, e.g. with `?`, is excluded from its caller too.
+#[doc(hidden)]
+pub struct AttributeAllocs(AllocSnapshot);
+
+#[doc(hidden)]
+pub fn attribute_allocs_on_drop() -> AttributeAllocs {
+    AttributeAllocs(alloc_snapshot())
+}
+
+impl Drop for AttributeAllocs {
+    fn drop(&mut self) {
+        attribute_allocs_since(self.0);
+    }
+}
@@ -0,0 +1,131 @@
+//! Collectors declared with `register_collector!`, registered with the global registry without
+//! any registration code in `main`.
+use crate::poison::LockExt;
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::MetricFamily;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[doc(hidden)]
+pub use lazy_static::lazy::Lazy;
+
+/// Returns a collector declared with `register_collector!`, initializing it.
+#[doc(hidden)]
+pub type Getter = fn() -> &'static dyn Collector;
+
+lazy_static! {
+    static ref PENDING: Mutex<Vec<Getter>> = Mutex::new(Vec::new());
+    /// The addresses of the registered collectors.
+    static ref REGISTERED: Mutex<HashSet<usize>> = Mutex::new(HashSet::new());
+}
+
+static HAS_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Queues a collector for registration. Called by the code generated by `register_collector!` at
+/// program startup.
+#[doc(hidden)]
+pub fn declare(getter: Getter) {
+    PENDING.lock_or_recover().push(getter);
+    HAS_PENDING.store(true, Ordering::Release);
+}
+
+/// A collector owned by a static.
+struct StaticCollector(&'static dyn Collector);
+
+impl Collector for StaticCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.0.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.0.collect()
+    }
+}
+
+/// Registers the declared collectors that aren't registered yet.
+pub(crate) fn register_pending() {
+    if !HAS_PENDING.load(Ordering::Acquire) {
+        return;
+    }
+    // The initializers of the collectors can use the registry, so the lock isn't held while they
+    // run.
+    let pending = std::mem::take(&mut *PENDING.lock_or_recover());
+    HAS_PENDING.store(false, Ordering::Release);
+    for getter in pending {
+        let collector = getter();
+        let address = collector as *const dyn Collector as *const () as usize;
+        if !REGISTERED.lock_or_recover().insert(address) {
+            continue;
+        }
+        crate::register_builtin(Box::new(StaticCollector(collector)));
+    }
+}
+
+/// Declares a static collector, like `lazy_static!`, registered with the global registry the
+/// first time it's used, wherever the collector is declared in the dependency graph.
+///
+/// ```rust
+/// use instrumented::prometheus::{IntGauge, Opts};
+///
+/// instrumented::register_collector! {
+///     pub static ref WORKERS: IntGauge =
+///         IntGauge::with_opts(Opts::new("workers", "Number of workers")).unwrap();
+/// }
+///
+/// WORKERS.set(4);
+/// assert!(instrumented::render_metrics().contains("workers 4"));
+/// ```
+///
+/// The collectors are queued at program startup, on Linux, macOS and Windows, and registered on
+/// the next gathering or registration, in any order. A collector is only registered once. The
+/// initializer runs then, rather than on first dereference.
+#[macro_export]
+macro_rules! register_collector {
+    ($(#[$attr:meta])* $vis:vis static ref $name:ident : $ty:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])*
+        #[allow(non_camel_case_types)]
+        $vis struct $name {
+            __private_field: (),
+        }
+
+        #[doc(hidden)]
+        $vis static $name: $name = $name { __private_field: () };
+
+        impl ::std::ops::Deref for $name {
+            type Target = $ty;
+
+            fn deref(&self) -> &$ty {
+                fn __initialize() -> $ty {
+                    $init
+                }
+                static LAZY: $crate::auto_register::Lazy<$ty> =
+                    $crate::auto_register::Lazy::INIT;
+                LAZY.get(__initialize)
+            }
+        }
+
+        const _: () = {
+            fn __instrumented_collector() -> &'static dyn $crate::prometheus::core::Collector {
+                &*$name
+            }
+
+            #[used]
+            #[cfg_attr(
+                any(target_os = "linux", target_os = "android", target_os = "freebsd"),
+                link_section = ".init_array"
+            )]
+            #[cfg_attr(any(target_os = "macos", target_os = "ios"), link_section = "__DATA,__mod_init_func")]
+            #[cfg_attr(windows, link_section = ".CRT$XCU")]
+            static __INSTRUMENTED_DECLARATION: extern "C" fn() = {
+                extern "C" fn __instrumented_declare() {
+                    $crate::auto_register::declare(__instrumented_collector);
+                }
+                __instrumented_declare
+            };
+        };
+
+        $crate::register_collector!($($rest)*);
+    };
+    () => {};
+}
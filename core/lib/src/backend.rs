@@ -0,0 +1,221 @@
+//! The backend recording the core metrics of instrumented functions: calls, errors, durations
+//! and inflight calls.
+//!
+//! The backend is selected with a cargo feature:
+//!
+//! * `backend-prometheus` (the default) records into the prometheus vecs of the global registry.
+//! * `backend-metrics` forwards into the [`metrics`](https://docs.rs/metrics/) facade, so that
+//!   instrumented functions can be exported by any `metrics` recorder. The other metrics (apdex,
+//!   poll delay, streams, CPU time and allocations) are still recorded into the global registry.
+//!
+//! Exactly one of them must be enabled. The `crate` label of `instrumented::set_crate_label` is
+//! only added by `backend-prometheus`.
+use std::time::Duration;
+
+#[cfg(all(feature = "backend-prometheus", feature = "backend-metrics"))]
+compile_error!(
+    "only one of the `backend-prometheus` and `backend-metrics` features can be enabled"
+);
+
+#[cfg(not(any(feature = "backend-prometheus", feature = "backend-metrics")))]
+compile_error!("one of the `backend-prometheus` and `backend-metrics` features must be enabled");
+
+/// Records the core metrics of instrumented functions, defined in the crate `krate`.
+pub trait Backend: Send + Sync {
+    /// Counts a call of the function.
+    fn record_call(&self, name: &'static str, ctx: &'static str, krate: &'static str);
+    /// Counts an error returned by the function, or `injected` by `instrumented::chaos`.
+    fn record_error(
+        &self,
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        err: String,
+        injected: bool,
+    );
+    /// Records the duration of a call of the function.
+    fn record_duration(
+        &self,
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        elapsed: Duration,
+    );
+    /// Records the durations of several calls of the function, as buffered by `local_metrics`.
+    fn record_durations(
+        &self,
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        elapsed: &[Duration],
+    ) {
+        for elapsed in elapsed {
+            self.record_duration(name, ctx, krate, *elapsed);
+        }
+    }
+    /// Counts a call of the function starting.
+    fn inc_inflight(&self, name: &'static str, ctx: &'static str, krate: &'static str);
+    /// Counts a call of the function completing.
+    fn dec_inflight(&self, name: &'static str, ctx: &'static str, krate: &'static str);
+}
+
+/// Records into the prometheus vecs of the global registry.
+#[cfg(feature = "backend-prometheus")]
+pub struct PrometheusBackend;
+
+#[cfg(feature = "backend-prometheus")]
+impl Backend for PrometheusBackend {
+    fn record_call(&self, name: &'static str, ctx: &'static str, krate: &'static str) {
+        crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            crate::FUNC_CALLED.with_label_values(labels).inc()
+        });
+    }
+
+    fn record_error(
+        &self,
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        err: String,
+        injected: bool,
+    ) {
+        let injected = injected.to_string();
+        let labels = ["func_call", name, ctx, &err, &injected];
+        crate::crate_label::with_values(&labels, krate, |labels| {
+            crate::FUNC_ERRORS.with_label_values(labels).inc();
+            crate::series_ttl::touch(crate::series_ttl::Tracked::Errors, labels);
+        });
+    }
+
+    fn record_duration(
+        &self,
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        elapsed: Duration,
+    ) {
+        crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            crate::FUNC_TIMER
+                .with_label_values(labels)
+                .observe(crate::time_unit().scale(elapsed))
+        });
+    }
+
+    fn record_durations(
+        &self,
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        elapsed: &[Duration],
+    ) {
+        // Accumulates the batch locally, so the shared buckets are only updated once.
+        let histogram =
+            crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+                crate::FUNC_TIMER.with_label_values(labels).local()
+            });
+        let unit = crate::time_unit();
+        for elapsed in elapsed {
+            histogram.observe(unit.scale(*elapsed));
+        }
+        histogram.flush();
+    }
+
+    fn inc_inflight(&self, name: &'static str, ctx: &'static str, krate: &'static str) {
+        crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            crate::FUNC_INFLIGHT.with_label_values(labels).inc()
+        });
+    }
+
+    fn dec_inflight(&self, name: &'static str, ctx: &'static str, krate: &'static str) {
+        crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            crate::FUNC_INFLIGHT.with_label_values(labels).dec()
+        });
+    }
+}
+
+/// Forwards into the `metrics` facade, using the same names and labels as the prometheus backend.
+#[cfg(feature = "backend-metrics")]
+pub struct MetricsBackend;
+
+#[cfg(feature = "backend-metrics")]
+impl Backend for MetricsBackend {
+    fn record_call(&self, name: &'static str, ctx: &'static str, _krate: &'static str) {
+        metrics::increment_counter!(
+            "function_called_total",
+            "type" => "func_call",
+            "name" => name,
+            "ctx" => ctx
+        );
+    }
+
+    fn record_error(
+        &self,
+        name: &'static str,
+        ctx: &'static str,
+        _krate: &'static str,
+        err: String,
+        injected: bool,
+    ) {
+        metrics::increment_counter!(
+            "function_error_total",
+            "type" => "func_call",
+            "name" => name,
+            "ctx" => ctx,
+            "err" => err,
+            "injected" => injected.to_string()
+        );
+    }
+
+    fn record_duration(
+        &self,
+        name: &'static str,
+        ctx: &'static str,
+        _krate: &'static str,
+        elapsed: Duration,
+    ) {
+        let unit = crate::time_unit();
+        let value = unit.scale(elapsed);
+        match unit {
+            crate::TimeUnit::Seconds => metrics::histogram!(
+                "function_time_seconds",
+                value,
+                "type" => "func_call",
+                "name" => name,
+                "ctx" => ctx
+            ),
+            crate::TimeUnit::Milliseconds => metrics::histogram!(
+                "function_time_milliseconds",
+                value,
+                "type" => "func_call",
+                "name" => name,
+                "ctx" => ctx
+            ),
+        }
+    }
+
+    fn inc_inflight(&self, name: &'static str, ctx: &'static str, _krate: &'static str) {
+        metrics::increment_gauge!(
+            "function_calls_inflight_total",
+            1.0,
+            "type" => "func_call",
+            "name" => name,
+            "ctx" => ctx
+        );
+    }
+
+    fn dec_inflight(&self, name: &'static str, ctx: &'static str, _krate: &'static str) {
+        metrics::decrement_gauge!(
+            "function_calls_inflight_total",
+            1.0,
+            "type" => "func_call",
+            "name" => name,
+            "ctx" => ctx
+        );
+    }
+}
+
+#[cfg(feature = "backend-prometheus")]
+pub(crate) static BACKEND: PrometheusBackend = PrometheusBackend;
+
+#[cfg(all(feature = "backend-metrics", not(feature = "backend-prometheus")))]
+pub(crate) static BACKEND: MetricsBackend = MetricsBackend;
@@ -0,0 +1,95 @@
+//! How the binary was built, to tell apart the builds compared in the metrics, with
+//! `instrumented::register_build_flags!`.
+//!
+//! The macro exports a single `build_flags_info` gauge, set to 1, labelled with:
+//!
+//! * `debug_assertions` - `true` or `false`, as seen by the crate calling the macro
+//! * `opt_level` - The optimization level, e.g. `3`
+//! * `target` - The target triple, e.g. `x86_64-unknown-linux-gnu`
+//! * `features` - The features given to the macro, sorted and comma separated
+//!
+//! The optimization level and the target are only known to build scripts, so they're `unknown`
+//! unless the build script of the crate calling the macro passes them through:
+//!
+//! ```rust,ignore
+//! // build.rs
+//! fn main() {
+//!     for var in &["OPT_LEVEL", "TARGET"] {
+//!         let value = std::env::var(var).unwrap_or_default();
+//!         println!("cargo:rustc-env=INSTRUMENTED_{}={}", var, value);
+//!     }
+//! }
+//! ```
+//!
+//! Calling the macro again replaces the labels.
+use crate::prometheus::IntGaugeVec;
+
+const UNKNOWN: &str = "unknown";
+
+lazy_static! {
+    static ref BUILD_FLAGS: IntGaugeVec = {
+        let gauge_opts = crate::prometheus::Opts::new(
+            "build_flags_info",
+            "Build profile and features of the binary, as labels",
+        );
+        let gauge = IntGaugeVec::new(
+            gauge_opts,
+            &["debug_assertions", "opt_level", "target", "features"],
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+}
+
+/// Exports the build flags captured by `register_build_flags!`.
+#[doc(hidden)]
+pub fn register_for(
+    debug_assertions: bool,
+    opt_level: Option<&str>,
+    target: Option<&str>,
+    features: &[&str],
+) {
+    let mut features = features.to_vec();
+    features.sort();
+    features.dedup();
+    let features = features.join(",");
+    let debug_assertions = if debug_assertions { "true" } else { "false" };
+
+    BUILD_FLAGS.reset();
+    BUILD_FLAGS
+        .with_label_values(&[
+            debug_assertions,
+            opt_level
+                .filter(|level| !level.is_empty())
+                .unwrap_or(UNKNOWN),
+            target
+                .filter(|target| !target.is_empty())
+                .unwrap_or(UNKNOWN),
+            &features,
+        ])
+        .set(1);
+}
+
+/// Exports how the calling crate was built in `build_flags_info`, with the cargo features given,
+/// e.g. the ones enabled with `cfg!(feature = "...")`. See the `build_flags` module for the
+/// build script passing the optimization level and the target through.
+///
+/// ```rust
+/// instrumented::register_build_flags!();
+/// instrumented::register_build_flags!("simd", "tls");
+/// assert!(instrumented::render_metrics().contains("features=\"simd,tls\""));
+/// ```
+#[macro_export]
+macro_rules! register_build_flags {
+    ($($feature:expr),* $(,)?) => {
+        $crate::build_flags::register_for(
+            cfg!(debug_assertions),
+            option_env!("INSTRUMENTED_OPT_LEVEL"),
+            option_env!("INSTRUMENTED_TARGET"),
+            &[$($feature),*],
+        )
+    };
+}
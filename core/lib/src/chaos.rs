@@ -0,0 +1,179 @@
+//! Failure injection, for chaos testing instrumented functions.
+//!
+//! Only functions instrumented with the `chaos` attribute consult the injection rules, so other
+//! functions can never be affected. A function can be given an error rule and a latency rule,
+//! which apply to a fraction of its calls, until cleared:
+//!
+//! ```rust
+//! use instrumented::{chaos, instrument};
+//! use std::time::Duration;
+//!
+//! #[derive(Debug)]
+//! enum MyError {
+//!     Synthetic,
+//! }
+//!
+//! #[instrument(INFO, chaos)]
+//! fn my_func() -> Result<(), MyError> {
+//!     Ok(())
+//! }
+//!
+//! chaos::inject_error("my_func", 1.0, || MyError::Synthetic);
+//! chaos::inject_latency("my_func", Duration::from_millis(1), 0.5);
+//! assert!(my_func().is_err());
+//! chaos::clear("my_func");
+//! assert!(my_func().is_ok());
+//! ```
+//!
+//! Injected errors are returned without running the function, and are counted in
+//! `function_error_total` with the `injected="true"` label. The error rule only applies if its
+//! error type is the error type returned by the function.
+use crate::poison::RwLockExt;
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Builds an injected error, as a `Box<dyn Fn() -> E + Send + Sync>`.
+type MakeError = Box<dyn Any + Send + Sync>;
+
+#[derive(Default)]
+struct Rules {
+    errors: HashMap<String, (f64, MakeError)>,
+    latencies: HashMap<String, (Duration, f64)>,
+}
+
+lazy_static! {
+    static ref RULES: RwLock<Rules> = RwLock::new(Rules::default());
+}
+
+static HAS_RULES: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static RNG: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+/// Returns whether a call is selected for injection, with a probability of `rate`.
+fn selected(rate: f64) -> bool {
+    // xorshift64*, seeded per thread.
+    let value = RNG.with(|rng| {
+        let mut x = rng.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        rng.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    });
+    ((value >> 11) as f64 / (1u64 << 53) as f64) < rate
+}
+
+fn update_has_rules(rules: &Rules) {
+    HAS_RULES.store(
+        !rules.errors.is_empty() || !rules.latencies.is_empty(),
+        Ordering::Release,
+    );
+}
+
+/// Makes a fraction `rate` (between 0 and 1) of the calls of the function `name` return
+/// `Err(error())` instead of running, replacing its previous error rule.
+pub fn inject_error<E, F>(name: &str, rate: f64, error: F)
+where
+    E: 'static,
+    F: Fn() -> E + Send + Sync + 'static,
+{
+    let make: Box<dyn Fn() -> E + Send + Sync> = Box::new(error);
+    let mut rules = RULES.write_or_recover();
+    rules
+        .errors
+        .insert(name.to_string(), (rate, Box::new(make) as MakeError));
+    update_has_rules(&rules);
+}
+
+/// Delays a fraction `rate` (between 0 and 1) of the calls of the function `name` by `latency`,
+/// replacing its previous latency rule.
+pub fn inject_latency(name: &str, latency: Duration, rate: f64) {
+    let mut rules = RULES.write_or_recover();
+    rules.latencies.insert(name.to_string(), (latency, rate));
+    update_has_rules(&rules);
+}
+
+/// Clears the error and latency rules of the function `name`.
+pub fn clear(name: &str) {
+    let mut rules = RULES.write_or_recover();
+    rules.errors.remove(name);
+    rules.latencies.remove(name);
+    update_has_rules(&rules);
+}
+
+/// Clears the rules of all functions.
+pub fn clear_all() {
+    let mut rules = RULES.write_or_recover();
+    *rules = Rules::default();
+    update_has_rules(&rules);
+}
+
+#[doc(hidden)]
+pub fn injected_error<E: 'static>(name: &'static str) -> Option<E> {
+    if !HAS_RULES.load(Ordering::Acquire) {
+        return None;
+    }
+    let rules = RULES.read_or_recover();
+    let (rate, make) = rules.errors.get(name)?;
+    if !selected(*rate) {
+        return None;
+    }
+    match make.downcast_ref::<Box<dyn Fn() -> E + Send + Sync>>() {
+        Some(make) => Some(make()),
+        None => {
+            warn!(
+                "The injected error for {}() isn't of the error type it returns",
+                name
+            );
+            None
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn inject_latency_for(name: &'static str) {
+    if !HAS_RULES.load(Ordering::Acquire) {
+        return;
+    }
+    let latency = match RULES.read_or_recover().latencies.get(name) {
+        Some((latency, rate)) if selected(*rate) => *latency,
+        _ => return,
+    };
+    std::thread::sleep(latency);
+}
+
+#[doc(hidden)]
+pub fn inc_injected_error_counter_for(name: &'static str, ctx: &'static str, err: String) {
+    crate::record_error(name, ctx, "", err, true);
+}
+
+#[doc(hidden)]
+pub fn inc_injected_error_counter_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    err: String,
+) {
+    crate::record_error(name, ctx, krate, err, true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::selected;
+
+    #[test]
+    fn selects_at_rate() {
+        assert!(!(0..1000).any(|_| selected(0.0)));
+        assert!((0..1000).all(|_| selected(1.0)));
+        let count = (0..10_000).filter(|_| selected(0.25)).count();
+        assert!((2000..3000).contains(&count), "{}", count);
+    }
+}
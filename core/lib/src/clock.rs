@@ -0,0 +1,124 @@
+//! The clock used to time instrumented functions.
+//!
+//! Durations are only ever measured with the monotonic `Instant`, so that stepping the wall clock,
+//! e.g. by NTP, doesn't skew them. The wall clock is only read for timestamps, such as the ones
+//! pushed to remote_write and Graphite, and for the stamps of messages crossing processes. The
+//! durations computed from it are clamped to zero when negative, and counted in
+//! `clock_anomalies_total`.
+//!
+//! `std::time::Instant` panics on `wasm32-unknown-unknown`, which has no clock without a JS
+//! runtime. There, durations are recorded as zero, while calls and errors are still counted.
+use crate::prometheus::IntCounter;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use std::time::Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod wasm {
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Instant;
+
+    impl Instant {
+        pub fn now() -> Self {
+            Instant
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Duration::from_secs(0)
+        }
+
+        pub fn duration_since(&self, _earlier: Instant) -> Duration {
+            Duration::from_secs(0)
+        }
+
+        pub fn checked_sub(&self, _duration: Duration) -> Option<Instant> {
+            Some(Instant)
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use self::wasm::Instant;
+
+lazy_static! {
+    static ref CLOCK_ANOMALIES: IntCounter = {
+        let counter = IntCounter::new(
+            "clock_anomalies_total",
+            "Number of negative durations computed from the wall clock, clamped to zero",
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+/// The wall clock time from `earlier` to `later`, clamped to zero if `later` is before `earlier`:
+/// the clock stepped back between the readings, or `earlier` comes from a host whose clock is
+/// ahead. Such anomalies are counted in `clock_anomalies_total`.
+pub(crate) fn wall_clock_since(earlier: SystemTime, later: SystemTime) -> Duration {
+    later.duration_since(earlier).unwrap_or_else(|_| {
+        CLOCK_ANOMALIES.inc();
+        Duration::from_secs(0)
+    })
+}
+
+/// The time since the unix epoch of a wall clock time, for timestamps.
+pub(crate) fn since_epoch(time: SystemTime) -> Duration {
+    wall_clock_since(UNIX_EPOCH, time)
+}
+
+/// Returns the current unix time in seconds, or `None` without a wall clock.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn unix_time() -> Option<u64> {
+    Some(since_epoch(system_time()).as_secs())
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub fn unix_time() -> Option<u64> {
+    None
+}
+
+/// Returns the current time, or the unix epoch without a wall clock.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn system_time() -> SystemTime {
+    SystemTime::now()
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub fn system_time() -> SystemTime {
+    UNIX_EPOCH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{since_epoch, wall_clock_since, CLOCK_ANOMALIES};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn negative_durations_are_clamped_and_counted() {
+        let now = super::system_time();
+        let anomalies = CLOCK_ANOMALIES.get();
+        assert_eq!(
+            wall_clock_since(now - Duration::from_secs(1), now),
+            Duration::from_secs(1)
+        );
+        assert_eq!(CLOCK_ANOMALIES.get(), anomalies);
+
+        // The clock stepped back 5 seconds between the readings.
+        let stepped = now - Duration::from_secs(5);
+        assert_eq!(wall_clock_since(now, stepped), Duration::from_secs(0));
+        assert_eq!(CLOCK_ANOMALIES.get(), anomalies + 1);
+
+        // A clock set before the epoch.
+        assert_eq!(
+            since_epoch(UNIX_EPOCH - Duration::from_secs(1)),
+            Duration::from_secs(0)
+        );
+        assert_eq!(CLOCK_ANOMALIES.get(), anomalies + 2);
+    }
+}
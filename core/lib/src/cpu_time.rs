@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+/// Returns the CPU time consumed by the current thread, or `None` on unsupported platforms.
+#[cfg(unix)]
+#[doc(hidden)]
+pub fn thread_cpu_time() -> Option<Duration> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Safety: `ts` is a valid, writable timespec.
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) } != 0 {
+        return None;
+    }
+    Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// Returns the CPU time consumed by the current thread, or `None` on unsupported platforms.
+#[cfg(windows)]
+#[doc(hidden)]
+pub fn thread_cpu_time() -> Option<Duration> {
+    use winapi::shared::minwindef::FILETIME;
+    use winapi::um::processthreadsapi::{GetCurrentThread, GetThreadTimes};
+
+    let empty = || FILETIME {
+        dwLowDateTime: 0,
+        dwHighDateTime: 0,
+    };
+    let (mut creation, mut exit, mut kernel, mut user) = (empty(), empty(), empty(), empty());
+    // Safety: the pseudo handle of the current thread is always valid, and the out pointers are
+    // valid, writable FILETIMEs.
+    let ok = unsafe {
+        GetThreadTimes(
+            GetCurrentThread(),
+            &mut creation,
+            &mut exit,
+            &mut kernel,
+            &mut user,
+        )
+    };
+    if ok == 0 {
+        return None;
+    }
+    // FILETIMEs count 100ns intervals.
+    let ticks = |t: FILETIME| (u64::from(t.dwHighDateTime) << 32) | u64::from(t.dwLowDateTime);
+    Some(Duration::from_nanos((ticks(kernel) + ticks(user)) * 100))
+}
+
+/// Returns the CPU time consumed by the current thread, or `None` on unsupported platforms.
+#[cfg(not(any(unix, windows)))]
+#[doc(hidden)]
+pub fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+/// Records the CPU time consumed by the current thread since `start`, as returned by
+/// `thread_cpu_time`.
+#[doc(hidden)]
+pub fn observe_cpu_time_for(name: &'static str, ctx: &'static str, start: Option<Duration>) {
+    observe_cpu_time_in_crate(name, ctx, "", start);
+}
+
+/// Records the CPU time of a call of a function defined in the crate `krate`, for the `crate`
+/// label.
+#[doc(hidden)]
+pub fn observe_cpu_time_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    start: Option<Duration>,
+) {
+    if !crate::is_enabled() {
+        return;
+    }
+    if let (Some(start), Some(end)) = (start, thread_cpu_time()) {
+        crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            crate::FUNC_CPU_TIME
+                .with_label_values(labels)
+                .observe(crate::time_unit().scale(end - start))
+        });
+    }
+}
+
+#[cfg(all(test, any(unix, windows)))]
+mod tests {
+    use super::thread_cpu_time;
+
+    #[test]
+    fn increases_with_work() {
+        let start = thread_cpu_time().unwrap();
+        let mut x = 0u64;
+        for i in 0..10_000_000u64 {
+            x = x.wrapping_add(i * i);
+        }
+        assert!(x > 0);
+        assert!(thread_cpu_time().unwrap() > start);
+    }
+}
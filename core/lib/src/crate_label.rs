@@ -0,0 +1,80 @@
+//! The `crate` label of the built-in function metrics, off by default so that the existing series
+//! keep their labels.
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+const UNSET: u8 = 0;
+const OFF: u8 = 1;
+const ON: u8 = 2;
+
+static ENABLED: AtomicU8 = AtomicU8::new(UNSET);
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// Adds the name of the crate defining each instrumented function as the `crate` label of the
+/// built-in function metrics, from `function_called_total` to the poll, stream, deadline, rate
+/// limit and load shedding metrics, to slice the metrics of a workspace by crate. The metrics of
+/// phases, retries, percentiles, HDR histograms, extremes, allocations, error ratios and details,
+/// and the function info keep their labels.
+///
+/// Defaults to the value of the `METRICS_CRATE_LABEL` env var (`true` or `false`), or off. Like
+/// the time unit, it must be set before the first instrumented function is called, and changing
+/// it afterwards returns an error. Functions not instrumented with `#[instrument]`, e.g. wrapped
+/// with `wrap_fn`, have an empty `crate` label.
+pub fn set_crate_label(enabled: bool) -> crate::prometheus::Result<()> {
+    if REGISTERED.load(Ordering::SeqCst) && crate_label() != enabled {
+        return Err(crate::prometheus::Error::Msg(
+            "the crate label can't be changed once the function metrics are registered".to_string(),
+        ));
+    }
+    ENABLED.store(if enabled { ON } else { OFF }, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns whether the built-in function metrics have a `crate` label.
+pub fn crate_label() -> bool {
+    let enabled = ENABLED.load(Ordering::Relaxed);
+    if enabled != UNSET {
+        return enabled == ON;
+    }
+
+    let enabled = match std::env::var("METRICS_CRATE_LABEL") {
+        Ok(value) => match value.as_str() {
+            "true" | "1" => ON,
+            "false" | "0" => OFF,
+            _ => {
+                warn!("Unknown METRICS_CRATE_LABEL {:?}, leaving it off", value);
+                OFF
+            }
+        },
+        Err(_) => OFF,
+    };
+    match ENABLED.compare_exchange(UNSET, enabled, Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(_) => enabled == ON,
+        Err(current) => current == ON,
+    }
+}
+
+/// The label names of a built-in function metric, fixing whether it has the `crate` label.
+pub(crate) fn label_names<'a>(names: &[&'a str]) -> Vec<&'a str> {
+    REGISTERED.store(true, Ordering::SeqCst);
+    let mut names = names.to_vec();
+    if crate_label() {
+        names.push("crate");
+    }
+    names
+}
+
+/// Calls `f` with the label values of a built-in function metric, with `krate` when the metrics
+/// have the `crate` label. Only allocates when they do.
+pub(crate) fn with_values<'a, T>(
+    values: &[&'a str],
+    krate: &'a str,
+    f: impl FnOnce(&[&'a str]) -> T,
+) -> T {
+    if crate_label() {
+        let mut values = values.to_vec();
+        values.push(krate);
+        f(&values)
+    } else {
+        f(values)
+    }
+}
@@ -0,0 +1,68 @@
+//! CSV rendering of the metric families, for spreadsheets.
+use crate::prometheus::proto::MetricFamily;
+use crate::series::series;
+
+const HEADER: &str = "metric,name,ctx,labels,value\r\n";
+
+/// Quotes a field if it contains a comma, a quote or a line break, doubling the quotes, as per
+/// RFC 4180.
+fn field(value: &str) -> String {
+    if value.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Formats a value the way the text format does.
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Renders the metric families as CSV, one row per series. The `labels` column holds the labels
+/// other than `name` and `ctx`, as `label=value` pairs separated by `;`.
+pub(crate) fn render(families: &[MetricFamily]) -> String {
+    let mut csv = HEADER.to_string();
+    for series in series(families) {
+        let mut metric = "";
+        let mut name = "";
+        let mut ctx = "";
+        let mut labels = vec![];
+        for (label, value) in &series.labels {
+            match label.as_str() {
+                "__name__" => metric = value,
+                "name" => name = value,
+                "ctx" => ctx = value,
+                _ => labels.push(format!("{}={}", label, value)),
+            }
+        }
+        csv.push_str(&format!(
+            "{},{},{},{},{}\r\n",
+            field(metric),
+            field(name),
+            field(ctx),
+            field(&labels.join(";")),
+            number(series.value)
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::field;
+
+    #[test]
+    fn quotes_fields() {
+        assert_eq!(field("plain"), "plain");
+        assert_eq!(field("a,b"), "\"a,b\"");
+        assert_eq!(field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(field("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -0,0 +1,219 @@
+//! Ad-hoc metrics in the global registry, created on first use and cached by name.
+//!
+//! ```rust
+//! let depth = instrumented::gauge("queue_depth", "Current queue depth").unwrap();
+//! depth.set(3);
+//! // Returns the same gauge.
+//! assert_eq!(instrumented::gauge("queue_depth", "Current queue depth").unwrap().get(), 3);
+//! ```
+//!
+//! `register_or_get` does the same for metrics built by the caller: registering a metric that was
+//! already registered the same way returns the existing one, instead of failing with `AlreadyReg`.
+//!
+//! The metrics get the prefix and the default labels of the registry. Asking for a registered
+//! name with another type, help, labels or buckets is an error, and so is a name starting with
+//! `function_`, which is reserved for the metrics of instrumented functions.
+use crate::poison::LockExt;
+use crate::prometheus::core::Collector;
+use crate::prometheus::{
+    Counter, CounterVec, Error, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Result,
+};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// The metric types that can be registered with `register_or_get`.
+pub trait Managed: Collector + Clone + 'static {
+    /// The name of the type, for error messages.
+    const KIND: &'static str;
+}
+
+macro_rules! managed {
+    ($($ty:ident),*) => {
+        $(impl Managed for $ty {
+            const KIND: &'static str = stringify!($ty);
+        })*
+    };
+}
+
+managed!(
+    Counter,
+    CounterVec,
+    Gauge,
+    GaugeVec,
+    Histogram,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    IntGaugeVec
+);
+
+/// What a metric was registered with, to detect conflicting registrations.
+struct Definition {
+    kind: &'static str,
+    help: String,
+    labels: Vec<String>,
+    const_labels: Vec<(String, String)>,
+    /// The bucket upper bounds of histograms, when known: they aren't part of the descriptor.
+    buckets: Option<Vec<f64>>,
+}
+
+impl Definition {
+    fn new(kind: &'static str, help: &str, labels: &[&str], buckets: Option<&[f64]>) -> Self {
+        Definition {
+            kind,
+            help: help.to_string(),
+            labels: labels.iter().map(|label| label.to_string()).collect(),
+            const_labels: vec![],
+            buckets: buckets.map(<[f64]>::to_vec),
+        }
+    }
+
+    fn matches(&self, other: &Definition) -> bool {
+        self.kind == other.kind
+            && self.help == other.help
+            && self.labels == other.labels
+            && self.const_labels == other.const_labels
+            && match (&self.buckets, &other.buckets) {
+                (Some(buckets), Some(other)) => buckets == other,
+                _ => true,
+            }
+    }
+}
+
+struct Entry {
+    definition: Definition,
+    metric: Box<dyn Any + Send>,
+}
+
+lazy_static! {
+    static ref METRICS: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the metric registered as `name` if its definition matches, or else registers the metric
+/// returned by `create`.
+fn managed<M: Managed + Send>(
+    name: &str,
+    definition: Definition,
+    create: impl FnOnce() -> Result<M>,
+) -> Result<M> {
+    // The built-in metrics are registered lazily, and would fail to register after a namesake.
+    if name.starts_with("function_") {
+        return Err(Error::Msg(format!(
+            "metric `{}` uses the `function_` prefix, which is reserved for instrumented functions",
+            name
+        )));
+    }
+    let mut metrics = METRICS.lock_or_recover();
+    if let Some(entry) = metrics.get(name) {
+        let existing = &entry.definition;
+        if !existing.matches(&definition) {
+            return Err(Error::Msg(format!(
+                "metric `{}` is already registered as {} with help {:?}, labels {:?}, constant \
+                 labels {:?} and buckets {:?}",
+                name,
+                existing.kind,
+                existing.help,
+                existing.labels,
+                existing.const_labels,
+                existing.buckets
+            )));
+        }
+        let metric = entry.metric.downcast_ref::<M>();
+        return Ok(metric.expect("metric of the registered kind").clone());
+    }
+    let metric = create()?;
+    // Metrics registered with `register` aren't in the map, and fail here.
+    if let Err(err) = crate::register(Box::new(metric.clone())) {
+        return Err(Error::Msg(format!(
+            "metric `{}` couldn't be registered: {}",
+            name, err
+        )));
+    }
+    metrics.insert(
+        name.to_string(),
+        Entry {
+            definition,
+            metric: Box::new(metric.clone()),
+        },
+    );
+    Ok(metric)
+}
+
+/// Registers `metric` in the global registry, or returns the metric already registered with the
+/// same name, type, help and labels. Fails if the name is registered with another definition.
+pub fn register_or_get<M: Managed + Send>(metric: M) -> Result<M> {
+    let desc = match metric.desc().first() {
+        Some(desc) => (*desc).clone(),
+        None => return Err(Error::Msg("metric without a descriptor".to_string())),
+    };
+    let mut const_labels: Vec<(String, String)> = desc
+        .const_label_pairs
+        .iter()
+        .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+        .collect();
+    const_labels.sort();
+    let definition = Definition {
+        kind: M::KIND,
+        help: desc.help.clone(),
+        labels: desc.variable_labels.clone(),
+        const_labels,
+        buckets: None,
+    };
+    managed(&desc.fq_name, definition, || Ok(metric))
+}
+
+/// Returns the counter `name`, registering it in the global registry on first use.
+pub fn counter(name: &str, help: &str) -> Result<IntCounter> {
+    let definition = Definition::new(IntCounter::KIND, help, &[], None);
+    managed(name, definition, || IntCounter::new(name, help))
+}
+
+/// Returns the gauge `name`, registering it in the global registry on first use.
+pub fn gauge(name: &str, help: &str) -> Result<IntGauge> {
+    let definition = Definition::new(IntGauge::KIND, help, &[], None);
+    managed(name, definition, || IntGauge::new(name, help))
+}
+
+/// Returns the histogram `name` with the given bucket upper bounds, registering it in the global
+/// registry on first use.
+pub fn histogram(name: &str, help: &str, buckets: Vec<f64>) -> Result<Histogram> {
+    let definition = Definition::new(Histogram::KIND, help, &[], Some(&buckets));
+    managed(name, definition, || {
+        Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets))
+    })
+}
+
+/// Returns the counter `name` with the given labels, registering it in the global registry on
+/// first use.
+pub fn counter_vec(name: &str, help: &str, labels: &[&str]) -> Result<IntCounterVec> {
+    let definition = Definition::new(IntCounterVec::KIND, help, labels, None);
+    managed(name, definition, || {
+        IntCounterVec::new(Opts::new(name, help), labels)
+    })
+}
+
+/// Returns the gauge `name` with the given labels, registering it in the global registry on
+/// first use.
+pub fn gauge_vec(name: &str, help: &str, labels: &[&str]) -> Result<IntGaugeVec> {
+    let definition = Definition::new(IntGaugeVec::KIND, help, labels, None);
+    managed(name, definition, || {
+        IntGaugeVec::new(Opts::new(name, help), labels)
+    })
+}
+
+/// Returns the histogram `name` with the given labels and bucket upper bounds, registering it in
+/// the global registry on first use.
+pub fn histogram_vec(
+    name: &str,
+    help: &str,
+    labels: &[&str],
+    buckets: Vec<f64>,
+) -> Result<HistogramVec> {
+    let definition = Definition::new(HistogramVec::KIND, help, labels, Some(&buckets));
+    managed(name, definition, || {
+        HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels)
+    })
+}
@@ -0,0 +1,279 @@
+//! Per-call deadlines of instrumented async functions.
+//!
+//! The deadlines are tracked by a timer thread, so that a call is counted in
+//! `function_deadline_exceeded_total` as soon as its deadline elapses, even if the future isn't
+//! polled again. There are no threads on `wasm32-unknown-unknown`, where deadlines are ignored.
+use crate::poison::LockExt;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// The error returned by an instrumented async function with `deadline_cancel` when its deadline
+/// elapses. The error type of the function must implement `From<DeadlineExceeded>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadlineExceeded {
+    /// The name of the function.
+    pub name: &'static str,
+    /// The deadline of the function.
+    pub deadline: Duration,
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}() exceeded its deadline of {:?}",
+            self.name, self.deadline
+        )
+    }
+}
+
+impl Error for DeadlineExceeded {}
+
+struct DeadlineState {
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    /// Set once the call completed or was dropped.
+    done: AtomicBool,
+    expired: AtomicBool,
+    /// Woken when the deadline elapses, for the call to be cancelled.
+    waker: Mutex<Option<Waker>>,
+}
+
+impl DeadlineState {
+    fn expire(&self) {
+        if self.done.load(Ordering::SeqCst) {
+            return;
+        }
+        self.expired.store(true, Ordering::SeqCst);
+        if crate::is_enabled() {
+            let labels = ["func_call", self.name, self.ctx];
+            crate::crate_label::with_values(&labels, self.krate, |labels| {
+                crate::FUNC_DEADLINE_EXCEEDED
+                    .with_label_values(labels)
+                    .inc()
+            });
+        }
+        if let Some(waker) = self.waker.lock_or_recover().take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod timer {
+    use super::DeadlineState;
+    use crate::poison::{self, LockExt};
+    use std::cmp::Ordering;
+    use std::collections::BinaryHeap;
+    use std::sync::{Arc, Condvar, Mutex, Weak};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    struct Entry {
+        at: Instant,
+        state: Weak<DeadlineState>,
+    }
+
+    impl PartialEq for Entry {
+        fn eq(&self, other: &Self) -> bool {
+            self.at == other.at
+        }
+    }
+
+    impl Eq for Entry {}
+
+    impl PartialOrd for Entry {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    // Reversed, for the heap to pop the earliest deadline first.
+    impl Ord for Entry {
+        fn cmp(&self, other: &Self) -> Ordering {
+            other.at.cmp(&self.at)
+        }
+    }
+
+    struct Timer {
+        queue: Mutex<BinaryHeap<Entry>>,
+        condvar: Condvar,
+    }
+
+    lazy_static! {
+        static ref TIMER: Arc<Timer> = {
+            let timer = Arc::new(Timer {
+                queue: Mutex::new(BinaryHeap::new()),
+                condvar: Condvar::new(),
+            });
+            let thread_timer = timer.clone();
+            thread::Builder::new()
+                .name("instrumented-deadlines".into())
+                .spawn(move || run(&thread_timer))
+                .expect("spawning the deadline timer thread");
+            timer
+        };
+    }
+
+    fn run(timer: &Timer) {
+        let mut queue = timer.queue.lock_or_recover();
+        loop {
+            let now = Instant::now();
+            match queue.peek().map(|entry| entry.at) {
+                Some(at) if at <= now => {
+                    let entry = queue.pop().unwrap();
+                    drop(queue);
+                    if let Some(state) = entry.state.upgrade() {
+                        state.expire();
+                    }
+                    queue = timer.queue.lock_or_recover();
+                }
+                Some(at) => queue = poison::wait_timeout(&timer.condvar, queue, at - now),
+                None => queue = poison::wait(&timer.condvar, queue),
+            }
+        }
+    }
+
+    pub(super) fn schedule(deadline: Duration, state: &Arc<DeadlineState>) {
+        TIMER.queue.lock_or_recover().push(Entry {
+            at: Instant::now() + deadline,
+            state: Arc::downgrade(state),
+        });
+        TIMER.condvar.notify_one();
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod timer {
+    use super::DeadlineState;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    pub(super) fn schedule(_deadline: Duration, _state: &Arc<DeadlineState>) {}
+}
+
+/// Counts the call in `function_deadline_exceeded_total` if the deadline, measured from the
+/// creation of the future, elapses before the future completes. The call isn't interrupted.
+#[doc(hidden)]
+pub struct DeadlineFuture<F> {
+    inner: F,
+    state: Arc<DeadlineState>,
+    cancel: bool,
+}
+
+impl<F: Future> DeadlineFuture<F> {
+    pub fn new(name: &'static str, ctx: &'static str, deadline: Duration, inner: F) -> Self {
+        Self::new_in_crate(name, ctx, "", deadline, inner)
+    }
+
+    /// Tracks the deadline of a function defined in the crate `krate`, for the `crate` label.
+    pub fn new_in_crate(
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        deadline: Duration,
+        inner: F,
+    ) -> Self {
+        let state = Arc::new(DeadlineState {
+            name,
+            ctx,
+            krate,
+            done: AtomicBool::new(false),
+            expired: AtomicBool::new(false),
+            waker: Mutex::new(None),
+        });
+        timer::schedule(deadline, &state);
+        DeadlineFuture {
+            inner,
+            state,
+            cancel: false,
+        }
+    }
+}
+
+impl<F: Future> Future for DeadlineFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        // Safety: `inner` is never moved out of `self`, not even in `Drop`.
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.cancel {
+            // Stored before polling, so that a deadline elapsing while the inner future is
+            // pending wakes the call.
+            *this.state.waker.lock_or_recover() = Some(cx.waker().clone());
+        }
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        let poll = inner.poll(cx);
+        if poll.is_ready() {
+            this.state.done.store(true, Ordering::SeqCst);
+        }
+        poll
+    }
+}
+
+impl<F> Drop for DeadlineFuture<F> {
+    fn drop(&mut self) {
+        self.state.done.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Like `DeadlineFuture`, but also cancels the call when the deadline elapses, completing with a
+/// `DeadlineExceeded` error instead.
+#[doc(hidden)]
+pub struct CancelAtDeadline<F> {
+    inner: DeadlineFuture<F>,
+    deadline: Duration,
+}
+
+impl<F: Future> CancelAtDeadline<F> {
+    pub fn new(name: &'static str, ctx: &'static str, deadline: Duration, inner: F) -> Self {
+        Self::new_in_crate(name, ctx, "", deadline, inner)
+    }
+
+    /// Cancels a call of a function defined in the crate `krate`, for the `crate` label.
+    pub fn new_in_crate(
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        deadline: Duration,
+        inner: F,
+    ) -> Self {
+        let mut inner = DeadlineFuture::new_in_crate(name, ctx, krate, deadline, inner);
+        inner.cancel = true;
+        CancelAtDeadline { inner, deadline }
+    }
+}
+
+impl<F, T, E> Future for CancelAtDeadline<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: From<DeadlineExceeded>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        // Safety: `inner` is never moved out of `self`.
+        let this = unsafe { self.get_unchecked_mut() };
+        if !this.inner.state.expired.load(Ordering::SeqCst) {
+            let poll = unsafe { Pin::new_unchecked(&mut this.inner) }.poll(cx);
+            // Checked again once the waker is stored, in case the deadline elapsed in between
+            // and woke the previous waker, or none.
+            if poll.is_ready() || !this.inner.state.expired.load(Ordering::SeqCst) {
+                return poll;
+            }
+        }
+        let state = &this.inner.state;
+        state.done.store(true, Ordering::SeqCst);
+        Poll::Ready(Err(E::from(DeadlineExceeded {
+            name: state.name,
+            deadline: this.deadline,
+        })))
+    }
+}
@@ -0,0 +1,79 @@
+//! Best-effort detection of several copies of `instrumented` in one binary, e.g. two versions
+//! required by different dependencies. Every copy has its own registry and only one is served, so
+//! the metrics recorded through the others are silently lost.
+//!
+//! The copies are tracked in a process-global env var, as they don't share any static.
+use std::env;
+
+/// The env var listing the identities of the copies with a registry, comma separated.
+const MARKER: &str = "INSTRUMENTED_REGISTRIES";
+
+/// Identifies this copy of the crate by its version and the address of one of its statics, so
+/// that two copies of the same version are told apart too.
+fn identity() -> String {
+    static ANCHOR: u8 = 0;
+    format!("{}@{:p}", env!("CARGO_PKG_VERSION"), &ANCHOR)
+}
+
+/// The copies listed in `marker`, other than `own`.
+fn others(marker: &str, own: &str) -> Vec<String> {
+    marker
+        .split(',')
+        .filter(|copy| !copy.is_empty() && *copy != own)
+        .map(str::to_string)
+        .collect()
+}
+
+/// The other copies that created their registry so far.
+pub(crate) fn other_copies() -> Vec<String> {
+    others(&env::var(MARKER).unwrap_or_default(), &identity())
+}
+
+/// Adds this copy to the marker when its registry is created, warning if others are listed.
+pub(crate) fn claim() {
+    let own = identity();
+    let marker = env::var(MARKER).unwrap_or_default();
+    let others = others(&marker, &own);
+    if marker.split(',').all(|copy| copy != own) {
+        let mut copies = others.clone();
+        copies.push(own);
+        env::set_var(MARKER, copies.join(","));
+    }
+    if !others.is_empty() {
+        warn_duplicates(&others);
+    }
+}
+
+/// Warns when `init()` runs if other copies were found, as their metrics won't be served.
+pub(crate) fn check_at_init() {
+    let others = other_copies();
+    if !others.is_empty() {
+        warn_duplicates(&others);
+    }
+}
+
+fn warn_duplicates(others: &[String]) {
+    warn!(
+        "Several copies of the instrumented crate are linked into this binary (this one is {}, \
+         others: {}). Each copy has its own registry and only one is served, so the metrics \
+         recorded through the others are lost. Run `cargo tree -d -i instrumented` to find the \
+         dependencies requiring different versions, and align them on a single version.",
+        identity(),
+        others.join(", ")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::others;
+
+    #[test]
+    fn lists_other_copies() {
+        assert!(others("", "0.1.0@0x1").is_empty());
+        assert!(others("0.1.0@0x1", "0.1.0@0x1").is_empty());
+        assert_eq!(
+            others("0.1.0@0x1,0.2.0@0x2", "0.1.0@0x1"),
+            vec!["0.2.0@0x2".to_string()]
+        );
+    }
+}
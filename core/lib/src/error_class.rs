@@ -0,0 +1,27 @@
+//! A bounded taxonomy of errors, for `err_label = "class"`.
+
+/// Classifies an error into a few bounded labels, instead of its `Debug` representation, which
+/// may embed unbounded values such as paths or ids.
+///
+/// Usually derived, with `#[derive(InstrumentedErrorClass)]` and
+/// `#[instrumented(class = "...", retryable)]` on the variants. With `err_label = "class"`, the
+/// class is the `err` label of `function_error_total`, and the errors are counted by `class` and
+/// `retryable` in `function_error_detail_total`.
+pub trait InstrumentedErrorClass {
+    /// The class of the error, e.g. `timeout`. Keep the classes few.
+    fn class(&self) -> &'static str;
+
+    /// Whether the operation may succeed if retried.
+    fn retryable(&self) -> bool;
+}
+
+/// The labels of `function_error_detail_total` for `err_label = "class"`.
+#[doc(hidden)]
+pub fn error_class_labels<E: InstrumentedErrorClass + ?Sized>(
+    err: &E,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("class", err.class().to_string()),
+        ("retryable", err.retryable().to_string()),
+    ]
+}
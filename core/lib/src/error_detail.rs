@@ -0,0 +1,173 @@
+//! Errors counted by the labels of a user-provided classifier, with `err_labels`.
+use crate::poison::RwLockExt;
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::MetricFamily;
+use crate::prometheus::{IntCounterVec, Opts};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once, RwLock};
+
+static REGISTER: Once = Once::new();
+
+lazy_static! {
+    static ref DETAILS: RwLock<HashMap<(&'static str, &'static str), Arc<Detail>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// The error counter of a function, with the label names of its first classified error.
+struct Detail {
+    label_names: Vec<&'static str>,
+    /// `None` if the label names aren't valid prometheus label names.
+    counter: Option<IntCounterVec>,
+    /// Whether a call with other label names was already logged.
+    mismatch_logged: AtomicBool,
+}
+
+impl Detail {
+    fn new(name: &str, ctx: &str, label_names: Vec<&'static str>) -> Self {
+        let mut names = vec!["type", "name", "ctx"];
+        names.extend(&label_names);
+        let counter = IntCounterVec::new(
+            Opts::new(
+                "function_error_detail_total",
+                "Number of errors returned by a function, by the labels of its classifier",
+            ),
+            &names,
+        );
+        if let Err(err) = &counter {
+            error!(
+                "The error labels {:?} of {} (ctx {}) are invalid, its errors won't be classified: {}",
+                label_names, name, ctx, err
+            );
+        }
+        Detail {
+            label_names,
+            counter: counter.ok(),
+            mismatch_logged: AtomicBool::new(false),
+        }
+    }
+
+    fn inc(&self, name: &'static str, ctx: &'static str, labels: &[(&'static str, String)]) {
+        let counter = match &self.counter {
+            Some(counter) => counter,
+            None => return,
+        };
+        // The labels may come in any order, as long as they have the same names.
+        let values = if labels.len() == self.label_names.len() {
+            self.label_names
+                .iter()
+                .map(|label| {
+                    labels
+                        .iter()
+                        .find(|(other, _)| other == label)
+                        .map(|(_, value)| value.as_str())
+                })
+                .collect::<Option<Vec<_>>>()
+        } else {
+            None
+        };
+        match values {
+            Some(values) => {
+                let mut all = vec!["func_call", name, ctx];
+                all.extend(values);
+                counter.with_label_values(&all).inc();
+            }
+            None => {
+                if !self.mismatch_logged.swap(true, Ordering::Relaxed) {
+                    let names = labels.iter().map(|(label, _)| *label).collect::<Vec<_>>();
+                    error!(
+                        "The error labels {:?} of {} (ctx {}) differ from {:?} of its first error, \
+                         such errors won't be classified",
+                        names, name, ctx, self.label_names
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn inc_error_detail_for(
+    name: &'static str,
+    ctx: &'static str,
+    labels: Vec<(&'static str, String)>,
+) {
+    if !crate::is_enabled() {
+        return;
+    }
+    REGISTER.call_once(|| crate::register_builtin(Box::new(ErrorDetailCollector::new())));
+    let labels: Vec<_> = labels
+        .into_iter()
+        .map(|(label, value)| (label, crate::normalize::normalize_label(label, value)))
+        .collect();
+    if let Some(detail) = DETAILS.read_or_recover().get(&(name, ctx)) {
+        detail.inc(name, ctx, &labels);
+        return;
+    }
+    let detail = DETAILS
+        .write_or_recover()
+        .entry((name, ctx))
+        .or_insert_with(|| {
+            let names = labels.iter().map(|(label, _)| *label).collect();
+            Arc::new(Detail::new(name, ctx, names))
+        })
+        .clone();
+    detail.inc(name, ctx, &labels);
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    for detail in DETAILS.read_or_recover().values() {
+        if let Some(counter) = &detail.counter {
+            counter.reset();
+        }
+    }
+}
+
+/// Exports the counters of all the functions as a single family, as their label names differ.
+struct ErrorDetailCollector {
+    desc: Desc,
+}
+
+impl ErrorDetailCollector {
+    fn new() -> Self {
+        let desc = Desc::new(
+            "function_error_detail_total".to_string(),
+            "Number of errors returned by a function, by the labels of its classifier".to_string(),
+            vec!["type".to_string(), "name".to_string(), "ctx".to_string()],
+            HashMap::new(),
+        )
+        .unwrap();
+        ErrorDetailCollector { desc }
+    }
+}
+
+impl Collector for ErrorDetailCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut family: Option<MetricFamily> = None;
+        for detail in DETAILS.read_or_recover().values() {
+            let counter = match &detail.counter {
+                Some(counter) => counter,
+                None => continue,
+            };
+            for mut collected in counter.collect() {
+                match &mut family {
+                    Some(family) => {
+                        for metric in collected.take_metric().into_iter() {
+                            family.mut_metric().push(metric);
+                        }
+                    }
+                    None => family = Some(collected),
+                }
+            }
+        }
+        family
+            .into_iter()
+            .filter(|family| !family.get_metric().is_empty())
+            .collect()
+    }
+}
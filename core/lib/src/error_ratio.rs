@@ -0,0 +1,202 @@
+//! The error ratio of each function over a sliding window, kept in a ring of per-second buckets.
+use crate::clock::Instant;
+use crate::poison::{LockExt, RwLockExt};
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::MetricFamily;
+use crate::prometheus::{GaugeVec, Opts};
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError, RwLock};
+
+/// The length of the window, in seconds.
+const WINDOW_SECS: usize = 60;
+
+lazy_static! {
+    static ref EPOCH: Instant = Instant::now();
+    static ref WINDOWS: RwLock<HashMap<(&'static str, &'static str), Mutex<Window>>> =
+        RwLock::new(HashMap::new());
+}
+
+#[derive(Clone, Copy, Default)]
+struct Bucket {
+    /// The second the counts are for, since the epoch.
+    second: u64,
+    calls: u64,
+    errors: u64,
+}
+
+/// Calls and errors over the last `WINDOW_SECS` seconds.
+struct Window {
+    buckets: [Bucket; WINDOW_SECS],
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Window {
+            buckets: [Bucket::default(); WINDOW_SECS],
+        }
+    }
+}
+
+impl Window {
+    /// Returns the bucket of `now`, clearing it if it holds counts of an older second.
+    fn bucket(&mut self, now: u64) -> &mut Bucket {
+        let bucket = &mut self.buckets[now as usize % WINDOW_SECS];
+        if bucket.second != now {
+            *bucket = Bucket {
+                second: now,
+                ..Bucket::default()
+            };
+        }
+        bucket
+    }
+
+    fn record_call(&mut self, now: u64) {
+        self.bucket(now).calls += 1;
+    }
+
+    fn record_error(&mut self, now: u64) {
+        self.bucket(now).errors += 1;
+    }
+
+    /// The ratio of errors to calls in the window ending at `now`, or `None` without calls.
+    fn ratio(&self, now: u64) -> Option<f64> {
+        let (calls, errors) = self
+            .buckets
+            .iter()
+            .filter(|bucket| bucket.second + (WINDOW_SECS as u64) > now && bucket.second <= now)
+            .fold((0, 0), |(calls, errors), bucket| {
+                (calls + bucket.calls, errors + bucket.errors)
+            });
+        if calls == 0 {
+            None
+        } else {
+            Some((errors as f64 / calls as f64).min(1.0))
+        }
+    }
+}
+
+fn now() -> u64 {
+    EPOCH.elapsed().as_secs()
+}
+
+fn with_window<F: FnOnce(&mut Window)>(name: &'static str, ctx: &'static str, f: F) {
+    if let Some(window) = WINDOWS.read_or_recover().get(&(name, ctx)) {
+        f(&mut window.lock_or_recover());
+        return;
+    }
+    let mut windows = WINDOWS.write_or_recover();
+    let window = windows
+        .entry((name, ctx))
+        .or_insert_with(|| Mutex::new(Window::default()));
+    f(window.get_mut().unwrap_or_else(PoisonError::into_inner));
+}
+
+pub(crate) fn record_call(name: &'static str, ctx: &'static str) {
+    with_window(name, ctx, |window| window.record_call(now()));
+}
+
+pub(crate) fn record_error(name: &'static str, ctx: &'static str) {
+    with_window(name, ctx, |window| window.record_error(now()));
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    WINDOWS.write_or_recover().clear();
+}
+
+/// Returns the ratio of errors to calls of a function over the last 60 seconds, or `None` if it
+/// wasn't called in that time. Ignored errors aren't counted.
+///
+/// ```rust
+/// # use instrumented::instrument;
+/// #[instrument(INFO, ctx = "docs")]
+/// fn flaky(fail: bool) -> Result<(), String> {
+///     if fail { Err("failed".into()) } else { Ok(()) }
+/// }
+///
+/// let _ = flaky(true);
+/// let _ = flaky(false);
+/// assert_eq!(instrumented::error_ratio("flaky", "docs"), Some(0.5));
+/// ```
+pub fn error_ratio(name: &str, ctx: &str) -> Option<f64> {
+    let now = now();
+    WINDOWS
+        .read_or_recover()
+        .iter()
+        .find(|((n, c), _)| *n == name && *c == ctx)
+        .and_then(|(_, window)| window.lock_or_recover().ratio(now))
+}
+
+/// Exports `function_error_ratio`, refreshed at gather time.
+pub(crate) struct ErrorRatioCollector {
+    desc: Vec<Desc>,
+    ratio: GaugeVec,
+}
+
+impl ErrorRatioCollector {
+    pub(crate) fn new() -> Self {
+        let ratio = GaugeVec::new(
+            Opts::new(
+                "function_error_ratio",
+                "Ratio of errors to calls of a function over the last 60 seconds",
+            ),
+            &["type", "name", "ctx"],
+        )
+        .unwrap();
+
+        ErrorRatioCollector {
+            desc: ratio.desc().into_iter().cloned().collect(),
+            ratio,
+        }
+    }
+}
+
+impl Collector for ErrorRatioCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.desc.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let now = now();
+        self.ratio.reset();
+        for ((name, ctx), window) in WINDOWS.read_or_recover().iter() {
+            if let Some(ratio) = window.lock_or_recover().ratio(now) {
+                self.ratio
+                    .with_label_values(&["func_call", name, ctx])
+                    .set(ratio);
+            }
+        }
+        self.ratio.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Window;
+
+    #[test]
+    fn ratio_over_window() {
+        let mut window = Window::default();
+        assert_eq!(window.ratio(0), None);
+
+        for _ in 0..3 {
+            window.record_call(10);
+        }
+        window.record_error(10);
+        window.record_call(11);
+        assert_eq!(window.ratio(11), Some(0.25));
+
+        window.record_call(40);
+        window.record_error(40);
+        assert_eq!(window.ratio(40), Some(0.4));
+
+        // The calls of second 10 leave the window, then those of second 11.
+        assert_eq!(window.ratio(70), Some(0.5));
+        assert_eq!(window.ratio(71), Some(1.0));
+        assert_eq!(window.ratio(100), None);
+
+        // A bucket is cleared when reused for a later second.
+        window.record_call(130);
+        assert_eq!(window.ratio(130), Some(0.0));
+    }
+}
@@ -0,0 +1,239 @@
+//! The unix time of the last occurrence of rare, important events, such as a failover, from
+//! which dashboards can derive annotations.
+use crate::poison::LockExt;
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::{Gauge, LabelPair, Metric, MetricFamily, MetricType};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Mutex, Once};
+
+/// The maximum number of distinct event names.
+pub const MAX_EVENT_NAMES: usize = 64;
+/// The maximum number of distinct label sets of an event.
+pub const MAX_EVENT_SERIES: usize = 32;
+/// The maximum number of labels of an event.
+pub const MAX_EVENT_LABELS: usize = 8;
+/// The maximum length of an event name, and of a label value.
+pub const MAX_EVENT_VALUE_LEN: usize = 128;
+
+const METRIC_NAME: &str = "event_timestamp_seconds";
+const METRIC_HELP: &str = "Unix time of the last occurrence of an event";
+
+static REGISTER: Once = Once::new();
+
+type Labels = Vec<(String, String)>;
+
+lazy_static! {
+    static ref EVENTS: Mutex<BTreeMap<String, BTreeMap<Labels, f64>>> = Mutex::new(BTreeMap::new());
+}
+
+/// An event rejected by `instrumented::event`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventError {
+    /// The event name is empty or longer than `MAX_EVENT_VALUE_LEN`.
+    InvalidName(String),
+    /// A label name isn't a valid prometheus label name, is `event`, or is repeated.
+    InvalidLabelName(String),
+    /// The value of the label is longer than `MAX_EVENT_VALUE_LEN`.
+    LabelValueTooLong(String),
+    /// The event has more than `MAX_EVENT_LABELS` labels.
+    TooManyLabels(usize),
+    /// `MAX_EVENT_NAMES` other events were already recorded.
+    TooManyEvents(String),
+    /// `MAX_EVENT_SERIES` other label sets of the event were already recorded.
+    TooManySeries(String),
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventError::InvalidName(name) => write!(f, "invalid event name {:?}", name),
+            EventError::InvalidLabelName(name) => write!(f, "invalid event label name {:?}", name),
+            EventError::LabelValueTooLong(name) => {
+                write!(f, "the value of the event label {:?} is too long", name)
+            }
+            EventError::TooManyLabels(count) => write!(
+                f,
+                "{} event labels, at most {} are allowed",
+                count, MAX_EVENT_LABELS
+            ),
+            EventError::TooManyEvents(name) => write!(
+                f,
+                "event {:?} not recorded, {} distinct events already are",
+                name, MAX_EVENT_NAMES
+            ),
+            EventError::TooManySeries(name) => write!(
+                f,
+                "event {:?} not recorded, {} of its label sets already are",
+                name, MAX_EVENT_SERIES
+            ),
+        }
+    }
+}
+
+impl Error for EventError {}
+
+fn validate(name: &str, labels: &[(&str, &str)]) -> Result<Labels, EventError> {
+    if name.is_empty() || name.len() > MAX_EVENT_VALUE_LEN {
+        return Err(EventError::InvalidName(name.to_string()));
+    }
+    if labels.len() > MAX_EVENT_LABELS {
+        return Err(EventError::TooManyLabels(labels.len()));
+    }
+    let mut sorted = Labels::with_capacity(labels.len());
+    for (label, value) in labels {
+        if !crate::validate::is_label_name(label)
+            || *label == "event"
+            || sorted.iter().any(|(other, _)| other == label)
+        {
+            return Err(EventError::InvalidLabelName(label.to_string()));
+        }
+        let value = crate::normalize::normalize_label(label, value.to_string());
+        if value.len() > MAX_EVENT_VALUE_LEN {
+            return Err(EventError::LabelValueTooLong(label.to_string()));
+        }
+        sorted.push((label.to_string(), value));
+    }
+    sorted.sort();
+    Ok(sorted)
+}
+
+/// Records the current unix time as the last occurrence of the event `name` with `labels`,
+/// exported as `event_timestamp_seconds{event="<name>",...}`. Meant for rare events, such as a
+/// config reload or a failover: the number of events and of label sets per event are capped, see
+/// `MAX_EVENT_NAMES` and `MAX_EVENT_SERIES`.
+///
+/// ```rust
+/// instrumented::event("failover", &[("from", "a"), ("to", "b")]).unwrap();
+/// ```
+pub fn event(name: &str, labels: &[(&str, &str)]) -> Result<(), EventError> {
+    let labels = validate(name, labels)?;
+    if !crate::is_enabled() {
+        return Ok(());
+    }
+    REGISTER.call_once(|| crate::register_builtin(Box::new(EventCollector::new())));
+    let now = crate::duration_to_seconds(crate::clock::since_epoch(crate::clock::system_time()));
+
+    let mut events = EVENTS.lock_or_recover();
+    if !events.contains_key(name) && events.len() >= MAX_EVENT_NAMES {
+        return Err(EventError::TooManyEvents(name.to_string()));
+    }
+    let series = events.entry(name.to_string()).or_default();
+    if !series.contains_key(&labels) && series.len() >= MAX_EVENT_SERIES {
+        return Err(EventError::TooManySeries(name.to_string()));
+    }
+    series.insert(labels, now);
+    Ok(())
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    EVENTS.lock_or_recover().clear();
+}
+
+/// Exports all the events as a single family, as their label names differ.
+struct EventCollector {
+    desc: Desc,
+}
+
+impl EventCollector {
+    fn new() -> Self {
+        let desc = Desc::new(
+            METRIC_NAME.to_string(),
+            METRIC_HELP.to_string(),
+            vec!["event".to_string()],
+            HashMap::new(),
+        )
+        .unwrap();
+        EventCollector { desc }
+    }
+}
+
+impl Collector for EventCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let events = EVENTS.lock_or_recover();
+        if events.is_empty() {
+            return vec![];
+        }
+        let mut family = MetricFamily::new();
+        family.set_name(METRIC_NAME.to_string());
+        family.set_help(METRIC_HELP.to_string());
+        family.set_field_type(MetricType::GAUGE);
+        for (name, series) in events.iter() {
+            for (labels, timestamp) in series {
+                let mut pairs = vec![("event", name.as_str())];
+                pairs.extend(
+                    labels
+                        .iter()
+                        .map(|(label, value)| (label.as_str(), value.as_str())),
+                );
+                pairs.sort();
+                let mut metric = Metric::new();
+                metric.set_label(
+                    pairs
+                        .into_iter()
+                        .map(|(label, value)| {
+                            let mut pair = LabelPair::new();
+                            pair.set_name(label.to_string());
+                            pair.set_value(value.to_string());
+                            pair
+                        })
+                        .collect::<Vec<_>>()
+                        .into(),
+                );
+                let mut gauge = Gauge::new();
+                gauge.set_value(*timestamp);
+                metric.set_gauge(gauge);
+                family.mut_metric().push(metric);
+            }
+        }
+        vec![family]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, EventError, MAX_EVENT_LABELS};
+
+    #[test]
+    fn label_validation() {
+        assert_eq!(
+            validate("failover", &[("to", "b"), ("from", "a")]),
+            Ok(vec![
+                ("from".to_string(), "a".to_string()),
+                ("to".to_string(), "b".to_string())
+            ])
+        );
+        assert_eq!(
+            validate("", &[]),
+            Err(EventError::InvalidName(String::new()))
+        );
+        assert_eq!(
+            validate("failover", &[("event", "a")]),
+            Err(EventError::InvalidLabelName("event".to_string()))
+        );
+        assert_eq!(
+            validate("failover", &[("from", "a"), ("from", "b")]),
+            Err(EventError::InvalidLabelName("from".to_string()))
+        );
+        assert_eq!(
+            validate("failover", &[("from-zone", "a")]),
+            Err(EventError::InvalidLabelName("from-zone".to_string()))
+        );
+        let long = "a".repeat(200);
+        assert_eq!(
+            validate("failover", &[("from", &long)]),
+            Err(EventError::LabelValueTooLong("from".to_string()))
+        );
+        let labels = vec![("from", "a"); MAX_EVENT_LABELS + 1];
+        assert_eq!(
+            validate("failover", &labels),
+            Err(EventError::TooManyLabels(MAX_EVENT_LABELS + 1))
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! A non-blocking stream of the completed instrumented calls, for custom pipelines.
+//!
+//! Unlike observers, subscribers consume the events on their own thread. Every subscriber gets
+//! its own bounded buffer: when it's full, the event is dropped for that subscriber and counted in
+//! `dropped_events_total`, so that instrumented calls never block. Calls of functions returning
+//! streams aren't sent.
+//!
+//! ```rust
+//! use instrumented::{events, instrument};
+//!
+//! #[instrument(INFO)]
+//! fn my_func() {}
+//!
+//! let rx = events::subscribe(1024);
+//! my_func();
+//! let event = rx.recv().unwrap();
+//! assert_eq!(event.name, "my_func");
+//! ```
+use crate::observer::{self, Completion, Observer};
+use crate::poison::RwLockExt;
+use crate::prometheus::IntCounter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Once, RwLock};
+use std::time::{Duration, SystemTime};
+
+lazy_static! {
+    static ref SUBSCRIBERS: RwLock<Vec<Subscriber>> = RwLock::new(Vec::new());
+    static ref DROPPED_EVENTS: IntCounter = {
+        let counter = IntCounter::new(
+            "dropped_events_total",
+            "Number of call events dropped because the buffer of a subscriber was full",
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+static REGISTER: Once = Once::new();
+
+/// The outcome of a call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The call returned normally, or returned an error listed in `ignore_err`.
+    Ok,
+    /// The call returned an error, with its error label.
+    Err(String),
+}
+
+/// A completed instrumented call.
+#[derive(Debug, Clone)]
+pub struct CallEvent {
+    pub name: &'static str,
+    pub ctx: &'static str,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    pub outcome: Outcome,
+}
+
+struct Subscriber {
+    tx: SyncSender<CallEvent>,
+    /// Set once the receiver is dropped, for the subscriber to be removed.
+    disconnected: AtomicBool,
+}
+
+struct Publisher;
+
+impl Observer for Publisher {
+    fn on_completion(&self, completion: &Completion) {
+        let event = CallEvent {
+            name: completion.name,
+            ctx: completion.ctx,
+            started_at: crate::clock::system_time() - completion.duration,
+            duration: completion.duration,
+            outcome: match completion.outcome {
+                observer::Outcome::Ok => Outcome::Ok,
+                observer::Outcome::Err(err) => Outcome::Err(err.to_string()),
+            },
+        };
+        for subscriber in SUBSCRIBERS.read_or_recover().iter() {
+            match subscriber.tx.try_send(event.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => DROPPED_EVENTS.inc(),
+                // Removed on the next subscription, as the lock can't be upgraded here.
+                Err(TrySendError::Disconnected(_)) => {
+                    subscriber.disconnected.store(true, Ordering::Relaxed)
+                }
+            }
+        }
+    }
+}
+
+/// Subscribes to the completed calls, buffering up to `capacity` events until they're received.
+pub fn subscribe(capacity: usize) -> Receiver<CallEvent> {
+    lazy_static::initialize(&DROPPED_EVENTS);
+    let (tx, rx) = mpsc::sync_channel(capacity);
+    let mut subscribers = SUBSCRIBERS.write_or_recover();
+    subscribers.retain(|subscriber| !subscriber.disconnected.load(Ordering::Relaxed));
+    subscribers.push(Subscriber {
+        tx,
+        disconnected: AtomicBool::new(false),
+    });
+    REGISTER.call_once(|| observer::add_observer(Box::new(Publisher)));
+    rx
+}
@@ -0,0 +1,1395 @@
+use crate::limits::{LimitedIncoming, Limits};
+use crate::poison::LockExt;
+use crate::prometheus::proto::MetricFamily;
+use crate::validate::{ConfigError, ConfigReport};
+use hyper::http::StatusCode;
+use hyper::rt::Future;
+use hyper::service::service_fn_ok;
+use hyper::{Body, Method, Request, Response, Server};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::tcp;
+use tokio::prelude::task::AtomicTask;
+use tokio::prelude::{Async, Poll};
+use tokio::reactor::Handle;
+
+lazy_static! {
+    static ref STARTED: Instant = Instant::now();
+    /// The running exporters, with the channel their runtime thread signals once stopped.
+    static ref RUNNING: Mutex<Vec<(Arc<Stop>, Receiver<()>)>> = Mutex::new(Vec::new());
+    static ref SELFTEST: crate::prometheus::IntCounter = {
+        let counter = crate::prometheus::IntCounter::new(
+            "selftest_total",
+            "Number of self-tests of the metrics pipeline, see `Config::selftest`",
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+/// Configuration of the metrics exporter.
+///
+/// ```rust,no_run
+/// let config = instrumented::Config::new("127.0.0.1:5000")
+///     .bearer_token("secret")
+///     .admin(true);
+/// instrumented::init_with_config(config);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    addr: String,
+    bearer_token: Option<String>,
+    ctx_tokens: Vec<(String, Vec<String>)>,
+    shared_series: bool,
+    admin: bool,
+    admin_reset: bool,
+    functions: bool,
+    selftest: bool,
+    slow_log: Option<(PathBuf, Duration, u64)>,
+    #[cfg(all(unix, feature = "flight-recorder"))]
+    flight_recorder: Option<(PathBuf, usize)>,
+    #[cfg(feature = "relabel")]
+    relabel: Vec<crate::relabel::RelabelRule>,
+    alias_prefix: Option<String>,
+    alias_ctxs: Vec<(String, String)>,
+    label_files: Vec<(String, PathBuf)>,
+    limits: Limits,
+    keep_alive: bool,
+    http2: bool,
+}
+
+impl Config {
+    /// Creates a configuration serving metrics at `addr`.
+    pub fn new(addr: &str) -> Self {
+        Config {
+            addr: addr.to_string(),
+            bearer_token: None,
+            ctx_tokens: vec![],
+            shared_series: true,
+            admin: false,
+            admin_reset: false,
+            functions: false,
+            selftest: false,
+            slow_log: None,
+            #[cfg(all(unix, feature = "flight-recorder"))]
+            flight_recorder: None,
+            #[cfg(feature = "relabel")]
+            relabel: vec![],
+            alias_prefix: None,
+            alias_ctxs: vec![],
+            label_files: vec![],
+            limits: Limits {
+                max_connections: 64,
+                header_read_timeout: Duration::from_secs(10),
+                request_timeout: Duration::from_secs(30),
+            },
+            keep_alive: true,
+            http2: true,
+        }
+    }
+
+    /// Requires every request to carry an `Authorization: Bearer <token>` header.
+    pub fn bearer_token(mut self, token: &str) -> Self {
+        self.bearer_token = Some(token.to_string());
+        self
+    }
+
+    /// Adds a bearer token that can only scrape the series whose `ctx` label is one of `ctxs`, e.g.
+    /// to give each team its own credential. The token is only allowed on the metrics endpoints.
+    /// Once a ctx token is added, requests without a token are rejected, even without
+    /// `bearer_token`.
+    ///
+    /// ```rust,no_run
+    /// let config = instrumented::Config::new("127.0.0.1:5000")
+    ///     .bearer_token("operator")
+    ///     .ctx_token("billing-secret", &["billing", "invoices"])
+    ///     .ctx_token("search-secret", &["search"]);
+    /// instrumented::init_with_config(config);
+    /// ```
+    pub fn ctx_token(mut self, token: &str, ctxs: &[&str]) -> Self {
+        let ctxs = ctxs.iter().map(|ctx| ctx.to_string()).collect();
+        self.ctx_tokens.push((token.to_string(), ctxs));
+        self
+    }
+
+    /// Sets whether the series without a `ctx` label, like the process metrics, are visible to
+    /// the ctx tokens (visible by default).
+    pub fn shared_series(mut self, visible: bool) -> Self {
+        self.shared_series = visible;
+        self
+    }
+
+    /// Enables the admin endpoints (disabled by default):
+    ///
+    /// * `POST /admin/instrumentation?enabled=<bool>` enables or disables instrumentation
+    /// * `POST /admin/reset` resets the built-in metrics, when allowed by `admin_reset`
+    /// * `GET /admin/status` returns the instrumentation status as JSON
+    /// * `GET`, `POST` and `DELETE /admin/relabel` list, add and remove the relabel rules, with
+    ///   the `relabel` feature, see `instrumented::relabel`
+    pub fn admin(mut self, enabled: bool) -> Self {
+        self.admin = enabled;
+        self
+    }
+
+    /// Allows the destructive `POST /admin/reset` endpoint (disabled by default).
+    pub fn admin_reset(mut self, enabled: bool) -> Self {
+        self.admin_reset = enabled;
+        self
+    }
+
+    /// Enables the `GET /functions` endpoint (disabled by default), which returns the instrumented
+    /// functions along with their call and error counts as JSON.
+    pub fn functions(mut self, enabled: bool) -> Self {
+        self.functions = enabled;
+        self
+    }
+
+    /// Enables the `GET /selftest` endpoint (disabled by default), e.g. for a CI step, which
+    /// increments `selftest_total`, gathers and encodes the metrics as served, and checks that the
+    /// counter is in there with the prefix and the default labels. It returns a JSON report, with
+    /// a 500 status and the reason when the check fails.
+    pub fn selftest(mut self, enabled: bool) -> Self {
+        self.selftest = enabled;
+        self
+    }
+
+    /// Appends the calls slower than `threshold` to a log file at `path`, rotated once it would
+    /// exceed `max_size` bytes. See `instrumented::slow_log`.
+    pub fn slow_log<P: AsRef<Path>>(mut self, path: P, threshold: Duration, max_size: u64) -> Self {
+        self.slow_log = Some((path.as_ref().to_path_buf(), threshold, max_size));
+        self
+    }
+
+    /// Keeps the last `capacity` calls in a memory-mapped file at `path`, which survives a crash
+    /// of the process. See `instrumented::flight_recorder`.
+    #[cfg(all(unix, feature = "flight-recorder"))]
+    pub fn flight_recorder<P: AsRef<Path>>(mut self, path: P, capacity: usize) -> Self {
+        self.flight_recorder = Some((path.as_ref().to_path_buf(), capacity));
+        self
+    }
+
+    /// Adds a rule applied to the served metrics, after the previous ones. The rules can also be
+    /// replaced at runtime with the admin endpoints. See `instrumented::relabel`.
+    #[cfg(feature = "relabel")]
+    pub fn relabel(mut self, rule: crate::relabel::RelabelRule) -> Self {
+        self.relabel.push(rule);
+        self
+    }
+
+    /// Also exports the metrics under the `old` prefix, during a rename of `METRICS_PREFIX`. See
+    /// `instrumented::alias`.
+    pub fn alias_prefix(mut self, old: &str) -> Self {
+        self.alias_prefix = Some(old.to_string());
+        self
+    }
+
+    /// Also exports the series of the `new` ctx under the `old` ctx, during a rename of the ctx.
+    /// See `instrumented::alias`.
+    pub fn alias_ctx(mut self, old: &str, new: &str) -> Self {
+        self.alias_ctxs.push((old.to_string(), new.to_string()));
+        self
+    }
+
+    /// Adds a default label read from a file, like `instrumented::label_from_file`. The label is
+    /// only added if the registry isn't created yet when `init_with_config` is called.
+    pub fn label_from_file<P: AsRef<Path>>(mut self, label: &str, path: P) -> Self {
+        self.label_files
+            .push((label.to_string(), path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Sets the maximum number of open connections (64 by default). Further connections wait
+    /// to be accepted until one is closed.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.limits.max_connections = max;
+        self
+    }
+
+    /// Enables HTTP/1 keep-alive (enabled by default).
+    pub fn keep_alive(mut self, enabled: bool) -> Self {
+        self.keep_alive = enabled;
+        self
+    }
+
+    /// Serves HTTP/2 cleartext (h2c) with prior knowledge, on the same port as HTTP/1.1 (enabled
+    /// by default). Connections starting with the HTTP/2 preface are served over HTTP/2, and the
+    /// others over HTTP/1.1.
+    pub fn http2(mut self, enabled: bool) -> Self {
+        self.http2 = enabled;
+        self
+    }
+
+    /// Sets the time allowed to read the headers of a request, from the connection being
+    /// accepted or the previous response being written (10 seconds by default). Slower clients,
+    /// and idle keep-alive connections, are disconnected.
+    pub fn header_read_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.header_read_timeout = timeout;
+        self
+    }
+
+    /// Sets the time allowed to serve a request, from its first byte to the response being
+    /// written (30 seconds by default). Slower clients are disconnected.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.request_timeout = timeout;
+        self
+    }
+
+    /// Checks the configuration like `instrumented::validate_config`, along with the address, and
+    /// returns the effective configuration, without applying it.
+    pub fn validate(&self) -> Result<ConfigReport, ConfigError> {
+        let mut report = crate::validate_config()?;
+        crate::validate::check_label_files(
+            self.label_files.iter().cloned().map(Ok),
+            &mut report.label_keys,
+        )?;
+        if let Some(prefix) = &self.alias_prefix {
+            if !prefix.is_empty() && !crate::validate::is_metric_name(prefix) {
+                return Err(ConfigError::InvalidPrefix(prefix.clone()));
+            }
+        }
+        if self.addr.parse::<SocketAddr>().is_err() {
+            return Err(ConfigError::InvalidAddr(self.addr.clone()));
+        }
+        report.addr = Some(self.addr.clone());
+        report.path = Some("/metrics");
+        Ok(report)
+    }
+
+    fn access(&self, req: &Request<Body>) -> Access<'_> {
+        let token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let scoped = token.and_then(|token| {
+            self.ctx_tokens
+                .iter()
+                .find(|(ctx_token, _)| tokens_eq(token, ctx_token))
+        });
+        match (&self.bearer_token, scoped) {
+            (Some(expected), _) if token.is_some_and(|token| tokens_eq(token, expected)) => {
+                Access::All
+            }
+            (_, Some((_, ctxs))) => Access::Ctxs(ctxs),
+            (None, None) if self.ctx_tokens.is_empty() => Access::All,
+            _ => Access::Denied,
+        }
+    }
+}
+
+/// Compares a token in a time independent of where it differs from the expected one, so that it
+/// can't be guessed byte by byte from the response times. Only its length can.
+fn tokens_eq(token: &str, expected: &str) -> bool {
+    let (token, expected) = (token.as_bytes(), expected.as_bytes());
+    token.len() == expected.len()
+        && token
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// What a request is allowed to read.
+enum Access<'a> {
+    All,
+    /// The series of these contexts, with a ctx token.
+    Ctxs(&'a [String]),
+    Denied,
+}
+
+/// Keeps the series whose `ctx` label is one of `ctxs`, along with the series without a `ctx`
+/// label if `shared`. The families left without series are removed.
+fn filter_ctx(families: Vec<MetricFamily>, ctxs: &[String], shared: bool) -> Vec<MetricFamily> {
+    families
+        .into_iter()
+        .filter_map(|mut family| {
+            let metrics: Vec<_> = family
+                .take_metric()
+                .into_iter()
+                .filter(|metric| {
+                    match metric
+                        .get_label()
+                        .iter()
+                        .find(|label| label.get_name() == "ctx")
+                    {
+                        Some(label) => ctxs.iter().any(|ctx| ctx == label.get_value()),
+                        None => shared,
+                    }
+                })
+                .collect();
+            if metrics.is_empty() {
+                None
+            } else {
+                family.set_metric(metrics.into());
+                Some(family)
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "relabel")]
+use crate::relabel::apply as relabel;
+
+#[cfg(not(feature = "relabel"))]
+fn relabel(families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+    families
+}
+
+fn respond(status: StatusCode, body: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(body))
+        .expect("Error constructing response")
+}
+
+fn render<E: crate::prometheus::Encoder>(
+    encoder: &E,
+    metric_families: &[MetricFamily],
+) -> Response<Body> {
+    let mut buffer = vec![];
+    encoder.encode(metric_families, &mut buffer).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", encoder.format_type())
+        .body(Body::from(buffer))
+        .expect("Error constructing response")
+}
+
+/// Whether the scraper accepts the protobuf exposition format, as Prometheus does when native
+/// histograms are enabled.
+fn accepts_protobuf(req: &Request<Body>) -> bool {
+    req.headers()
+        .get_all(hyper::header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|accept| accept.split(','))
+        .any(|media| {
+            let mut parts = media.split(';').map(str::trim);
+            if parts.next() != Some("application/vnd.google.protobuf") {
+                return false;
+            }
+            let params = parts.collect::<Vec<_>>();
+            params.contains(&"proto=io.prometheus.client.MetricFamily")
+                && params.contains(&"encoding=delimited")
+                && !params.iter().any(|param| {
+                    param.starts_with("q=") && param[2..].parse::<f64>().ok() == Some(0.0)
+                })
+        })
+}
+
+fn query_param<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.uri().query().and_then(|query| {
+        query.split('&').find_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) if key == name => Some(value),
+                _ => None,
+            }
+        })
+    })
+}
+
+fn status_json(config: &Config) -> String {
+    format!(
+        "{{\"enabled\":{},\"registry_size\":{},\"uptime_seconds\":{},\"config\":{}}}",
+        crate::is_enabled(),
+        crate::gather().len(),
+        crate::duration_to_seconds(STARTED.elapsed()),
+        match config.validate() {
+            Ok(report) => config_json(&report),
+            Err(err) => format!("{{\"error\":{}}}", json_string(&err.to_string())),
+        },
+    )
+}
+
+fn config_json(report: &ConfigReport) -> String {
+    let strings = |values: &[&str]| {
+        let values: Vec<String> = values.iter().map(|value| json_string(value)).collect();
+        format!("[{}]", values.join(","))
+    };
+    let optional = |value: Option<&str>| value.map_or("null".to_string(), json_string);
+    let label_keys: Vec<&str> = report.label_keys.iter().map(String::as_str).collect();
+    format!(
+        "{{\"prefix\":{},\"label_keys\":{},\"time_unit\":{},\"addr\":{},\"path\":{},\"features\":{}}}",
+        optional(report.prefix.as_ref().map(String::as_str)),
+        strings(&label_keys),
+        json_string(report.time_unit),
+        optional(report.addr.as_ref().map(String::as_str)),
+        optional(report.path),
+        strings(&report.features),
+    )
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn functions_json() -> String {
+    let functions: Vec<String> = crate::list_functions()
+        .iter()
+        .map(|function| {
+            format!(
+                "{{\"name\":{},\"ctx\":{},\"calls\":{},\"errors\":{}}}",
+                json_string(&function.name),
+                json_string(&function.ctx),
+                function.calls,
+                function.errors,
+            )
+        })
+        .collect();
+    format!("[{}]", functions.join(","))
+}
+
+/// Increments `selftest_total`, and checks that it's in the encoded `families` returned by
+/// `gather`, with the prefix and the default labels. Returns the JSON report, or the reason of
+/// the failure.
+fn selftest<G: Fn() -> Vec<MetricFamily>>(gather: G) -> Result<String, String> {
+    use crate::prometheus::Encoder;
+
+    SELFTEST.inc();
+    let expected = SELFTEST.get();
+    let families = gather();
+    let mut buffer = vec![];
+    crate::prometheus::TextEncoder::new()
+        .encode(&families, &mut buffer)
+        .map_err(|err| format!("the metrics can't be encoded: {}", err))?;
+    let text = String::from_utf8(buffer)
+        .map_err(|_| "the encoded metrics aren't valid UTF-8".to_string())?;
+    let series = |name: &str| {
+        text.lines()
+            .find(|line| {
+                line.strip_prefix(name)
+                    .map_or(false, |rest| rest.starts_with('{') || rest.starts_with(' '))
+            })
+            .map(str::to_string)
+    };
+
+    let name = match &*crate::METRICS_PREFIX {
+        Some(prefix) => format!("{}_selftest_total", prefix),
+        None => "selftest_total".to_string(),
+    };
+    let line = match (series(&name), &*crate::METRICS_PREFIX) {
+        (Some(line), _) => line,
+        (None, Some(prefix)) if series("selftest_total").is_some() => {
+            return Err(format!(
+                "selftest_total is exported without the prefix {:?}",
+                prefix
+            ))
+        }
+        (None, _) => return Err(format!("{} is missing from the encoded metrics", name)),
+    };
+    let mut labels: Vec<_> = crate::METRICS_LABELS.iter().flatten().collect();
+    labels.sort();
+    for (label, value) in &labels {
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        if !line.contains(&format!("{}=\"{}\"", label, value)) {
+            return Err(format!("{} is missing the default label {}", name, label));
+        }
+    }
+    let value = line
+        .rsplit(' ')
+        .next()
+        .and_then(|value| value.parse::<f64>().ok());
+    match value {
+        Some(value) if value >= expected as f64 => {}
+        _ => {
+            return Err(format!(
+                "{} is exported as {:?}, expected at least {}",
+                name, value, expected
+            ))
+        }
+    }
+
+    let labels: Vec<String> = labels.iter().map(|(label, _)| json_string(label)).collect();
+    Ok(format!(
+        "{{\"ok\":true,\"metric\":{},\"value\":{},\"labels\":[{}],\"families\":{},\"bytes\":{}}}",
+        json_string(&name),
+        expected,
+        labels.join(","),
+        families.len(),
+        text.len()
+    ))
+}
+
+fn selftest_response(report: Result<String, String>) -> Response<Body> {
+    let (status, body) = match report {
+        Ok(report) => (StatusCode::OK, report),
+        Err(reason) => {
+            warn!("Self-test of the metrics pipeline failed: {}", reason);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("{{\"ok\":false,\"error\":{}}}", json_string(&reason)),
+            )
+        }
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .expect("Error constructing response")
+}
+
+#[cfg(feature = "debug-introspection")]
+fn introspection_json() -> String {
+    let records: Vec<String> = crate::introspection::records()
+        .iter()
+        .map(|record| {
+            let flags: Vec<String> = record.flags.iter().map(|flag| json_string(flag)).collect();
+            format!(
+                "{{\"name\":{},\"ctx\":{},\"crate\":{},\"module\":{},\"file\":{},\"line\":{},\"flags\":[{}]}}",
+                json_string(record.name),
+                json_string(record.ctx),
+                json_string(record.crate_name()),
+                json_string(record.module),
+                json_string(record.file),
+                record.line,
+                flags.join(",")
+            )
+        })
+        .collect();
+    format!("[{}]", records.join(","))
+}
+
+fn handle_admin(req: &Request<Body>, config: &Config) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::POST, "/admin/instrumentation") => {
+            match query_param(req, "enabled").map(str::parse::<bool>) {
+                Some(Ok(enabled)) => {
+                    crate::set_enabled(enabled);
+                    info!(
+                        "Instrumentation enabled set to {} by admin request",
+                        enabled
+                    );
+                    respond(StatusCode::OK, "OK.")
+                }
+                _ => respond(
+                    StatusCode::BAD_REQUEST,
+                    "Expected an `enabled=true|false` query parameter.",
+                ),
+            }
+        }
+        (&Method::POST, "/admin/reset") => {
+            if config.admin_reset {
+                crate::reset_builtin_metrics();
+                info!("Built-in metrics reset by admin request");
+                respond(StatusCode::OK, "OK.")
+            } else {
+                respond(StatusCode::FORBIDDEN, "Reset is disabled.")
+            }
+        }
+        (&Method::GET, "/admin/status") => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(status_json(config)))
+            .expect("Error constructing response"),
+        #[cfg(feature = "relabel")]
+        (_, "/admin/relabel") => handle_relabel(req),
+        (_, "/admin/instrumentation") | (_, "/admin/reset") | (_, "/admin/status") => {
+            respond(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed.")
+        }
+        _ => respond(StatusCode::NOT_FOUND, "Not found."),
+    }
+}
+
+/// Lists the relabel rules, one per line, adds one from the `rule` query parameter, or removes
+/// them all.
+#[cfg(feature = "relabel")]
+fn handle_relabel(req: &Request<Body>) -> Response<Body> {
+    use crate::relabel::{relabel_rules, set_relabel_rules, RelabelRule};
+
+    match *req.method() {
+        Method::GET => {
+            let rules: String = relabel_rules()
+                .iter()
+                .map(|rule| format!("{}\n", rule))
+                .collect();
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(Body::from(rules))
+                .expect("Error constructing response")
+        }
+        Method::POST => match query_param(req, "rule").and_then(percent_decode) {
+            Some(rule) => match RelabelRule::parse(&rule) {
+                Ok(rule) => {
+                    info!("Relabel rule `{}` added by admin request", rule);
+                    let mut rules = relabel_rules();
+                    rules.push(rule);
+                    set_relabel_rules(rules);
+                    respond(StatusCode::OK, "OK.")
+                }
+                Err(err) => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(err))
+                    .expect("Error constructing response"),
+            },
+            None => respond(
+                StatusCode::BAD_REQUEST,
+                "Expected a percent-encoded `rule` query parameter.",
+            ),
+        },
+        Method::DELETE => {
+            set_relabel_rules(vec![]);
+            info!("Relabel rules removed by admin request");
+            respond(StatusCode::OK, "OK.")
+        }
+        _ => respond(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed."),
+    }
+}
+
+/// Decodes a query parameter value, with `+` for spaces. Returns `None` if it isn't valid UTF-8
+/// or has an invalid escape.
+#[cfg(feature = "relabel")]
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        match byte {
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b'+' => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Handles a request to the exporter.
+pub(crate) fn handle(req: &Request<Body>, config: &Config) -> Response<Body> {
+    let ctxs = match config.access(req) {
+        Access::All => None,
+        Access::Ctxs(ctxs) => Some(ctxs),
+        Access::Denied => return respond(StatusCode::UNAUTHORIZED, "Unauthorized."),
+    };
+    // The series visible to the request.
+    let gather = || {
+        relabel(match ctxs {
+            Some(ctxs) => filter_ctx(crate::gather(), ctxs, config.shared_series),
+            None => crate::gather(),
+        })
+    };
+
+    let path = req.uri().path();
+    #[cfg(feature = "json")]
+    {
+        if path == "/metrics.json" {
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(crate::json::render(&gather())))
+                .expect("Error constructing response");
+        }
+    }
+    #[cfg(feature = "csv")]
+    {
+        if path == "/metrics.csv" {
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/csv; charset=utf-8; header=present")
+                .body(Body::from(crate::csv::render(&gather())))
+                .expect("Error constructing response");
+        }
+    }
+    if path == "/metrics" {
+        if accepts_protobuf(req) {
+            // Copied first, so that no native bucket counts more observations than its series.
+            let natives = crate::native_histogram::snapshot();
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", crate::protobuf::EXPOSITION_FORMAT)
+                .body(Body::from(crate::protobuf::encode_exposition(
+                    &gather(),
+                    &natives,
+                )))
+                .expect("Error constructing response");
+        }
+        return render(&crate::prometheus::TextEncoder::new(), &gather());
+    }
+    if ctxs.is_some() {
+        return respond(StatusCode::FORBIDDEN, "Forbidden.");
+    }
+    #[cfg(feature = "debug-introspection")]
+    {
+        if path == "/debug/instrumented" {
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(introspection_json()))
+                .expect("Error constructing response");
+        }
+    }
+    if config.functions && path == "/functions" {
+        match *req.method() {
+            Method::GET => Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(functions_json()))
+                .expect("Error constructing response"),
+            _ => respond(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed."),
+        }
+    } else if config.selftest && path == "/selftest" {
+        match *req.method() {
+            Method::GET => selftest_response(selftest(gather)),
+            _ => respond(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed."),
+        }
+    } else if config.admin && path.starts_with("/admin/") {
+        handle_admin(req, config)
+    } else {
+        respond(StatusCode::NOT_FOUND, "Not found.")
+    }
+}
+
+/// Initializes the metrics context, and starts an HTTP server
+/// to serve metrics.
+pub fn init(addr: &str) {
+    init_with_config(Config::new(addr))
+}
+
+/// Initializes the metrics context, and starts an HTTP server to serve metrics using the given
+/// configuration.
+pub fn init_with_config(config: Config) {
+    let addr = config.addr.clone();
+    let exporter = serve(config, vec![Listen::Addr(addr)]);
+    if let Some(err) = exporter.errors.first() {
+        panic!("{}", err);
+    }
+}
+
+/// Initializes the metrics context, and starts an HTTP server per address to serve metrics, e.g.
+/// on both `0.0.0.0:9090` and `[::]:9090` for dual-stack clusters. The addresses that can't be
+/// bound are reported individually in the returned `Exporter`, while metrics are served on the
+/// others.
+///
+/// ```rust,no_run
+/// let exporter = instrumented::init_multi(&["0.0.0.0:9090", "[::]:9090"]);
+/// for addr in exporter.addrs() {
+///     println!("Serving metrics at http://{}/metrics", addr);
+/// }
+/// ```
+pub fn init_multi(addrs: &[&str]) -> Exporter {
+    init_multi_with_config(
+        Config::new(addrs.first().copied().unwrap_or_default()),
+        addrs,
+    )
+}
+
+/// Like `init_multi`, using the given configuration, whose address is ignored.
+pub fn init_multi_with_config(config: Config, addrs: &[&str]) -> Exporter {
+    let listeners = addrs
+        .iter()
+        .map(|addr| Listen::Addr(addr.to_string()))
+        .collect();
+    serve(config, listeners)
+}
+
+/// Initializes the metrics context, and serves metrics on a listener opened by the caller, e.g.
+/// handed over by a process manager for zero-downtime restarts, or bound to a privileged port
+/// before dropping privileges. The listener is switched to nonblocking mode, and on unix its
+/// descriptor is marked close-on-exec so that child processes don't inherit it.
+///
+/// ```rust,no_run
+/// let listener = std::net::TcpListener::bind("127.0.0.1:5000").unwrap();
+/// instrumented::init_with_listener(listener);
+/// ```
+pub fn init_with_listener(listener: TcpListener) {
+    let addr = listener
+        .local_addr()
+        .map_or_else(|_| "0.0.0.0:0".to_string(), |addr| addr.to_string());
+    init_with_config_and_listener(Config::new(&addr), listener)
+}
+
+/// Like `init_with_listener`, using the given configuration, whose address is ignored.
+pub fn init_with_config_and_listener(config: Config, listener: TcpListener) {
+    let exporter = serve(config, vec![Listen::Listener(listener)]);
+    if let Some(err) = exporter.errors.first() {
+        panic!("{}", err);
+    }
+}
+
+/// Like `init_with_config`, but serves metrics on the listener socket passed by systemd socket
+/// activation (the `LISTEN_FDS` and `LISTEN_PID` env vars), so that the port is owned by systemd
+/// and stays open across restarts. When the process isn't socket activated, metrics are served
+/// at the address of the configuration instead.
+///
+/// ```rust,no_run
+/// instrumented::init_from_systemd(instrumented::Config::new("127.0.0.1:5000"));
+/// ```
+#[cfg(all(unix, feature = "systemd"))]
+pub fn init_from_systemd(config: Config) {
+    let listen = match crate::systemd::activated_listener() {
+        Some(listener) => Listen::Listener(listener),
+        None => Listen::Addr(config.addr.clone()),
+    };
+    let exporter = serve(config, vec![listen]);
+    if let Some(err) = exporter.errors.first() {
+        panic!("{}", err);
+    }
+}
+
+/// The metrics servers started by `init_multi`.
+#[derive(Debug)]
+pub struct Exporter {
+    addrs: Vec<SocketAddr>,
+    errors: Vec<BindError>,
+}
+
+impl Exporter {
+    /// Returns the addresses metrics are served at, one per bound listener.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Returns the errors of the addresses that couldn't be bound.
+    pub fn errors(&self) -> &[BindError] {
+        &self.errors
+    }
+}
+
+/// An address metrics couldn't be served at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindError {
+    /// The address, as given.
+    pub addr: String,
+    /// Why it couldn't be bound.
+    pub message: String,
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unable to serve metrics at {}: {}",
+            self.addr, self.message
+        )
+    }
+}
+
+impl Error for BindError {}
+
+/// Where a metrics server listens.
+enum Listen {
+    Addr(String),
+    /// A listener socket passed by the environment or the caller.
+    Listener(TcpListener),
+}
+
+/// Tells the servers of an exporter to stop accepting connections.
+#[derive(Default)]
+struct Stop {
+    stopping: AtomicBool,
+    task: AtomicTask,
+}
+
+/// Resolves once the exporter is stopped, see `with_graceful_shutdown`.
+struct Stopping(Arc<Stop>);
+
+impl Future for Stopping {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        // Registered first, so that a stop after the check wakes the server.
+        self.0.task.register();
+        if self.0.stopping.load(Ordering::SeqCst) {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Stops the servers of every exporter gracefully: the listeners are closed, and the requests in
+/// flight are served. Waits at most `timeout` for the connections to be closed.
+pub(crate) fn stop(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let running: Vec<_> = RUNNING.lock_or_recover().drain(..).collect();
+    for (stop, _) in &running {
+        stop.stopping.store(true, Ordering::SeqCst);
+        stop.task.notify();
+    }
+    for (_, stopped) in running {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match stopped.recv_timeout(remaining) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => {}
+            Err(RecvTimeoutError::Timeout) => warn!(
+                "The metrics exporter didn't stop within {:?}, leaving its connections open",
+                timeout
+            ),
+        }
+    }
+}
+
+/// Returns the address of a listener, and the stream of its connections.
+fn incoming(listener: TcpListener) -> io::Result<(SocketAddr, tcp::Incoming)> {
+    let local_addr = listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+    #[cfg(unix)]
+    set_cloexec(&listener)?;
+    let listener = tcp::TcpListener::from_std(listener, &Handle::default())?;
+    Ok((local_addr, listener.incoming()))
+}
+
+/// Keeps a listener from leaking into the processes spawned by the application, as it may come
+/// from a process manager without `FD_CLOEXEC`, unlike the sockets opened by `std`.
+#[cfg(unix)]
+fn set_cloexec(listener: &TcpListener) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = listener.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn serve(config: Config, listeners: Vec<Listen>) -> Exporter {
+    lazy_static::initialize(&STARTED);
+    crate::init_state::mark_initialized();
+    crate::duplicates::check_at_init();
+    for (label, path) in &config.label_files {
+        if let Err(err) = crate::label_from_file(label, path) {
+            warn!("{}", err);
+        }
+    }
+    if let Some((path, threshold, max_size)) = &config.slow_log {
+        crate::slow_log::init(path, *threshold, *max_size);
+    }
+    if let Some(old) = &config.alias_prefix {
+        crate::alias::set_alias_prefix(old);
+    }
+    for (old, new) in &config.alias_ctxs {
+        crate::alias::add_alias_ctx(old, new);
+    }
+    #[cfg(feature = "relabel")]
+    {
+        if !config.relabel.is_empty() {
+            crate::relabel::set_relabel_rules(config.relabel.clone());
+        }
+    }
+    #[cfg(all(unix, feature = "flight-recorder"))]
+    {
+        if let Some((path, capacity)) = &config.flight_recorder {
+            if let Err(err) = crate::flight_recorder::init(path, *capacity) {
+                warn!("Unable to open the flight recorder {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    let report = config.validate();
+    if let Err(err) = &report {
+        warn!("Exporting metrics with an invalid configuration: {}", err);
+    }
+
+    let mut rt = tokio::runtime::Builder::new()
+        .core_threads(1) // one thread is sufficient
+        .build()
+        .expect("Unable to build metrics exporter tokio runtime");
+
+    let mut exporter = Exporter {
+        addrs: vec![],
+        errors: vec![],
+    };
+    let stop = Arc::new(Stop::default());
+    for listen in listeners {
+        let (addr, listener) = match listen {
+            Listen::Addr(addr) => {
+                let listener = addr
+                    .parse::<SocketAddr>()
+                    .map_err(|err| err.to_string())
+                    .and_then(|parsed| TcpListener::bind(parsed).map_err(|err| err.to_string()));
+                (addr, listener)
+            }
+            Listen::Listener(listener) => {
+                let addr = listener.local_addr().map_or_else(
+                    |_| "the passed listener".to_string(),
+                    |addr| addr.to_string(),
+                );
+                (addr, Ok(listener))
+            }
+        };
+        let incoming =
+            listener.and_then(|listener| incoming(listener).map_err(|err| err.to_string()));
+        let (local_addr, incoming) = match incoming {
+            Ok(incoming) => incoming,
+            Err(message) => {
+                let err = BindError { addr, message };
+                error!("{}", err);
+                exporter.errors.push(err);
+                continue;
+            }
+        };
+
+        let config = config.clone();
+        let incoming = LimitedIncoming::new(incoming, config.limits);
+        let server = Server::builder(incoming)
+            .http1_keepalive(config.keep_alive)
+            .http1_only(!config.http2)
+            .serve(move || {
+                let config = config.clone();
+                // This is the `Service` that will handle the connection.
+                // `service_fn_ok` is a helper to convert a function that
+                // returns a Response into a `Service`.
+                service_fn_ok(move |req: Request<Body>| handle(&req, &config))
+            });
+        if let Ok(report) = &report {
+            let mut report = report.clone();
+            report.addr = Some(local_addr.to_string());
+            info!("Exporting metrics at {}", report);
+        }
+        exporter.addrs.push(local_addr);
+        let server = server.with_graceful_shutdown(Stopping(stop.clone()));
+        rt.spawn(server.map_err(|e| error!("server error: {}", e)));
+    }
+
+    let (stopped, on_stopped) = mpsc::channel();
+    RUNNING.lock_or_recover().push((stop, on_stopped));
+    std::thread::spawn(move || {
+        rt.shutdown_on_idle().wait().unwrap();
+        let _ = stopped.send(());
+    });
+    exporter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle, json_string, selftest, status_json, tokens_eq, Config};
+    use hyper::http::StatusCode;
+    use hyper::{Body, Request};
+
+    fn request(method: &str, uri: &str, token: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder();
+        builder.method(method).uri(uri);
+        if let Some(token) = token {
+            builder.header("Authorization", format!("Bearer {}", token));
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    fn admin_config() -> Config {
+        Config::new("127.0.0.1:0")
+            .bearer_token("secret")
+            .admin(true)
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(tokens_eq("secret", "secret"));
+        assert!(!tokens_eq("secreT", "secret"));
+        assert!(!tokens_eq("secret", "secrets"));
+        assert!(!tokens_eq("", "secret"));
+    }
+
+    #[test]
+    fn admin_disabled_by_default() {
+        let config = Config::new("127.0.0.1:0");
+        let res = handle(&request("GET", "/admin/status", None), &config);
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn rejects_unauthorized() {
+        let config = admin_config();
+        for (method, uri) in &[
+            ("GET", "/metrics"),
+            ("GET", "/admin/status"),
+            ("POST", "/admin/reset"),
+            ("POST", "/admin/instrumentation?enabled=false"),
+        ] {
+            let res = handle(&request(method, uri, None), &config);
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            let res = handle(&request(method, uri, Some("wrong")), &config);
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[test]
+    fn ctx_tokens_scrape_their_ctx() {
+        use hyper::rt::{Future, Stream};
+
+        let scrape = |config: &Config, token: &str| {
+            let res = handle(&request("GET", "/metrics", Some(token)), config);
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = res.into_body().concat2().wait().unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+        crate::inc_called_counter_for("billing_fn", "billing");
+        crate::inc_called_counter_for("search_fn", "search");
+        let config = Config::new("127.0.0.1:0")
+            .ctx_token("billing-secret", &["billing"])
+            .ctx_token("search-secret", &["search"]);
+
+        let billing = scrape(&config, "billing-secret");
+        assert!(billing.contains("ctx=\"billing\""));
+        assert!(!billing.contains("ctx=\"search\""));
+        assert!(billing.contains("metrics_series_total"));
+        let search = scrape(&config, "search-secret");
+        assert!(search.contains("ctx=\"search\""));
+        assert!(!search.contains("ctx=\"billing\""));
+        assert!(search.contains("metrics_series_total"));
+
+        let search = scrape(&config.clone().shared_series(false), "search-secret");
+        assert!(search.contains("ctx=\"search\""));
+        assert!(!search.contains("metrics_series_total"));
+
+        // Only the metrics endpoints, and not without a token.
+        let config = config.bearer_token("secret").admin(true);
+        let res = handle(
+            &request("GET", "/admin/status", Some("search-secret")),
+            &config,
+        );
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = handle(&request("GET", "/metrics", None), &config);
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let all = scrape(&config, "secret");
+        assert!(all.contains("ctx=\"billing\"") && all.contains("ctx=\"search\""));
+    }
+
+    #[test]
+    fn toggles_instrumentation() {
+        let config = admin_config();
+        let res = handle(
+            &request(
+                "POST",
+                "/admin/instrumentation?enabled=false",
+                Some("secret"),
+            ),
+            &config,
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!crate::is_enabled());
+        assert!(status_json(&config).starts_with("{\"enabled\":false,"));
+
+        let res = handle(
+            &request(
+                "POST",
+                "/admin/instrumentation?enabled=true",
+                Some("secret"),
+            ),
+            &config,
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(crate::is_enabled());
+
+        let res = handle(
+            &request(
+                "POST",
+                "/admin/instrumentation?enabled=maybe",
+                Some("secret"),
+            ),
+            &config,
+        );
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = handle(
+            &request(
+                "GET",
+                "/admin/instrumentation?enabled=false",
+                Some("secret"),
+            ),
+            &config,
+        );
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn reset_requires_flag() {
+        let res = handle(
+            &request("POST", "/admin/reset", Some("secret")),
+            &admin_config(),
+        );
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        crate::inc_called_counter_for("reset_requires_flag", "default");
+        let config = admin_config().admin_reset(true);
+        let res = handle(&request("POST", "/admin/reset", Some("secret")), &config);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(crate::FUNC_CALLED
+            .get_metric_with_label_values(&["func_call", "reset_requires_flag", "default"])
+            .map_or(true, |counter| counter.get() == 0));
+    }
+
+    #[test]
+    fn status() {
+        let res = handle(
+            &request("GET", "/admin/status", Some("secret")),
+            &admin_config(),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        let status = status_json(&admin_config());
+        assert!(status.contains("\"registry_size\":"));
+        assert!(status.contains("\"uptime_seconds\":"));
+        assert!(status.contains("\"config\":{\"prefix\":"));
+    }
+
+    #[test]
+    fn functions_endpoint() {
+        let res = handle(
+            &request("GET", "/functions", None),
+            &Config::new("127.0.0.1:0"),
+        );
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let config = Config::new("127.0.0.1:0").functions(true);
+        let res = handle(&request("GET", "/functions", None), &config);
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = handle(&request("POST", "/functions", None), &config);
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn selftest_endpoint() {
+        use hyper::rt::{Future, Stream};
+
+        let res = handle(
+            &request("GET", "/selftest", None),
+            &Config::new("127.0.0.1:0"),
+        );
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let config = Config::new("127.0.0.1:0").selftest(true);
+        let res = handle(&request("GET", "/selftest", None), &config);
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().concat2().wait().unwrap();
+        let report = String::from_utf8(body.to_vec()).unwrap();
+        assert!(report.starts_with("{\"ok\":true,\"metric\":"), "{}", report);
+        assert!(report.contains("selftest_total\""), "{}", report);
+        let res = handle(&request("POST", "/selftest", None), &config);
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn selftest_reports_failures() {
+        use crate::prometheus::proto::MetricFamily;
+
+        // A collector returning a family without any series can't be encoded.
+        let err = selftest(|| {
+            let mut families = crate::gather();
+            let mut invalid = MetricFamily::new();
+            invalid.set_name("invalid".to_string());
+            families.push(invalid);
+            families
+        })
+        .unwrap_err();
+        assert!(err.contains("can't be encoded"), "{}", err);
+
+        let err = selftest(|| {
+            crate::gather()
+                .into_iter()
+                .filter(|family| !family.get_name().ends_with("selftest_total"))
+                .collect()
+        })
+        .unwrap_err();
+        assert!(err.contains("missing from the encoded metrics"), "{}", err);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn metrics_json_endpoint() {
+        use hyper::rt::{Future, Stream};
+
+        let res = handle(
+            &request("GET", "/metrics.json", None),
+            &Config::new("127.0.0.1:0"),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["Content-Type"], "application/json");
+        let body = res.into_body().concat2().wait().unwrap();
+        let families: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(families
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|family| family["name"] == "metrics_series_total"));
+    }
+
+    #[cfg(feature = "debug-introspection")]
+    #[test]
+    fn debug_instrumented_endpoint() {
+        use crate::introspection::FunctionRecord;
+        use hyper::rt::{Future, Stream};
+
+        static RECORD: FunctionRecord = FunctionRecord {
+            name: "introspected",
+            ctx: "default",
+            module: "my_crate::jobs",
+            file: "src/jobs.rs",
+            line: 12,
+            flags: &["calls", "timer", "errors"],
+        };
+        crate::introspection::register(&RECORD);
+
+        let res = handle(
+            &request("GET", "/debug/instrumented", None),
+            &Config::new("127.0.0.1:0"),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["Content-Type"], "application/json");
+        let body = res.into_body().concat2().wait().unwrap();
+        let records: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let record = records
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|record| record["name"] == "introspected")
+            .unwrap();
+        assert_eq!(record["crate"], "my_crate");
+        assert_eq!(record["line"], 12);
+        assert_eq!(
+            record["flags"],
+            serde_json::json!(["calls", "timer", "errors"])
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn metrics_csv_endpoint() {
+        let res = handle(
+            &request("GET", "/metrics.csv", None),
+            &Config::new("127.0.0.1:0"),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()["Content-Type"],
+            "text/csv; charset=utf-8; header=present"
+        );
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+}
@@ -0,0 +1,131 @@
+//! The shortest and longest call of each function since the previous scrape.
+use crate::poison::RwLockExt;
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::MetricFamily;
+use crate::prometheus::GaugeVec;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Once, RwLock};
+use std::time::Duration;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REGISTER: Once = Once::new();
+
+lazy_static! {
+    static ref EXTREMES: RwLock<HashMap<(&'static str, &'static str), Arc<Extremes>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// The extreme durations, in nanoseconds, observed since the last snapshot.
+struct Extremes {
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Extremes {
+    fn new() -> Self {
+        Extremes {
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, nanos: u64) {
+        self.min.fetch_min(nanos, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the extremes observed since the last snapshot, if any, and resets them.
+    fn snapshot(&self) -> Option<(Duration, Duration)> {
+        let min = self.min.swap(u64::MAX, Ordering::Relaxed);
+        let max = self.max.swap(0, Ordering::Relaxed);
+        if min == u64::MAX {
+            None
+        } else {
+            Some((Duration::from_nanos(min), Duration::from_nanos(max)))
+        }
+    }
+}
+
+/// Enables or disables the `function_time_min_seconds` and `function_time_max_seconds` gauges,
+/// with the shortest and longest call of each function since the previous gather. Disabled by
+/// default, as it adds a couple of atomic operations to every call.
+///
+/// The extremes are reset by every gather, so they should only be gathered by a single scraper.
+pub fn set_duration_extremes(enabled: bool) {
+    if enabled {
+        REGISTER.call_once(|| crate::register_builtin(Box::new(ExtremesCollector::new())));
+    }
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn observe(name: &'static str, ctx: &'static str, elapsed: Duration) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let nanos = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos());
+    if let Some(extremes) = EXTREMES.read_or_recover().get(&(name, ctx)) {
+        extremes.observe(nanos);
+        return;
+    }
+    EXTREMES
+        .write_or_recover()
+        .entry((name, ctx))
+        .or_insert_with(|| Arc::new(Extremes::new()))
+        .observe(nanos);
+}
+
+struct ExtremesCollector {
+    descs: Vec<Desc>,
+    min: GaugeVec,
+    max: GaugeVec,
+}
+
+impl ExtremesCollector {
+    fn new() -> Self {
+        let min = GaugeVec::new(
+            crate::time_unit::gauge_opts(
+                "function_time_min",
+                "Shortest function call since the previous scrape",
+            ),
+            &["type", "name", "ctx"],
+        )
+        .unwrap();
+        let max = GaugeVec::new(
+            crate::time_unit::gauge_opts(
+                "function_time_max",
+                "Longest function call since the previous scrape",
+            ),
+            &["type", "name", "ctx"],
+        )
+        .unwrap();
+
+        let mut descs = Vec::new();
+        descs.extend(min.desc().into_iter().cloned());
+        descs.extend(max.desc().into_iter().cloned());
+
+        ExtremesCollector { descs, min, max }
+    }
+}
+
+impl Collector for ExtremesCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let unit = crate::time_unit();
+        self.min.reset();
+        self.max.reset();
+        for ((name, ctx), extremes) in EXTREMES.read_or_recover().iter() {
+            if let Some((min, max)) = extremes.snapshot() {
+                let labels = ["func_call", name, ctx];
+                self.min.with_label_values(&labels).set(unit.scale(min));
+                self.max.with_label_values(&labels).set(unit.scale(max));
+            }
+        }
+        let mut families = self.min.collect();
+        families.extend(self.max.collect());
+        families
+    }
+}
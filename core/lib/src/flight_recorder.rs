@@ -0,0 +1,308 @@
+//! A memory-mapped ring buffer of the last instrumented calls, which survives a crash of the
+//! process on disk, with the `flight-recorder` feature, on unix.
+//!
+//! Enabled with `Config::flight_recorder`, or `init`. Every completed call is written as a
+//! fixed-size record, with the ids of its name and ctx, its start time, duration and outcome, and
+//! the last `capacity` calls are kept. Records are written without locks: writers claim a slot by
+//! incrementing an atomic head index, and mark the record complete last, so that a record torn
+//! by a crash is skipped when reading. The names and ctx are written once, in a string table
+//! ahead of the records, on their first call.
+//!
+//! The file lives in the page cache of the kernel, so it outlives the process, but not a power
+//! loss. After a crash, `read_flight_record` decodes it, e.g. from a debugging tool:
+//!
+//! ```rust,no_run
+//! for event in instrumented::flight_recorder::read_flight_record("/var/run/app.flight").unwrap() {
+//!     println!("{} ctx={} {:?} {:?}", event.name, event.ctx, event.duration, event.outcome);
+//! }
+//! ```
+//!
+//! The file is only meant to be read on a host of the same architecture, by the same version of
+//! the crate.
+use crate::observer::{add_observer, Completion, Observer, Outcome};
+use crate::poison::{LockExt, RwLockExt};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use std::sync::{Mutex, Once, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 8] = b"IFLIGHT1";
+/// The magic, the capacity, the head index and the length of the string table, padded.
+const HEADER_SIZE: usize = 64;
+const CAPACITY_OFFSET: usize = 8;
+const HEAD_OFFSET: usize = 16;
+const STRINGS_LEN_OFFSET: usize = 24;
+/// The size of the string table, which holds the names and ctx as a length and UTF-8 bytes.
+const STRINGS_SIZE: usize = 64 * 1024;
+const RECORDS_OFFSET: usize = HEADER_SIZE + STRINGS_SIZE;
+/// The sequence number, start, duration, name and ctx ids, and outcome of a call, as words.
+const RECORD_WORDS: usize = 5;
+const RECORD_SIZE: usize = RECORD_WORDS * 8;
+/// The id of the names and ctx that didn't fit in the string table.
+const UNKNOWN_ID: u32 = u32::MAX;
+const UNKNOWN: &str = "<unknown>";
+
+static RECORDER: AtomicPtr<Recorder> = AtomicPtr::new(ptr::null_mut());
+static REGISTER: Once = Once::new();
+
+/// The outcome of a recorded call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlightOutcome {
+    Ok,
+    Err,
+}
+
+/// A call read back from a flight record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlightEvent {
+    /// The position of the call among all the calls recorded, from 1.
+    pub seq: u64,
+    pub name: String,
+    pub ctx: String,
+    /// When the call started, by the wall clock.
+    pub start: SystemTime,
+    pub duration: Duration,
+    pub outcome: FlightOutcome,
+}
+
+/// The mapping of the flight record file, never unmapped once open.
+struct Recorder {
+    base: *mut u8,
+    capacity: u64,
+    ids: RwLock<HashMap<&'static str, u32>>,
+    /// The length of the string table and the number of strings, for the writer of new strings.
+    strings: Mutex<(usize, u32)>,
+}
+
+// The mapping is only accessed through atomics, and the string table under `strings`.
+unsafe impl Send for Recorder {}
+unsafe impl Sync for Recorder {}
+
+impl Recorder {
+    fn word(&self, offset: usize) -> &AtomicU64 {
+        unsafe { &*(self.base.add(offset) as *const AtomicU64) }
+    }
+
+    /// The id of `value` in the string table, written on first use.
+    fn id(&self, value: &'static str) -> u32 {
+        if let Some(id) = self.ids.read_or_recover().get(value) {
+            return *id;
+        }
+        let mut strings = self.strings.lock_or_recover();
+        let mut ids = self.ids.write_or_recover();
+        if let Some(id) = ids.get(value) {
+            return *id;
+        }
+        let (len, count) = *strings;
+        let bytes = value.as_bytes();
+        let id = if len + 2 + bytes.len() <= STRINGS_SIZE && bytes.len() <= usize::from(u16::MAX)
+        {
+            unsafe {
+                let entry = self.base.add(HEADER_SIZE + len);
+                ptr::copy_nonoverlapping((bytes.len() as u16).to_le_bytes().as_ptr(), entry, 2);
+                ptr::copy_nonoverlapping(bytes.as_ptr(), entry.add(2), bytes.len());
+            }
+            *strings = (len + 2 + bytes.len(), count + 1);
+            // Published after the bytes, so that a reader never sees a partial string.
+            self.word(STRINGS_LEN_OFFSET)
+                .store(((len + 2 + bytes.len()) as u64).to_le(), Ordering::Release);
+            count
+        } else {
+            warn!(
+                "The string table of the flight recorder is full, {:?} is recorded as {}",
+                value, UNKNOWN
+            );
+            UNKNOWN_ID
+        };
+        ids.insert(value, id);
+        id
+    }
+
+    fn record(&self, completion: &Completion) {
+        let name = self.id(completion.name);
+        let ctx = self.id(completion.ctx);
+        let start = crate::clock::since_epoch(crate::clock::system_time() - completion.duration);
+        let err = match completion.outcome {
+            Outcome::Ok => 0,
+            Outcome::Err(_) => 1,
+        };
+
+        let slot = self.word(HEAD_OFFSET).fetch_add(1, Ordering::Relaxed);
+        let offset = RECORDS_OFFSET + (slot % self.capacity) as usize * RECORD_SIZE;
+        let words = [
+            start.as_nanos() as u64,
+            completion.duration.as_nanos() as u64,
+            u64::from(name) | u64::from(ctx) << 32,
+            err,
+        ];
+        // Invalidated first, and completed last, so that a torn record is skipped.
+        self.word(offset).store(0, Ordering::Relaxed);
+        for (i, word) in words.iter().enumerate() {
+            self.word(offset + (i + 1) * 8)
+                .store(word.to_le(), Ordering::Relaxed);
+        }
+        self.word(offset).store((slot + 1).to_le(), Ordering::Release);
+    }
+}
+
+struct FlightRecorder;
+
+impl Observer for FlightRecorder {
+    fn on_completion(&self, completion: &Completion) {
+        let recorder = RECORDER.load(Ordering::Acquire);
+        if !recorder.is_null() {
+            unsafe { &*recorder }.record(completion);
+        }
+    }
+}
+
+/// Creates the flight record file at `path`, replacing any previous one, and records the last
+/// `capacity` calls into it from now on. Only one file can be open per process.
+pub fn init<P: AsRef<Path>>(path: P, capacity: usize) -> io::Result<()> {
+    if !RECORDER.load(Ordering::Acquire).is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the flight recorder is already open",
+        ));
+    }
+    let len = capacity
+        .checked_mul(RECORD_SIZE)
+        .and_then(|records| records.checked_add(RECORDS_OFFSET))
+        .filter(|_| capacity > 0)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid flight recorder capacity {}", capacity),
+            )
+        })?;
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.set_len(len as u64)?;
+    let base = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            std::os::unix::io::AsRawFd::as_raw_fd(&file),
+            0,
+        )
+    };
+    if base == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    let base = base as *mut u8;
+    unsafe { ptr::copy_nonoverlapping(MAGIC.as_ptr(), base, MAGIC.len()) };
+
+    let recorder = Box::new(Recorder {
+        base,
+        capacity: capacity as u64,
+        ids: RwLock::new(HashMap::new()),
+        strings: Mutex::new((0, 0)),
+    });
+    recorder
+        .word(CAPACITY_OFFSET)
+        .store((capacity as u64).to_le(), Ordering::Relaxed);
+    let recorder = Box::into_raw(recorder);
+    if RECORDER
+        .compare_exchange(
+            ptr::null_mut(),
+            recorder,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_err()
+    {
+        unsafe {
+            drop(Box::from_raw(recorder));
+            libc::munmap(base as *mut libc::c_void, len);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the flight recorder is already open",
+        ));
+    }
+    REGISTER.call_once(|| add_observer(Box::new(FlightRecorder)));
+    Ok(())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads the calls kept in the flight record file at `path`, oldest first.
+pub fn read_flight_record<P: AsRef<Path>>(path: P) -> io::Result<Vec<FlightEvent>> {
+    let bytes = std::fs::read(path)?;
+    if bytes.len() < RECORDS_OFFSET || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a flight record"));
+    }
+    let capacity = read_u64(&bytes, CAPACITY_OFFSET);
+    let expected = (capacity as usize)
+        .checked_mul(RECORD_SIZE)
+        .and_then(|records| records.checked_add(RECORDS_OFFSET));
+    if capacity == 0 || expected != Some(bytes.len()) {
+        return Err(invalid("truncated flight record"));
+    }
+
+    let strings_len = read_u64(&bytes, STRINGS_LEN_OFFSET) as usize;
+    if strings_len > STRINGS_SIZE {
+        return Err(invalid("corrupted flight record string table"));
+    }
+    let table = &bytes[HEADER_SIZE..HEADER_SIZE + strings_len];
+    let mut strings = vec![];
+    let mut at = 0;
+    while at + 2 <= table.len() {
+        let len = usize::from(u16::from_le_bytes([table[at], table[at + 1]]));
+        let value = table
+            .get(at + 2..at + 2 + len)
+            .ok_or_else(|| invalid("corrupted flight record string table"))?;
+        strings.push(String::from_utf8_lossy(value).into_owned());
+        at += 2 + len;
+    }
+    let string = |id: u64| {
+        strings
+            .get(id as usize)
+            .map_or_else(|| UNKNOWN.to_string(), Clone::clone)
+    };
+
+    let head = read_u64(&bytes, HEAD_OFFSET);
+    let mut events = vec![];
+    for slot in 0..capacity {
+        let offset = RECORDS_OFFSET + slot as usize * RECORD_SIZE;
+        let seq = read_u64(&bytes, offset);
+        // Never written, torn, or overwritten by a later call that didn't complete.
+        if seq == 0 || (seq - 1) % capacity != slot || seq > head || head - seq >= capacity {
+            continue;
+        }
+        let ids = read_u64(&bytes, offset + 24);
+        events.push(FlightEvent {
+            seq,
+            name: string(ids & u64::from(u32::MAX)),
+            ctx: string(ids >> 32),
+            start: UNIX_EPOCH + Duration::from_nanos(read_u64(&bytes, offset + 8)),
+            duration: Duration::from_nanos(read_u64(&bytes, offset + 16)),
+            outcome: if read_u64(&bytes, offset + 32) == 0 {
+                FlightOutcome::Ok
+            } else {
+                FlightOutcome::Err
+            },
+        });
+    }
+    events.sort_by_key(|event| event.seq);
+    Ok(events)
+}
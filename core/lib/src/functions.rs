@@ -0,0 +1,134 @@
+use crate::poison::LockExt;
+use crate::prometheus::core::Collector;
+use crate::prometheus::proto::Metric;
+use crate::prometheus::IntGaugeVec;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref REGISTERED: Mutex<Vec<(&'static str, &'static str)>> = Mutex::new(Vec::new());
+    /// The first lines of the doc comments of the registered functions.
+    static ref HELP: Mutex<Vec<(&'static str, &'static str, &'static str)>> =
+        Mutex::new(Vec::new());
+    static ref FUNC_INFO: IntGaugeVec = {
+        let gauge_opts = crate::prometheus::Opts::new(
+            "function_info",
+            "Doc comment of an instrumented function, as the help label",
+        );
+        let gauge = IntGaugeVec::new(gauge_opts, &["type", "name", "ctx", "help"]).unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+}
+
+static EXPORT_INFO: AtomicBool = AtomicBool::new(false);
+
+/// Registers an instrumented function. Called by the generated code at program startup, so that
+/// functions are listed before they are first called.
+#[doc(hidden)]
+pub fn register_function(name: &'static str, ctx: &'static str) {
+    let mut registered = REGISTERED.lock_or_recover();
+    if !registered.contains(&(name, ctx)) {
+        registered.push((name, ctx));
+    }
+}
+
+/// Registers the first line of the doc comment of an instrumented function. Called by the
+/// generated code at program startup, along with `register_function`.
+#[doc(hidden)]
+pub fn register_function_help(name: &'static str, ctx: &'static str, help: &'static str) {
+    let mut registered = HELP.lock_or_recover();
+    if !registered.contains(&(name, ctx, help)) {
+        registered.push((name, ctx, help));
+        if EXPORT_INFO.load(Ordering::Acquire) {
+            export_help(name, ctx, help);
+        }
+    }
+}
+
+/// Exports the first line of the doc comment of every documented instrumented function as the
+/// `help` label of `function_info`, e.g. for the tooltips of dashboards:
+///
+/// ```text
+/// function_info{type="func_call",name="get_user",ctx="users",help="Fetches a user by id"} 1
+/// ```
+///
+/// Off by default, as the labels are long. The line is trimmed, without its final period, and
+/// truncated to 100 characters. Functions are registered at startup on Linux, macOS and Windows
+/// only, and functions without a doc comment aren't exported.
+pub fn set_function_info(enabled: bool) {
+    let registered = HELP.lock_or_recover();
+    EXPORT_INFO.store(enabled, Ordering::Release);
+    if enabled {
+        for (name, ctx, help) in registered.iter() {
+            export_help(name, ctx, help);
+        }
+    } else {
+        FUNC_INFO.reset();
+    }
+}
+
+fn export_help(name: &str, ctx: &str, help: &str) {
+    FUNC_INFO
+        .with_label_values(&["func_call", name, ctx, help])
+        .set(1);
+}
+
+/// An instrumented function, along with its current call and error counts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionStats {
+    pub name: String,
+    pub ctx: String,
+    pub calls: u64,
+    pub errors: u64,
+}
+
+pub(crate) fn label<'a>(metric: &'a Metric, name: &str) -> &'a str {
+    metric
+        .get_label()
+        .iter()
+        .find(|pair| pair.get_name() == name)
+        .map_or("", |pair| pair.get_value())
+}
+
+/// Lists the instrumented functions, sorted by name and ctx.
+///
+/// Functions are registered at startup on Linux, macOS and Windows. On other platforms, only the
+/// functions that have been called at least once are listed.
+pub fn list_functions() -> Vec<FunctionStats> {
+    let mut functions = BTreeMap::new();
+    for (name, ctx) in REGISTERED.lock_or_recover().iter() {
+        functions.insert((name.to_string(), ctx.to_string()), (0, 0));
+    }
+    for family in crate::FUNC_CALLED.collect() {
+        for metric in family.get_metric() {
+            let key = (
+                label(metric, "name").to_string(),
+                label(metric, "ctx").to_string(),
+            );
+            functions.entry(key).or_insert((0, 0)).0 += metric.get_counter().get_value() as u64;
+        }
+    }
+    for family in crate::FUNC_ERRORS.collect() {
+        for metric in family.get_metric() {
+            let key = (
+                label(metric, "name").to_string(),
+                label(metric, "ctx").to_string(),
+            );
+            functions.entry(key).or_insert((0, 0)).1 += metric.get_counter().get_value() as u64;
+        }
+    }
+
+    functions
+        .into_iter()
+        .map(|((name, ctx), (calls, errors))| FunctionStats {
+            name,
+            ctx,
+            calls,
+            errors,
+        })
+        .collect()
+}
@@ -0,0 +1,88 @@
+use crate::clock::Instant;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Wraps the body of an instrumented `async fn`.
+///
+/// The future notes the instant it was created (when the function was called) and the instant
+/// it was first polled, recording the difference as the poll delay. It also tracks the number of
+/// polls, and keeps the inflight gauge accurate when the future is dropped before completing.
+#[doc(hidden)]
+pub struct InstrumentedFuture<F> {
+    inner: F,
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    created: Instant,
+    started: bool,
+    finished: bool,
+}
+
+impl<F: Future> InstrumentedFuture<F> {
+    pub fn new(name: &'static str, ctx: &'static str, inner: F) -> Self {
+        Self::new_in_crate(name, ctx, "", inner)
+    }
+
+    /// Wraps the body of a function defined in the crate `krate`, for the `crate` label.
+    pub fn new_in_crate(
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        inner: F,
+    ) -> Self {
+        InstrumentedFuture {
+            inner,
+            name,
+            ctx,
+            krate,
+            created: Instant::now(),
+            started: false,
+            finished: false,
+        }
+    }
+}
+
+impl<F: Future> Future for InstrumentedFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        // Safety: `inner` is never moved out of `self`, not even in `Drop`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let enabled = crate::is_enabled();
+        if !this.started {
+            this.started = true;
+            if enabled {
+                let labels = ["func_call", this.name, this.ctx];
+                crate::crate_label::with_values(&labels, this.krate, |labels| {
+                    crate::FUNC_POLL_DELAY
+                        .with_label_values(labels)
+                        .observe(crate::time_unit().scale(this.created.elapsed()))
+                });
+            }
+            crate::inc_inflight_in_crate(this.name, this.ctx, this.krate);
+        }
+        if enabled {
+            let labels = ["func_call", this.name, this.ctx];
+            crate::crate_label::with_values(&labels, this.krate, |labels| {
+                crate::FUNC_POLLS.with_label_values(labels).inc()
+            });
+        }
+
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        let poll = inner.poll(cx);
+        if poll.is_ready() {
+            this.finished = true;
+            crate::dec_inflight_in_crate(this.name, this.ctx, this.krate);
+        }
+        poll
+    }
+}
+
+impl<F> Drop for InstrumentedFuture<F> {
+    fn drop(&mut self) {
+        if self.started && !self.finished {
+            crate::dec_inflight_in_crate(self.name, self.ctx, self.krate);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Callbacks run at scrape time, to refresh metrics right before they're gathered.
+use crate::poison::RwLockExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::RwLock;
+
+type Hook = Box<dyn Fn() + Send + Sync>;
+
+lazy_static! {
+    static ref HOOKS: RwLock<Vec<Hook>> = RwLock::new(Vec::new());
+}
+
+/// Registers a callback invoked every time the global registry is gathered, immediately before
+/// the metrics are collected, e.g. to update gauges that are costly to keep up to date.
+///
+/// Hooks are invoked in registration order, on the gathering thread. A hook that panics is logged
+/// and skipped for that gathering. Hooks can't register other hooks.
+pub fn on_gather(hook: Box<dyn Fn() + Send + Sync>) {
+    HOOKS.write_or_recover().push(hook);
+}
+
+pub(crate) fn run() {
+    for (index, hook) in HOOKS.read_or_recover().iter().enumerate() {
+        if panic::catch_unwind(AssertUnwindSafe(hook)).is_err() {
+            error!("Gather hook #{} panicked, skipping it", index);
+        }
+    }
+}
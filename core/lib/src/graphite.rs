@@ -0,0 +1,229 @@
+//! Flushes the metrics to a Graphite endpoint, in the plaintext protocol.
+//!
+//! Requires the `graphite` feature.
+use crate::prometheus::IntCounter;
+use crate::series::Series;
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The longest delay between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref DROPPED_FLUSHES: IntCounter = {
+        let counter = IntCounter::new(
+            "graphite_dropped_flushes_total",
+            "Number of flushes to Graphite dropped while disconnected",
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+/// Configuration of the Graphite exporter.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// let config = instrumented::graphite::GraphiteConfig::new("graphite.example.com:2003")
+///     .prefix("myapp")
+///     .interval(Duration::from_secs(10));
+/// instrumented::graphite::init_graphite(config);
+/// ```
+#[derive(Debug, Clone)]
+pub struct GraphiteConfig {
+    addr: String,
+    prefix: String,
+    interval: Duration,
+    tags: bool,
+}
+
+impl GraphiteConfig {
+    /// Creates a configuration flushing to the plaintext endpoint at `addr` every 60 seconds.
+    pub fn new(addr: &str) -> Self {
+        GraphiteConfig {
+            addr: addr.to_string(),
+            prefix: String::new(),
+            interval: Duration::from_secs(60),
+            tags: true,
+        }
+    }
+
+    /// Prefixes the path of every series with `prefix.` (no prefix by default).
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Sets the interval between flushes.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sends labels as Graphite tags (`name;label=value`, the default), or when disabled, as
+    /// dotted path components (`name.label.value`).
+    pub fn tags(mut self, enabled: bool) -> Self {
+        self.tags = enabled;
+        self
+    }
+}
+
+/// Starts a thread flushing the gathered metrics to Graphite.
+///
+/// While the endpoint can't be reached, reconnection is attempted with an exponential backoff,
+/// capped at a minute, and the flushes in between are dropped and counted in
+/// `graphite_dropped_flushes_total`.
+pub fn init_graphite(config: GraphiteConfig) {
+    lazy_static::initialize(&DROPPED_FLUSHES);
+    crate::init_state::mark_initialized();
+    info!(
+        "Flushing metrics to Graphite at {} every {:?}",
+        config.addr, config.interval
+    );
+
+    thread::Builder::new()
+        .name("instrumented-graphite".into())
+        .spawn(move || {
+            let mut stream: Option<TcpStream> = None;
+            let mut backoff = config.interval;
+            let mut next_attempt = Instant::now();
+            loop {
+                thread::sleep(config.interval);
+                if stream.is_none() && Instant::now() >= next_attempt {
+                    match TcpStream::connect(&config.addr) {
+                        Ok(connected) => {
+                            stream = Some(connected);
+                            backoff = config.interval;
+                        }
+                        Err(err) => {
+                            warn!("Unable to connect to Graphite at {}: {}", config.addr, err);
+                            next_attempt = Instant::now() + backoff;
+                            backoff = (backoff * 2).min(MAX_BACKOFF);
+                        }
+                    }
+                }
+                let connected = match stream.as_mut() {
+                    Some(connected) => connected,
+                    None => {
+                        DROPPED_FLUSHES.inc();
+                        continue;
+                    }
+                };
+                let timestamp = crate::clock::since_epoch(crate::clock::system_time()).as_secs();
+                let lines = format_lines(&config, &crate::gather(), timestamp);
+                if let Err(err) = connected.write_all(lines.as_bytes()) {
+                    warn!(
+                        "Lost the connection to Graphite at {}: {}",
+                        config.addr, err
+                    );
+                    stream = None;
+                    DROPPED_FLUSHES.inc();
+                }
+            }
+        })
+        .expect("spawning the Graphite thread");
+}
+
+/// Replaces the characters with a meaning in the plaintext protocol.
+fn sanitize(value: &str, dotted: bool) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ' ' | ';' | '~' | '=' => '_',
+            '.' if dotted => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn format_line(config: &GraphiteConfig, series: &Series, timestamp: u64) -> String {
+    let mut path = config.prefix.clone();
+    let name = series
+        .labels
+        .iter()
+        .find(|(label, _)| label == "__name__")
+        .map_or("", |(_, name)| name.as_str());
+    if !path.is_empty() {
+        path.push('.');
+    }
+    path.push_str(&sanitize(name, true));
+    for (label, value) in series.labels.iter().filter(|(l, _)| l != "__name__") {
+        if config.tags {
+            path.push_str(&format!(
+                ";{}={}",
+                sanitize(label, false),
+                sanitize(value, false)
+            ));
+        } else {
+            path.push_str(&format!(
+                ".{}.{}",
+                sanitize(label, true),
+                sanitize(value, true)
+            ));
+        }
+    }
+    format!("{} {} {}\n", path, series.value, timestamp)
+}
+
+fn format_lines(
+    config: &GraphiteConfig,
+    families: &[crate::prometheus::proto::MetricFamily],
+    timestamp: u64,
+) -> String {
+    crate::series::series(families)
+        .iter()
+        .map(|series| format_line(config, series, timestamp))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_lines, GraphiteConfig};
+    use crate::prometheus::core::Collector;
+    use crate::prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts};
+
+    #[test]
+    fn tagged_and_dotted_lines() {
+        let counter =
+            IntCounterVec::new(Opts::new("function_called_total", "help"), &["name", "ctx"])
+                .unwrap();
+        counter.with_label_values(&["my_func", "a.b c"]).inc_by(42);
+
+        let tagged = GraphiteConfig::new("localhost:2003").prefix("app");
+        assert_eq!(
+            format_lines(&tagged, &counter.collect(), 1_699_999_999),
+            "app.function_called_total;ctx=a.b_c;name=my_func 42 1699999999\n"
+        );
+
+        let dotted = GraphiteConfig::new("localhost:2003").tags(false);
+        assert_eq!(
+            format_lines(&dotted, &counter.collect(), 1_699_999_999),
+            "function_called_total.ctx.a_b_c.name.my_func 42 1699999999\n"
+        );
+    }
+
+    #[test]
+    fn histogram_lines() {
+        let histogram = HistogramVec::new(
+            HistogramOpts::new("function_time_seconds", "help").buckets(vec![0.5]),
+            &["name"],
+        )
+        .unwrap();
+        histogram.with_label_values(&["my_func"]).observe(0.25);
+
+        let config = GraphiteConfig::new("localhost:2003");
+        assert_eq!(
+            format_lines(&config, &histogram.collect(), 1),
+            "function_time_seconds_bucket;le=0.5;name=my_func 1 1\n\
+             function_time_seconds_bucket;le=+Inf;name=my_func 1 1\n\
+             function_time_seconds_sum;name=my_func 0.25 1\n\
+             function_time_seconds_count;name=my_func 1 1\n"
+        );
+    }
+}
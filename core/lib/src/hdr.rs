@@ -0,0 +1,167 @@
+//! High-resolution timings for functions running in microseconds or less, with the `hdr` option
+//! of `#[instrument]`.
+//!
+//! Requires the `hdr` feature. The durations of such functions are recorded in nanoseconds into
+//! an in-process [HDR histogram](https://docs.rs/hdrhistogram/) per function, instead of the
+//! buckets of `function_time_seconds`. At gather time, each function exports:
+//!
+//! * `function_time_hdr_seconds` - The p50, p90, p99 and p999 durations since the start, with a
+//!   `quantile` label
+//! * `function_time_hdr_count` - The number of calls recorded
+//!
+//! The histograms keep 3 significant digits, so the quantiles are within 0.1% of the recorded
+//! durations, from a nanosecond up to a minute. Longer calls are recorded as a minute. Each
+//! histogram takes about 216 KiB, allocated on the first call of its function, and its memory
+//! doesn't grow afterwards.
+//!
+//! ```rust,ignore
+//! #[instrument(INFO, hdr)]
+//! fn dot(a: &[f32; 8], b: &[f32; 8]) -> f32 {
+//!     a.iter().zip(b).map(|(a, b)| a * b).sum()
+//! }
+//! ```
+use crate::clock::Instant;
+use crate::poison::{LockExt, RwLockExt};
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::MetricFamily;
+use crate::prometheus::{GaugeVec, IntCounter, Opts};
+use hdrhistogram::Histogram;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// The longest duration tracked, in nanoseconds.
+const HIGHEST_NANOS: u64 = 60_000_000_000;
+const SIGNIFICANT_DIGITS: u8 = 3;
+const QUANTILES: [(f64, &str); 4] = [(0.5, "0.5"), (0.9, "0.9"), (0.99, "0.99"), (0.999, "0.999")];
+
+type Shared = Arc<Mutex<Histogram<u64>>>;
+
+lazy_static! {
+    static ref HISTOGRAMS: RwLock<HashMap<(&'static str, &'static str), Shared>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Records a duration measured elsewhere into the HDR histogram of a function, as if it was a
+/// call of a function with `hdr`.
+pub fn record(name: &'static str, ctx: &'static str, elapsed: Duration) {
+    let nanos = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos());
+    // The histogram can't record 0.
+    let nanos = nanos.max(1);
+    if let Some(histogram) = HISTOGRAMS.read_or_recover().get(&(name, ctx)) {
+        histogram.lock_or_recover().saturating_record(nanos);
+        return;
+    }
+    HISTOGRAMS
+        .write_or_recover()
+        .entry((name, ctx))
+        .or_insert_with(|| {
+            let histogram = Arc::new(Mutex::new(
+                Histogram::new_with_bounds(1, HIGHEST_NANOS, SIGNIFICANT_DIGITS).unwrap(),
+            ));
+            crate::register_builtin(Box::new(HdrCollector::new(name, ctx, histogram.clone())));
+            histogram
+        })
+        .lock_or_recover()
+        .saturating_record(nanos);
+}
+
+#[doc(hidden)]
+pub fn observe_duration_for(name: &'static str, ctx: &'static str, start: Instant) -> f64 {
+    let elapsed = start.elapsed();
+    if crate::is_enabled() {
+        record(name, ctx, elapsed);
+        crate::extremes::observe(name, ctx, elapsed);
+    }
+    crate::duration_to_seconds(elapsed)
+}
+
+/// Returns the durations at `quantiles` of the HDR histogram of a function, if it was called.
+pub(crate) fn quantiles(name: &str, ctx: &str, quantiles: &[f64]) -> Option<Vec<Duration>> {
+    let histograms = HISTOGRAMS.read_or_recover();
+    let histogram = histograms.get(&(name, ctx))?.lock_or_recover();
+    if histogram.is_empty() {
+        return None;
+    }
+    Some(
+        quantiles
+            .iter()
+            .map(|quantile| Duration::from_nanos(histogram.value_at_quantile(*quantile)))
+            .collect(),
+    )
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    for histogram in HISTOGRAMS.read_or_recover().values() {
+        histogram.lock_or_recover().reset();
+    }
+}
+
+/// Exports the quantiles of the histogram of a function, with its name and ctx as constant
+/// labels, so that the collectors of all the functions can be registered side by side.
+struct HdrCollector {
+    descs: Vec<Desc>,
+    histogram: Shared,
+    quantiles: GaugeVec,
+    count_opts: Opts,
+}
+
+impl HdrCollector {
+    fn new(name: &str, ctx: &str, histogram: Shared) -> Self {
+        let labels = |opts: Opts| {
+            opts.const_label("type", "func_call")
+                .const_label("name", name)
+                .const_label("ctx", ctx)
+        };
+        let quantiles = GaugeVec::new(
+            labels(crate::time_unit::gauge_opts(
+                "function_time_hdr",
+                "Quantiles of the function call times recorded in an HDR histogram",
+            )),
+            &["quantile"],
+        )
+        .unwrap();
+        let count_opts = labels(Opts::new(
+            "function_time_hdr_count",
+            "Number of function calls recorded in an HDR histogram",
+        ));
+        let count = IntCounter::with_opts(count_opts.clone()).unwrap();
+
+        let mut descs = Vec::new();
+        descs.extend(quantiles.desc().into_iter().cloned());
+        descs.extend(count.desc().into_iter().cloned());
+
+        HdrCollector {
+            descs,
+            histogram,
+            quantiles,
+            count_opts,
+        }
+    }
+}
+
+impl Collector for HdrCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let unit = crate::time_unit();
+        let histogram = self.histogram.lock_or_recover();
+        for (quantile, label) in &QUANTILES {
+            let nanos = histogram.value_at_quantile(*quantile);
+            self.quantiles
+                .with_label_values(&[label])
+                .set(unit.scale(Duration::from_nanos(nanos)));
+        }
+        // Counted afresh from the histogram, which may have been reset.
+        let count = IntCounter::with_opts(self.count_opts.clone()).unwrap();
+        count.inc_by(histogram.len() as i64);
+        drop(histogram);
+
+        let mut families = self.quantiles.collect();
+        families.extend(count.collect());
+        families
+    }
+}
@@ -0,0 +1,84 @@
+//! The errors of the initialization of the built-in metrics, which are logged and worked around
+//! instead of panicking at the first instrumented call.
+use crate::poison::LockExt;
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+
+/// An error of the initialization of the built-in metrics. The instrumented functions keep
+/// running: the metrics that couldn't be registered are still recorded, but aren't exported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitError {
+    /// `METRICS_PREFIX` isn't a valid metric name, or the registry rejected the prefix or the
+    /// default labels, and the metrics are exported without them.
+    InvalidConfig(String),
+    /// A built-in collector couldn't be registered, e.g. because a collector with the same name
+    /// was registered first, and its metrics aren't exported.
+    Registration {
+        /// The names of the metrics of the collector.
+        metrics: Vec<String>,
+        error: String,
+    },
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InitError::InvalidConfig(error) => write!(
+                f,
+                "the metrics prefix or default labels are invalid, and ignored: {}",
+                error
+            ),
+            InitError::Registration { metrics, error } => write!(
+                f,
+                "{} couldn't be registered, and won't be exported: {}",
+                metrics.join(", "),
+                error
+            ),
+        }
+    }
+}
+
+impl Error for InitError {}
+
+lazy_static! {
+    pub(crate) static ref ERRORS: Mutex<Vec<InitError>> = Mutex::new(Vec::new());
+}
+
+/// Returns the errors of the initialization of the built-in metrics so far, empty if all of them
+/// are exported. The built-in metrics are initialized on first use, so check after `init`, or
+/// after the first instrumented calls.
+pub fn health() -> Vec<InitError> {
+    ERRORS.lock_or_recover().clone()
+}
+
+pub(crate) fn record(error: InitError) {
+    error!("{}", error);
+    ERRORS.lock_or_recover().push(error);
+}
+
+/// Registers a collector with the registry being initialized, recording the error if it fails.
+pub(crate) fn register_with(
+    registry: &crate::prometheus::Registry,
+    collector: Box<dyn crate::prometheus::core::Collector>,
+) -> bool {
+    let metrics = collector_names(&*collector);
+    match registry.register(collector) {
+        Ok(()) => true,
+        Err(err) => {
+            record(InitError::Registration {
+                metrics,
+                error: err.to_string(),
+            });
+            false
+        }
+    }
+}
+
+pub(crate) fn collector_names(collector: &dyn crate::prometheus::core::Collector) -> Vec<String> {
+    collector
+        .desc()
+        .iter()
+        .map(|desc| desc.fq_name.clone())
+        .collect()
+}
@@ -0,0 +1,41 @@
+//! Whether the metrics are exported yet, to warn once about functions called before `init`.
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const UNINITIALIZED: u8 = 0;
+#[cfg(feature = "exporter")]
+const WARNED: u8 = 1;
+const INITIALIZED: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(UNINITIALIZED);
+
+/// Returns whether the metrics are exported, by `instrumented::init` or one of its variants, or
+/// by `init_graphite` or `init_remote_write`.
+pub fn is_initialized() -> bool {
+    STATE.load(Ordering::Relaxed) == INITIALIZED
+}
+
+#[cfg_attr(
+    not(any(feature = "exporter", feature = "graphite", feature = "remote-write")),
+    allow(dead_code)
+)]
+pub(crate) fn mark_initialized() {
+    STATE.store(INITIALIZED, Ordering::Relaxed);
+}
+
+/// Warns the first time an instrumented function is called before the metrics are exported.
+/// Afterwards, it's a single relaxed load.
+#[cfg(feature = "exporter")]
+pub(crate) fn check(name: &'static str) {
+    if STATE.load(Ordering::Relaxed) == UNINITIALIZED
+        && STATE
+            .compare_exchange(UNINITIALIZED, WARNED, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    {
+        warn!(
+            "{} was called before `instrumented::init`: its metrics are recorded, but they aren't \
+             served until `init` is called, and `METRICS_PREFIX` and `METRICS_LABELS` were already \
+             read",
+            name
+        );
+    }
+}
@@ -0,0 +1,3 @@
+//! Integrations with third party services, each behind a cargo feature of the same name.
+#[cfg(feature = "sentry")]
+pub mod sentry;
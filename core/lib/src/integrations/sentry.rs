@@ -0,0 +1,93 @@
+//! Reports errors returned by instrumented functions to Sentry as breadcrumbs.
+//!
+//! ```rust,ignore
+//! let _guard = sentry::init("https://key@sentry.io/42");
+//! instrumented::integrations::sentry::install(Default::default());
+//! ```
+use crate::observer::{self, Completion, Observer, Outcome};
+use crate::poison::LockExt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Options for the Sentry integration.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Only report errors of functions with one of these contexts; all errors are reported when
+    /// empty.
+    pub ctxs: Vec<&'static str>,
+    /// Maximum number of breadcrumbs recorded per second, further errors are dropped.
+    pub max_per_second: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            ctxs: Vec::new(),
+            max_per_second: 10,
+        }
+    }
+}
+
+struct SentryObserver {
+    options: Options,
+    /// The start of the current rate limiting window, and the breadcrumbs recorded in it.
+    window: Mutex<(Instant, u32)>,
+}
+
+impl SentryObserver {
+    fn allow(&self) -> bool {
+        let mut window = self.window.lock_or_recover();
+        let now = Instant::now();
+        if now.duration_since(window.0) >= Duration::from_secs(1) {
+            *window = (now, 0);
+        }
+        if window.1 < self.options.max_per_second {
+            window.1 += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Observer for SentryObserver {
+    fn on_completion(&self, completion: &Completion) {
+        let err = match completion.outcome {
+            Outcome::Err(err) => err,
+            Outcome::Ok => return,
+        };
+        if !self.options.ctxs.is_empty() && !self.options.ctxs.contains(&completion.ctx) {
+            return;
+        }
+        if !self.allow() {
+            return;
+        }
+
+        let mut breadcrumb = ::sentry::Breadcrumb {
+            ty: "error".into(),
+            category: Some("instrumented".into()),
+            level: ::sentry::Level::Error,
+            message: Some(format!("{}() => {}", completion.name, err)),
+            ..Default::default()
+        };
+        breadcrumb
+            .data
+            .insert("name".into(), completion.name.into());
+        breadcrumb.data.insert("ctx".into(), completion.ctx.into());
+        breadcrumb.data.insert(
+            "duration_seconds".into(),
+            completion.duration.as_secs_f64().into(),
+        );
+        breadcrumb.data.insert("err".into(), err.into());
+        ::sentry::add_breadcrumb(breadcrumb);
+    }
+}
+
+/// Installs a global observer recording a Sentry breadcrumb for every error returned by an
+/// instrumented function.
+pub fn install(options: Options) {
+    observer::add_observer(Box::new(SentryObserver {
+        options,
+        window: Mutex::new((Instant::now(), 0)),
+    }));
+}
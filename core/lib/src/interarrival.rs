@@ -0,0 +1,42 @@
+use crate::clock::Instant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+lazy_static! {
+    /// The reference point of the call timestamps stored by instrumented functions.
+    static ref EPOCH: Instant = Instant::now();
+}
+
+/// Observes the time since the previous call of a function into `function_interarrival_seconds`.
+///
+/// `last_call` holds the time of the previous call, in nanoseconds since a process epoch plus one,
+/// or 0 before the first call, which isn't observed.
+#[doc(hidden)]
+pub fn observe_interarrival_for(name: &'static str, ctx: &'static str, last_call: &AtomicU64) {
+    observe_interarrival_in_crate(name, ctx, "", last_call);
+}
+
+/// Observes the time since the previous call of a function defined in the crate `krate`, for the
+/// `crate` label.
+#[doc(hidden)]
+pub fn observe_interarrival_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    last_call: &AtomicU64,
+) {
+    if !crate::is_enabled() {
+        return;
+    }
+    let elapsed = EPOCH.elapsed();
+    let now = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos()) + 1;
+    let previous = last_call.swap(now, Ordering::Relaxed);
+    // Concurrent calls can store their timestamps out of order.
+    if previous != 0 && previous <= now {
+        crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            crate::FUNC_INTERARRIVAL
+                .with_label_values(labels)
+                .observe(crate::time_unit().scale(Duration::from_nanos(now - previous)))
+        });
+    }
+}
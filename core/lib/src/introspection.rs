@@ -0,0 +1,52 @@
+//! What `#[instrument]` generated for each function, to check the instrumentation of a release
+//! binary.
+//!
+//! Requires the `debug-introspection` feature, with which every instrumented function records a
+//! `FunctionRecord` at program startup, on Linux, macOS and Windows. The records are served as
+//! JSON at `GET /debug/instrumented`.
+use crate::poison::LockExt;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref RECORDS: Mutex<Vec<&'static FunctionRecord>> = Mutex::new(Vec::new());
+}
+
+/// An instrumented function, as expanded.
+#[derive(Debug, PartialEq)]
+pub struct FunctionRecord {
+    pub name: &'static str,
+    pub ctx: &'static str,
+    /// The module path of the function, starting with its crate.
+    pub module: &'static str,
+    pub file: &'static str,
+    pub line: u32,
+    /// What was generated: `calls` and `timer` for every function, `errors` when errors are
+    /// counted, and the names of the other enabled attributes, e.g. `apdex` or `cpu_time`.
+    pub flags: &'static [&'static str],
+}
+
+impl FunctionRecord {
+    /// The crate of the function.
+    pub fn crate_name(&self) -> &'static str {
+        self.module.split("::").next().unwrap_or(self.module)
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(&flag)
+    }
+}
+
+#[doc(hidden)]
+pub fn register(record: &'static FunctionRecord) {
+    let mut records = RECORDS.lock_or_recover();
+    if !records.iter().any(|known| std::ptr::eq(*known, record)) {
+        records.push(record);
+    }
+}
+
+/// Lists the records of the instrumented functions, sorted by name and ctx.
+pub fn records() -> Vec<&'static FunctionRecord> {
+    let mut records = RECORDS.lock_or_recover().clone();
+    records.sort_by_key(|record| (record.name, record.ctx));
+    records
+}
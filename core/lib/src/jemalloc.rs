@@ -0,0 +1,171 @@
+//! Exports jemalloc allocator statistics, read at gather time.
+//!
+//! Requires the `jemalloc-metrics` feature, and jemalloc to be the global allocator:
+//!
+//! ```rust,ignore
+//! #[global_allocator]
+//! static ALLOCATOR: jemallocator::Jemalloc = jemallocator::Jemalloc;
+//!
+//! instrumented::jemalloc::install().unwrap();
+//! ```
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::MetricFamily;
+use crate::prometheus::{Gauge, IntGauge, Opts};
+
+/// A collector of jemalloc statistics:
+///
+/// * `jemalloc_allocated_bytes` - Bytes allocated by the application
+/// * `jemalloc_active_bytes` - Bytes in active pages allocated by the application
+/// * `jemalloc_metadata_bytes` - Bytes dedicated to jemalloc metadata
+/// * `jemalloc_resident_bytes` - Bytes in physically resident data pages mapped by jemalloc
+/// * `jemalloc_mapped_bytes` - Bytes in active extents mapped by jemalloc
+/// * `jemalloc_retained_bytes` - Bytes in virtual memory mappings retained by jemalloc
+/// * `jemalloc_active_ratio` - Active over allocated bytes, the page level fragmentation
+/// * `jemalloc_resident_ratio` - Resident over allocated bytes, the overall overhead
+///
+/// Statistics that can't be read are left out of the gathered metrics.
+pub struct JemallocCollector {
+    descs: Vec<Desc>,
+    allocated: IntGauge,
+    active: IntGauge,
+    metadata: IntGauge,
+    resident: IntGauge,
+    mapped: IntGauge,
+    retained: IntGauge,
+    active_ratio: Gauge,
+    resident_ratio: Gauge,
+}
+
+impl JemallocCollector {
+    pub fn new() -> Self {
+        let int_gauge = |name: &str, help: &str| {
+            IntGauge::with_opts(Opts::new(name, help)).expect("valid jemalloc gauge")
+        };
+        let gauge = |name: &str, help: &str| {
+            Gauge::with_opts(Opts::new(name, help)).expect("valid jemalloc gauge")
+        };
+
+        let allocated = int_gauge(
+            "jemalloc_allocated_bytes",
+            "Total number of bytes allocated by the application",
+        );
+        let active = int_gauge(
+            "jemalloc_active_bytes",
+            "Total number of bytes in active pages allocated by the application",
+        );
+        let metadata = int_gauge(
+            "jemalloc_metadata_bytes",
+            "Total number of bytes dedicated to jemalloc metadata",
+        );
+        let resident = int_gauge(
+            "jemalloc_resident_bytes",
+            "Total number of bytes in physically resident data pages mapped by jemalloc",
+        );
+        let mapped = int_gauge(
+            "jemalloc_mapped_bytes",
+            "Total number of bytes in active extents mapped by jemalloc",
+        );
+        let retained = int_gauge(
+            "jemalloc_retained_bytes",
+            "Total number of bytes in virtual memory mappings retained by jemalloc",
+        );
+        let active_ratio = gauge(
+            "jemalloc_active_ratio",
+            "Ratio of active to allocated bytes",
+        );
+        let resident_ratio = gauge(
+            "jemalloc_resident_ratio",
+            "Ratio of resident to allocated bytes",
+        );
+
+        let mut descs = Vec::new();
+        for collector in &[
+            &allocated, &active, &metadata, &resident, &mapped, &retained,
+        ] {
+            descs.extend(collector.desc().into_iter().cloned());
+        }
+        for collector in &[&active_ratio, &resident_ratio] {
+            descs.extend(collector.desc().into_iter().cloned());
+        }
+
+        JemallocCollector {
+            descs,
+            allocated,
+            active,
+            metadata,
+            resident,
+            mapped,
+            retained,
+            active_ratio,
+            resident_ratio,
+        }
+    }
+}
+
+impl Default for JemallocCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads a statistic, logging failures.
+fn read(name: &str, stat: jemalloc_ctl::Result<usize>) -> Option<usize> {
+    stat.map_err(|err| warn!("Unable to read jemalloc {} statistic: {}", name, err))
+        .ok()
+}
+
+impl Collector for JemallocCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        // The statistics are cached by jemalloc, and only refreshed when the epoch advances.
+        if let Err(err) = jemalloc_ctl::epoch::advance() {
+            warn!("Unable to refresh jemalloc statistics: {}", err);
+            return Vec::new();
+        }
+
+        let mut families = Vec::new();
+        let mut collect = |gauge: &IntGauge, value: Option<usize>| {
+            if let Some(value) = value {
+                gauge.set(value as i64);
+                families.extend(gauge.collect());
+            }
+        };
+        let allocated = read("allocated", jemalloc_ctl::stats::allocated::read());
+        let active = read("active", jemalloc_ctl::stats::active::read());
+        let resident = read("resident", jemalloc_ctl::stats::resident::read());
+        collect(&self.allocated, allocated);
+        collect(&self.active, active);
+        collect(
+            &self.metadata,
+            read("metadata", jemalloc_ctl::stats::metadata::read()),
+        );
+        collect(&self.resident, resident);
+        collect(
+            &self.mapped,
+            read("mapped", jemalloc_ctl::stats::mapped::read()),
+        );
+        collect(
+            &self.retained,
+            read("retained", jemalloc_ctl::stats::retained::read()),
+        );
+
+        let mut ratio = |gauge: &Gauge, value: Option<usize>| {
+            if let (Some(value), Some(allocated)) = (value, allocated.filter(|a| *a > 0)) {
+                gauge.set(value as f64 / allocated as f64);
+                families.extend(gauge.collect());
+            }
+        };
+        ratio(&self.active_ratio, active);
+        ratio(&self.resident_ratio, resident);
+
+        families
+    }
+}
+
+/// Registers a `JemallocCollector` with the global registry.
+pub fn install() -> crate::prometheus::Result<()> {
+    crate::register(Box::new(JemallocCollector::new()))
+}
@@ -0,0 +1,155 @@
+//! JSON rendering of the metric families, for tooling without a Prometheus text parser.
+use crate::prometheus::proto::{MetricFamily, MetricType};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A metric family: its name, help, type and series.
+#[derive(Serialize)]
+struct Family<'a> {
+    name: &'a str,
+    help: &'a str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    series: Vec<Series<'a>>,
+}
+
+/// A series of a family. Counters, gauges and untyped metrics have a `value`, histograms have
+/// `buckets`, `count` and `sum`, and summaries have `quantiles`, `count` and `sum`.
+#[derive(Serialize)]
+struct Series<'a> {
+    labels: BTreeMap<&'a str, &'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<Number>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buckets: Option<Vec<Bucket>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quantiles: Option<Vec<Quantile>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sum: Option<Number>,
+}
+
+/// A cumulative histogram bucket. The implicit `+Inf` bucket is the series' `count`.
+#[derive(Serialize)]
+struct Bucket {
+    le: Number,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct Quantile {
+    quantile: Number,
+    value: Number,
+}
+
+/// A number, serialized as `null` when it isn't finite since JSON has no NaN or infinities.
+struct Number(f64);
+
+impl Serialize for Number {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.is_finite() {
+            serializer.serialize_f64(self.0)
+        } else {
+            serializer.serialize_none()
+        }
+    }
+}
+
+impl<'a> Series<'a> {
+    fn new(labels: BTreeMap<&'a str, &'a str>) -> Self {
+        Series {
+            labels,
+            value: None,
+            buckets: None,
+            quantiles: None,
+            count: None,
+            sum: None,
+        }
+    }
+}
+
+fn family(family: &MetricFamily) -> Family<'_> {
+    let kind = match family.get_field_type() {
+        MetricType::COUNTER => "counter",
+        MetricType::GAUGE => "gauge",
+        MetricType::HISTOGRAM => "histogram",
+        MetricType::SUMMARY => "summary",
+        MetricType::UNTYPED => "untyped",
+    };
+    let series = family
+        .get_metric()
+        .iter()
+        .map(|metric| {
+            let labels = metric
+                .get_label()
+                .iter()
+                .map(|l| (l.get_name(), l.get_value()))
+                .collect();
+            let mut series = Series::new(labels);
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    series.value = Some(Number(metric.get_counter().get_value()))
+                }
+                MetricType::GAUGE => series.value = Some(Number(metric.get_gauge().get_value())),
+                MetricType::UNTYPED => {
+                    series.value = Some(Number(metric.get_untyped().get_value()))
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    series.buckets = Some(
+                        histogram
+                            .get_bucket()
+                            .iter()
+                            .map(|bucket| Bucket {
+                                le: Number(bucket.get_upper_bound()),
+                                count: bucket.get_cumulative_count(),
+                            })
+                            .collect(),
+                    );
+                    series.count = Some(histogram.get_sample_count());
+                    series.sum = Some(Number(histogram.get_sample_sum()));
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    series.quantiles = Some(
+                        summary
+                            .get_quantile()
+                            .iter()
+                            .map(|quantile| Quantile {
+                                quantile: Number(quantile.get_quantile()),
+                                value: Number(quantile.get_value()),
+                            })
+                            .collect(),
+                    );
+                    series.count = Some(summary.get_sample_count());
+                    series.sum = Some(Number(summary.get_sample_sum()));
+                }
+            }
+            series
+        })
+        .collect();
+    Family {
+        name: family.get_name(),
+        help: family.get_help(),
+        kind,
+        series,
+    }
+}
+
+/// Renders the metric families as a JSON array.
+pub(crate) fn render(families: &[MetricFamily]) -> String {
+    let families: Vec<Family> = families.iter().map(family).collect();
+    serde_json::to_string(&families).expect("Error encoding metrics as JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Number;
+
+    #[test]
+    fn non_finite_numbers_are_null() {
+        let numbers = vec![Number(1.5), Number(f64::NAN), Number(f64::INFINITY)];
+        assert_eq!(serde_json::to_string(&numbers).unwrap(), "[1.5,null,null]");
+    }
+}
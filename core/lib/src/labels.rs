@@ -0,0 +1,115 @@
+//! Default labels of the metrics, from the `METRICS_LABELS` env var and from files, e.g. the
+//! values mounted into a container by the orchestrator.
+use crate::poison::LockExt;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+lazy_static! {
+    /// The label files added with `label_from_file`.
+    static ref LABEL_FILES: Mutex<Vec<(String, PathBuf)>> = Mutex::new(Vec::new());
+}
+
+/// Whether the default labels were read, which happens once, when the registry is created.
+static READ: AtomicBool = AtomicBool::new(false);
+
+/// Splits a comma separated list of `label=value` pairs. Entries without `=` are returned as
+/// errors.
+pub(crate) fn parse_pairs(value: &str) -> Vec<Result<(&str, &str), &str>> {
+    value
+        .split(',')
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(label), Some(value)) => Ok((label, value)),
+                _ => Err(pair),
+            }
+        })
+        .collect()
+}
+
+/// Reads the value of a label from a file, without the trailing newlines.
+pub(crate) fn read_label_file(path: &Path) -> io::Result<String> {
+    let value = fs::read_to_string(path)?;
+    Ok(value.trim_end_matches(&['\n', '\r'][..]).to_string())
+}
+
+/// Adds a default label read from a file, along with the ones of the `METRICS_LABELS_FILES` env
+/// var, a comma separated list of `label=path` pairs.
+///
+/// ```rust,no_run
+/// instrumented::label_from_file("node_pool", "/etc/podinfo/pool").unwrap();
+/// ```
+///
+/// The files are read once, when the registry is created, which happens on the first call of an
+/// instrumented function or registration of a metric. Adding a label afterwards returns an error.
+/// Unreadable files are skipped with a warning.
+pub fn label_from_file<P: AsRef<Path>>(label: &str, path: P) -> crate::prometheus::Result<()> {
+    let mut files = LABEL_FILES.lock_or_recover();
+    if READ.load(Ordering::SeqCst) {
+        return Err(crate::prometheus::Error::Msg(format!(
+            "the label {} can't be read from a file once the registry is created",
+            label
+        )));
+    }
+    files.push((label.to_string(), path.as_ref().to_path_buf()));
+    Ok(())
+}
+
+/// Returns the label files of the `METRICS_LABELS_FILES` env var, followed by the ones added with
+/// `label_from_file`. Malformed entries of the env var are returned as errors.
+pub(crate) fn label_files() -> Vec<Result<(String, PathBuf), String>> {
+    let mut files: Vec<_> = match std::env::var("METRICS_LABELS_FILES") {
+        Ok(value) => parse_pairs(&value)
+            .into_iter()
+            .map(|pair| {
+                pair.map(|(label, path)| (label.to_string(), PathBuf::from(path)))
+                    .map_err(str::to_string)
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    files.extend(LABEL_FILES.lock_or_recover().iter().cloned().map(Ok));
+    files
+}
+
+/// Reads the default labels of the registry.
+pub(crate) fn default_labels() -> Option<HashMap<String, String>> {
+    // Set under the lock, so that no label file is added once they are listed.
+    {
+        let _files = LABEL_FILES.lock_or_recover();
+        READ.store(true, Ordering::SeqCst);
+    }
+    let files = label_files();
+
+    let mut labels = std::env::var("METRICS_LABELS").ok().map(|value| {
+        parse_pairs(&value)
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|(label, value)| (label.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>()
+    });
+    for file in files {
+        match file {
+            Ok((label, path)) => match read_label_file(&path) {
+                Ok(value) => {
+                    labels.get_or_insert_with(HashMap::new).insert(label, value);
+                }
+                Err(err) => warn!(
+                    "Skipping the label {}, its file {} is unreadable: {}",
+                    label,
+                    path.display(),
+                    err
+                ),
+            },
+            Err(entry) => warn!(
+                "Skipping malformed METRICS_LABELS_FILES entry {:?}, expected label=path",
+                entry
+            ),
+        }
+    }
+    labels
+}
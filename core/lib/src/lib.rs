@@ -20,6 +20,30 @@
 //! METRICS_LABELS=app=myapp,env=prod,region=us
 //! ```
 //!
+//! Label values can also be read from files with `METRICS_LABELS_FILES`, a list of `label=path`
+//! pairs, or `instrumented::label_from_file`.
+//!
+//! Function timings are recorded in seconds by default. Set `METRICS_TIME_UNIT=milliseconds`, or
+//! call `instrumented::set_time_unit` before the first instrumented function is called, to record
+//! them into `_milliseconds` histograms instead.
+//!
+//! ## Without the HTTP server
+//!
+//! The HTTP server is behind the default `exporter` feature. With `default-features = false`,
+//! the crate only provides the macro, the registry, `register()` and `render_metrics()`, and
+//! builds for `wasm32-unknown-unknown`, where durations are recorded as zero.
+//!
+//! ```toml
+//! instrumented = { version = "0.1", default-features = false, features = ["backend-prometheus"] }
+//! ```
+//!
+//! ## With the `metrics` facade
+//!
+//! With the `backend-metrics` feature instead of the default `backend-prometheus`, the calls,
+//! errors, durations and inflight calls of instrumented functions are forwarded into the
+//! [`metrics`](https://docs.rs/metrics/) facade, under the same names and labels. See
+//! [`backend`](backend/index.html).
+//!
 //! ## Example
 //!
 //! ```rust
@@ -78,15 +102,201 @@
 //! ```
 #[macro_use]
 extern crate lazy_static;
+#[cfg(feature = "logging")]
 #[macro_use]
 extern crate log;
+
+// Without the `logging` feature, the warnings and errors of the crate are written to stderr, and
+// the other messages are dropped.
+#[cfg(not(feature = "logging"))]
+macro_rules! error {
+    ($($arg:tt)+) => {
+        eprintln!("instrumented: {}", format_args!($($arg)+))
+    };
+}
+#[cfg(not(feature = "logging"))]
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        eprintln!("instrumented: {}", format_args!($($arg)+))
+    };
+}
+#[cfg(not(feature = "logging"))]
+macro_rules! info {
+    ($($arg:tt)+) => {
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
+#[cfg(not(feature = "logging"))]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        info!($($arg)+)
+    };
+}
+#[cfg(feature = "exporter")]
 extern crate hyper;
 #[allow(unused_imports)]
 #[macro_use]
 extern crate instrumented_codegen;
 
 /// Codegen crate
-pub use instrumented_codegen::instrument;
+pub use instrumented_codegen::{
+    instrument, instrument_drop, instrument_mod, instrument_trait, InstrumentedErrorClass,
+};
+
+pub mod alias;
+pub mod alloc;
+#[doc(hidden)]
+pub mod auto_register;
+pub mod backend;
+pub mod build_flags;
+pub mod chaos;
+mod clock;
+mod cpu_time;
+mod crate_label;
+#[cfg(feature = "csv")]
+mod csv;
+mod custom;
+mod deadline;
+mod duplicates;
+mod error_class;
+mod error_detail;
+mod error_ratio;
+mod event_time;
+pub mod events;
+mod extremes;
+#[cfg(feature = "exporter")]
+mod exporter;
+#[cfg(all(unix, feature = "flight-recorder"))]
+pub mod flight_recorder;
+mod functions;
+mod future;
+mod gather_hooks;
+mod health;
+mod init_state;
+#[cfg(feature = "graphite")]
+pub mod graphite;
+#[cfg(feature = "hdr")]
+pub mod hdr;
+mod interarrival;
+pub mod integrations;
+#[cfg(feature = "debug-introspection")]
+pub mod introspection;
+#[cfg(feature = "jemalloc-metrics")]
+pub mod jemalloc;
+#[cfg(feature = "json")]
+mod json;
+mod labels;
+pub mod lifecycle;
+pub mod load_shed;
+#[cfg(feature = "exporter")]
+mod limits;
+mod local_metrics;
+mod location;
+#[cfg(feature = "ctx-log-level")]
+mod log_level;
+mod log_limit;
+pub mod native_histogram;
+pub mod naming;
+mod normalize;
+pub mod observer;
+pub mod percentiles;
+pub mod phase;
+mod poison;
+#[cfg(all(unix, not(target_os = "linux")))]
+mod process;
+mod protobuf;
+mod queue;
+pub mod rate_limit;
+#[cfg(feature = "rayon-metrics")]
+pub mod rayon;
+mod recovery;
+#[cfg(feature = "relabel")]
+pub mod relabel;
+pub mod retry;
+#[cfg(feature = "remote-write")]
+pub mod remote_write;
+#[cfg(any(feature = "remote-write", feature = "graphite", feature = "csv"))]
+mod series;
+pub mod series_ttl;
+#[cfg(feature = "exporter")]
+pub mod slow_log;
+mod shutdown;
+pub mod stats;
+mod stream;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(all(unix, feature = "systemd"))]
+mod systemd;
+pub mod thread;
+mod threads;
+mod time_unit;
+mod timed;
+mod timed_drop;
+mod validate;
+mod wrap;
+
+pub use crate::custom::{
+    counter, counter_vec, gauge, gauge_vec, histogram, histogram_vec, register_or_get, Managed,
+};
+pub use crate::cpu_time::{observe_cpu_time_for, thread_cpu_time};
+pub use crate::crate_label::{crate_label, set_crate_label};
+pub use crate::deadline::{CancelAtDeadline, DeadlineExceeded, DeadlineFuture};
+pub use crate::error_class::{error_class_labels, InstrumentedErrorClass};
+pub use crate::error_detail::inc_error_detail_for;
+pub use crate::error_ratio::error_ratio;
+pub use crate::event_time::{
+    event, EventError, MAX_EVENT_LABELS, MAX_EVENT_NAMES, MAX_EVENT_SERIES, MAX_EVENT_VALUE_LEN,
+};
+pub use crate::extremes::set_duration_extremes;
+#[doc(hidden)]
+pub use crate::clock::Instant;
+#[cfg(feature = "exporter")]
+pub use crate::exporter::{
+    init, init_multi, init_multi_with_config, init_with_config, init_with_config_and_listener,
+    init_with_listener, BindError, Config, Exporter,
+};
+#[cfg(all(unix, feature = "systemd"))]
+pub use crate::exporter::init_from_systemd;
+pub use crate::functions::{
+    list_functions, register_function, register_function_help, set_function_info, FunctionStats,
+};
+pub use crate::future::InstrumentedFuture;
+pub use crate::gather_hooks::on_gather;
+pub use crate::health::{health, InitError};
+pub use crate::init_state::is_initialized;
+pub use crate::interarrival::observe_interarrival_for;
+pub use crate::labels::label_from_file;
+pub use crate::lifecycle::{mark_ready, mark_stopping};
+pub use crate::local_metrics::{
+    flush_local_metrics, observe_duration_local_for, observe_duration_local_in_crate,
+    set_local_metrics_batch,
+};
+pub use crate::location::{register_location, set_location_prefix, Location};
+#[cfg(feature = "ctx-log-level")]
+#[doc(hidden)]
+pub use crate::log_level::CtxLogLevel;
+#[cfg(feature = "ctx-log-level")]
+pub use crate::log_level::{clear_ctx_log_level, set_ctx_log_level};
+#[cfg(feature = "logging")]
+#[doc(hidden)]
+pub use crate::log_limit::err_log_permit;
+pub use crate::log_limit::format_count;
+pub use crate::normalize::{
+    default_label_normalizer, reset_label_normalizer, set_label_normalizer, MAX_LABEL_VALUE_LEN,
+};
+pub use crate::phase::ffi_section;
+pub use crate::queue::{queue_depth, Stamp};
+pub use crate::recovery::observe_recovery_for;
+pub use crate::shutdown::shutdown;
+pub use crate::stream::{never_item_error, result_item_error, InstrumentedStream};
+pub use crate::threads::ThreadPoolMetrics;
+pub use crate::time_unit::{set_time_unit, time_unit, TimeUnit};
+pub use crate::timed::Timed;
+pub use crate::timed_drop::{timed_drop, DropTimer, TimedDrop};
+pub use crate::validate::{validate_config, ConfigError, ConfigReport};
+pub use crate::wrap::{wrap_fn, wrap_fn_mut, wrap_fn_once, Outcome};
 
 /// `rust-prometheus` crate
 pub mod prometheus {
@@ -95,10 +305,9 @@ pub mod prometheus {
     pub use self::prometheus::*;
 }
 
-use hyper::http::StatusCode;
-use hyper::rt::Future;
-use hyper::service::service_fn_ok;
-use hyper::{Body, Request, Response, Server};
+use crate::backend::{Backend, BACKEND};
+use crate::poison::LockExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 #[cfg(all(target_os = "linux"))]
 fn register_default_process_collector(
@@ -111,31 +320,70 @@ fn register_default_process_collector(
 }
 
 lazy_static! {
+    static ref METRICS_PREFIX: Option<String> = match std::env::var("METRICS_PREFIX") {
+        Ok(prefix) if !validate::is_metric_name(&prefix) => {
+            let err = validate::ConfigError::InvalidPrefix(prefix);
+            health::record(health::InitError::InvalidConfig(err.to_string()));
+            None
+        }
+        prefix => prefix.ok(),
+    };
+    static ref METRICS_LABELS: Option<std::collections::HashMap<String, String>> =
+        labels::default_labels();
     static ref INSTRUMENTED_REGISTRY: ::prometheus::Registry = {
-        use std::collections::HashMap;
+        let prefix = METRICS_PREFIX.clone();
+        let labels = METRICS_LABELS.clone();
 
-        let prefix =  match std::env::var("METRICS_PREFIX")  {
-            Ok(value) => Some(value.to_string()),
-            Err(_) => None,
+        let reg = match ::prometheus::Registry::new_custom(prefix, labels) {
+            Ok(reg) => reg,
+            Err(err) => {
+                health::record(health::InitError::InvalidConfig(err.to_string()));
+                ::prometheus::Registry::new()
+            }
         };
-        let labels =  match std::env::var("METRICS_LABELS")  {
-            Ok(value) => {
-                let mut labels = HashMap::new();
-                value.split(',').for_each(|s| {let v: Vec<&str> = s.splitn(2, '=').collect();
-                if v.len() ==2 {
-                    labels.insert(v[0].to_owned(), v[1].to_owned());
-                }});
-                Some(labels)
-            },
-            Err(_) => None,
-        };
-
-        #[allow(clippy::let_and_return)]
-        let reg = ::prometheus::Registry::new_custom(prefix, labels).unwrap();
 
         // Register a default process collector.
         #[cfg(all(target_os = "linux"))]
-        register_default_process_collector(&reg).unwrap();
+        {
+            match register_default_process_collector(&reg) {
+                Ok(()) => {
+                    REGISTERED_COLLECTORS.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(err) => health::record(health::InitError::Registration {
+                    metrics: vec!["process_*".to_string()],
+                    error: err.to_string(),
+                }),
+            }
+        }
+
+        // Other unix platforms get a lightweight fallback.
+        #[cfg(all(unix, not(target_os = "linux")))]
+        {
+            if health::register_with(&reg, Box::new(process::ProcessCollector::new())) {
+                REGISTERED_COLLECTORS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        if health::register_with(&reg, Box::new(threads::ThreadsCollector::new())) {
+            REGISTERED_COLLECTORS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if health::register_with(&reg, Box::new(error_ratio::ErrorRatioCollector::new())) {
+            REGISTERED_COLLECTORS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // A canary telling which copy of the crate is served, when several are linked.
+        let canary_opts = prometheus::Opts::new(
+            "instrumented_registry_info",
+            "Version of the instrumented crate serving this registry",
+        )
+        .const_label("version", env!("CARGO_PKG_VERSION"));
+        let canary = prometheus::IntGauge::with_opts(canary_opts).unwrap();
+        canary.set(1);
+        if health::register_with(&reg, Box::new(canary)) {
+            REGISTERED_COLLECTORS.fetch_add(1, Ordering::Relaxed);
+        }
+        duplicates::claim();
 
         reg
     };
@@ -144,10 +392,54 @@ lazy_static! {
             "function_called_total",
             "Number of times a function was called",
         );
-        let counter = prometheus::IntCounterVec::new(counter_opts, &["type","name","ctx"]).unwrap();
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_CALLED_BY_THREAD: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_called_by_thread_total",
+            "Number of times a function was called, by calling thread",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx","thread"]),
+        ).unwrap();
 
-        INSTRUMENTED_REGISTRY
-            .register(Box::new(counter.clone())).unwrap();
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_WORK_UNITS: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_work_units_total",
+            "Units of work processed by a function, as weighted by its calls",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_RESULT_CLASS: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_result_class_total",
+            "Number of values returned by a function, by class",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx","class"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
 
         counter
     };
@@ -156,120 +448,945 @@ lazy_static! {
             "function_error_total",
             "Number of times the result of a function was an error",
         );
-        let counter = prometheus::IntCounterVec::new(counter_opts, &["type","name","ctx","err"]).unwrap();
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx","err","injected"]),
+        ).unwrap();
 
-        INSTRUMENTED_REGISTRY
-            .register(Box::new(counter.clone())).unwrap();
+        register_builtin(Box::new(counter.clone()));
 
         counter
     };
     static ref FUNC_TIMER: prometheus::HistogramVec = {
-        let histogram_opts = prometheus::HistogramOpts::new(
-            "function_time_seconds",
+        let histogram_opts = time_unit::histogram_opts(
+            "function_time",
             "Histogram of function call times observed",
         );
-        let histogram = prometheus::HistogramVec::new(histogram_opts, &["type","name","ctx"]).unwrap();
+        let histogram = prometheus::HistogramVec::new(
+            histogram_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref FUNC_TIME_BY_SIZE_CLASS: prometheus::HistogramVec = {
+        let histogram_opts = time_unit::histogram_opts(
+            "function_time_by_size_class",
+            "Histogram of function call times observed, by the size class of the call",
+        );
+        let histogram = prometheus::HistogramVec::new(
+            histogram_opts,
+            &crate_label::label_names(&["type","name","ctx","size_class"]),
+        ).unwrap();
+
+        register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref FUNC_CPU_TIME: prometheus::HistogramVec = {
+        let histogram_opts = time_unit::histogram_opts(
+            "function_cpu",
+            "Histogram of thread CPU time consumed by function calls",
+        );
+        let histogram = prometheus::HistogramVec::new(
+            histogram_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref FUNC_INTERARRIVAL: prometheus::HistogramVec = {
+        let histogram_opts = time_unit::histogram_opts(
+            "function_interarrival",
+            "Histogram of the time between consecutive function calls",
+        );
+        let histogram = prometheus::HistogramVec::new(
+            histogram_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
 
-        INSTRUMENTED_REGISTRY
-            .register(Box::new(histogram.clone())).unwrap();
+        register_builtin(Box::new(histogram.clone()));
 
         histogram
     };
+    static ref FUNC_RECOVERY: prometheus::HistogramVec = {
+        let histogram_opts = time_unit::histogram_opts(
+            "function_recovery",
+            "Histogram of the time between the first error of a failure episode and the next success",
+        );
+        let histogram = prometheus::HistogramVec::new(
+            histogram_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref FUNC_LAST_CALLED: prometheus::IntGaugeVec = {
+        let gauge_opts = prometheus::Opts::new(
+            "function_last_called_timestamp_seconds",
+            "Unix time of the last call of the function",
+        );
+        let gauge = prometheus::IntGaugeVec::new(
+            gauge_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+    static ref FUNC_DEADLINE_EXCEEDED: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_deadline_exceeded_total",
+            "Number of function calls still running at their deadline",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_RATE_LIMITED: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_rate_limited_total",
+            "Number of function calls over the `max_rate` of the function, or the global one",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_LOAD_SHED: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_load_shed_total",
+            "Number of function calls rejected at the `max_inflight` of the function",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
     static ref FUNC_INFLIGHT: prometheus::IntGaugeVec = {
         let gauge_opts = prometheus::Opts::new(
             "function_calls_inflight_total",
             "Number of function calls currently in flight",
         );
-        let gauge = prometheus::IntGaugeVec::new(gauge_opts, &["type","name","ctx"]).unwrap();
+        let gauge = prometheus::IntGaugeVec::new(
+            gauge_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
 
-        INSTRUMENTED_REGISTRY
-            .register(Box::new(gauge.clone())).unwrap();
+        register_builtin(Box::new(gauge.clone()));
 
         gauge
     };
+    static ref FUNC_APDEX_SATISFIED: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_apdex_satisfied_total",
+            "Number of function calls completing within the apdex threshold",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_APDEX_TOLERATING: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_apdex_tolerating_total",
+            "Number of function calls completing within 4x the apdex threshold",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_APDEX_FRUSTRATED: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_apdex_frustrated_total",
+            "Number of function calls slower than 4x the apdex threshold, or returning an error",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_SLO_GOOD: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_slo_good_total",
+            "Number of function calls meeting their latency SLO, completing within the threshold without an error",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_SLO_TOTAL: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_slo_total",
+            "Number of function calls judged against their latency SLO",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_POLL_DELAY: prometheus::HistogramVec = {
+        let histogram_opts = time_unit::histogram_opts(
+            "function_poll_delay",
+            "Histogram of time between an async function being called and its future first being polled",
+        );
+        let histogram = prometheus::HistogramVec::new(
+            histogram_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref FUNC_POLLS: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_polls_total",
+            "Number of times the future of an async function was polled",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref FUNC_STREAM_FIRST_ITEM: prometheus::HistogramVec = {
+        let histogram_opts = time_unit::histogram_opts(
+            "function_stream_first_item",
+            "Histogram of time between a function being called and its stream yielding the first item",
+        );
+        let histogram = prometheus::HistogramVec::new(
+            histogram_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref FUNC_STREAM_DURATION: prometheus::HistogramVec = {
+        let histogram_opts = time_unit::histogram_opts(
+            "function_stream_duration",
+            "Histogram of time between a function being called and its stream completing",
+        );
+        let histogram = prometheus::HistogramVec::new(
+            histogram_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref FUNC_STREAM_ITEMS: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_stream_items_total",
+            "Number of items yielded by streams returned from a function",
+        );
+        let counter = prometheus::IntCounterVec::new(
+            counter_opts,
+            &crate_label::label_names(&["type","name","ctx"]),
+        ).unwrap();
+
+        register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static REGISTERED_COLLECTORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Enables or disables the recording of instrumented function metrics at runtime.
+///
+/// While disabled, instrumented functions aren't counted, timed or observed. The inflight gauge
+/// is still maintained, so that it stays accurate when instrumentation is enabled again.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether the recording of instrumented function metrics is enabled.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Resets all the built-in function metrics, except for the inflight gauge.
+#[cfg(feature = "exporter")]
+pub(crate) fn reset_builtin_metrics() {
+    FUNC_CALLED.reset();
+    FUNC_CALLED_BY_THREAD.reset();
+    FUNC_WORK_UNITS.reset();
+    FUNC_RESULT_CLASS.reset();
+    FUNC_ERRORS.reset();
+    FUNC_TIMER.reset();
+    FUNC_TIME_BY_SIZE_CLASS.reset();
+    FUNC_CPU_TIME.reset();
+    FUNC_INTERARRIVAL.reset();
+    FUNC_RECOVERY.reset();
+    FUNC_LAST_CALLED.reset();
+    FUNC_DEADLINE_EXCEEDED.reset();
+    FUNC_RATE_LIMITED.reset();
+    FUNC_LOAD_SHED.reset();
+    retry::reset();
+    series_ttl::reset();
+    #[cfg(feature = "sync")]
+    sync::reset();
+    #[cfg(feature = "rayon-metrics")]
+    rayon::reset();
+    error_ratio::reset();
+    error_detail::reset();
+    event_time::reset();
+    #[cfg(feature = "hdr")]
+    hdr::reset();
+    native_histogram::reset();
+    percentiles::reset();
+    phase::reset();
+    thread::reset();
+    #[cfg(feature = "alloc")]
+    alloc::reset_metrics();
+    FUNC_APDEX_SATISFIED.reset();
+    FUNC_APDEX_TOLERATING.reset();
+    FUNC_APDEX_FRUSTRATED.reset();
+    FUNC_SLO_GOOD.reset();
+    FUNC_SLO_TOTAL.reset();
+    FUNC_POLL_DELAY.reset();
+    FUNC_POLLS.reset();
+    FUNC_STREAM_FIRST_ITEM.reset();
+    FUNC_STREAM_DURATION.reset();
+    FUNC_STREAM_ITEMS.reset();
+}
+
+fn duration_to_seconds(d: std::time::Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9
+}
+
+/// Returns the context label of `ctx_from_module`: the last segment of a `module_path!()`, the
+/// name of the crate at its root. A `const fn`, so that it can be used in statics.
+#[doc(hidden)]
+pub const fn module_ctx(module_path: &'static str) -> &'static str {
+    let bytes = module_path.as_bytes();
+    let mut start = bytes.len();
+    while start > 0 && bytes[start - 1] != b':' {
+        start -= 1;
+    }
+    match std::str::from_utf8(bytes.split_at(start).1) {
+        Ok(segment) => segment,
+        Err(_) => module_path,
+    }
 }
 
 #[doc(hidden)]
 pub fn inc_called_counter_for(name: &'static str, ctx: &'static str) {
-    FUNC_CALLED
-        .with_label_values(&["func_call", name, ctx])
-        .inc();
+    inc_called_counter_in_crate(name, ctx, "");
+}
+
+/// Counts a call of a function defined in the crate `krate`, for the `crate` label.
+#[doc(hidden)]
+pub fn inc_called_counter_in_crate(name: &'static str, ctx: &'static str, krate: &'static str) {
+    if !is_enabled() {
+        return;
+    }
+    #[cfg(feature = "exporter")]
+    init_state::check(name);
+    BACKEND.record_call(name, ctx, krate);
+    error_ratio::record_call(name, ctx);
+}
+
+/// The longest thread label kept, in characters.
+const MAX_THREAD_LABEL: usize = 32;
+
+/// Makes a thread label from a thread name, which is user controlled: characters other than
+/// alphanumerics, `-`, `_` and `.` are replaced with `_`, and the label is truncated.
+fn thread_label(name: Option<&str>) -> String {
+    name.unwrap_or("unnamed")
+        .chars()
+        .take(MAX_THREAD_LABEL)
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Counts a call of a function by the name of the current thread.
+#[doc(hidden)]
+pub fn inc_thread_counter_for(name: &'static str, ctx: &'static str) {
+    inc_thread_counter_in_crate(name, ctx, "");
+}
+
+/// Counts a call of a function defined in the crate `krate` by the name of the current thread.
+#[doc(hidden)]
+pub fn inc_thread_counter_in_crate(name: &'static str, ctx: &'static str, krate: &'static str) {
+    if !is_enabled() {
+        return;
+    }
+    let thread = std::thread::current();
+    let labels = ["func_call", name, ctx, &thread_label(thread.name())];
+    crate_label::with_values(&labels, krate, |labels| {
+        FUNC_CALLED_BY_THREAD.with_label_values(labels).inc();
+        series_ttl::touch(series_ttl::Tracked::CallsByThread, labels);
+    });
+}
+
+/// Counts the units of work processed by a call of a function.
+#[doc(hidden)]
+pub fn inc_work_units_for(name: &'static str, ctx: &'static str, weight: u64) {
+    inc_work_units_in_crate(name, ctx, "", weight);
+}
+
+/// Counts the units of work processed by a call of a function defined in the crate `krate`.
+#[doc(hidden)]
+pub fn inc_work_units_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    weight: u64,
+) {
+    if !is_enabled() {
+        return;
+    }
+    crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+        FUNC_WORK_UNITS
+            .with_label_values(labels)
+            .inc_by(weight as i64)
+    });
+}
+
+/// Counts a value returned by a function under its class.
+#[doc(hidden)]
+pub fn inc_result_class_for(name: &'static str, ctx: &'static str, class: &'static str) {
+    inc_result_class_in_crate(name, ctx, "", class);
+}
+
+/// Counts a value returned by a function defined in the crate `krate` under its class.
+#[doc(hidden)]
+pub fn inc_result_class_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    class: &'static str,
+) {
+    if !is_enabled() {
+        return;
+    }
+    crate_label::with_values(&["func_call", name, ctx, class], krate, |labels| {
+        FUNC_RESULT_CLASS.with_label_values(labels).inc()
+    });
+}
+
+/// Sets the last called timestamp of a function to the current (wall clock) unix time.
+#[doc(hidden)]
+pub fn set_last_called_for(name: &'static str, ctx: &'static str) {
+    set_last_called_in_crate(name, ctx, "");
+}
+
+/// Sets the last called timestamp of a function defined in the crate `krate`.
+#[doc(hidden)]
+pub fn set_last_called_in_crate(name: &'static str, ctx: &'static str, krate: &'static str) {
+    if !is_enabled() {
+        return;
+    }
+    if let Some(now) = clock::unix_time() {
+        crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            FUNC_LAST_CALLED.with_label_values(labels).set(now as i64)
+        });
+    }
 }
 
 #[doc(hidden)]
 pub fn inc_error_counter_for(name: &'static str, ctx: &'static str, err: String) {
-    FUNC_ERRORS
-        .with_label_values(&["func_call", name, ctx, &err])
-        .inc();
+    record_error(name, ctx, "", err, false);
+}
+
+/// Counts an error of a function defined in the crate `krate`, for the `crate` label.
+#[doc(hidden)]
+pub fn inc_error_counter_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    err: String,
+) {
+    record_error(name, ctx, krate, err, false);
+}
+
+/// Counts an error, `injected` by `chaos` or not.
+fn record_error(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    err: String,
+    injected: bool,
+) {
+    if !is_enabled() {
+        return;
+    }
+    let err = normalize::normalize_label("err", err);
+    BACKEND.record_error(name, ctx, krate, err, injected);
+    error_ratio::record_error(name, ctx);
+}
+
+/// Extracts the variant name from the `Debug` representation of an error, e.g. `NotFound` from
+/// `NotFound`, `NotFound("path")` or `Error::NotFound { path: .. }`.
+pub(crate) fn err_variant(debug: &str) -> &str {
+    let end = debug
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(debug.len());
+    debug[..end].rsplit("::").next().unwrap_or("")
+}
+
+/// Strips the module path and generic arguments from a type name.
+pub(crate) fn short_type_name(type_name: &str) -> &str {
+    let end = type_name.find('<').unwrap_or(type_name.len());
+    type_name[..end].rsplit("::").next().unwrap_or("")
+}
+
+#[doc(hidden)]
+pub fn type_name_of<T>(_: &T) -> &'static str {
+    std::any::type_name::<T>()
+}
+
+#[doc(hidden)]
+pub fn is_ignored_err(debug: &str, type_name: &str, ignored: &[&str]) -> bool {
+    let variant = err_variant(debug);
+    let type_name = short_type_name(type_name);
+    ignored
+        .iter()
+        .any(|name| *name == variant || *name == type_name)
+}
+
+#[doc(hidden)]
+pub fn io_kind_label<E: std::error::Error + 'static>(err: &E) -> Option<String> {
+    let mut current: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(err) = current {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            return Some(format!("{:?}", io_err.kind()));
+        }
+        current = err.source();
+    }
+    None
 }
 
+/// Starts a timer for the function timing histogram. The timer always observes seconds, so
+/// `observe_duration_for` should be used instead when the time unit may be milliseconds.
 #[doc(hidden)]
 pub fn get_timer_for(name: &'static str, ctx: &'static str) -> prometheus::HistogramTimer {
-    FUNC_TIMER
-        .with_label_values(&["func_call", name, ctx])
-        .start_timer()
+    get_timer_in_crate(name, ctx, "")
+}
+
+/// Starts a timer for a function defined in the crate `krate`, for the `crate` label.
+#[doc(hidden)]
+pub fn get_timer_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+) -> prometheus::HistogramTimer {
+    crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+        FUNC_TIMER.with_label_values(labels).start_timer()
+    })
+}
+
+#[doc(hidden)]
+pub fn observe_duration_for(name: &'static str, ctx: &'static str, start: Instant) -> f64 {
+    observe_duration_in_crate(name, ctx, "", start)
+}
+
+/// Observes the duration of a call of a function defined in the crate `krate`, for the `crate`
+/// label.
+#[doc(hidden)]
+pub fn observe_duration_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    start: Instant,
+) -> f64 {
+    let elapsed = start.elapsed();
+    if is_enabled() {
+        native_histogram::record(
+            name,
+            ctx,
+            krate,
+            elapsed,
+            native_histogram::histogram_mode(),
+        );
+        extremes::observe(name, ctx, elapsed);
+    }
+    duration_to_seconds(elapsed)
+}
+
+#[doc(hidden)]
+pub fn observe_size_class_for(
+    name: &'static str,
+    ctx: &'static str,
+    size_class: &'static str,
+    elapsed: f64,
+) {
+    observe_size_class_in_crate(name, ctx, "", size_class, elapsed);
+}
+
+/// Observes the duration of a call of a function defined in the crate `krate` under its size
+/// class, for the `crate` label.
+#[doc(hidden)]
+pub fn observe_size_class_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    size_class: &'static str,
+    elapsed: f64,
+) {
+    if !is_enabled() {
+        return;
+    }
+    crate_label::with_values(&["func_call", name, ctx, size_class], krate, |labels| {
+        FUNC_TIME_BY_SIZE_CLASS
+            .with_label_values(labels)
+            .observe(time_unit().scale(std::time::Duration::from_secs_f64(elapsed)))
+    });
+}
+
+#[doc(hidden)]
+pub fn observe_apdex_for(
+    name: &'static str,
+    ctx: &'static str,
+    threshold: f64,
+    elapsed: f64,
+    is_err: bool,
+) {
+    observe_apdex_in_crate(name, ctx, "", threshold, elapsed, is_err);
+}
+
+/// Counts a call of a function defined in the crate `krate` in its Apdex bucket, for the `crate`
+/// label.
+#[doc(hidden)]
+pub fn observe_apdex_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    threshold: f64,
+    elapsed: f64,
+    is_err: bool,
+) {
+    if !is_enabled() {
+        return;
+    }
+    let counter = if is_err || elapsed > 4.0 * threshold {
+        &*FUNC_APDEX_FRUSTRATED
+    } else if elapsed > threshold {
+        &*FUNC_APDEX_TOLERATING
+    } else {
+        &*FUNC_APDEX_SATISFIED
+    };
+    crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+        counter.with_label_values(labels).inc()
+    });
+}
+
+#[doc(hidden)]
+pub fn observe_slo_for(
+    name: &'static str,
+    ctx: &'static str,
+    threshold: f64,
+    elapsed: f64,
+    is_err: bool,
+) {
+    observe_slo_in_crate(name, ctx, "", threshold, elapsed, is_err);
+}
+
+/// Counts a call of a function defined in the crate `krate` against its SLO, for the `crate`
+/// label.
+#[doc(hidden)]
+pub fn observe_slo_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    threshold: f64,
+    elapsed: f64,
+    is_err: bool,
+) {
+    if !is_enabled() {
+        return;
+    }
+    crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+        FUNC_SLO_TOTAL.with_label_values(labels).inc();
+        if !is_err && elapsed <= threshold {
+            FUNC_SLO_GOOD.with_label_values(labels).inc();
+        }
+    });
+}
+
+#[doc(hidden)]
+pub fn notify_observers(name: &'static str, ctx: &'static str, elapsed: f64, err: Option<&str>) {
+    if !is_enabled() {
+        return;
+    }
+    observer::notify(name, ctx, elapsed, err);
 }
 
 #[doc(hidden)]
 pub fn inc_inflight_for(name: &'static str, ctx: &'static str) {
-    FUNC_INFLIGHT
-        .with_label_values(&["func_call", name, ctx])
-        .inc();
+    BACKEND.inc_inflight(name, ctx, "");
+}
+
+#[doc(hidden)]
+pub fn inc_inflight_in_crate(name: &'static str, ctx: &'static str, krate: &'static str) {
+    BACKEND.inc_inflight(name, ctx, krate);
 }
 
 #[doc(hidden)]
 pub fn dec_inflight_for(name: &'static str, ctx: &'static str) {
-    FUNC_INFLIGHT
-        .with_label_values(&["func_call", name, ctx])
-        .dec();
-}
-
-/// Initializes the metrics context, and starts an HTTP server
-/// to serve metrics.
-pub fn init(addr: &str) {
-    let parsed_addr = addr.parse().unwrap();
-    let server = Server::bind(&parsed_addr)
-        .serve(|| {
-            // This is the `Service` that will handle the connection.
-            // `service_fn_ok` is a helper to convert a function that
-            // returns a Response into a `Service`.
-            service_fn_ok(move |req: Request<Body>| {
-                use crate::prometheus::*;
-                if req.uri().path() == "/metrics" {
-                    let metric_families = INSTRUMENTED_REGISTRY.gather();
-                    let mut buffer = vec![];
-                    let encoder = TextEncoder::new();
-                    encoder.encode(&metric_families, &mut buffer).unwrap();
-
-                    Response::builder()
-                        .status(StatusCode::OK)
-                        .header("Content-Type", encoder.format_type())
-                        .body(Body::from(buffer))
-                        .expect("Error constructing response")
-                } else {
-                    Response::builder()
-                        .status(StatusCode::NOT_FOUND)
-                        .body(Body::from("Not found."))
-                        .expect("Error constructing response")
-                }
-            })
+    BACKEND.dec_inflight(name, ctx, "");
+}
+
+#[doc(hidden)]
+pub fn dec_inflight_in_crate(name: &'static str, ctx: &'static str, krate: &'static str) {
+    BACKEND.dec_inflight(name, ctx, krate);
+}
+
+/// Gathers all metric families from the global registry.
+///
+/// The result also reports on the registry itself, with the `metrics_registered_collectors` and
+/// `metrics_series_total` gauges. The latter counts the samples of every other family, to catch
+/// cardinality growth early. The errors the instrumentation recovered from, such as poisoned
+/// locks, are counted in `instrumentation_internal_errors_total`.
+///
+/// The collectors declared with `register_collector!` are registered first, and the hooks
+/// registered with `on_gather` are run. The durations buffered by `local_metrics` functions on
+/// the calling thread are observed.
+pub fn gather() -> Vec<prometheus::proto::MetricFamily> {
+    flush_local_metrics();
+    auto_register::register_pending();
+    lifecycle::register();
+    gather_hooks::run();
+    series_ttl::evict();
+    #[cfg(feature = "logging")]
+    log_limit::flush_summaries();
+    let mut families = INSTRUMENTED_REGISTRY.gather();
+    native_histogram::add_native_series(&mut families);
+    alias::apply(&mut families);
+    let series = families.iter().map(series_count).sum::<usize>();
+    families.push(self_gauge(
+        "metrics_registered_collectors",
+        "Number of collectors registered with the instrumented registry",
+        REGISTERED_COLLECTORS.load(Ordering::Relaxed),
+    ));
+    families.push(self_gauge(
+        "metrics_series_total",
+        "Number of series exported by the instrumented registry, excluding its own gauges",
+        series,
+    ));
+    families.push(self_counter(
+        "instrumentation_internal_errors_total",
+        "Number of internal errors of the instrumentation, such as poisoned locks, recovered from \
+         rather than panicking",
+        poison::internal_errors(),
+    ));
+    families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+    families
+}
+
+/// Counts the samples a metric family is exported as.
+fn series_count(family: &prometheus::proto::MetricFamily) -> usize {
+    use crate::prometheus::proto::MetricType;
+
+    family
+        .get_metric()
+        .iter()
+        .map(|metric| match family.get_field_type() {
+            // Every bucket, plus `+Inf`, `_sum` and `_count`.
+            MetricType::HISTOGRAM => metric.get_histogram().get_bucket().len() + 3,
+            // Every quantile, plus `_sum` and `_count`.
+            MetricType::SUMMARY => metric.get_summary().get_quantile().len() + 2,
+            _ => 1,
         })
-        .map_err(|e| error!("server error: {}", e));
+        .sum()
+}
+
+/// Builds a gauge family about the registry itself, with the same prefix and labels as the
+/// registered metrics.
+fn self_gauge(name: &str, help: &str, value: usize) -> prometheus::proto::MetricFamily {
+    use crate::prometheus::core::Collector;
 
-    info!("Exporting metrics at http://{}/metrics", addr);
+    let gauge = prometheus::IntGauge::with_opts(self_opts(name, help)).unwrap();
+    gauge.set(value as i64);
+    gauge.collect().pop().unwrap()
+}
 
-    let mut rt = tokio::runtime::Builder::new()
-        .core_threads(1) // one thread is sufficient
-        .build()
-        .expect("Unable to build metrics exporter tokio runtime");
+/// Builds a counter family about the instrumentation itself, like `self_gauge`.
+fn self_counter(name: &str, help: &str, value: u64) -> prometheus::proto::MetricFamily {
+    use crate::prometheus::core::Collector;
 
-    std::thread::spawn(move || {
-        rt.spawn(server);
-        rt.shutdown_on_idle().wait().unwrap();
-    });
+    let counter = prometheus::IntCounter::with_opts(self_opts(name, help)).unwrap();
+    counter.inc_by(value as i64);
+    counter.collect().pop().unwrap()
+}
+
+fn self_opts(name: &str, help: &str) -> prometheus::Opts {
+    let name = match &*METRICS_PREFIX {
+        Some(prefix) => format!("{}_{}", prefix, name),
+        None => name.to_string(),
+    };
+    prometheus::Opts::new(name, help.to_string())
+        .const_labels(METRICS_LABELS.clone().unwrap_or_default())
+}
+
+/// Renders all metric families from the global registry in the delimited protobuf exposition
+/// format, the only one with the buckets of native histograms, see `instrumented::native_histogram`.
+pub fn render_protobuf() -> Vec<u8> {
+    let natives = native_histogram::snapshot();
+    protobuf::encode_exposition(&gather(), &natives)
+}
+
+/// Renders all metric families from the global registry in the Prometheus text format.
+pub fn render_metrics() -> String {
+    use crate::prometheus::Encoder;
+
+    let mut buffer = vec![];
+    prometheus::TextEncoder::new()
+        .encode(&gather(), &mut buffer)
+        .expect("Error encoding metrics");
+    String::from_utf8(buffer).expect("Metrics aren't valid UTF-8")
+}
+
+/// Renders all metric families from the global registry as a JSON array of families, each with
+/// its `name`, `help`, `type` and `series`. Non-finite values are rendered as `null`.
+#[cfg(feature = "json")]
+pub fn render_json() -> String {
+    json::render(&gather())
+}
+
+/// Renders all metric families from the global registry as CSV, with the columns
+/// `metric,name,ctx,labels,value` and one row per series. Histograms are flattened into a row per
+/// bucket, plus `_sum` and `_count` rows.
+#[cfg(feature = "csv")]
+pub fn render_csv() -> String {
+    csv::render(&gather())
 }
 
 /// Register a collector with the global registry.
-pub fn register(c: Box<dyn::prometheus::core::Collector>) -> ::prometheus::Result<()> {
-    INSTRUMENTED_REGISTRY.register(c)
+///
+/// The names of its metrics are checked against the naming conventions, as set by
+/// `instrumented::naming::set_naming_strictness`.
+pub fn register(c: Box<dyn ::prometheus::core::Collector>) -> ::prometheus::Result<()> {
+    if let Some(issues) = naming::check(&*c) {
+        return Err(::prometheus::Error::Msg(format!(
+            "metric names don't follow the naming conventions: {}",
+            issues
+        )));
+    }
+    register_unchecked(c)
+}
+
+fn register_unchecked(c: Box<dyn ::prometheus::core::Collector>) -> ::prometheus::Result<()> {
+    auto_register::register_pending();
+    INSTRUMENTED_REGISTRY.register(c)?;
+    REGISTERED_COLLECTORS.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Registers a built-in metric with the global registry, logging instead of panicking when its
+/// name is already taken, so that a collision doesn't take the instrumented functions down. The
+/// metric is still recorded, but not exported, and the error is kept for `instrumented::health`.
+pub(crate) fn register_builtin(c: Box<dyn ::prometheus::core::Collector>) {
+    let metrics = health::collector_names(&*c);
+    if let Err(err) = register_unchecked(c) {
+        let hint = if duplicates::other_copies().is_empty() {
+            "another collector was registered under its name"
+        } else {
+            "several copies of the instrumented crate are linked into this binary"
+        };
+        error!(
+            "A built-in metric couldn't be registered, and won't be exported ({}): {}",
+            hint, err
+        );
+        poison::count_internal_error();
+        health::ERRORS.lock_or_recover().push(health::InitError::Registration {
+            metrics,
+            error: err.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{err_variant, is_ignored_err, short_type_name, thread_label};
+
+    #[test]
+    fn error_variant() {
+        assert_eq!(err_variant("NotFound"), "NotFound");
+        assert_eq!(err_variant("NotFound(\"path\")"), "NotFound");
+        assert_eq!(err_variant("NotFound { path: \"a\" }"), "NotFound");
+        assert_eq!(err_variant("Error::NotFound"), "NotFound");
+    }
+
+    #[test]
+    fn type_name() {
+        assert_eq!(short_type_name("my_crate::errors::MyError"), "MyError");
+        assert_eq!(
+            short_type_name("my_crate::Wrapper<std::io::Error>"),
+            "Wrapper"
+        );
+    }
+
+    #[test]
+    fn ignored_err() {
+        let ignored = ["NotFound", "Timeout"];
+        assert!(is_ignored_err("NotFound(1)", "my_crate::Error", &ignored));
+        assert!(is_ignored_err("Expired", "my_crate::Timeout", &ignored));
+        assert!(!is_ignored_err("Denied", "my_crate::Error", &ignored));
+    }
+
+    #[test]
+    fn thread_labels() {
+        assert_eq!(thread_label(None), "unnamed");
+        assert_eq!(thread_label(Some("worker-1")), "worker-1");
+        assert_eq!(thread_label(Some("io pool/3")), "io_pool_3");
+        assert_eq!(thread_label(Some(&"x".repeat(100))), "x".repeat(32));
+    }
 }
@@ -0,0 +1,155 @@
+//! The lifecycle of the process: its startup, readiness and graceful shutdown.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! instrumented::init("127.0.0.1:5000");
+//! // Load the configuration, warm the caches...
+//! instrumented::mark_ready();
+//! // Serve until asked to stop.
+//! instrumented::mark_stopping();
+//! // Drain the connections...
+//! instrumented::shutdown(Duration::from_secs(5));
+//! ```
+//!
+//! * `process_ready` is 0 until `mark_ready`, 1 until `mark_stopping` or `shutdown`, then 0 again
+//! * `process_startup_seconds` is the time from the start of the process to the first
+//!   `mark_ready`
+//! * `process_shutdown_seconds` is the time from the first `mark_stopping` to `shutdown`, which
+//!   marks the process as stopping itself if it wasn't
+//!
+//! The start of the process is recorded before `main` on Linux, macOS and Windows, and on the first
+//! use of the crate elsewhere. The durations are gauges in seconds, as they're set once.
+//!
+//! With `#[instrument(INFO, main)]` on `fn main`, `shutdown` is called when `main` returns, after
+//! its call is recorded, so that its metrics are pushed with the `remote-write` feature. When
+//! `main` called `shutdown` itself, the metrics are pushed once more instead.
+use crate::clock::Instant;
+use crate::prometheus::{Gauge, IntGauge};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The timeout of the shutdown when an instrumented `main` returns.
+const MAIN_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether `mark_ready` was called.
+static READY: AtomicBool = AtomicBool::new(false);
+/// The time `mark_stopping` was first called at, in nanoseconds since the start, or 0.
+static STOPPING: AtomicU64 = AtomicU64::new(0);
+/// The time the clock was advanced by, for tests.
+#[cfg(any(test, feature = "test-hooks"))]
+static ADVANCED: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref START: Instant = Instant::now();
+    static ref PROCESS_READY: IntGauge = {
+        let gauge = IntGauge::new(
+            "process_ready",
+            "Whether the process is ready, between `mark_ready` and `mark_stopping`",
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+    static ref PROCESS_STARTUP: Gauge = {
+        let gauge = Gauge::new(
+            "process_startup_seconds",
+            "Time from the start of the process to `mark_ready`",
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+    static ref PROCESS_SHUTDOWN: Gauge = {
+        let gauge = Gauge::new(
+            "process_shutdown_seconds",
+            "Time from `mark_stopping` to the shutdown of the metrics",
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+}
+
+// Records the start of the process at program startup, by placing a constructor in the platform's
+// initializer section.
+#[used]
+#[cfg_attr(
+    any(target_os = "linux", target_os = "android", target_os = "freebsd"),
+    link_section = ".init_array"
+)]
+#[cfg_attr(any(target_os = "macos", target_os = "ios"), link_section = "__DATA,__mod_init_func")]
+#[cfg_attr(windows, link_section = ".CRT$XCU")]
+static RECORD_START: extern "C" fn() = {
+    extern "C" fn record_start() {
+        lazy_static::initialize(&START);
+    }
+    record_start
+};
+
+/// Marks the process as ready, e.g. once it's initialized and serving: sets `process_ready` to 1,
+/// and records the time since the start of the process in `process_startup_seconds`. Only the
+/// first call records the startup.
+pub fn mark_ready() {
+    if READY.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    PROCESS_STARTUP.set(crate::duration_to_seconds(now()));
+    if STOPPING.load(Ordering::SeqCst) == 0 {
+        PROCESS_READY.set(1);
+    }
+}
+
+/// Marks the process as stopping, e.g. when it's asked to terminate and starts draining: sets
+/// `process_ready` to 0, and starts the time of the shutdown, recorded by `shutdown`. Only the
+/// first call starts it.
+pub fn mark_stopping() {
+    PROCESS_READY.set(0);
+    let nanos = (now().as_nanos() as u64).max(1);
+    let _ = STOPPING.compare_exchange(0, nanos, Ordering::SeqCst, Ordering::SeqCst);
+}
+
+/// Records the time since `mark_stopping` in `process_shutdown_seconds`, before the final gather.
+pub(crate) fn stopped() {
+    let stopping = Duration::from_nanos(STOPPING.load(Ordering::SeqCst));
+    let elapsed = now().checked_sub(stopping).unwrap_or_default();
+    PROCESS_SHUTDOWN.set(crate::duration_to_seconds(elapsed));
+}
+
+/// Registers `process_ready`, so it's exported as 0 before `mark_ready`.
+pub(crate) fn register() {
+    lazy_static::initialize(&PROCESS_READY);
+}
+
+/// Shuts the metrics down when an instrumented `main` returns, see the `lifecycle` module.
+#[doc(hidden)]
+pub fn main_returned() {
+    if !crate::shutdown::is_shut_down() {
+        crate::shutdown(MAIN_SHUTDOWN_TIMEOUT);
+        return;
+    }
+    #[cfg(feature = "remote-write")]
+    crate::remote_write::final_push(&crate::gather(), MAIN_SHUTDOWN_TIMEOUT);
+}
+
+/// Advances the clock of the lifecycle, to test the recorded durations without waiting.
+#[cfg(any(test, feature = "test-hooks"))]
+#[doc(hidden)]
+pub fn advance_clock(by: Duration) {
+    ADVANCED.fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// The time since the start of the process.
+fn now() -> Duration {
+    #[cfg(any(test, feature = "test-hooks"))]
+    let advanced = Duration::from_nanos(ADVANCED.load(Ordering::Relaxed));
+    #[cfg(not(any(test, feature = "test-hooks")))]
+    let advanced = Duration::default();
+    START.elapsed() + advanced
+}
@@ -0,0 +1,262 @@
+//! Limits on the connections of the exporter, so that clients holding connections open (e.g.
+//! sending their headers byte by byte) can't exhaust the file descriptors of the process.
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::prelude::task::AtomicTask;
+use tokio::prelude::{Async, AsyncRead, AsyncWrite, Future, Poll, Stream};
+use tokio::timer::Delay;
+
+/// How long accepting connections is paused after an error, e.g. when out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The limits of the exporter connections.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    pub max_connections: usize,
+    pub header_read_timeout: Duration,
+    pub request_timeout: Duration,
+}
+
+/// The number of open connections, and the accept loop waiting for one to close.
+#[derive(Default)]
+struct Open {
+    count: AtomicUsize,
+    accept: AtomicTask,
+}
+
+/// Accepts connections while fewer than `max_connections` are open.
+pub(crate) struct LimitedIncoming<S> {
+    incoming: S,
+    limits: Limits,
+    open: Arc<Open>,
+    backoff: Option<Delay>,
+}
+
+impl<S> LimitedIncoming<S> {
+    pub fn new(incoming: S, limits: Limits) -> Self {
+        LimitedIncoming {
+            incoming,
+            limits,
+            open: Arc::new(Open::default()),
+            backoff: None,
+        }
+    }
+}
+
+impl<S> Stream for LimitedIncoming<S>
+where
+    S: Stream<Error = io::Error>,
+{
+    type Item = LimitedConnection<S::Item>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        if let Some(backoff) = &mut self.backoff {
+            match backoff.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                _ => self.backoff = None,
+            }
+        }
+        // Registered first, so that a connection closed after the check wakes the accept loop.
+        self.open.accept.register();
+        if self.open.count.load(Ordering::SeqCst) >= self.limits.max_connections {
+            return Ok(Async::NotReady);
+        }
+
+        match self.incoming.poll() {
+            Ok(Async::Ready(Some(io))) => {
+                self.open.count.fetch_add(1, Ordering::SeqCst);
+                Ok(Async::Ready(Some(LimitedConnection::new(
+                    io,
+                    self.limits,
+                    self.open.clone(),
+                ))))
+            }
+            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => {
+                warn!("Unable to accept a metrics connection: {}", err);
+                self.backoff = Some(Delay::new(Instant::now() + ACCEPT_BACKOFF));
+                self.poll()
+            }
+        }
+    }
+}
+
+/// A connection closed with a `TimedOut` error once a deadline elapses: the headers of a request
+/// must be read within `header_read_timeout` of the connection being accepted or the previous
+/// response being written, and a request must be served within `request_timeout` of its first
+/// byte.
+pub(crate) struct LimitedConnection<T> {
+    io: T,
+    limits: Limits,
+    open: Arc<Open>,
+    header_deadline: Delay,
+    request_deadline: Option<Delay>,
+}
+
+impl<T> LimitedConnection<T> {
+    fn new(io: T, limits: Limits, open: Arc<Open>) -> Self {
+        LimitedConnection {
+            io,
+            limits,
+            open,
+            header_deadline: Delay::new(Instant::now() + limits.header_read_timeout),
+            request_deadline: None,
+        }
+    }
+
+    fn check(deadline: &mut Delay) -> io::Result<()> {
+        match deadline.poll() {
+            Ok(Async::NotReady) => Ok(()),
+            // Without a timer, the deadline can't be enforced, so the connection is closed too.
+            Ok(Async::Ready(())) | Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "metrics connection timed out",
+            )),
+        }
+    }
+
+    fn check_request(&mut self) -> io::Result<()> {
+        match &mut self.request_deadline {
+            Some(deadline) => Self::check(deadline),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T> Drop for LimitedConnection<T> {
+    fn drop(&mut self) {
+        self.open.count.fetch_sub(1, Ordering::SeqCst);
+        self.open.accept.notify();
+    }
+}
+
+impl<T: Read> Read for LimitedConnection<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Self::check(&mut self.header_deadline)?;
+        self.check_request()?;
+        let read = self.io.read(buf)?;
+        if read > 0 && self.request_deadline.is_none() {
+            self.request_deadline = Some(Delay::new(Instant::now() + self.limits.request_timeout));
+        }
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for LimitedConnection<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_request()?;
+        let written = self.io.write(buf)?;
+        self.header_deadline
+            .reset(Instant::now() + self.limits.header_read_timeout);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.check_request()?;
+        self.io.flush()?;
+        self.request_deadline = None;
+        Ok(())
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for LimitedConnection<T> {}
+
+impl<T: AsyncWrite> AsyncWrite for LimitedConnection<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LimitedIncoming, Limits};
+    use std::io::{self, Read, Write};
+    use std::time::{Duration, Instant};
+    use tokio::prelude::{future, stream, Async, Stream};
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    /// A connection whose reads never complete, like a client that stopped sending.
+    struct Stalled;
+
+    impl Read for Stalled {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for Stalled {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const LIMITS: Limits = Limits {
+        max_connections: 2,
+        header_read_timeout: Duration::from_millis(50),
+        request_timeout: Duration::from_millis(500),
+    };
+
+    #[test]
+    fn accepts_up_to_max_connections() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            let mut incoming = LimitedIncoming::new(
+                stream::iter_ok::<_, io::Error>(vec![Stalled, Stalled, Stalled]),
+                LIMITS,
+            );
+            let first = incoming.poll().unwrap();
+            let second = incoming.poll().unwrap();
+            assert!(first.is_ready() && second.is_ready());
+            assert!(incoming.poll().unwrap().is_not_ready());
+
+            drop(first);
+            assert!(incoming.poll().unwrap().is_ready());
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn times_out_reading_headers() {
+        let mut rt = Runtime::new().unwrap();
+        let mut incoming =
+            LimitedIncoming::new(stream::iter_ok::<_, io::Error>(vec![Stalled]), LIMITS);
+        let mut connection = rt
+            .block_on(future::lazy(move || match incoming.poll() {
+                Ok(Async::Ready(Some(connection))) => Ok(connection),
+                _ => Err(()),
+            }))
+            .unwrap();
+        let mut buf = [0; 16];
+        let err = rt
+            .block_on(future::lazy(|| connection.read(&mut buf)))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        let timeout = Delay::new(Instant::now() + Duration::from_millis(60));
+        rt.block_on(timeout).unwrap();
+        let err = rt
+            .block_on(future::lazy(|| connection.read(&mut buf)))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // Writing a response gives the next request its own time.
+        rt.block_on(future::lazy(|| {
+            connection.write_all(b"HTTP/1.1 200 OK\r\n\r\n")
+        }))
+        .unwrap();
+        let err = rt
+            .block_on(future::lazy(|| connection.read(&mut buf)))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+}
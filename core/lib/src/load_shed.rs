@@ -0,0 +1,111 @@
+//! Inflight limits of instrumented functions, with the `max_inflight` option of `#[instrument]`.
+//!
+//! A function returning a `Result` with `max_inflight = N` runs at most `N` calls at once. The
+//! calls over the limit return the `overload_err` error immediately, without running the
+//! function, and are counted in `function_load_shed_total` rather than as calls:
+//!
+//! ```rust,ignore
+//! #[instrument(INFO, max_inflight = 64, overload_err = "ApiError::Overloaded")]
+//! fn lookup(key: &str) -> Result<Value, ApiError> {
+//!     ...
+//! }
+//! ```
+//!
+//! Each function has a counter of its running calls, separate from the inflight gauge, which is
+//! checked and incremented with a single compare-and-swap. The calls of async functions hold
+//! their slot from the call to the completion or drop of their future.
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// The running calls of a function, in a static of the function.
+#[doc(hidden)]
+pub struct InflightLimiter {
+    running: AtomicU32,
+}
+
+impl InflightLimiter {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        InflightLimiter {
+            running: AtomicU32::new(0),
+        }
+    }
+
+    /// Takes a slot if fewer than `max` calls are running.
+    fn acquire(&'static self, max: u32) -> Option<InflightPermit> {
+        let mut running = self.running.load(Ordering::Relaxed);
+        loop {
+            if running >= max {
+                return None;
+            }
+            match self.running.compare_exchange_weak(
+                running,
+                running + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(InflightPermit { limiter: self }),
+                Err(actual) => running = actual,
+            }
+        }
+    }
+}
+
+/// The slot of a running call, released when dropped.
+#[doc(hidden)]
+pub struct InflightPermit {
+    limiter: &'static InflightLimiter,
+}
+
+impl Drop for InflightPermit {
+    fn drop(&mut self) {
+        self.limiter.running.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Takes a slot from the limiter of a function. Returns `None`, and counts the call in
+/// `function_load_shed_total`, if `max` calls are already running.
+#[doc(hidden)]
+pub fn acquire_for(
+    name: &'static str,
+    ctx: &'static str,
+    limiter: &'static InflightLimiter,
+    max: u32,
+) -> Option<InflightPermit> {
+    acquire_in_crate(name, ctx, "", limiter, max)
+}
+
+/// Takes a slot for a call of a function defined in the crate `krate`, for the `crate` label.
+#[doc(hidden)]
+pub fn acquire_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    limiter: &'static InflightLimiter,
+    max: u32,
+) -> Option<InflightPermit> {
+    let permit = limiter.acquire(max);
+    if permit.is_none() && crate::is_enabled() {
+        crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            crate::FUNC_LOAD_SHED.with_label_values(labels).inc()
+        });
+    }
+    permit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InflightLimiter;
+    use std::sync::atomic::Ordering;
+
+    static LIMITER: InflightLimiter = InflightLimiter::new();
+
+    #[test]
+    fn releases_slots_when_dropped() {
+        let permits: Vec<_> = (0..5).filter_map(|_| LIMITER.acquire(3)).collect();
+        assert_eq!(permits.len(), 3);
+        assert!(LIMITER.acquire(3).is_none());
+        drop(permits);
+        assert_eq!(LIMITER.running.load(Ordering::Relaxed), 0);
+        assert!(LIMITER.acquire(3).is_some());
+    }
+}
@@ -0,0 +1,90 @@
+//! Thread-local buffers of the durations of functions instrumented with `local_metrics`, which
+//! are observed into the histogram in batches rather than on every call.
+use crate::backend::{Backend, BACKEND};
+use crate::clock::Instant;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The number of calls a thread buffers before observing their durations.
+static BATCH: AtomicUsize = AtomicUsize::new(64);
+
+/// The durations buffered by a thread, observed when the thread exits.
+#[derive(Default)]
+struct Buffer(Vec<(&'static str, &'static str, &'static str, Duration)>);
+
+impl Buffer {
+    /// Observes the buffered durations, looking the histogram of each function up once.
+    fn flush(&mut self) {
+        self.0
+            .sort_by_key(|&(name, ctx, krate, _)| (name, ctx, krate));
+        let mut batch = Vec::new();
+        let mut entries = self.0.drain(..).peekable();
+        while let Some((name, ctx, krate, elapsed)) = entries.next() {
+            batch.push(elapsed);
+            let last = entries
+                .peek()
+                .map_or(true, |&(next_name, next_ctx, next_krate, _)| {
+                    (next_name, next_ctx, next_krate) != (name, ctx, krate)
+                });
+            if last {
+                BACKEND.record_durations(name, ctx, krate, &batch);
+                batch.clear();
+            }
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+thread_local! {
+    static BUFFER: RefCell<Buffer> = RefCell::new(Buffer::default());
+}
+
+/// Sets the number of calls of `local_metrics` functions a thread buffers before observing their
+/// durations, 64 by default. A batch of 1 observes every call.
+pub fn set_local_metrics_batch(calls: usize) {
+    BATCH.store(calls.max(1), Ordering::Relaxed);
+}
+
+/// Observes the durations buffered by the current thread.
+///
+/// Buffers are also flushed when full, when the thread exits and when the thread gathers the
+/// metrics, so this is only needed before reading the histogram of a long-lived thread.
+pub fn flush_local_metrics() {
+    let _ = BUFFER.try_with(|buffer| buffer.borrow_mut().flush());
+}
+
+#[doc(hidden)]
+pub fn observe_duration_local_for(name: &'static str, ctx: &'static str, start: Instant) -> f64 {
+    observe_duration_local_in_crate(name, ctx, "", start)
+}
+
+#[doc(hidden)]
+pub fn observe_duration_local_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    start: Instant,
+) -> f64 {
+    let elapsed = start.elapsed();
+    if crate::is_enabled() {
+        crate::extremes::observe(name, ctx, elapsed);
+        let buffered = BUFFER.try_with(|buffer| {
+            let mut buffer = buffer.borrow_mut();
+            buffer.0.push((name, ctx, krate, elapsed));
+            if buffer.0.len() >= BATCH.load(Ordering::Relaxed) {
+                buffer.flush();
+            }
+        });
+        // The buffer of an exiting thread may already be gone.
+        if buffered.is_err() {
+            BACKEND.record_duration(name, ctx, krate, elapsed);
+        }
+    }
+    crate::duration_to_seconds(elapsed)
+}
@@ -0,0 +1,72 @@
+//! The source locations of the functions instrumented with the `location` attribute, to tell
+//! apart namesakes across modules and crates.
+use crate::poison::RwLockExt;
+use crate::prometheus::IntGaugeVec;
+use std::fmt;
+use std::sync::RwLock;
+
+lazy_static! {
+    static ref PREFIX: RwLock<Option<String>> =
+        RwLock::new(std::env::var("METRICS_LOCATION_PREFIX").ok());
+    static ref FUNC_LOCATION: IntGaugeVec = {
+        let gauge_opts = crate::prometheus::Opts::new(
+            "function_location_info",
+            "Source location of an instrumented function, as a label",
+        );
+        let gauge = IntGaugeVec::new(gauge_opts, &["type", "name", "ctx", "location"]).unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+}
+
+/// Where an instrumented function is defined, captured with `file!()` and `line!()` at expansion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    file: &'static str,
+    line: u32,
+}
+
+impl Location {
+    #[doc(hidden)]
+    pub const fn new(file: &'static str, line: u32) -> Self {
+        Location { file, line }
+    }
+
+    /// The path of the source file, as given by `file!()`.
+    pub fn file(&self) -> &'static str {
+        self.file
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+}
+
+/// Formats as `file:line`, without the prefix set with `set_location_prefix`.
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = PREFIX.read_or_recover();
+        let file = prefix
+            .as_ref()
+            .and_then(|prefix| self.file.strip_prefix(prefix.as_str()))
+            .unwrap_or(self.file);
+        write!(f, "{}:{}", file, self.line)
+    }
+}
+
+/// Strips `prefix` from the file paths of the locations in logs and labels, e.g. a workspace
+/// directory that differs between CI and local builds. Defaults to the value of the
+/// `METRICS_LOCATION_PREFIX` env var. Labels already exported keep the previous prefix.
+pub fn set_location_prefix(prefix: &str) {
+    *PREFIX.write_or_recover() = Some(prefix.to_string());
+}
+
+/// Exports the location of a function as the `location` label of `function_location_info`.
+#[doc(hidden)]
+pub fn register_location(name: &'static str, ctx: &'static str, location: &Location) {
+    FUNC_LOCATION
+        .with_label_values(&["func_call", name, ctx, &location.to_string()])
+        .set(1);
+}
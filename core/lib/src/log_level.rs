@@ -0,0 +1,68 @@
+//! Runtime overrides of the log level of instrumented functions, by context.
+//!
+//! Requires the `ctx-log-level` feature, with which the generated logging looks its level up at
+//! runtime instead of using the level of the attribute.
+use crate::poison::RwLockExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+lazy_static! {
+    static ref OVERRIDES: RwLock<HashMap<String, log::Level>> = RwLock::new(HashMap::new());
+}
+
+/// Incremented on every change of the overrides, to invalidate the cached levels.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Logs the calls of the functions with the context `ctx` at `level`, or at their own level when
+/// it's more severe, e.g. to raise the verbosity of a single context during an incident.
+pub fn set_ctx_log_level(ctx: &str, level: log::Level) {
+    OVERRIDES.write_or_recover().insert(ctx.to_string(), level);
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// Clears the log level override of the context `ctx`.
+pub fn clear_ctx_log_level(ctx: &str) {
+    OVERRIDES.write_or_recover().remove(ctx);
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// The log level of a logging call site, caching the override of its context until the overrides
+/// change, so that the lookup is lock-free.
+#[doc(hidden)]
+pub struct CtxLogLevel {
+    generation: AtomicUsize,
+    /// The overriding `log::Level` as a `usize`, or 0 without an override.
+    level: AtomicUsize,
+}
+
+impl CtxLogLevel {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        CtxLogLevel {
+            generation: AtomicUsize::new(0),
+            level: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn level(&self, ctx: &str, default: log::Level) -> log::Level {
+        let generation = GENERATION.load(Ordering::Acquire);
+        if self.generation.load(Ordering::Acquire) != generation {
+            let level = OVERRIDES
+                .read_or_recover()
+                .get(ctx)
+                .map_or(0, |level| *level as usize);
+            self.level.store(level, Ordering::Relaxed);
+            self.generation.store(generation, Ordering::Release);
+        }
+        match self.level.load(Ordering::Relaxed) {
+            1 => log::Level::Error,
+            2 => log::Level::Warn,
+            3 => log::Level::Info,
+            4 => log::Level::Debug,
+            5 => log::Level::Trace,
+            _ => return default,
+        }
+        .min(default)
+    }
+}
@@ -0,0 +1,126 @@
+//! The rate limit of the error logs of the functions with `log_rate_limit`, with the `logging`
+//! feature.
+#[cfg(feature = "logging")]
+use crate::clock::Instant;
+#[cfg(feature = "logging")]
+use crate::poison::LockExt;
+#[cfg(feature = "logging")]
+use std::collections::HashMap;
+#[cfg(feature = "logging")]
+use std::sync::Mutex;
+
+/// The period over which a function may log up to `limit` errors.
+#[cfg(feature = "logging")]
+const PERIOD_SECONDS: f64 = 10.0;
+
+#[cfg(feature = "logging")]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    suppressed: u64,
+    /// The level the errors of the function are logged at.
+    level: log::Level,
+}
+
+#[cfg(feature = "logging")]
+lazy_static! {
+    static ref BUCKETS: Mutex<HashMap<&'static str, Bucket>> = Mutex::new(HashMap::new());
+}
+
+/// Takes a token from the error log bucket of `name`, which holds up to `limit` tokens and is
+/// refilled at `limit` tokens per 10 seconds. Returns `None` when the log line should be
+/// suppressed, or the number of log lines suppressed since the last permitted one. The lines still
+/// suppressed when the metrics are gathered are summarized at `level` then.
+#[cfg(feature = "logging")]
+#[doc(hidden)]
+pub fn err_log_permit(name: &'static str, limit: u32, level: log::Level) -> Option<u64> {
+    let limit = f64::from(limit);
+    let now = Instant::now();
+    let mut buckets = BUCKETS.lock_or_recover();
+    let bucket = buckets.entry(name).or_insert_with(|| Bucket {
+        tokens: limit,
+        last_refill: now,
+        suppressed: 0,
+        level,
+    });
+
+    let refill =
+        crate::duration_to_seconds(now.duration_since(bucket.last_refill)) * limit / PERIOD_SECONDS;
+    bucket.tokens = (bucket.tokens + refill).min(limit);
+    bucket.last_refill = now;
+
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        let suppressed = bucket.suppressed;
+        bucket.suppressed = 0;
+        Some(suppressed)
+    } else {
+        bucket.suppressed += 1;
+        None
+    }
+}
+
+/// Logs the summary of the error log lines suppressed since the last permitted one, so that the
+/// end of a burst isn't only summarized by the next permitted error, if any.
+#[cfg(feature = "logging")]
+pub(crate) fn flush_summaries() {
+    let mut buckets = BUCKETS.lock_or_recover();
+    for (name, bucket) in buckets
+        .iter_mut()
+        .filter(|(_, bucket)| bucket.suppressed > 0)
+    {
+        log!(
+            bucket.level,
+            "{}() => suppressed {} similar errors",
+            name,
+            format_count(bucket.suppressed)
+        );
+        bucket.suppressed = 0;
+    }
+}
+
+/// Formats a count with thousands separators, e.g. `4,812`.
+#[doc(hidden)]
+pub fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_count;
+
+    #[cfg(feature = "logging")]
+    #[test]
+    fn permits_up_to_limit() {
+        use super::{err_log_permit, BUCKETS};
+        use log::Level;
+
+        assert_eq!(
+            err_log_permit("permits_up_to_limit", 2, Level::Error),
+            Some(0)
+        );
+        assert_eq!(
+            err_log_permit("permits_up_to_limit", 2, Level::Error),
+            Some(0)
+        );
+        assert_eq!(err_log_permit("permits_up_to_limit", 2, Level::Error), None);
+        assert_eq!(err_log_permit("permits_up_to_limit", 2, Level::Error), None);
+        assert_eq!(BUCKETS.lock().unwrap()["permits_up_to_limit"].suppressed, 2);
+    }
+
+    #[test]
+    fn count() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(4812), "4,812");
+        assert_eq!(format_count(1_234_567), "1,234,567");
+    }
+}
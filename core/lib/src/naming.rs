@@ -0,0 +1,291 @@
+//! Checks of metric names against the Prometheus naming conventions, when they are registered.
+//!
+//! Disabled by default. With `set_naming_strictness(NamingStrictness::Warn)`, the metrics
+//! registered with `instrumented::register`, `register_or_get` or the helpers such as
+//! `instrumented::counter` are checked, and their issues are logged; with `Deny`, they fail to
+//! register instead. The built-in metrics aren't checked.
+//!
+//! ```rust
+//! use instrumented::naming::{lint_name, NamingIssue};
+//! use instrumented::prometheus::proto::MetricType;
+//!
+//! assert_eq!(
+//!     lint_name("http_requests", MetricType::COUNTER, None),
+//!     vec![NamingIssue::CounterWithoutTotal]
+//! );
+//! assert!(lint_name("http_request_duration_seconds", MetricType::HISTOGRAM, Some("seconds")).is_empty());
+//! ```
+use crate::prometheus::proto::MetricType;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// What is done about the naming issues of the metrics being registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamingStrictness {
+    /// Names aren't checked, the default.
+    Off,
+    /// The issues are logged as warnings, and the metric is registered anyway.
+    Warn,
+    /// The metric isn't registered, and the registration fails with the issues.
+    Deny,
+}
+
+static STRICTNESS: AtomicU8 = AtomicU8::new(0);
+
+/// Sets what is done about the naming issues of the metrics registered from now on.
+pub fn set_naming_strictness(strictness: NamingStrictness) {
+    let strictness = match strictness {
+        NamingStrictness::Off => 0,
+        NamingStrictness::Warn => 1,
+        NamingStrictness::Deny => 2,
+    };
+    STRICTNESS.store(strictness, Ordering::Relaxed);
+}
+
+/// Returns what is done about the naming issues of the metrics being registered.
+pub fn naming_strictness() -> NamingStrictness {
+    match STRICTNESS.load(Ordering::Relaxed) {
+        0 => NamingStrictness::Off,
+        1 => NamingStrictness::Warn,
+        _ => NamingStrictness::Deny,
+    }
+}
+
+/// A departure from the Prometheus naming conventions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NamingIssue {
+    /// The name doesn't match `[a-zA-Z_:][a-zA-Z0-9_:]*`, and can't be scraped.
+    InvalidCharacters,
+    /// The name contains `:`, which is reserved for recording rules.
+    Colon,
+    /// The name of a counter doesn't end with `_total`.
+    CounterWithoutTotal,
+    /// The name of a metric other than a counter ends with `_total`.
+    TotalOnNonCounter,
+    /// The name ends with a suffix that histograms and summaries add to their series.
+    ReservedSuffix(&'static str),
+    /// The name ends with a unit other than the base unit, e.g. `milliseconds` for `seconds`.
+    NonBaseUnit {
+        unit: &'static str,
+        base: &'static str,
+    },
+    /// The name doesn't end with the declared unit, before `_total` for counters.
+    UnitMismatch(String),
+}
+
+impl fmt::Display for NamingIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NamingIssue::InvalidCharacters => {
+                f.write_str("only ASCII letters, digits, `_` and `:` are allowed, not first digits")
+            }
+            NamingIssue::Colon => f.write_str("`:` is reserved for recording rules"),
+            NamingIssue::CounterWithoutTotal => f.write_str("counters should end with `_total`"),
+            NamingIssue::TotalOnNonCounter => f.write_str("only counters should end with `_total`"),
+            NamingIssue::ReservedSuffix(suffix) => write!(
+                f,
+                "`_{}` is added to the series of histograms and summaries",
+                suffix
+            ),
+            NamingIssue::NonBaseUnit { unit, base } => {
+                write!(f, "use the base unit `{}` instead of `{}`", base, unit)
+            }
+            NamingIssue::UnitMismatch(unit) => write!(f, "should end with the unit `{}`", unit),
+        }
+    }
+}
+
+/// The units that aren't base units, and their base units.
+const NON_BASE_UNITS: &[(&str, &str)] = &[
+    ("milliseconds", "seconds"),
+    ("microseconds", "seconds"),
+    ("nanoseconds", "seconds"),
+    ("ms", "seconds"),
+    ("minutes", "seconds"),
+    ("hours", "seconds"),
+    ("days", "seconds"),
+    ("kilobytes", "bytes"),
+    ("megabytes", "bytes"),
+    ("gigabytes", "bytes"),
+    ("kb", "bytes"),
+    ("mb", "bytes"),
+    ("percent", "ratio"),
+];
+
+const RESERVED_SUFFIXES: &[&str] = &["count", "sum", "bucket"];
+
+/// Checks a metric name against the Prometheus naming conventions, for a metric of type `kind`
+/// with an optional declared unit, such as `seconds` or `bytes`.
+pub fn lint_name(name: &str, kind: MetricType, unit: Option<&str>) -> Vec<NamingIssue> {
+    let mut issues = Vec::new();
+    let valid = name
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+    if !valid {
+        issues.push(NamingIssue::InvalidCharacters);
+    } else if name.contains(':') {
+        issues.push(NamingIssue::Colon);
+    }
+
+    let is_counter = kind == MetricType::COUNTER;
+    let ends_with_total = name.ends_with("_total");
+    if is_counter && !ends_with_total {
+        issues.push(NamingIssue::CounterWithoutTotal);
+    } else if !is_counter && ends_with_total {
+        issues.push(NamingIssue::TotalOnNonCounter);
+    }
+
+    let stem = if ends_with_total {
+        &name[..name.len() - "_total".len()]
+    } else {
+        name
+    };
+    let last_segment = stem.rsplit('_').next().unwrap_or(stem);
+    if let Some(suffix) = RESERVED_SUFFIXES
+        .iter()
+        .find(|suffix| name.rsplit('_').next() == Some(suffix))
+    {
+        issues.push(NamingIssue::ReservedSuffix(suffix));
+    }
+    if let Some((unit, base)) = NON_BASE_UNITS
+        .iter()
+        .find(|(unit, _)| last_segment.eq_ignore_ascii_case(unit))
+    {
+        issues.push(NamingIssue::NonBaseUnit { unit, base });
+    }
+    if let Some(unit) = unit {
+        if !stem.ends_with(&format!("_{}", unit)) {
+            issues.push(NamingIssue::UnitMismatch(unit.to_string()));
+        }
+    }
+    issues
+}
+
+/// Checks the metrics of a collector being registered, as set by `set_naming_strictness`.
+/// Returns the issues to fail the registration with, with `Deny`.
+pub(crate) fn check(collector: &dyn crate::prometheus::core::Collector) -> Option<String> {
+    let strictness = naming_strictness();
+    if strictness == NamingStrictness::Off {
+        return None;
+    }
+    let mut denied = Vec::new();
+    for family in collector.collect() {
+        let issues = lint_name(family.get_name(), family.get_field_type(), None);
+        if issues.is_empty() {
+            continue;
+        }
+        let issues: Vec<_> = issues.iter().map(NamingIssue::to_string).collect();
+        let message = format!("metric `{}`: {}", family.get_name(), issues.join(", "));
+        if strictness == NamingStrictness::Deny {
+            denied.push(message);
+        } else {
+            warn!("{}", message);
+        }
+    }
+    if denied.is_empty() {
+        None
+    } else {
+        Some(denied.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint_name, NamingIssue};
+    use crate::prometheus::proto::MetricType;
+
+    fn lint(name: &str, kind: MetricType) -> Vec<NamingIssue> {
+        lint_name(name, kind, None)
+    }
+
+    #[test]
+    fn charset() {
+        assert!(lint("http_requests_total", MetricType::COUNTER).is_empty());
+        assert!(lint("_private_total", MetricType::COUNTER).is_empty());
+        assert_eq!(
+            lint("http-requests_total", MetricType::COUNTER),
+            vec![NamingIssue::InvalidCharacters]
+        );
+        assert_eq!(
+            lint("2xx_responses_total", MetricType::COUNTER),
+            vec![NamingIssue::InvalidCharacters]
+        );
+        assert_eq!(
+            lint("job:requests:rate5m", MetricType::GAUGE),
+            vec![NamingIssue::Colon]
+        );
+    }
+
+    #[test]
+    fn total_suffix() {
+        assert!(lint("errors_total", MetricType::COUNTER).is_empty());
+        assert_eq!(
+            lint("errors", MetricType::COUNTER),
+            vec![NamingIssue::CounterWithoutTotal]
+        );
+        assert!(lint("queue_depth", MetricType::GAUGE).is_empty());
+        assert_eq!(
+            lint("queue_depth_total", MetricType::GAUGE),
+            vec![NamingIssue::TotalOnNonCounter]
+        );
+        assert_eq!(
+            lint("latency_seconds_total", MetricType::HISTOGRAM),
+            vec![NamingIssue::TotalOnNonCounter]
+        );
+    }
+
+    #[test]
+    fn reserved_suffixes() {
+        assert!(lint("request_size_bytes", MetricType::HISTOGRAM).is_empty());
+        assert_eq!(
+            lint("request_size_bucket", MetricType::HISTOGRAM),
+            vec![NamingIssue::ReservedSuffix("bucket")]
+        );
+        assert_eq!(
+            lint("jobs_count", MetricType::GAUGE),
+            vec![NamingIssue::ReservedSuffix("count")]
+        );
+        assert_eq!(
+            lint("latency_sum", MetricType::SUMMARY),
+            vec![NamingIssue::ReservedSuffix("sum")]
+        );
+    }
+
+    #[test]
+    fn base_units() {
+        assert!(lint("request_duration_seconds", MetricType::HISTOGRAM).is_empty());
+        assert!(lint("cpu_seconds_total", MetricType::COUNTER).is_empty());
+        assert_eq!(
+            lint("request_duration_milliseconds", MetricType::HISTOGRAM),
+            vec![NamingIssue::NonBaseUnit {
+                unit: "milliseconds",
+                base: "seconds"
+            }]
+        );
+        assert_eq!(
+            lint("sent_kilobytes_total", MetricType::COUNTER),
+            vec![NamingIssue::NonBaseUnit {
+                unit: "kilobytes",
+                base: "bytes"
+            }]
+        );
+    }
+
+    #[test]
+    fn declared_unit() {
+        assert!(lint_name("sent_bytes_total", MetricType::COUNTER, Some("bytes")).is_empty());
+        assert!(lint_name("heap_bytes", MetricType::GAUGE, Some("bytes")).is_empty());
+        assert_eq!(
+            lint_name("heap_size", MetricType::GAUGE, Some("bytes")),
+            vec![NamingIssue::UnitMismatch("bytes".to_string())]
+        );
+        assert_eq!(
+            lint_name("sent_total", MetricType::COUNTER, Some("bytes")),
+            vec![NamingIssue::UnitMismatch("bytes".to_string())]
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! Prometheus native histograms of the function durations, with exponential buckets.
+//!
+//! Native histograms (Prometheus 2.40+, with `--enable-feature=native-histograms`) only create
+//! buckets for the durations actually observed, so a function is a single series instead of one
+//! per bucket. They are only exported in the protobuf exposition format, which `/metrics` serves
+//! when the scraper asks for it, and `instrumented::render_protobuf` renders. In the text format,
+//! a function recorded only natively has its `_count` and `_sum`, without buckets.
+//!
+//! Classic histograms remain the default. `set_histogram_mode` selects native histograms, or
+//! both while migrating dashboards, for all functions, and `histogram = "native"` (or `"both"`,
+//! `"classic"`) for a single function:
+//!
+//! ```rust,ignore
+//! use instrumented::native_histogram::{set_histogram_mode, set_native_schema, HistogramMode};
+//!
+//! set_native_schema(3);
+//! set_histogram_mode(HistogramMode::Both);
+//! ```
+//!
+//! With the default schema of 3, each bucket is about 9% wider than the previous one, and a
+//! function whose calls take from a microsecond to a minute has at most 208 buckets.
+use crate::backend::{Backend, BACKEND};
+use crate::clock::Instant;
+use crate::poison::{LockExt, RwLockExt};
+use crate::prometheus::proto::{self, LabelPair, Metric, MetricFamily, MetricType};
+use crate::protobuf::{write_bytes, write_double, write_sint, write_uint};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicI8, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+/// Where the durations of the instrumented functions are observed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistogramMode {
+    /// The explicit buckets of `function_time_seconds`, the default.
+    Classic,
+    /// A native histogram, under the same name.
+    Native,
+    /// Both, in the same series, while migrating from the classic histogram.
+    Both,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(0);
+static SCHEMA: AtomicI8 = AtomicI8::new(3);
+/// The bits of 2^-128, the default of the official clients.
+static ZERO_THRESHOLD: AtomicU64 = AtomicU64::new(0x37F0_0000_0000_0000);
+
+lazy_static! {
+    static ref NATIVES: RwLock<HashMap<(&'static str, &'static str), Mutex<Native>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Sets where the durations of the functions without a `histogram` option are observed.
+pub fn set_histogram_mode(mode: HistogramMode) {
+    let mode = match mode {
+        HistogramMode::Classic => 0,
+        HistogramMode::Native => 1,
+        HistogramMode::Both => 2,
+    };
+    MODE.store(mode, Ordering::Relaxed);
+}
+
+/// Returns where the durations of the functions without a `histogram` option are observed.
+pub fn histogram_mode() -> HistogramMode {
+    match MODE.load(Ordering::Relaxed) {
+        0 => HistogramMode::Classic,
+        1 => HistogramMode::Native,
+        _ => HistogramMode::Both,
+    }
+}
+
+/// Sets the schema of the native histograms, from -4 to 8, 3 by default: the boundaries of the
+/// buckets are the powers of `2^(2^-schema)`. Higher schemas have more, narrower buckets.
+///
+/// Only the histograms of the functions not called yet are affected, so call it at startup.
+///
+/// # Panics
+///
+/// Panics if the schema isn't between -4 and 8.
+pub fn set_native_schema(schema: i8) {
+    assert!(
+        (-4..=8).contains(&schema),
+        "the schema of native histograms must be between -4 and 8, not {}",
+        schema
+    );
+    SCHEMA.store(schema, Ordering::Relaxed);
+}
+
+/// Sets the width of the zero bucket of the native histograms, in the time unit of the metrics:
+/// durations up to the threshold are counted in the zero bucket, instead of buckets of their own.
+/// The default, 2^-128, only catches durations of zero.
+///
+/// Only the histograms of the functions not called yet are affected, so call it at startup.
+///
+/// # Panics
+///
+/// Panics if the threshold is negative or not a number.
+pub fn set_native_zero_threshold(threshold: f64) {
+    assert!(
+        threshold >= 0.0,
+        "the zero threshold of native histograms must be positive, not {}",
+        threshold
+    );
+    ZERO_THRESHOLD.store(threshold.to_bits(), Ordering::Relaxed);
+}
+
+/// The native histogram of a function.
+#[derive(Clone)]
+pub(crate) struct Native {
+    schema: i8,
+    zero_threshold: f64,
+    zero_count: u64,
+    count: u64,
+    sum: f64,
+    /// The counts of the buckets, by index: bucket `i` holds the values in
+    /// `(base^(i-1), base^i]`, where `base = 2^(2^-schema)`.
+    buckets: BTreeMap<i32, u64>,
+}
+
+impl Native {
+    fn new() -> Self {
+        Native {
+            schema: SCHEMA.load(Ordering::Relaxed),
+            zero_threshold: f64::from_bits(ZERO_THRESHOLD.load(Ordering::Relaxed)),
+            zero_count: 0,
+            count: 0,
+            sum: 0.0,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        if value <= self.zero_threshold {
+            self.zero_count += 1;
+        } else {
+            *self
+                .buckets
+                .entry(bucket_index(value, self.schema))
+                .or_insert(0) += 1;
+        }
+    }
+
+    /// Writes the native fields of a `Histogram` message: the schema, the zero bucket, and the
+    /// positive buckets as spans of consecutive indexes and deltas between their counts.
+    pub(crate) fn write_fields(&self, buf: &mut Vec<u8>) {
+        write_sint(buf, 5, i64::from(self.schema));
+        write_double(buf, 6, self.zero_threshold);
+        write_uint(buf, 7, self.zero_count);
+
+        let mut spans: Vec<(i32, u32)> = Vec::new();
+        let mut deltas = Vec::new();
+        let mut previous: Option<(i32, u64)> = None;
+        for (&index, &count) in &self.buckets {
+            match previous {
+                Some((previous_index, _)) if index == previous_index + 1 => {
+                    spans.last_mut().unwrap().1 += 1;
+                }
+                Some((previous_index, _)) => spans.push((index - previous_index - 1, 1)),
+                None => spans.push((index, 1)),
+            }
+            let previous_count = previous.map_or(0, |(_, count)| count);
+            deltas.push(count as i64 - previous_count as i64);
+            previous = Some((index, count));
+        }
+        // An empty span tells an empty native histogram from a classic one.
+        if spans.is_empty() && self.zero_count == 0 {
+            spans.push((0, 0));
+        }
+        for (offset, length) in spans {
+            let mut span = Vec::new();
+            write_sint(&mut span, 1, i64::from(offset));
+            write_uint(&mut span, 2, u64::from(length));
+            write_bytes(buf, 12, &span);
+        }
+        for delta in deltas {
+            write_sint(buf, 13, delta);
+        }
+    }
+}
+
+/// Returns the index of the bucket of a positive value.
+fn bucket_index(value: f64, schema: i8) -> i32 {
+    (value.log2() * 2f64.powi(i32::from(schema))).ceil() as i32
+}
+
+#[doc(hidden)]
+pub fn observe_duration_for(
+    name: &'static str,
+    ctx: &'static str,
+    start: Instant,
+    mode: HistogramMode,
+) -> f64 {
+    observe_duration_in_crate(name, ctx, "", start, mode)
+}
+
+#[doc(hidden)]
+pub fn observe_duration_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    start: Instant,
+    mode: HistogramMode,
+) -> f64 {
+    let elapsed = start.elapsed();
+    if crate::is_enabled() {
+        record(name, ctx, krate, elapsed, mode);
+        crate::extremes::observe(name, ctx, elapsed);
+    }
+    crate::duration_to_seconds(elapsed)
+}
+
+/// Records a duration into the classic histogram, the native one, or both.
+pub(crate) fn record(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    elapsed: Duration,
+    mode: HistogramMode,
+) {
+    // The classic histogram is observed first, so that a scrape never sees more observations in
+    // the native buckets than the count of the series.
+    if mode != HistogramMode::Native {
+        BACKEND.record_duration(name, ctx, krate, elapsed);
+    }
+    if mode == HistogramMode::Classic {
+        return;
+    }
+    let value = crate::time_unit().scale(elapsed);
+    if let Some(native) = NATIVES.read_or_recover().get(&(name, ctx)) {
+        native.lock_or_recover().observe(value);
+        return;
+    }
+    NATIVES
+        .write_or_recover()
+        .entry((name, ctx))
+        .or_insert_with(|| Mutex::new(Native::new()))
+        .get_mut()
+        .unwrap()
+        .observe(value);
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    NATIVES.write_or_recover().clear();
+}
+
+/// The name of the family of the function durations.
+fn family_name() -> String {
+    let name = format!("function_time_{}", crate::time_unit().suffix());
+    match &*crate::METRICS_PREFIX {
+        Some(prefix) => format!("{}_{}", prefix, name),
+        None => name,
+    }
+}
+
+/// The native histograms of all the functions at an instant, to encode.
+pub(crate) struct Natives {
+    family: String,
+    histograms: HashMap<(&'static str, &'static str), Native>,
+}
+
+impl Natives {
+    /// The name of the family the native histograms belong to.
+    pub(crate) fn family(&self) -> &str {
+        &self.family
+    }
+
+    /// Returns the native histogram of the function of a series of the family.
+    pub(crate) fn get(&self, metric: &Metric) -> Option<&Native> {
+        let label = |name| {
+            metric
+                .get_label()
+                .iter()
+                .find(|pair| pair.get_name() == name)
+                .map(LabelPair::get_value)
+        };
+        let (name, ctx) = (label("name")?, label("ctx")?);
+        self.histograms
+            .iter()
+            .find(|(key, _)| **key == (name, ctx))
+            .map(|(_, native)| native)
+    }
+}
+
+/// Copies the native histograms, before the metrics are gathered so that no bucket counts more
+/// observations than the series.
+pub(crate) fn snapshot() -> Natives {
+    let histograms = NATIVES
+        .read_or_recover()
+        .iter()
+        .map(|(key, native)| (*key, native.lock_or_recover().clone()))
+        .collect();
+    Natives {
+        family: family_name(),
+        histograms,
+    }
+}
+
+/// Adds a series without buckets to the family of the function durations for each function
+/// recorded only natively, so that the text format has its `_count` and `_sum`, and the
+/// protobuf format a series to add the native histogram to.
+pub(crate) fn add_native_series(families: &mut Vec<MetricFamily>) {
+    let natives = NATIVES.read_or_recover();
+    if natives.is_empty() {
+        return;
+    }
+    let name = family_name();
+    let index = match families.iter().position(|family| family.get_name() == name) {
+        Some(index) => index,
+        None => {
+            let mut family = MetricFamily::new();
+            family.set_name(name);
+            family.set_help("Histogram of function call times observed".to_string());
+            family.set_field_type(MetricType::HISTOGRAM);
+            families.push(family);
+            families.len() - 1
+        }
+    };
+    let family = &mut families[index];
+    let recorded = family
+        .get_metric()
+        .iter()
+        .filter_map(|metric| {
+            let label = |name| {
+                metric
+                    .get_label()
+                    .iter()
+                    .find(|pair| pair.get_name() == name)
+                    .map(|pair| pair.get_value().to_string())
+            };
+            Some((label("name")?, label("ctx")?))
+        })
+        .collect::<Vec<_>>();
+    for ((name, ctx), native) in natives.iter() {
+        if recorded
+            .iter()
+            .any(|(other_name, other_ctx)| other_name == name && other_ctx == ctx)
+        {
+            continue;
+        }
+        let native = native.lock_or_recover();
+        let mut histogram = proto::Histogram::new();
+        histogram.set_sample_count(native.count);
+        histogram.set_sample_sum(native.sum);
+        let mut labels = vec![("ctx", *ctx), ("name", *name), ("type", "func_call")];
+        if let Some(common) = &*crate::METRICS_LABELS {
+            labels.extend(
+                common
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+            );
+        }
+        let mut metric = Metric::new();
+        metric.set_label(
+            labels
+                .into_iter()
+                .map(|(name, value)| {
+                    let mut pair = LabelPair::new();
+                    pair.set_name(name.to_string());
+                    pair.set_value(value.to_string());
+                    pair
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        metric.set_histogram(histogram);
+        family.mut_metric().push(metric);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_index, Native};
+
+    #[test]
+    fn bucket_boundaries() {
+        // Schema 0 doubles the boundaries: (0.5, 1] is bucket 0, (1, 2] bucket 1.
+        assert_eq!(bucket_index(1.0, 0), 0);
+        assert_eq!(bucket_index(1.5, 0), 1);
+        assert_eq!(bucket_index(2.0, 0), 1);
+        assert_eq!(bucket_index(0.75, 0), 0);
+        assert_eq!(bucket_index(0.5, 0), -1);
+        // Schema 3 splits each power of two in 8.
+        assert_eq!(bucket_index(2.0, 3), 8);
+        assert_eq!(bucket_index(2.1, 3), 9);
+        // Schema -1 merges two powers of two.
+        assert_eq!(bucket_index(3.0, -1), 1);
+        assert_eq!(bucket_index(4.0, -1), 1);
+        assert_eq!(bucket_index(5.0, -1), 2);
+    }
+
+    #[test]
+    fn spans_and_deltas() {
+        let mut native = Native::new();
+        native.schema = 0;
+        for value in &[1.0, 2.0, 2.0, 8.0, 0.0] {
+            native.observe(*value);
+        }
+        assert_eq!(native.count, 5);
+        assert_eq!(native.zero_count, 1);
+
+        let mut buf = Vec::new();
+        native.write_fields(&mut buf);
+        let mut expected = Vec::new();
+        super::write_sint(&mut expected, 5, 0);
+        super::write_double(&mut expected, 6, native.zero_threshold);
+        super::write_uint(&mut expected, 7, 1);
+        // Buckets 0 and 1, then 3 after a gap of one.
+        for (offset, length) in &[(0, 2), (1, 1)] {
+            let mut span = Vec::new();
+            super::write_sint(&mut span, 1, *offset);
+            super::write_uint(&mut span, 2, *length);
+            super::write_bytes(&mut expected, 12, &span);
+        }
+        for delta in &[1, 1, -1] {
+            super::write_sint(&mut expected, 13, *delta);
+        }
+        assert_eq!(buf, expected);
+    }
+}
@@ -0,0 +1,106 @@
+//! Normalization of the dynamic label values, such as the `err` label of the error counter, the
+//! labels of `err_labels` and the labels of events, so that values differing only in casing or
+//! whitespace don't fragment series.
+use crate::poison::RwLockExt;
+use crate::prometheus::IntCounter;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::RwLock;
+
+/// The maximum length in bytes of a dynamic label value with the default normalizer.
+pub const MAX_LABEL_VALUE_LEN: usize = 256;
+
+type Normalizer = Box<dyn Fn(&str, &str) -> String + Send + Sync>;
+
+lazy_static! {
+    static ref NORMALIZER: RwLock<Option<Normalizer>> = RwLock::new(None);
+    static ref NORMALIZER_PANICS: IntCounter = {
+        let counter = IntCounter::new(
+            "label_normalizer_panics_total",
+            "Number of label values kept as is because the label normalizer panicked",
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+/// Replaces the normalizer of the dynamic label values, called with the name and the value of
+/// the label, e.g. to lowercase them:
+///
+/// ```rust
+/// instrumented::set_label_normalizer(|_, value| {
+///     instrumented::default_label_normalizer("", value).to_lowercase()
+/// });
+/// ```
+///
+/// The normalizer is called on every error and event, and should be cheap. A value whose
+/// normalizer panics is kept as is, and counted in `label_normalizer_panics_total`.
+pub fn set_label_normalizer<F>(normalizer: F)
+where
+    F: Fn(&str, &str) -> String + Send + Sync + 'static,
+{
+    *NORMALIZER.write_or_recover() = Some(Box::new(normalizer));
+}
+
+/// Restores the default normalizer, `default_label_normalizer`.
+pub fn reset_label_normalizer() {
+    *NORMALIZER.write_or_recover() = None;
+}
+
+/// The default normalizer: trims the whitespace around the value, and truncates it to
+/// `MAX_LABEL_VALUE_LEN` bytes.
+pub fn default_label_normalizer(_key: &str, value: &str) -> String {
+    truncate(value.trim()).to_string()
+}
+
+fn truncate(value: &str) -> &str {
+    if value.len() <= MAX_LABEL_VALUE_LEN {
+        return value;
+    }
+    let mut end = MAX_LABEL_VALUE_LEN;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// Normalizes the value of the label `key`, without copying it when the default normalizer
+/// leaves it unchanged.
+pub(crate) fn normalize_label(key: &str, value: String) -> String {
+    let normalizer = NORMALIZER.read_or_recover();
+    match &*normalizer {
+        Some(normalizer) => {
+            match panic::catch_unwind(AssertUnwindSafe(|| normalizer(key, &value))) {
+                Ok(normalized) => normalized,
+                Err(_) => {
+                    NORMALIZER_PANICS.inc();
+                    value
+                }
+            }
+        }
+        None => {
+            let normalized = truncate(value.trim());
+            if normalized.len() == value.len() {
+                value
+            } else {
+                normalized.to_string()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_label_normalizer, MAX_LABEL_VALUE_LEN};
+
+    #[test]
+    fn trims_and_truncates_on_char_boundaries() {
+        assert_eq!(default_label_normalizer("err", "  Timeout \n"), "Timeout");
+        let long = "é".repeat(MAX_LABEL_VALUE_LEN);
+        let truncated = default_label_normalizer("err", &long);
+        assert_eq!(truncated.len(), MAX_LABEL_VALUE_LEN);
+        assert!(truncated.chars().all(|c| c == 'é'));
+    }
+}
@@ -0,0 +1,73 @@
+//! Hooks for observing the completion of instrumented calls.
+//!
+//! Observers are invoked synchronously on the calling thread, after the call's metrics have been
+//! recorded, so they should be cheap. Calls of functions returning streams aren't observed.
+use crate::poison::RwLockExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// The outcome of an instrumented call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome<'a> {
+    /// The call returned normally, or returned an error listed in `ignore_err`.
+    Ok,
+    /// The call returned an error, with its error label.
+    Err(&'a str),
+}
+
+/// A completed instrumented call.
+#[derive(Debug, Clone, Copy)]
+pub struct Completion<'a> {
+    pub name: &'static str,
+    pub ctx: &'static str,
+    pub duration: Duration,
+    pub outcome: Outcome<'a>,
+}
+
+/// Receives a notification for every completed instrumented call.
+pub trait Observer: Send + Sync {
+    fn on_completion(&self, completion: &Completion);
+}
+
+lazy_static! {
+    static ref OBSERVERS: RwLock<Vec<Box<dyn Observer>>> = RwLock::new(Vec::new());
+}
+
+static HAS_OBSERVERS: AtomicBool = AtomicBool::new(false);
+
+/// Registers a global observer. Observers are invoked in registration order.
+pub fn add_observer(observer: Box<dyn Observer>) {
+    OBSERVERS.write_or_recover().push(observer);
+    HAS_OBSERVERS.store(true, Ordering::Release);
+}
+
+pub(crate) fn notify(name: &'static str, ctx: &'static str, elapsed: f64, err: Option<&str>) {
+    if !HAS_OBSERVERS.load(Ordering::Acquire) {
+        return;
+    }
+    let completion = Completion {
+        name,
+        ctx,
+        duration: Duration::from_secs_f64(elapsed),
+        outcome: match err {
+            Some(err) => Outcome::Err(err),
+            None => Outcome::Ok,
+        },
+    };
+    for observer in OBSERVERS.read_or_recover().iter() {
+        observer.on_completion(&completion);
+    }
+}
+
+/// Poisons the lock of the observers, which every instrumented call takes while observers are
+/// registered, for the tests of the recovery from poisoned locks.
+#[cfg(any(test, feature = "test-hooks"))]
+#[doc(hidden)]
+pub fn poison_for_tests() {
+    let _ = std::thread::spawn(|| {
+        let _observers = OBSERVERS.write_or_recover();
+        panic!("poisoning the lock of the observers");
+    })
+    .join();
+}
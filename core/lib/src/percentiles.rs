@@ -0,0 +1,245 @@
+//! Percentile gauges computed in the process, for dashboards and JSON consumers that can't run
+//! `histogram_quantile`, with the `percentiles` option of `#[instrument]`.
+//!
+//! Each function with `percentiles` exports, with its name and ctx:
+//!
+//! * `function_time_p50_seconds`
+//! * `function_time_p90_seconds`
+//! * `function_time_p99_seconds`
+//!
+//! These are **process-local approximations**: they can't be aggregated across instances, and
+//! are computed at gather time from a reservoir of `RESERVOIR_SIZE` durations sampled uniformly
+//! among all the calls since the start (or the last reset), so they follow a change of latency
+//! slowly. With `hdr`, they're read from the HDR histogram of the function instead. Only the
+//! functions that opted in are tracked, and the cost of a gather is bounded by their number times
+//! the reservoir size.
+//!
+//! ```rust
+//! # use instrumented::instrument;
+//! #[instrument(INFO, percentiles)]
+//! fn lookup(key: u32) -> Option<u32> {
+//!     Some(key)
+//! }
+//! ```
+use crate::poison::{LockExt, RwLockExt};
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::MetricFamily;
+use crate::prometheus::GaugeVec;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Once, RwLock};
+use std::time::Duration;
+
+/// The number of durations kept per function.
+pub const RESERVOIR_SIZE: usize = 1024;
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+const NAMES: [&str; 3] = [
+    "function_time_p50",
+    "function_time_p90",
+    "function_time_p99",
+];
+
+static REGISTER: Once = Once::new();
+
+/// Where the durations of a function are kept.
+enum Samples {
+    Reservoir(Mutex<Reservoir>),
+    #[cfg_attr(not(feature = "hdr"), allow(dead_code))]
+    Hdr,
+}
+
+lazy_static! {
+    static ref TRACKED: RwLock<HashMap<(&'static str, &'static str), Arc<Samples>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// A uniform sample of the durations of the calls, with Vitter's algorithm R.
+struct Reservoir {
+    /// Durations in seconds.
+    samples: Vec<f64>,
+    seen: u64,
+    /// The state of a xorshift generator, picking the samples to replace.
+    rng: u64,
+}
+
+impl Reservoir {
+    fn new() -> Self {
+        Reservoir {
+            samples: Vec::with_capacity(RESERVOIR_SIZE),
+            seen: 0,
+            rng: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn observe(&mut self, elapsed: f64) {
+        self.seen += 1;
+        if self.samples.len() < RESERVOIR_SIZE {
+            self.samples.push(elapsed);
+            return;
+        }
+        let index = self.next_random() % self.seen;
+        if let Some(sample) = self.samples.get_mut(index as usize) {
+            *sample = elapsed;
+        }
+    }
+
+    /// The durations at `QUANTILES`, by nearest rank, or `None` without samples.
+    fn quantiles(&self) -> Option<Vec<Duration>> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        Some(
+            QUANTILES
+                .iter()
+                .map(|quantile| {
+                    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+                    Duration::from_secs_f64(sorted[rank.max(1) - 1])
+                })
+                .collect(),
+        )
+    }
+}
+
+fn tracked(name: &'static str, ctx: &'static str, new: fn() -> Samples) -> Arc<Samples> {
+    if let Some(samples) = TRACKED.read_or_recover().get(&(name, ctx)) {
+        return samples.clone();
+    }
+    REGISTER.call_once(|| crate::register_builtin(Box::new(PercentilesCollector::new())));
+    TRACKED
+        .write_or_recover()
+        .entry((name, ctx))
+        .or_insert_with(|| Arc::new(new()))
+        .clone()
+}
+
+/// Samples the duration of a call, in seconds, into the reservoir of a function.
+#[doc(hidden)]
+pub fn observe_for(name: &'static str, ctx: &'static str, elapsed: f64) {
+    if !crate::is_enabled() {
+        return;
+    }
+    if let Samples::Reservoir(reservoir) = &*tracked(name, ctx, || {
+        Samples::Reservoir(Mutex::new(Reservoir::new()))
+    }) {
+        reservoir.lock_or_recover().observe(elapsed);
+    }
+}
+
+/// Exports the percentiles of a function with `hdr` from its HDR histogram.
+#[doc(hidden)]
+pub fn track_hdr_for(name: &'static str, ctx: &'static str) {
+    if crate::is_enabled() {
+        tracked(name, ctx, || Samples::Hdr);
+    }
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    for samples in TRACKED.read_or_recover().values() {
+        if let Samples::Reservoir(reservoir) = &**samples {
+            *reservoir.lock_or_recover() = Reservoir::new();
+        }
+    }
+}
+
+/// Exports the percentile gauges of the tracked functions, computed at gather time.
+struct PercentilesCollector {
+    descs: Vec<Desc>,
+    gauges: Vec<GaugeVec>,
+}
+
+impl PercentilesCollector {
+    fn new() -> Self {
+        let gauges = NAMES
+            .iter()
+            .zip(&QUANTILES)
+            .map(|(name, quantile)| {
+                let help = format!(
+                    "Process-local p{} of the function call times, from a sample of the calls",
+                    quantile * 100.0
+                );
+                GaugeVec::new(
+                    crate::time_unit::gauge_opts(name, &help),
+                    &["type", "name", "ctx"],
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        PercentilesCollector {
+            descs: gauges
+                .iter()
+                .flat_map(|gauge| gauge.desc().into_iter().cloned())
+                .collect(),
+            gauges,
+        }
+    }
+}
+
+impl Collector for PercentilesCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let unit = crate::time_unit();
+        for gauge in &self.gauges {
+            gauge.reset();
+        }
+        for ((name, ctx), samples) in TRACKED.read_or_recover().iter() {
+            let quantiles = match &**samples {
+                Samples::Reservoir(reservoir) => reservoir.lock_or_recover().quantiles(),
+                #[cfg(feature = "hdr")]
+                Samples::Hdr => crate::hdr::quantiles(name, ctx, &QUANTILES),
+                #[cfg(not(feature = "hdr"))]
+                Samples::Hdr => None,
+            };
+            for (gauge, duration) in self.gauges.iter().zip(quantiles.unwrap_or_default()) {
+                gauge
+                    .with_label_values(&["func_call", name, ctx])
+                    .set(unit.scale(duration));
+            }
+        }
+        self.gauges.iter().flat_map(Collector::collect).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reservoir, RESERVOIR_SIZE};
+
+    #[test]
+    fn exact_below_the_reservoir_size() {
+        let mut reservoir = Reservoir::new();
+        assert_eq!(reservoir.quantiles(), None);
+        for millis in (1..=100).rev() {
+            reservoir.observe(f64::from(millis) / 1e3);
+        }
+        let quantiles = reservoir.quantiles().unwrap();
+        for (quantile, expected) in quantiles.iter().zip(&[0.05, 0.09, 0.099]) {
+            assert!(
+                (quantile.as_secs_f64() - expected).abs() < 1e-9,
+                "{:?}",
+                quantiles
+            );
+        }
+    }
+
+    #[test]
+    fn bounded_sample_of_many_calls() {
+        let mut reservoir = Reservoir::new();
+        for i in 0..100_000 {
+            reservoir.observe(f64::from(i % 1000) / 1e3);
+        }
+        assert_eq!(reservoir.samples.len(), RESERVOIR_SIZE);
+        let p99 = reservoir.quantiles().unwrap()[2].as_secs_f64();
+        assert!((0.95..1.0).contains(&p99), "{}", p99);
+    }
+}
@@ -0,0 +1,301 @@
+//! The phases of an instrumented function, such as parsing, validating and writing, timed with
+//! `instrumented::phase!` without splitting the function.
+//!
+//! A function instrumented with `phases` is the frame of the `phase!` blocks run by its body,
+//! while it runs on the thread: each block is observed in `function_phase_seconds`, with the
+//! name and ctx of the innermost such function and the `phase` label.
+//!
+//! Phases nest: a `phase!("tokenize", ...)` block within `phase!("parse", ...)` is labelled
+//! `parse/tokenize`, and is also part of the duration of `parse`. Only sum the top-level phases
+//! to compare them with the duration of the function.
+//!
+//! ```rust
+//! use instrumented::{instrument, phase};
+//!
+//! #[instrument(INFO, phases)]
+//! fn import(input: &str) -> usize {
+//!     let rows = phase!("parse", { input.lines().collect::<Vec<_>>() });
+//!     phase!("write", { rows.len() })
+//! }
+//! # assert_eq!(import("a\nb"), 2);
+//! ```
+//!
+//! Outside such a function, e.g. in a function that isn't instrumented, the name and ctx must be
+//! given: `phase!("import", "etl", "parse", { ... })`. A `phase!` without either runs its block,
+//! but isn't timed, and a warning is logged once.
+//!
+//! The calls into foreign code are timed the same way, but in their own families so that the
+//! time spent in C libraries can be told apart uniformly, with `instrumented::ffi_section`.
+use crate::clock::Instant;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    static ref FUNC_PHASE: prometheus::HistogramVec = {
+        let histogram_opts = crate::time_unit::histogram_opts(
+            "function_phase",
+            "Histogram of the times of the phases of function calls",
+        );
+        let histogram =
+            prometheus::HistogramVec::new(histogram_opts, &["type", "name", "ctx", "phase"])
+                .unwrap();
+
+        crate::register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref FUNC_FFI: prometheus::HistogramVec = {
+        let histogram_opts = crate::time_unit::histogram_opts(
+            "function_ffi",
+            "Histogram of the times of the foreign calls of function calls",
+        );
+        let histogram =
+            prometheus::HistogramVec::new(histogram_opts, &["type", "name", "ctx", "section"])
+                .unwrap();
+
+        crate::register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref FUNC_FFI_CALLS: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "function_ffi_calls_total",
+            "Number of foreign calls made by function calls",
+        );
+        let counter =
+            prometheus::IntCounterVec::new(counter_opts, &["type", "name", "ctx", "section"])
+                .unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+/// Whether a phase outside of any frame was already logged.
+static ORPHAN_LOGGED: AtomicBool = AtomicBool::new(false);
+/// Whether a foreign call outside of any frame was already logged.
+static ORPHAN_FFI_LOGGED: AtomicBool = AtomicBool::new(false);
+
+/// An instrumented function running on the thread, and its phases running.
+struct Frame {
+    name: &'static str,
+    ctx: &'static str,
+    phases: Vec<&'static str>,
+}
+
+thread_local! {
+    static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    FUNC_PHASE.reset();
+    FUNC_FFI.reset();
+    FUNC_FFI_CALLS.reset();
+}
+
+/// Leaves the frame of an instrumented function when dropped.
+#[doc(hidden)]
+#[must_use]
+pub struct FrameGuard(());
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        FRAMES.with(|frames| frames.borrow_mut().pop());
+    }
+}
+
+/// Makes the function `name` the frame of the phases run on the thread, until the guard is
+/// dropped.
+#[doc(hidden)]
+pub fn enter_for(name: &'static str, ctx: &'static str) -> FrameGuard {
+    FRAMES.with(|frames| {
+        frames.borrow_mut().push(Frame {
+            name,
+            ctx,
+            phases: vec![],
+        })
+    });
+    FrameGuard(())
+}
+
+/// The name and ctx of the innermost instrumented function running on the thread, if any.
+#[cfg(feature = "rayon-metrics")]
+pub(crate) fn current_frame() -> Option<(&'static str, &'static str)> {
+    FRAMES.with(|frames| frames.borrow().last().map(|frame| (frame.name, frame.ctx)))
+}
+
+/// Makes the function `name` the frame of the phases run on the thread, with their labels
+/// starting with `stage`, until `leave_stage` is called.
+#[cfg(feature = "rayon-metrics")]
+pub(crate) fn enter_stage(name: &'static str, ctx: &'static str, stage: &'static str) {
+    FRAMES.with(|frames| {
+        frames.borrow_mut().push(Frame {
+            name,
+            ctx,
+            phases: vec![stage],
+        })
+    });
+}
+
+#[cfg(feature = "rayon-metrics")]
+pub(crate) fn leave_stage() {
+    FRAMES.with(|frames| frames.borrow_mut().pop());
+}
+
+/// Observes the duration of a phase when dropped.
+#[doc(hidden)]
+#[must_use]
+pub struct PhaseGuard {
+    /// `None` if the phase isn't in a frame.
+    start: Option<Instant>,
+    /// Whether the frame was entered for this phase, with `start_for`.
+    own_frame: bool,
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        let start = match self.start {
+            Some(start) => start,
+            None => return,
+        };
+        let elapsed = start.elapsed();
+        let phase = FRAMES.with(|frames| {
+            let mut frames = frames.borrow_mut();
+            let frame = frames.last_mut()?;
+            let phase = (frame.name, frame.ctx, frame.phases.join("/"));
+            frame.phases.pop();
+            if self.own_frame {
+                frames.pop();
+            }
+            Some(phase)
+        });
+        if let Some((name, ctx, phase)) = phase {
+            if crate::is_enabled() {
+                FUNC_PHASE
+                    .with_label_values(&["func_call", name, ctx, &phase])
+                    .observe(crate::time_unit().scale(elapsed));
+            }
+        }
+    }
+}
+
+/// Starts a phase of the innermost instrumented function running on the thread.
+#[doc(hidden)]
+pub fn start(phase: &'static str) -> PhaseGuard {
+    let in_frame = FRAMES.with(|frames| match frames.borrow_mut().last_mut() {
+        Some(frame) => {
+            frame.phases.push(phase);
+            true
+        }
+        None => false,
+    });
+    if !in_frame && !ORPHAN_LOGGED.swap(true, Ordering::Relaxed) {
+        warn!(
+            "The phase {:?} runs outside of a function instrumented with `phases`, and isn't \
+             timed: pass the name and ctx to `phase!`",
+            phase
+        );
+    }
+    PhaseGuard {
+        start: if in_frame { Some(Instant::now()) } else { None },
+        own_frame: false,
+    }
+}
+
+/// Starts a phase of the function `name`, whether it's instrumented or not.
+#[doc(hidden)]
+pub fn start_for(name: &'static str, ctx: &'static str, phase: &'static str) -> PhaseGuard {
+    FRAMES.with(|frames| {
+        frames.borrow_mut().push(Frame {
+            name,
+            ctx,
+            phases: vec![phase],
+        })
+    });
+    PhaseGuard {
+        start: Some(Instant::now()),
+        own_frame: true,
+    }
+}
+
+/// Times a block as a phase of the instrumented function running it, see the `phase` module.
+///
+/// `phase!("validate", { ... })` labels the block with the name and ctx of the innermost
+/// function instrumented with `phases` running on the thread, while
+/// `phase!("import", "etl", "validate", { ... })` gives them explicitly. The block's value is
+/// returned, and the phase is observed even if the block returns early or unwinds.
+#[macro_export]
+macro_rules! phase {
+    ($phase:expr, $body:block) => {{
+        let __instrumented_phase = $crate::phase::start($phase);
+        $body
+    }};
+    ($name:expr, $ctx:expr, $phase:expr, $body:block) => {{
+        let __instrumented_phase = $crate::phase::start_for($name, $ctx, $phase);
+        $body
+    }};
+}
+
+/// Observes the duration of a foreign call when dropped, even if it unwinds.
+struct FfiGuard {
+    name: &'static str,
+    ctx: &'static str,
+    section: &'static str,
+    start: Instant,
+}
+
+impl Drop for FfiGuard {
+    fn drop(&mut self) {
+        if !crate::is_enabled() {
+            return;
+        }
+        let labels = ["func_call", self.name, self.ctx, self.section];
+        FUNC_FFI
+            .with_label_values(&labels)
+            .observe(crate::time_unit().scale(self.start.elapsed()));
+        FUNC_FFI_CALLS.with_label_values(&labels).inc();
+    }
+}
+
+/// Runs `f`, a call into foreign code such as a C library, timing it in `function_ffi_seconds`
+/// and counting it in `function_ffi_calls_total`, with the name and ctx of the innermost function
+/// instrumented with `phases` running on the thread, and `section` as the `section` label.
+///
+/// Outside of such a function, `f` runs but isn't recorded, and a warning is logged once.
+///
+/// ```rust
+/// # mod libc {
+/// #     pub unsafe fn getpid() -> i32 { 42 }
+/// # }
+/// use instrumented::instrument;
+///
+/// #[instrument(INFO, phases)]
+/// fn pid() -> i32 {
+///     instrumented::ffi_section("getpid", || unsafe { libc::getpid() })
+/// }
+/// # pid();
+/// ```
+pub fn ffi_section<T>(section: &'static str, f: impl FnOnce() -> T) -> T {
+    let frame = FRAMES.with(|frames| frames.borrow().last().map(|frame| (frame.name, frame.ctx)));
+    let _guard = match frame {
+        Some((name, ctx)) => Some(FfiGuard {
+            name,
+            ctx,
+            section,
+            start: Instant::now(),
+        }),
+        None => {
+            if !ORPHAN_FFI_LOGGED.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "The foreign call {:?} runs outside of a function instrumented with `phases`, \
+                     and isn't timed",
+                    section
+                );
+            }
+            None
+        }
+    };
+    f()
+}
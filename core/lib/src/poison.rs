@@ -0,0 +1,94 @@
+//! Recovery from the poisoned locks of the runtime helpers, so that a panic while one of them is
+//! held doesn't make every later instrumented call panic too.
+//!
+//! The sidecar state behind these locks is only ever metrics, so a poisoned lock is used as is.
+//! The first recovery is logged, and every recovery is counted in
+//! `instrumentation_internal_errors_total`.
+use std::panic::Location;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Condvar, LockResult, Mutex, MutexGuard};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+static INTERNAL_ERRORS: AtomicU64 = AtomicU64::new(0);
+static LOGGED: AtomicBool = AtomicBool::new(false);
+
+/// Acquires a lock of the runtime helpers, recovering it if it's poisoned.
+pub(crate) trait LockExt<T: ?Sized> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+/// Acquires a read-write lock of the runtime helpers, recovering it if it's poisoned.
+pub(crate) trait RwLockExt<T: ?Sized> {
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T>;
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T: ?Sized> LockExt<T> for Mutex<T> {
+    #[track_caller]
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        recover(self.lock())
+    }
+}
+
+impl<T: ?Sized> RwLockExt<T> for RwLock<T> {
+    #[track_caller]
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        recover(self.read())
+    }
+
+    #[track_caller]
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        recover(self.write())
+    }
+}
+
+/// Waits on `condvar`, recovering the lock if it's poisoned.
+#[track_caller]
+pub(crate) fn wait<'a, T>(condvar: &Condvar, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+    recover(condvar.wait(guard))
+}
+
+/// Waits on `condvar` for at most `timeout`, recovering the lock if it's poisoned.
+#[track_caller]
+pub(crate) fn wait_timeout<'a, T>(
+    condvar: &Condvar,
+    guard: MutexGuard<'a, T>,
+    timeout: Duration,
+) -> MutexGuard<'a, T> {
+    recover(condvar.wait_timeout(guard, timeout)).0
+}
+
+#[track_caller]
+fn recover<G>(result: LockResult<G>) -> G {
+    match result {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            internal_error(&format!("lock poisoned at {}", Location::caller()));
+            poisoned.into_inner()
+        }
+    }
+}
+
+/// Counts an error of the instrumentation itself, which was skipped rather than crash the host
+/// application, and logs the first one.
+pub(crate) fn internal_error(description: &str) {
+    count_internal_error();
+    if !LOGGED.swap(true, Ordering::Relaxed) {
+        error!(
+            "Internal instrumentation error, recovering ({}); the next ones are only counted in \
+             instrumentation_internal_errors_total",
+            description
+        );
+    }
+}
+
+/// Counts an internal error that was already logged.
+pub(crate) fn count_internal_error() {
+    INTERNAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of internal errors so far.
+pub(crate) fn internal_errors() -> u64 {
+    INTERNAL_ERRORS.load(Ordering::Relaxed)
+}
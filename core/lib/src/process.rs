@@ -0,0 +1,127 @@
+//! A lightweight process collector for the unix platforms where the default, procfs based,
+//! process collector isn't available.
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::MetricFamily;
+use crate::prometheus::{IntGauge, Opts};
+
+/// Exports `process_resident_memory_bytes` and `process_open_fds`. Values that can't be read are
+/// left out of the gathered metrics.
+pub(crate) struct ProcessCollector {
+    descs: Vec<Desc>,
+    resident_memory: IntGauge,
+    open_fds: IntGauge,
+}
+
+impl ProcessCollector {
+    pub(crate) fn new() -> Self {
+        let resident_memory = IntGauge::with_opts(Opts::new(
+            "process_resident_memory_bytes",
+            "Resident memory size in bytes.",
+        ))
+        .unwrap();
+        let open_fds = IntGauge::with_opts(Opts::new(
+            "process_open_fds",
+            "Number of open file descriptors.",
+        ))
+        .unwrap();
+
+        let mut descs = Vec::new();
+        descs.extend(resident_memory.desc().into_iter().cloned());
+        descs.extend(open_fds.desc().into_iter().cloned());
+
+        ProcessCollector {
+            descs,
+            resident_memory,
+            open_fds,
+        }
+    }
+}
+
+impl Collector for ProcessCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut families = Vec::new();
+        if let Some(bytes) = resident_memory_bytes() {
+            self.resident_memory.set(bytes as i64);
+            families.extend(self.resident_memory.collect());
+        }
+        if let Some(fds) = open_fds() {
+            self.open_fds.set(fds as i64);
+            families.extend(self.open_fds.collect());
+        }
+        families
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn resident_memory_bytes() -> Option<u64> {
+    use std::mem;
+
+    let size = mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+    // Safety: `info` is plain old data, and `size` is its size.
+    let mut info: libc::proc_taskinfo = unsafe { mem::zeroed() };
+    let read = unsafe {
+        libc::proc_pidinfo(
+            libc::getpid(),
+            libc::PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut libc::proc_taskinfo as *mut libc::c_void,
+            size,
+        )
+    };
+    if read == size {
+        Some(info.pti_resident_size)
+    } else {
+        None
+    }
+}
+
+/// The peak resident memory, as the BSDs don't expose the current one without `kvm`.
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn resident_memory_bytes() -> Option<u64> {
+    // Safety: `usage` is plain old data.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    // `ru_maxrss` is in kilobytes.
+    Some(usage.ru_maxrss as u64 * 1024)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn open_fds() -> Option<u64> {
+    use std::{mem, ptr};
+
+    let pid = unsafe { libc::getpid() };
+    // Without a buffer, returns the size needed to hold the descriptors.
+    let needed = unsafe { libc::proc_pidinfo(pid, libc::PROC_PIDLISTFDS, 0, ptr::null_mut(), 0) };
+    if needed <= 0 {
+        return None;
+    }
+    let size = mem::size_of::<libc::proc_fdinfo>();
+    let mut fds: Vec<libc::proc_fdinfo> = Vec::with_capacity(needed as usize / size);
+    let read = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDLISTFDS,
+            0,
+            fds.as_mut_ptr() as *mut libc::c_void,
+            (fds.capacity() * size) as libc::c_int,
+        )
+    };
+    if read <= 0 {
+        None
+    } else {
+        Some(read as u64 / size as u64)
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn open_fds() -> Option<u64> {
+    // Listing the directory opens a descriptor, which isn't counted.
+    let fds = std::fs::read_dir("/dev/fd").ok()?.count() as u64;
+    Some(fds.saturating_sub(1))
+}
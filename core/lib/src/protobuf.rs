@@ -0,0 +1,148 @@
+//! A minimal protobuf writer, for the remote_write requests and the protobuf exposition format,
+//! whose native histogram fields the `prometheus` crate doesn't know about.
+use crate::native_histogram::Natives;
+use crate::prometheus::proto::{Metric, MetricFamily, MetricType};
+
+/// The content type of the delimited protobuf exposition format.
+pub(crate) const EXPOSITION_FORMAT: &str =
+    "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+
+pub(crate) const WIRE_VARINT: u64 = 0;
+pub(crate) const WIRE_FIXED64: u64 = 1;
+pub(crate) const WIRE_LENGTH_DELIMITED: u64 = 2;
+
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+pub(crate) fn write_key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    write_varint(buf, field << 3 | wire_type);
+}
+
+pub(crate) fn write_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_key(buf, field, WIRE_LENGTH_DELIMITED);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+pub(crate) fn write_uint(buf: &mut Vec<u8>, field: u64, value: u64) {
+    write_key(buf, field, WIRE_VARINT);
+    write_varint(buf, value);
+}
+
+/// Writes a `sint32` or `sint64`, zigzag encoded.
+pub(crate) fn write_sint(buf: &mut Vec<u8>, field: u64, value: i64) {
+    write_uint(buf, field, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+pub(crate) fn write_double(buf: &mut Vec<u8>, field: u64, value: f64) {
+    write_key(buf, field, WIRE_FIXED64);
+    buf.extend_from_slice(&value.to_bits().to_le_bytes());
+}
+
+/// Encodes the metric families in the delimited protobuf exposition format, adding the native
+/// histograms of `natives` to the function durations.
+pub(crate) fn encode_exposition(families: &[MetricFamily], natives: &Natives) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for family in families {
+        let natives = if family.get_name() == natives.family() {
+            Some(natives)
+        } else {
+            None
+        };
+        let mut message = Vec::new();
+        write_bytes(&mut message, 1, family.get_name().as_bytes());
+        write_bytes(&mut message, 2, family.get_help().as_bytes());
+        write_uint(&mut message, 3, family.get_field_type() as u64);
+        for metric in family.get_metric() {
+            let encoded = encode_metric(family.get_field_type(), metric, natives);
+            write_bytes(&mut message, 4, &encoded);
+        }
+        write_varint(&mut buf, message.len() as u64);
+        buf.extend_from_slice(&message);
+    }
+    buf
+}
+
+fn encode_metric(kind: MetricType, metric: &Metric, natives: Option<&Natives>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for pair in metric.get_label() {
+        let mut label = Vec::new();
+        write_bytes(&mut label, 1, pair.get_name().as_bytes());
+        write_bytes(&mut label, 2, pair.get_value().as_bytes());
+        write_bytes(&mut buf, 1, &label);
+    }
+    let mut value = Vec::new();
+    match kind {
+        MetricType::COUNTER => {
+            write_double(&mut value, 1, metric.get_counter().get_value());
+            write_bytes(&mut buf, 3, &value);
+        }
+        MetricType::GAUGE => {
+            write_double(&mut value, 1, metric.get_gauge().get_value());
+            write_bytes(&mut buf, 2, &value);
+        }
+        MetricType::UNTYPED => {
+            write_double(&mut value, 1, metric.get_untyped().get_value());
+            write_bytes(&mut buf, 5, &value);
+        }
+        MetricType::SUMMARY => {
+            let summary = metric.get_summary();
+            write_uint(&mut value, 1, summary.get_sample_count());
+            write_double(&mut value, 2, summary.get_sample_sum());
+            for quantile in summary.get_quantile() {
+                let mut encoded = Vec::new();
+                write_double(&mut encoded, 1, quantile.get_quantile());
+                write_double(&mut encoded, 2, quantile.get_value());
+                write_bytes(&mut value, 3, &encoded);
+            }
+            write_bytes(&mut buf, 4, &value);
+        }
+        MetricType::HISTOGRAM => {
+            let histogram = metric.get_histogram();
+            write_uint(&mut value, 1, histogram.get_sample_count());
+            write_double(&mut value, 2, histogram.get_sample_sum());
+            for bucket in histogram.get_bucket() {
+                let mut encoded = Vec::new();
+                write_uint(&mut encoded, 1, bucket.get_cumulative_count());
+                write_double(&mut encoded, 2, bucket.get_upper_bound());
+                write_bytes(&mut value, 3, &encoded);
+            }
+            if let Some(native) = natives.and_then(|natives| natives.get(metric)) {
+                native.write_fields(&mut value);
+            }
+            write_bytes(&mut buf, 7, &value);
+        }
+    }
+    if metric.has_timestamp_ms() {
+        write_key(&mut buf, 6, WIRE_VARINT);
+        write_varint(&mut buf, metric.get_timestamp_ms() as u64);
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_sint, write_varint};
+
+    #[test]
+    fn varint() {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 1);
+        write_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn zigzag() {
+        let mut buf = Vec::new();
+        for value in &[0, -1, 1, -2] {
+            write_sint(&mut buf, 1, *value);
+        }
+        assert_eq!(buf, vec![0x08, 0, 0x08, 1, 0x08, 2, 0x08, 3]);
+    }
+}
@@ -0,0 +1,96 @@
+//! The time messages wait in queues between a producer and a consumer.
+//!
+//! ```rust
+//! use instrumented::Stamp;
+//! use std::sync::mpsc;
+//!
+//! let (tx, rx) = mpsc::channel();
+//! instrumented::queue_depth("work_queue").inc();
+//! tx.send(("job", Stamp::now())).unwrap();
+//!
+//! let (job, stamp) = rx.recv().unwrap();
+//! instrumented::queue_depth("work_queue").dec();
+//! stamp.observe_wait("work_queue");
+//! ```
+//!
+//! With the `serde` feature, stamps are serialized as the wall clock time they were taken at, in
+//! nanoseconds since the unix epoch, for queues between processes. The observed wait then depends
+//! on the clocks of the producer and consumer hosts agreeing: a consumer clock behind the producer
+//! clock makes the wait shorter, down to zero.
+use crate::clock::Instant;
+use crate::prometheus::{HistogramVec, IntGauge, IntGaugeVec, Opts};
+use std::time::Duration;
+
+lazy_static! {
+    static ref QUEUE_WAIT: HistogramVec = {
+        let histogram_opts = crate::time_unit::histogram_opts(
+            "queue_wait",
+            "Histogram of the time messages waited in a queue",
+        );
+        let histogram = HistogramVec::new(histogram_opts, &["queue"]).unwrap();
+
+        crate::register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref QUEUE_DEPTH: IntGaugeVec = {
+        let gauge_opts = Opts::new("queue_depth", "Number of messages waiting in a queue");
+        let gauge = IntGaugeVec::new(gauge_opts, &["queue"]).unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+}
+
+/// The time a message was enqueued at, to attach to the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stamp(Instant);
+
+impl Stamp {
+    /// Stamps a message with the current time.
+    pub fn now() -> Self {
+        Stamp(Instant::now())
+    }
+
+    /// The time since the message was stamped.
+    pub fn elapsed(&self) -> Duration {
+        self.0.elapsed()
+    }
+
+    /// Observes the time since the message was stamped into `queue_wait_seconds` for `queue`, and
+    /// returns it.
+    pub fn observe_wait(&self, queue: &str) -> Duration {
+        let wait = self.elapsed();
+        QUEUE_WAIT
+            .with_label_values(&[queue])
+            .observe(crate::time_unit().scale(wait));
+        wait
+    }
+}
+
+/// The `queue_depth` gauge of `queue`, for producers to increment and consumers to decrement.
+pub fn queue_depth(queue: &str) -> IntGauge {
+    QUEUE_DEPTH.with_label_values(&[queue])
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Stamp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let stamped = crate::clock::system_time() - self.elapsed();
+        let nanos = crate::clock::since_epoch(stamped).as_nanos() as u64;
+        serializer.serialize_u64(nanos)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Stamp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let nanos = <u64 as serde::Deserialize>::deserialize(deserializer)?;
+        let stamped = std::time::UNIX_EPOCH + Duration::from_nanos(nanos);
+        // A stamp from the future, by the clock of this host, is taken as current.
+        let age = crate::clock::wall_clock_since(stamped, crate::clock::system_time());
+        let now = Instant::now();
+        Ok(Stamp(now.checked_sub(age).unwrap_or(now)))
+    }
+}
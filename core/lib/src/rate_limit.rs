@@ -0,0 +1,165 @@
+//! Call rate limits of instrumented functions, with the `max_rate` option of `#[instrument]`.
+//!
+//! Each function with `max_rate = N` has a token bucket holding up to `N` calls, refilled at `N`
+//! calls per second. Calls over the limit are counted in `function_rate_limited_total`, and not
+//! as calls. A function returning a `Result` with `rate_limit_err` returns that error instead of
+//! running its body; other functions run anyway, as the limit can only be recorded:
+//!
+//! ```rust,ignore
+//! #[instrument(INFO, max_rate = 1000, rate_limit_err = "ApiError::TooManyRequests")]
+//! fn lookup(key: &str) -> Result<Value, ApiError> {
+//!     ...
+//! }
+//! ```
+//!
+//! `set_global_max_rate` also limits the calls of all the functions with `max_rate` together.
+//!
+//! The buckets are a single atomic each, updated with a compare-and-swap: the theoretical arrival
+//! time of the next call, as in the generic cell rate algorithm. There's no clock on
+//! `wasm32-unknown-unknown`, where the buckets would never refill, so calls aren't limited there.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::clock::Instant;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+lazy_static! {
+    /// The reference point of the arrival times of the buckets.
+    static ref EPOCH: Instant = Instant::now();
+}
+
+static GLOBAL_RATE: AtomicU32 = AtomicU32::new(0);
+static GLOBAL: RateLimiter = RateLimiter::new();
+
+/// Limits the calls of all the functions with `max_rate` together to `rate` per second, on top of
+/// their own limits, or removes the global limit with `None`.
+pub fn set_global_max_rate(rate: Option<u32>) {
+    GLOBAL_RATE.store(rate.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Returns the global limit of `set_global_max_rate`.
+pub fn global_max_rate() -> Option<u32> {
+    match GLOBAL_RATE.load(Ordering::Relaxed) {
+        0 => None,
+        rate => Some(rate),
+    }
+}
+
+/// The token bucket of a function, in a static of the function.
+#[doc(hidden)]
+pub struct RateLimiter {
+    /// When the bucket is full again, in nanoseconds since the epoch.
+    full_at: AtomicU64,
+}
+
+impl RateLimiter {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        RateLimiter {
+            full_at: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes a token from the bucket if it has one. The bucket holds up to `rate` tokens, and is
+    /// refilled at `rate` tokens per second.
+    fn permit(&self, rate: u32, now: u64) -> bool {
+        let interval = NANOS_PER_SECOND / u64::from(rate.max(1));
+        let mut full_at = self.full_at.load(Ordering::Relaxed);
+        loop {
+            let next = full_at.max(now) + interval;
+            // The bucket is empty once it takes more than a second to refill.
+            if next - now > NANOS_PER_SECOND {
+                return false;
+            }
+            match self.full_at.compare_exchange_weak(
+                full_at,
+                next,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => full_at = actual,
+            }
+        }
+    }
+}
+
+/// The time since the epoch in nanoseconds, or `None` without a clock.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn now() -> Option<u64> {
+    let elapsed = EPOCH.elapsed();
+    Some(elapsed.as_secs() * NANOS_PER_SECOND + u64::from(elapsed.subsec_nanos()))
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn now() -> Option<u64> {
+    None
+}
+
+/// Takes a token from the bucket of a function, and from the global one, at `now`. Every call is
+/// permitted without a clock, as the buckets would never refill.
+fn permit_at(limiter: &RateLimiter, rate: u32, now: Option<u64>) -> bool {
+    match now {
+        Some(now) => {
+            limiter.permit(rate, now)
+                && global_max_rate().map_or(true, |global| GLOBAL.permit(global, now))
+        }
+        None => true,
+    }
+}
+
+/// Takes a token from the bucket of a function, and from the global one. Returns `false`, and
+/// counts the call in `function_rate_limited_total`, if either is empty.
+#[doc(hidden)]
+pub fn permit_for(name: &'static str, ctx: &'static str, limiter: &RateLimiter, rate: u32) -> bool {
+    permit_in_crate(name, ctx, "", limiter, rate)
+}
+
+/// Takes a token for a call of a function defined in the crate `krate`, for the `crate` label.
+#[doc(hidden)]
+pub fn permit_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    limiter: &RateLimiter,
+    rate: u32,
+) -> bool {
+    let permitted = permit_at(limiter, rate, now());
+    if !permitted && crate::is_enabled() {
+        crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            crate::FUNC_RATE_LIMITED.with_label_values(labels).inc()
+        });
+    }
+    permitted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{permit_at, RateLimiter, NANOS_PER_SECOND};
+
+    #[test]
+    fn permits_a_second_of_calls_then_refills() {
+        let limiter = RateLimiter::new();
+        let start = 5 * NANOS_PER_SECOND;
+        assert_eq!((0..20).filter(|_| limiter.permit(10, start)).count(), 10);
+        // A token every 100ms.
+        assert!(!limiter.permit(10, start + 50_000_000));
+        assert!(limiter.permit(10, start + 100_000_000));
+        assert!(!limiter.permit(10, start + 100_000_000));
+        // Full again after a second idle.
+        let later = start + 3 * NANOS_PER_SECOND;
+        assert_eq!((0..20).filter(|_| limiter.permit(10, later)).count(), 10);
+    }
+
+    #[test]
+    fn permits_every_call_without_a_clock() {
+        let limiter = RateLimiter::new();
+        assert!((0..20).all(|_| permit_at(&limiter, 10, None)));
+        // The bucket wasn't touched.
+        assert_eq!(
+            (0..20).filter(|_| permit_at(&limiter, 10, Some(0))).count(),
+            10
+        );
+    }
+}
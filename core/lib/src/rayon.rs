@@ -0,0 +1,108 @@
+//! The stages of rayon parallel iterators, with the `rayon-metrics` feature.
+//!
+//! `scope_metrics` times a stage in `parallel_stage_seconds{stage}`, and makes it the frame of the
+//! `phase!` blocks and `ffi_section`s run by the threads of the rayon pool meanwhile, which would
+//! otherwise run outside of any instrumented function:
+//!
+//! ```rust
+//! use instrumented::phase;
+//! use rayon::prelude::*;
+//!
+//! let lengths: Vec<usize> = instrumented::rayon::scope_metrics("tokenize", || {
+//!     vec!["a b", "c"]
+//!         .par_iter()
+//!         .map(|line| phase!("split", { line.split(' ').count() }))
+//!         .collect()
+//! });
+//! assert_eq!(lengths, vec![2, 1]);
+//! ```
+//!
+//! The phases are labelled with the stage first, e.g. `tokenize/split`, with the name and ctx of
+//! the innermost function instrumented with `phases` calling `scope_metrics`, or the name of the
+//! stage and an empty ctx. The CPU time of each thread of the pool during the stage is observed in
+//! `parallel_stage_busy_seconds{stage}`, on Linux, macOS and Windows.
+//!
+//! The stage is installed on the threads of the current pool with `rayon::broadcast`, so the
+//! stages of a pool shouldn't overlap, e.g. be started by several threads at once.
+use crate::clock::Instant;
+use crate::phase;
+use std::time::Duration;
+
+lazy_static! {
+    static ref PARALLEL_STAGE: prometheus::HistogramVec = {
+        let histogram_opts = crate::time_unit::histogram_opts(
+            "parallel_stage",
+            "Histogram of the times of the stages of parallel iterators",
+        );
+        let histogram = prometheus::HistogramVec::new(histogram_opts, &["stage"]).unwrap();
+
+        crate::register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref PARALLEL_STAGE_BUSY: prometheus::HistogramVec = {
+        let histogram_opts = crate::time_unit::histogram_opts(
+            "parallel_stage_busy",
+            "Histogram of the CPU times of the threads of the pool during the stages of parallel \
+             iterators",
+        );
+        let histogram = prometheus::HistogramVec::new(histogram_opts, &["stage"]).unwrap();
+
+        crate::register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    PARALLEL_STAGE.reset();
+    PARALLEL_STAGE_BUSY.reset();
+}
+
+/// Runs `f`, e.g. a parallel iterator, as the stage `stage` of the threads of the current rayon
+/// pool, see the `rayon` module. The stage is timed even if `f` unwinds.
+pub fn scope_metrics<R>(stage: &'static str, f: impl FnOnce() -> R) -> R {
+    let (name, ctx) = phase::current_frame().unwrap_or((stage, ""));
+    let cpu_starts = ::rayon::broadcast(|_| {
+        phase::enter_stage(name, ctx, stage);
+        crate::thread_cpu_time()
+    });
+    let _guard = StageGuard {
+        stage,
+        start: Instant::now(),
+        cpu_starts,
+    };
+    f()
+}
+
+/// Leaves the stage on the threads of the pool, and observes it, when dropped.
+struct StageGuard {
+    stage: &'static str,
+    start: Instant,
+    /// The CPU times of the threads of the pool when the stage started, by index.
+    cpu_starts: Vec<Option<Duration>>,
+}
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let cpu_starts = &self.cpu_starts;
+        let busy = ::rayon::broadcast(|context| {
+            phase::leave_stage();
+            let start = cpu_starts.get(context.index()).copied().flatten()?;
+            Some(crate::thread_cpu_time()?.checked_sub(start).unwrap_or_default())
+        });
+        if !crate::is_enabled() {
+            return;
+        }
+        let unit = crate::time_unit();
+        PARALLEL_STAGE
+            .with_label_values(&[self.stage])
+            .observe(unit.scale(elapsed));
+        let histogram = PARALLEL_STAGE_BUSY.with_label_values(&[self.stage]);
+        for busy in busy.into_iter().flatten() {
+            histogram.observe(unit.scale(busy));
+        }
+    }
+}
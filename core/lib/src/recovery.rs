@@ -0,0 +1,92 @@
+use crate::clock::Instant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+lazy_static! {
+    /// The reference point of the failure timestamps stored by instrumented functions.
+    static ref EPOCH: Instant = Instant::now();
+}
+
+/// Observes how long a function stayed broken into `function_recovery_seconds`, when a success
+/// follows one or more errors.
+///
+/// `failing_since` holds the time of the first error of the current failure episode, in
+/// nanoseconds since a process epoch plus one, or 0 while the function succeeds.
+#[doc(hidden)]
+pub fn observe_recovery_for(
+    name: &'static str,
+    ctx: &'static str,
+    failing_since: &AtomicU64,
+    ok: bool,
+) {
+    observe_recovery_in_crate(name, ctx, "", failing_since, ok);
+}
+
+/// Observes how long a function defined in the crate `krate` stayed broken, for the `crate`
+/// label.
+#[doc(hidden)]
+pub fn observe_recovery_in_crate(
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    failing_since: &AtomicU64,
+    ok: bool,
+) {
+    if !crate::is_enabled() {
+        return;
+    }
+    let elapsed = EPOCH.elapsed();
+    let now = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos()) + 1;
+    if let Some(recovery) = transition(failing_since, now, ok) {
+        crate::crate_label::with_values(&["func_call", name, ctx], krate, |labels| {
+            crate::FUNC_RECOVERY
+                .with_label_values(labels)
+                .observe(crate::time_unit().scale(recovery))
+        });
+    }
+}
+
+/// Updates the state of a function with the outcome of a call at `now`, returning the duration
+/// of the failure episode it ended, if any.
+fn transition(failing_since: &AtomicU64, now: u64, ok: bool) -> Option<Duration> {
+    if !ok {
+        // Only the first error of an episode starts it.
+        let _ = failing_since.compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed);
+        return None;
+    }
+    // Avoids a write on every success.
+    if failing_since.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    match failing_since.swap(0, Ordering::Relaxed) {
+        // Another success ended the episode concurrently.
+        0 => None,
+        // Concurrent calls can complete out of order.
+        since if since > now => None,
+        since => Some(Duration::from_nanos(now - since)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transition;
+    use std::sync::atomic::AtomicU64;
+    use std::time::Duration;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn observes_failure_episodes() {
+        let state = AtomicU64::new(0);
+        assert_eq!(transition(&state, SECOND, true), None);
+        assert_eq!(transition(&state, 2 * SECOND, false), None);
+        assert_eq!(transition(&state, 3 * SECOND, false), None);
+        assert_eq!(
+            transition(&state, 5 * SECOND + SECOND / 2, true),
+            Some(Duration::from_millis(3500))
+        );
+        assert_eq!(transition(&state, 6 * SECOND, true), None);
+        // An episode that never recovers isn't observed.
+        assert_eq!(transition(&state, 7 * SECOND, false), None);
+    }
+}
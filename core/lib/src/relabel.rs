@@ -0,0 +1,284 @@
+//! Relabeling of the scraped metrics, to drop or relabel series at the exporter without changing
+//! code, e.g. to drop the high-cardinality `err` label during an incident, with the `relabel`
+//! feature.
+//!
+//! Set with `Config::relabel`, or at runtime with the `/admin/relabel` endpoint. The rules are
+//! applied in order to the metrics served by the exporter:
+//!
+//! ```rust,no_run
+//! use instrumented::relabel::RelabelRule;
+//!
+//! let config = instrumented::Config::new("127.0.0.1:5000")
+//!     .relabel(RelabelRule::drop_metric("function_cpu_.*").unwrap())
+//!     .relabel(RelabelRule::drop_label("function_error_total", "err").unwrap())
+//!     .relabel(RelabelRule::add_label("cluster", "eu-1").unwrap());
+//! instrumented::init_with_config(config);
+//! ```
+//!
+//! The series that a dropped label collapses together are aggregated: the values of counters,
+//! gauges and untyped series, and the counts, sums and buckets of histograms, are summed, and the
+//! quantiles of summaries are left out. Patterns are regexes matching the whole metric name, as in
+//! Prometheus. The buckets of native histograms are left as recorded.
+use crate::poison::RwLockExt;
+use crate::prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+use regex::Regex;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::RwLock;
+
+lazy_static! {
+    static ref RULES: RwLock<Vec<RelabelRule>> = RwLock::new(Vec::new());
+}
+
+/// A rule applied to the scraped metrics.
+#[derive(Debug, Clone)]
+pub enum RelabelRule {
+    /// Drops the families whose name matches.
+    DropMetric(Regex),
+    /// Drops a label from the families whose name matches, aggregating the series left with the
+    /// same labels.
+    DropLabel { metric: Regex, label: String },
+    /// Adds a label to every series, replacing its value if the series already has it.
+    AddLabel { label: String, value: String },
+}
+
+/// A relabel rule that can't be applied.
+#[derive(Debug, Clone)]
+pub enum RelabelError {
+    /// A pattern isn't a valid regex.
+    InvalidPattern(regex::Error),
+    /// A label name isn't a valid prometheus label name.
+    InvalidLabelName(String),
+}
+
+impl fmt::Display for RelabelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelabelError::InvalidPattern(err) => err.fmt(f),
+            RelabelError::InvalidLabelName(name) => write!(f, "invalid label name {:?}", name),
+        }
+    }
+}
+
+impl Error for RelabelError {}
+
+impl From<regex::Error> for RelabelError {
+    fn from(err: regex::Error) -> Self {
+        RelabelError::InvalidPattern(err)
+    }
+}
+
+impl RelabelRule {
+    /// Drops the families whose name matches `pattern`.
+    pub fn drop_metric(pattern: &str) -> Result<Self, RelabelError> {
+        Ok(RelabelRule::DropMetric(anchored(pattern)?))
+    }
+
+    /// Drops `label` from the families whose name matches `pattern`.
+    pub fn drop_label(pattern: &str, label: &str) -> Result<Self, RelabelError> {
+        Ok(RelabelRule::DropLabel {
+            metric: anchored(pattern)?,
+            label: label_name(label)?,
+        })
+    }
+
+    /// Adds `label="value"` to every series.
+    pub fn add_label(label: &str, value: &str) -> Result<Self, RelabelError> {
+        Ok(RelabelRule::AddLabel {
+            label: label_name(label)?,
+            value: value.to_string(),
+        })
+    }
+
+    /// Parses a rule written as by `Display`: `drop_metric <pattern>`,
+    /// `drop_label <pattern> <label>` or `add_label <label>=<value>`.
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let mut words = rule.split_whitespace();
+        let parsed = match (words.next(), words.next(), words.next()) {
+            (Some("drop_metric"), Some(pattern), None) => Self::drop_metric(pattern),
+            (Some("drop_label"), Some(pattern), Some(label)) => Self::drop_label(pattern, label),
+            (Some("add_label"), Some(pair), None) => match pair.find('=') {
+                Some(at) => Self::add_label(&pair[..at], &pair[at + 1..]),
+                None => return Err(format!("expected `<label>=<value>`, got {:?}", pair)),
+            },
+            _ => return Err(format!("invalid relabel rule {:?}", rule)),
+        };
+        if words.next().is_some() {
+            return Err(format!("invalid relabel rule {:?}", rule));
+        }
+        parsed.map_err(|err| err.to_string())
+    }
+}
+
+impl fmt::Display for RelabelRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelabelRule::DropMetric(metric) => write!(f, "drop_metric {}", unanchored(metric)),
+            RelabelRule::DropLabel { metric, label } => {
+                write!(f, "drop_label {} {}", unanchored(metric), label)
+            }
+            RelabelRule::AddLabel { label, value } => write!(f, "add_label {}={}", label, value),
+        }
+    }
+}
+
+fn anchored(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+fn label_name(label: &str) -> Result<String, RelabelError> {
+    if !crate::validate::is_label_name(label) {
+        return Err(RelabelError::InvalidLabelName(label.to_string()));
+    }
+    Ok(label.to_string())
+}
+
+fn unanchored(regex: &Regex) -> &str {
+    let pattern = regex.as_str();
+    &pattern[4..pattern.len() - 2]
+}
+
+/// Replaces the rules applied to the scraped metrics.
+pub fn set_relabel_rules(rules: Vec<RelabelRule>) {
+    *RULES.write_or_recover() = rules;
+}
+
+/// Returns the rules applied to the scraped metrics.
+pub fn relabel_rules() -> Vec<RelabelRule> {
+    RULES.read_or_recover().clone()
+}
+
+/// Applies the rules to the families, in order.
+pub(crate) fn apply(mut families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+    for rule in RULES.read_or_recover().iter() {
+        match rule {
+            RelabelRule::DropMetric(metric) => {
+                families.retain(|family| !metric.is_match(family.get_name()))
+            }
+            RelabelRule::DropLabel { metric, label } => {
+                for family in &mut families {
+                    if metric.is_match(family.get_name()) {
+                        drop_label(family, label);
+                    }
+                }
+            }
+            RelabelRule::AddLabel { label, value } => {
+                for family in &mut families {
+                    for metric in family.mut_metric().iter_mut() {
+                        add_label(metric, label, value);
+                    }
+                }
+            }
+        }
+    }
+    families
+}
+
+fn drop_label(family: &mut MetricFamily, label: &str) {
+    let kind = family.get_field_type();
+    let mut merged: Vec<Metric> = vec![];
+    // The index in `merged` of the series with each set of labels.
+    let mut indexes: HashMap<Vec<(String, String)>, usize> = HashMap::new();
+    for mut metric in family.take_metric().into_iter() {
+        let labels = metric.take_label();
+        metric.set_label(
+            labels
+                .into_iter()
+                .filter(|pair| pair.get_name() != label)
+                .collect(),
+        );
+        let key = metric
+            .get_label()
+            .iter()
+            .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+            .collect();
+        match indexes.get(&key) {
+            Some(&index) => aggregate(kind, &mut merged[index], &metric),
+            None => {
+                indexes.insert(key, merged.len());
+                merged.push(metric);
+            }
+        }
+    }
+    family.set_metric(merged.into());
+}
+
+/// Adds the values of `metric` to `into`, which has the same labels.
+fn aggregate(kind: MetricType, into: &mut Metric, metric: &Metric) {
+    match kind {
+        MetricType::COUNTER => {
+            let value = into.get_counter().get_value() + metric.get_counter().get_value();
+            into.mut_counter().set_value(value);
+        }
+        MetricType::GAUGE => {
+            let value = into.get_gauge().get_value() + metric.get_gauge().get_value();
+            into.mut_gauge().set_value(value);
+        }
+        MetricType::UNTYPED => {
+            let value = into.get_untyped().get_value() + metric.get_untyped().get_value();
+            into.mut_untyped().set_value(value);
+        }
+        MetricType::HISTOGRAM => {
+            let histogram = metric.get_histogram();
+            let into = into.mut_histogram();
+            into.set_sample_count(into.get_sample_count() + histogram.get_sample_count());
+            into.set_sample_sum(into.get_sample_sum() + histogram.get_sample_sum());
+            for bucket in into.mut_bucket().iter_mut() {
+                let count = histogram
+                    .get_bucket()
+                    .iter()
+                    .find(|other| other.get_upper_bound() == bucket.get_upper_bound())
+                    .map_or(0, |other| other.get_cumulative_count());
+                bucket.set_cumulative_count(bucket.get_cumulative_count() + count);
+            }
+        }
+        MetricType::SUMMARY => {
+            let summary = metric.get_summary();
+            let into = into.mut_summary();
+            into.set_sample_count(into.get_sample_count() + summary.get_sample_count());
+            into.set_sample_sum(into.get_sample_sum() + summary.get_sample_sum());
+            into.mut_quantile().clear();
+        }
+    }
+}
+
+/// Adds a label to a series, keeping its labels sorted by name.
+fn add_label(metric: &mut Metric, label: &str, value: &str) {
+    let mut labels = metric.take_label().into_vec();
+    let at = labels
+        .iter()
+        .position(|pair| pair.get_name() >= label)
+        .unwrap_or_else(|| labels.len());
+    if labels.get(at).map(LabelPair::get_name) == Some(label) {
+        labels[at].set_value(value.to_string());
+    } else {
+        let mut pair = LabelPair::new();
+        pair.set_name(label.to_string());
+        pair.set_value(value.to_string());
+        labels.insert(at, pair);
+    }
+    metric.set_label(labels.into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RelabelRule;
+
+    #[test]
+    fn parses_rules_as_displayed() {
+        for rule in &[
+            "drop_metric function_cpu_.*",
+            "drop_label function_error_total err",
+            "add_label cluster=eu-1",
+        ] {
+            assert_eq!(RelabelRule::parse(rule).unwrap().to_string(), *rule);
+        }
+        assert!(RelabelRule::parse("drop_metric (").is_err());
+        assert!(RelabelRule::parse("add_label cluster").is_err());
+        assert!(RelabelRule::parse("add_label clu-ster=eu-1").is_err());
+        assert!(RelabelRule::parse("add_label __name__=up").is_err());
+        assert!(RelabelRule::parse("drop_label function_error_total 1err").is_err());
+        assert!(RelabelRule::parse("rename a b").is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! Pushes the metrics to a Prometheus `remote_write` endpoint, for deployments that can't be
+//! scraped.
+//!
+//! Requires the `remote-write` feature.
+use crate::poison::LockExt;
+use crate::prometheus::proto::MetricFamily;
+use crate::prometheus::IntCounter;
+use crate::protobuf::{write_bytes, write_key, write_varint, WIRE_FIXED64, WIRE_VARINT};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// The number of attempts of a push, before it's counted as failed.
+const ATTEMPTS: u32 = 3;
+/// The delay before the first retry of a push, doubled on every retry.
+const BACKOFF: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    static ref PUSH_FAILURES: IntCounter = {
+        let counter = IntCounter::new(
+            "remote_write_failures_total",
+            "Number of pushes to the remote_write endpoint that failed after all retries",
+        )
+        .unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    /// The endpoint and credentials of `init_remote_write`, for the final push of
+    /// `instrumented::shutdown`.
+    static ref TARGET: Mutex<Option<(String, Auth)>> = Mutex::new(None);
+}
+
+/// The credentials sent to the remote_write endpoint.
+#[derive(Debug, Clone)]
+pub enum Auth {
+    None,
+    /// An `Authorization: Bearer <token>` header.
+    Bearer(String),
+    /// HTTP basic authentication.
+    Basic {
+        username: String,
+        password: String,
+    },
+}
+
+/// Starts a thread pushing the gathered metrics to the remote_write endpoint at `url` every
+/// `interval`.
+///
+/// Each push is retried with an exponential backoff, and counted in
+/// `remote_write_failures_total` when all attempts failed. The samples are timestamped with the
+/// wall clock time of the gather.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// instrumented::remote_write::init_remote_write(
+///     "https://prometheus.example.com/api/v1/write",
+///     Duration::from_secs(15),
+///     instrumented::remote_write::Auth::Bearer("secret".into()),
+/// )
+/// .unwrap();
+/// ```
+pub fn init_remote_write(url: &str, interval: Duration, auth: Auth) -> reqwest::Result<()> {
+    lazy_static::initialize(&PUSH_FAILURES);
+    let client = reqwest::Client::builder().timeout(interval).build()?;
+    crate::init_state::mark_initialized();
+    info!("Pushing metrics to {} every {:?}", url, interval);
+    let url = url.to_string();
+    *TARGET.lock_or_recover() = Some((url.clone(), auth.clone()));
+
+    thread::Builder::new()
+        .name("instrumented-remote-write".into())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            let body = encode(&crate::gather(), now());
+            if !push_with_retries(&client, &url, &auth, &body) {
+                PUSH_FAILURES.inc();
+            }
+        })
+        .expect("spawning the remote_write thread");
+
+    Ok(())
+}
+
+/// Pushes the metric families once to the endpoint of `init_remote_write`, if any, waiting at
+/// most `timeout`. Unlike the periodic pushes, a failure isn't retried.
+pub(crate) fn final_push(families: &[MetricFamily], timeout: Duration) {
+    let target = TARGET.lock_or_recover().clone();
+    let (url, auth) = match target {
+        Some(target) => target,
+        None => return,
+    };
+    let client = match reqwest::Client::builder().timeout(timeout).build() {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("Unable to push the final metrics to {}: {}", url, err);
+            PUSH_FAILURES.inc();
+            return;
+        }
+    };
+    if let Err(err) = push(&client, &url, &auth, &encode(families, now())) {
+        warn!("Unable to push the final metrics to {}: {}", url, err);
+        PUSH_FAILURES.inc();
+    }
+}
+
+/// The wall clock time, in milliseconds since the unix epoch, as samples are timestamped with
+/// the wall clock.
+fn now() -> i64 {
+    crate::clock::since_epoch(crate::clock::system_time()).as_millis() as i64
+}
+
+fn push_with_retries(client: &reqwest::Client, url: &str, auth: &Auth, body: &[u8]) -> bool {
+    let mut backoff = BACKOFF;
+    for attempt in 1..=ATTEMPTS {
+        match push(client, url, auth, body) {
+            Ok(()) => return true,
+            Err(err) => {
+                warn!(
+                    "Unable to push metrics to {} (attempt {}/{}): {}",
+                    url, attempt, ATTEMPTS, err
+                );
+                if attempt < ATTEMPTS {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+            }
+        }
+    }
+    false
+}
+
+fn push(client: &reqwest::Client, url: &str, auth: &Auth, body: &[u8]) -> Result<(), String> {
+    let compressed = snap::raw::Encoder::new()
+        .compress_vec(body)
+        .map_err(|err| err.to_string())?;
+    let request = client
+        .post(url)
+        .header("Content-Encoding", "snappy")
+        .header("Content-Type", "application/x-protobuf")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .body(compressed);
+    let request = match auth {
+        Auth::None => request,
+        Auth::Bearer(token) => request.bearer_auth(token),
+        Auth::Basic { username, password } => request.basic_auth(username, Some(password)),
+    };
+    let response = request.send().map_err(|err| err.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("unexpected status {}", response.status()))
+    }
+}
+
+/// Encodes the metric families as a remote_write `WriteRequest` protobuf message, with every
+/// sample at `timestamp` (in milliseconds since the unix epoch).
+pub(crate) fn encode(families: &[MetricFamily], timestamp: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for series in crate::series::series(families) {
+        let mut time_series = Vec::new();
+        for (name, value) in &series.labels {
+            let mut label = Vec::new();
+            write_bytes(&mut label, 1, name.as_bytes());
+            write_bytes(&mut label, 2, value.as_bytes());
+            write_bytes(&mut time_series, 1, &label);
+        }
+        let mut sample = Vec::new();
+        write_key(&mut sample, 1, WIRE_FIXED64);
+        sample.extend_from_slice(&series.value.to_bits().to_le_bytes());
+        write_key(&mut sample, 2, WIRE_VARINT);
+        write_varint(&mut sample, timestamp as u64);
+        write_bytes(&mut time_series, 2, &sample);
+
+        write_bytes(&mut request, 1, &time_series);
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode;
+    use crate::prometheus::core::Collector;
+    use crate::prometheus::{Histogram, HistogramOpts};
+
+    #[test]
+    fn histogram_series() {
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("latency", "help").buckets(vec![1.0])).unwrap();
+        histogram.observe(0.5);
+        let encoded = encode(&histogram.collect(), 1);
+        let text = String::from_utf8_lossy(&encoded);
+        for name in &["latency_bucket", "+Inf", "latency_sum", "latency_count"] {
+            assert!(text.contains(name), "{}", name);
+        }
+    }
+}
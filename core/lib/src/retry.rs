@@ -0,0 +1,229 @@
+//! Retry loops around fallible operations, recorded as one logical operation with its attempts.
+//!
+//! `instrumented_retry` and `instrumented_retry_async` run an operation until it succeeds or the
+//! attempts of the policy are exhausted, and record, with the `name` and `ctx` labels:
+//!
+//! * `function_retry_attempts` - Histogram of the attempts per logical operation
+//! * `function_retries_total` - Number of attempts after the first
+//! * `function_retry_exhausted_total` - Number of operations that failed on their last attempt
+//!
+//! An operation that is itself instrumented keeps recording each attempt as a call:
+//!
+//! ```rust
+//! use instrumented::retry::{instrumented_retry, RetryPolicy};
+//! use std::time::Duration;
+//!
+//! let mut attempts = 0;
+//! let policy = RetryPolicy::new(5).exponential_backoff(Duration::from_millis(1));
+//! let result = instrumented_retry("connect", "db", policy, || {
+//!     attempts += 1;
+//!     if attempts < 3 { Err("refused") } else { Ok(attempts) }
+//! });
+//! assert_eq!(result, Ok(3));
+//! ```
+use crate::prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts};
+use std::future::Future;
+use std::time::Duration;
+
+lazy_static! {
+    static ref RETRY_ATTEMPTS: HistogramVec = {
+        let histogram_opts = HistogramOpts::new(
+            "function_retry_attempts",
+            "Histogram of the attempts per retried operation",
+        )
+        .buckets(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 15.0, 20.0]);
+        let histogram = HistogramVec::new(histogram_opts, &["type", "name", "ctx"]).unwrap();
+
+        crate::register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref RETRIES: IntCounterVec = {
+        let counter_opts = Opts::new(
+            "function_retries_total",
+            "Number of attempts of retried operations after their first",
+        );
+        let counter = IntCounterVec::new(counter_opts, &["type", "name", "ctx"]).unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref RETRY_EXHAUSTED: IntCounterVec = {
+        let counter_opts = Opts::new(
+            "function_retry_exhausted_total",
+            "Number of retried operations that failed on their last attempt",
+        );
+        let counter = IntCounterVec::new(counter_opts, &["type", "name", "ctx"]).unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+/// How many times an operation is attempted, and how long to wait between the attempts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    multiplier: f64,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Attempts an operation up to `max_attempts` times, at least once, without waiting between
+    /// the attempts.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_secs(0),
+            multiplier: 1.0,
+            max_backoff: Duration::from_secs(0),
+        }
+    }
+
+    /// Waits `backoff` between the attempts.
+    pub fn fixed_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self.multiplier = 1.0;
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Waits `initial` after the first attempt, and twice as long after each of the next ones,
+    /// up to a minute unless limited by `max_backoff`.
+    pub fn exponential_backoff(mut self, initial: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.multiplier = 2.0;
+        self.max_backoff = Duration::from_secs(60).max(initial);
+        self
+    }
+
+    /// Limits the wait between two attempts.
+    pub fn max_backoff(mut self, max: Duration) -> Self {
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns the wait after the failed attempt `attempt`, counted from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        let backoff = crate::duration_to_seconds(self.initial_backoff) * factor;
+        let max = crate::duration_to_seconds(self.max_backoff);
+        Duration::from_secs_f64(backoff.min(max))
+    }
+}
+
+fn record(name: &'static str, ctx: &'static str, attempts: u32, exhausted: bool) {
+    if !crate::is_enabled() {
+        return;
+    }
+    let labels = ["func_call", name, ctx];
+    RETRY_ATTEMPTS
+        .with_label_values(&labels)
+        .observe(f64::from(attempts));
+    if attempts > 1 {
+        RETRIES
+            .with_label_values(&labels)
+            .inc_by(i64::from(attempts - 1));
+    }
+    if exhausted {
+        RETRY_EXHAUSTED.with_label_values(&labels).inc();
+    }
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    RETRY_ATTEMPTS.reset();
+    RETRIES.reset();
+    RETRY_EXHAUSTED.reset();
+}
+
+/// Runs `op` until it succeeds, or until the attempts of `policy` are exhausted, sleeping the
+/// thread between the attempts. Returns the result of the last attempt.
+pub fn instrumented_retry<T, E, F>(
+    name: &'static str,
+    ctx: &'static str,
+    policy: RetryPolicy,
+    mut op: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Result<T, E>,
+{
+    let mut attempt = 1;
+    loop {
+        let result = op();
+        if result.is_ok() || attempt >= policy.max_attempts {
+            record(name, ctx, attempt, result.is_err());
+            return result;
+        }
+        let backoff = policy.backoff(attempt);
+        if backoff > Duration::from_secs(0) {
+            std::thread::sleep(backoff);
+        }
+        attempt += 1;
+    }
+}
+
+/// Runs the futures returned by `op` until one succeeds, or until the attempts of `policy` are
+/// exhausted, like `instrumented_retry`. The waits between the attempts are the futures returned
+/// by `sleep`, e.g. `tokio::time::sleep`, so that any runtime can be used.
+pub async fn instrumented_retry_async<T, E, F, Fut, S, SleepFut>(
+    name: &'static str,
+    ctx: &'static str,
+    policy: RetryPolicy,
+    mut op: F,
+    mut sleep: S,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    S: FnMut(Duration) -> SleepFut,
+    SleepFut: Future<Output = ()>,
+{
+    let mut attempt = 1;
+    loop {
+        let result = op().await;
+        if result.is_ok() || attempt >= policy.max_attempts {
+            record(name, ctx, attempt, result.is_err());
+            return result;
+        }
+        let backoff = policy.backoff(attempt);
+        if backoff > Duration::from_secs(0) {
+            sleep(backoff).await;
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::new(5)
+            .exponential_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(300));
+        let backoffs: Vec<_> = (1..5).map(|attempt| policy.backoff(attempt)).collect();
+        assert_eq!(
+            backoffs,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(300),
+                Duration::from_millis(300),
+            ]
+        );
+        let fixed = RetryPolicy::new(3).fixed_backoff(Duration::from_millis(50));
+        assert_eq!(fixed.backoff(2), Duration::from_millis(50));
+        assert_eq!(RetryPolicy::new(0).max_attempts(), 1);
+        assert_eq!(RetryPolicy::new(3).backoff(1), Duration::from_secs(0));
+    }
+}
@@ -0,0 +1,65 @@
+//! Flattening of metric families into series, for the push exporters.
+use crate::prometheus::proto::{MetricFamily, MetricType};
+
+/// A series: its labels, including `__name__`, and its value.
+pub(crate) struct Series {
+    pub(crate) labels: Vec<(String, String)>,
+    pub(crate) value: f64,
+}
+
+/// Flattens the metric families into series, the way they are exposed in the text format.
+pub(crate) fn series(families: &[MetricFamily]) -> Vec<Series> {
+    let mut series = Vec::new();
+    for family in families {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let labels: Vec<(String, String)> = metric
+                .get_label()
+                .iter()
+                .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+                .collect();
+            let mut push = |suffix: &str, extra: Option<(&str, String)>, value: f64| {
+                let mut labels = labels.clone();
+                labels.push(("__name__".to_string(), format!("{}{}", name, suffix)));
+                if let Some((label, label_value)) = extra {
+                    labels.push((label.to_string(), label_value));
+                }
+                // Remote write requires the labels to be sorted by name.
+                labels.sort();
+                series.push(Series { labels, value });
+            };
+            match family.get_field_type() {
+                MetricType::COUNTER => push("", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => push("", None, metric.get_gauge().get_value()),
+                MetricType::UNTYPED => push("", None, metric.get_untyped().get_value()),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        push(
+                            "_bucket",
+                            Some(("le", bucket.get_upper_bound().to_string())),
+                            bucket.get_cumulative_count() as f64,
+                        );
+                    }
+                    let count = histogram.get_sample_count() as f64;
+                    push("_bucket", Some(("le", "+Inf".to_string())), count);
+                    push("_sum", None, histogram.get_sample_sum());
+                    push("_count", None, count);
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        push(
+                            "",
+                            Some(("quantile", quantile.get_quantile().to_string())),
+                            quantile.get_value(),
+                        );
+                    }
+                    push("_sum", None, summary.get_sample_sum());
+                    push("_count", None, summary.get_sample_count() as f64);
+                }
+            }
+        }
+    }
+    series
+}
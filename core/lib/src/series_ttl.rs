@@ -0,0 +1,117 @@
+//! Eviction of the series of dynamic label values that stopped being updated.
+//!
+//! The `err` label of `function_error_total` and the `thread` label of
+//! `function_called_by_thread_total` take values that may never recur, such as an error message
+//! with a tenant id, or a thread named after a connection. Their series are kept forever by
+//! default. With `set_series_ttl(Some(ttl))`, the last update of each of them is tracked, and the
+//! series not updated for longer than `ttl` are removed when the metrics are gathered:
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! instrumented::series_ttl::set_series_ttl(Some(Duration::from_secs(3600)));
+//! ```
+//!
+//! The series of the other metrics, whose labels are the name and ctx of the functions, or values
+//! bounded by the code, are never removed. A counter whose series was removed starts again from
+//! 0 if its label value recurs, which Prometheus handles as a counter reset. Tracking takes a lock
+//! on every update of the tracked series, so it's only done while a TTL is set.
+use crate::clock::Instant;
+use crate::poison::LockExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The metrics whose series are tracked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Tracked {
+    Errors,
+    CallsByThread,
+}
+
+impl Tracked {
+    fn remove(self, labels: &[&str]) {
+        // The series may already have been removed by a reset of the metrics.
+        let _ = match self {
+            Tracked::Errors => crate::FUNC_ERRORS.remove_label_values(labels),
+            Tracked::CallsByThread => crate::FUNC_CALLED_BY_THREAD.remove_label_values(labels),
+        };
+    }
+}
+
+/// The TTL in nanoseconds, or 0 when series aren't evicted.
+static TTL: AtomicU64 = AtomicU64::new(0);
+/// The time the clock was advanced by, for tests.
+#[cfg(any(test, feature = "test-hooks"))]
+static ADVANCED: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref EPOCH: Instant = Instant::now();
+    /// The time each tracked series was last updated at.
+    static ref UPDATED: Mutex<HashMap<(Tracked, Vec<String>), u64>> = Mutex::new(HashMap::new());
+}
+
+/// Removes the series of dynamic label values not updated for longer than `ttl` when gathering
+/// the metrics, or keeps them forever with `None`, the default.
+pub fn set_series_ttl(ttl: Option<Duration>) {
+    let nanos = ttl.map_or(0, |ttl| (ttl.as_nanos() as u64).max(1));
+    TTL.store(nanos, Ordering::Relaxed);
+    if nanos == 0 {
+        UPDATED.lock_or_recover().clear();
+    }
+}
+
+/// Returns the TTL of the series of dynamic label values.
+pub fn series_ttl() -> Option<Duration> {
+    match TTL.load(Ordering::Relaxed) {
+        0 => None,
+        nanos => Some(Duration::from_nanos(nanos)),
+    }
+}
+
+/// Advances the clock of the TTLs, to test the eviction without waiting.
+#[cfg(any(test, feature = "test-hooks"))]
+#[doc(hidden)]
+pub fn advance_clock(by: Duration) {
+    ADVANCED.fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+}
+
+fn now() -> u64 {
+    #[cfg(any(test, feature = "test-hooks"))]
+    let advanced = ADVANCED.load(Ordering::Relaxed);
+    #[cfg(not(any(test, feature = "test-hooks")))]
+    let advanced = 0;
+    EPOCH.elapsed().as_nanos() as u64 + advanced
+}
+
+/// Records an update of a tracked series.
+pub(crate) fn touch(metric: Tracked, labels: &[&str]) {
+    if TTL.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let labels = labels.iter().map(|label| label.to_string()).collect();
+    UPDATED.lock_or_recover().insert((metric, labels), now());
+}
+
+/// Removes the tracked series not updated within the TTL.
+pub(crate) fn evict() {
+    let ttl = TTL.load(Ordering::Relaxed);
+    if ttl == 0 {
+        return;
+    }
+    let now = now();
+    UPDATED.lock_or_recover().retain(|(metric, labels), updated| {
+        let idle = now.saturating_sub(*updated) > ttl;
+        if idle {
+            let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+            metric.remove(&labels);
+        }
+        !idle
+    });
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    UPDATED.lock_or_recover().clear();
+}
@@ -0,0 +1,55 @@
+//! Stopping the exporters on shutdown, so the last increments aren't lost.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Whether `shutdown` was called.
+static SHUT_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Stops exporting the metrics, before the process exits:
+///
+/// * the process is marked as stopping, if it wasn't, see `instrumented::lifecycle`
+/// * the HTTP servers of `instrumented::init` are stopped gracefully, serving the scrapes in
+///   flight, for at most `timeout`
+/// * the buffered lines of the slow call log are flushed
+/// * the gather hooks are run, observing the durations buffered by `local_metrics` on the calling
+///   thread, and with the `remote-write` feature, the metrics are pushed once
+///   more to the endpoint of `init_remote_write`, waiting at most `timeout`
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// instrumented::init("127.0.0.1:5000");
+/// // ...
+/// instrumented::shutdown(Duration::from_secs(5));
+/// ```
+///
+/// Only the first call shuts down, later calls return immediately.
+#[cfg_attr(
+    not(any(feature = "exporter", feature = "remote-write")),
+    allow(unused_variables)
+)]
+pub fn shutdown(timeout: Duration) {
+    if SHUT_DOWN.swap(true, Ordering::SeqCst) {
+        debug!("Metrics already shut down");
+        return;
+    }
+    info!("Shutting down metrics");
+    crate::lifecycle::mark_stopping();
+
+    #[cfg(feature = "exporter")]
+    {
+        crate::exporter::stop(timeout);
+        crate::slow_log::flush();
+    }
+    crate::lifecycle::stopped();
+    // Gathering runs the hooks, so the final values are up to date.
+    #[cfg(feature = "remote-write")]
+    crate::remote_write::final_push(&crate::gather(), timeout);
+    #[cfg(not(feature = "remote-write"))]
+    crate::gather();
+}
+
+/// Whether `shutdown` was called.
+pub(crate) fn is_shut_down() -> bool {
+    SHUT_DOWN.load(Ordering::SeqCst)
+}
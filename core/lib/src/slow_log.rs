@@ -0,0 +1,198 @@
+//! A log file of slow calls, like a database slow query log, independent from the logger.
+//!
+//! Enabled with `Config::slow_log`. Every call slower than the threshold is appended to the file
+//! as a line such as `2024-05-01T12:00:00Z my_func ctx=payment 1.34s Err(Timeout)`. Writes are
+//! buffered and flushed every second, and by `flush()`, which should be called on shutdown. Once
+//! the file would exceed its maximum size, it's rotated to `<path>.1`, replacing the previous one.
+use crate::observer::{add_observer, Completion, Observer, Outcome};
+use crate::poison::LockExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref WRITER: Mutex<Option<Writer>> = Mutex::new(None);
+}
+
+static REGISTER: Once = Once::new();
+
+struct Writer {
+    path: PathBuf,
+    threshold: Duration,
+    max_size: u64,
+    size: u64,
+    file: BufWriter<File>,
+}
+
+impl Writer {
+    fn open(path: &Path, threshold: Duration, max_size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Writer {
+            path: path.to_path_buf(),
+            threshold,
+            max_size,
+            size: file.metadata()?.len(),
+            file: BufWriter::new(file),
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, &rotated)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+struct SlowLog;
+
+impl Observer for SlowLog {
+    fn on_completion(&self, completion: &Completion) {
+        let mut writer = WRITER.lock_or_recover();
+        let writer = match writer.as_mut() {
+            Some(writer) if completion.duration >= writer.threshold => writer,
+            _ => return,
+        };
+        let line = format_line(completion, crate::clock::unix_time().unwrap_or(0));
+        if let Err(err) = writer.write_line(&line) {
+            warn!(
+                "Unable to write to the slow call log {}: {}",
+                writer.path.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Opens the slow call log, replacing the file of a previous call.
+pub(crate) fn init(path: &Path, threshold: Duration, max_size: u64) {
+    match Writer::open(path, threshold, max_size) {
+        Ok(writer) => *WRITER.lock_or_recover() = Some(writer),
+        Err(err) => {
+            error!(
+                "Unable to open the slow call log {}: {}",
+                path.display(),
+                err
+            );
+            return;
+        }
+    }
+    REGISTER.call_once(|| {
+        add_observer(Box::new(SlowLog));
+        thread::Builder::new()
+            .name("instrumented-slow-log".into())
+            .spawn(|| loop {
+                thread::sleep(FLUSH_INTERVAL);
+                flush();
+            })
+            .expect("spawning the slow call log thread");
+    });
+}
+
+/// Flushes the buffered lines of the slow call log, if enabled. Call it on shutdown, so the calls
+/// since the last periodic flush aren't lost.
+pub fn flush() {
+    if let Some(writer) = WRITER.lock_or_recover().as_mut() {
+        if let Err(err) = writer.file.flush() {
+            warn!(
+                "Unable to flush the slow call log {}: {}",
+                writer.path.display(),
+                err
+            );
+        }
+    }
+}
+
+fn format_line(completion: &Completion, timestamp: u64) -> String {
+    let outcome = match completion.outcome {
+        Outcome::Ok => "Ok".to_string(),
+        Outcome::Err(err) => format!("Err({})", err),
+    };
+    format!(
+        "{} {} ctx={} {:.2}s {}\n",
+        rfc3339(timestamp),
+        completion.name,
+        completion.ctx,
+        completion.duration.as_secs_f64(),
+        outcome
+    )
+}
+
+/// Formats a unix time in seconds as an RFC 3339 UTC timestamp.
+fn rfc3339(timestamp: u64) -> String {
+    // Converts the days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let seconds = timestamp % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_line, rfc3339};
+    use crate::observer::{Completion, Outcome};
+    use std::time::Duration;
+
+    #[test]
+    fn formats_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_714_564_800), "2024-05-01T12:00:00Z");
+    }
+
+    #[test]
+    fn formats_lines() {
+        let completion = Completion {
+            name: "my_func",
+            ctx: "payment",
+            duration: Duration::from_millis(1340),
+            outcome: Outcome::Err("Timeout"),
+        };
+        assert_eq!(
+            format_line(&completion, 1_714_564_800),
+            "2024-05-01T12:00:00Z my_func ctx=payment 1.34s Err(Timeout)\n"
+        );
+    }
+}
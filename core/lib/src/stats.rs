@@ -0,0 +1,56 @@
+//! Reads of the metrics of an instrumented function, for decisions made in-process, e.g. by an
+//! adaptive concurrency limiter, without scraping the exporter.
+//!
+//! These are point-in-time reads of the built-in metrics: they can be stale as soon as they
+//! return, and the reads of different metrics aren't taken together. Rates, such as the errors of
+//! the last N seconds, are the difference of two reads. A function that wasn't called yet reads
+//! as 0, and reading doesn't create its series.
+//!
+//! With the `backend-metrics` backend, the function metrics are recorded into the `metrics`
+//! facade instead, and every read returns 0.
+//!
+//! ```rust
+//! let inflight = instrumented::stats::inflight("fetch", "default");
+//! let errors = instrumented::stats::errors("fetch", "default");
+//! assert_eq!((inflight, errors), (0, 0));
+//! ```
+use crate::functions::label;
+use crate::prometheus::core::Collector;
+use crate::prometheus::proto::Metric;
+
+/// Sums `value` over the series of `collector` labelled with the function `name` and `ctx`.
+fn sum<C: Collector, T: std::iter::Sum<T>>(
+    collector: &C,
+    name: &str,
+    ctx: &str,
+    value: fn(&Metric) -> T,
+) -> T {
+    collector
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .filter(|metric| label(metric, "name") == name && label(metric, "ctx") == ctx)
+        .map(value)
+        .sum()
+}
+
+/// Returns the number of calls of a function currently in flight.
+pub fn inflight(name: &str, ctx: &str) -> i64 {
+    sum(&*crate::FUNC_INFLIGHT, name, ctx, |metric| {
+        metric.get_gauge().get_value() as i64
+    })
+}
+
+/// Returns the number of times a function was called.
+pub fn calls(name: &str, ctx: &str) -> u64 {
+    sum(&*crate::FUNC_CALLED, name, ctx, |metric| {
+        metric.get_counter().get_value() as u64
+    })
+}
+
+/// Returns the number of errors returned by a function, of every kind.
+pub fn errors(name: &str, ctx: &str) -> u64 {
+    sum(&*crate::FUNC_ERRORS, name, ctx, |metric| {
+        metric.get_counter().get_value() as u64
+    })
+}
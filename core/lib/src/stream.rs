@@ -0,0 +1,114 @@
+use crate::clock::Instant;
+use futures_core::Stream;
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Wraps the stream returned by a function instrumented with the `stream` attribute.
+///
+/// Records the time from the function call to the first item, the total time until the stream
+/// completes, and the number of items yielded. Items for which `classify` returns an error label
+/// are counted as errors.
+#[doc(hidden)]
+pub struct InstrumentedStream<S: Stream> {
+    inner: S,
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    started: Instant,
+    classify: fn(&S::Item) -> Option<String>,
+    seen_first_item: bool,
+    finished: bool,
+}
+
+impl<S: Stream> InstrumentedStream<S> {
+    pub fn new(
+        name: &'static str,
+        ctx: &'static str,
+        started: Instant,
+        inner: S,
+        classify: fn(&S::Item) -> Option<String>,
+    ) -> Self {
+        Self::new_in_crate(name, ctx, "", started, inner, classify)
+    }
+
+    /// Wraps the stream of a function defined in the crate `krate`, for the `crate` label.
+    pub fn new_in_crate(
+        name: &'static str,
+        ctx: &'static str,
+        krate: &'static str,
+        started: Instant,
+        inner: S,
+        classify: fn(&S::Item) -> Option<String>,
+    ) -> Self {
+        InstrumentedStream {
+            inner,
+            name,
+            ctx,
+            krate,
+            started,
+            classify,
+            seen_first_item: false,
+            finished: false,
+        }
+    }
+}
+
+impl<S: Stream> Stream for InstrumentedStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        // Safety: `inner` is never moved out of `self`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        let poll = inner.poll_next(cx);
+        if !crate::is_enabled() {
+            return poll;
+        }
+        let labels = ["func_call", this.name, this.ctx];
+        match &poll {
+            Poll::Ready(Some(item)) => {
+                if !this.seen_first_item {
+                    this.seen_first_item = true;
+                    crate::crate_label::with_values(&labels, this.krate, |labels| {
+                        crate::FUNC_STREAM_FIRST_ITEM
+                            .with_label_values(labels)
+                            .observe(crate::time_unit().scale(this.started.elapsed()))
+                    });
+                }
+                crate::crate_label::with_values(&labels, this.krate, |labels| {
+                    crate::FUNC_STREAM_ITEMS.with_label_values(labels).inc()
+                });
+                if let Some(err) = (this.classify)(item) {
+                    crate::inc_error_counter_in_crate(this.name, this.ctx, this.krate, err);
+                }
+            }
+            Poll::Ready(None) if !this.finished => {
+                this.finished = true;
+                crate::crate_label::with_values(&labels, this.krate, |labels| {
+                    crate::FUNC_STREAM_DURATION
+                        .with_label_values(labels)
+                        .observe(crate::time_unit().scale(this.started.elapsed()))
+                });
+            }
+            _ => (),
+        }
+        poll
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// Classifies `Result` stream items, labelling errors with their `Debug` representation.
+#[doc(hidden)]
+pub fn result_item_error<T, E: Debug>(item: &Result<T, E>) -> Option<String> {
+    item.as_ref().err().map(|err| format!("{:?}", err))
+}
+
+/// Classifies stream items that can't be errors.
+#[doc(hidden)]
+pub fn never_item_error<T>(_: &T) -> Option<String> {
+    None
+}
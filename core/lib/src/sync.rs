@@ -0,0 +1,207 @@
+//! Locks that export their contention, with the `sync` feature.
+//!
+//! `Mutex` and `RwLock` wrap the locks of `parking_lot` under a name given at construction. An
+//! acquisition that has to wait is counted in `lock_contended_total{name}`, and its wait is
+//! observed in `lock_wait_seconds{name}`. An uncontended acquisition costs a single branch over
+//! `parking_lot`'s own, and isn't recorded.
+//!
+//! ```rust
+//! use instrumented::sync::Mutex;
+//!
+//! let jobs = Mutex::new("jobs", vec![1, 2]);
+//! jobs.lock().push(3);
+//! assert_eq!(jobs.lock().len(), 3);
+//! ```
+use crate::clock::Instant;
+use std::fmt;
+use std::time::Duration;
+
+pub use parking_lot::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+lazy_static! {
+    static ref LOCK_WAIT: prometheus::HistogramVec = {
+        let mut histogram_opts = crate::time_unit::histogram_opts(
+            "lock_wait",
+            "Histogram of the time waited for a contended lock",
+        );
+        // From a microsecond to about a second, as most waits are far shorter than calls.
+        let first = crate::time_unit().scale(Duration::from_micros(1));
+        histogram_opts.buckets = prometheus::exponential_buckets(first, 4.0, 11).unwrap();
+        let histogram = prometheus::HistogramVec::new(histogram_opts, &["name"]).unwrap();
+
+        crate::register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+    static ref LOCK_CONTENDED: prometheus::IntCounterVec = {
+        let counter_opts = prometheus::Opts::new(
+            "lock_contended_total",
+            "Number of acquisitions of a lock that had to wait",
+        );
+        let counter = prometheus::IntCounterVec::new(counter_opts, &["name"]).unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+/// Records a contended acquisition of the lock `name`, which started waiting at `start`.
+#[cold]
+fn record_wait(name: &str, start: Instant) {
+    if !crate::is_enabled() {
+        return;
+    }
+    LOCK_CONTENDED.with_label_values(&[name]).inc();
+    LOCK_WAIT
+        .with_label_values(&[name])
+        .observe(crate::time_unit().scale(start.elapsed()));
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    LOCK_WAIT.reset();
+    LOCK_CONTENDED.reset();
+}
+
+/// A `parking_lot::Mutex` exporting its contention under `name`.
+pub struct Mutex<T: ?Sized> {
+    name: &'static str,
+    inner: parking_lot::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub fn new(name: &'static str, value: T) -> Self {
+        Mutex {
+            name,
+            inner: parking_lot::Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Acquires the mutex, recording the wait if it's held.
+    #[inline]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        match self.inner.try_lock() {
+            Some(guard) => guard,
+            None => self.lock_contended(),
+        }
+    }
+
+    #[cold]
+    fn lock_contended(&self) -> MutexGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.inner.lock();
+        record_wait(self.name, start);
+        guard
+    }
+
+    /// Acquires the mutex if it isn't held, without recording anything.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.inner.try_lock()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mutex")
+            .field("name", &self.name)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+/// A `parking_lot::RwLock` exporting its contention under `name`, for readers and writers alike.
+pub struct RwLock<T: ?Sized> {
+    name: &'static str,
+    inner: parking_lot::RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub fn new(name: &'static str, value: T) -> Self {
+        RwLock {
+            name,
+            inner: parking_lot::RwLock::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Acquires a shared read lock, recording the wait if a writer holds the lock.
+    #[inline]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        match self.inner.try_read() {
+            Some(guard) => guard,
+            None => self.read_contended(),
+        }
+    }
+
+    #[cold]
+    fn read_contended(&self) -> RwLockReadGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.inner.read();
+        record_wait(self.name, start);
+        guard
+    }
+
+    /// Acquires an exclusive write lock, recording the wait if the lock is held.
+    #[inline]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        match self.inner.try_write() {
+            Some(guard) => guard,
+            None => self.write_contended(),
+        }
+    }
+
+    #[cold]
+    fn write_contended(&self) -> RwLockWriteGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.inner.write();
+        record_wait(self.name, start);
+        guard
+    }
+
+    /// Acquires a read lock if no writer holds the lock, without recording anything.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.inner.try_read()
+    }
+
+    /// Acquires a write lock if the lock isn't held, without recording anything.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.inner.try_write()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RwLock")
+            .field("name", &self.name)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
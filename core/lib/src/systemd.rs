@@ -0,0 +1,100 @@
+//! The listener socket passed by systemd socket activation, see `instrumented::init_from_systemd`.
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+/// The first file descriptor passed by systemd.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Returns whether the `LISTEN_PID` and `LISTEN_FDS` env vars pass at least one socket to the
+/// process `pid`.
+fn is_activated(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> bool {
+    let listen_pid = listen_pid.and_then(|value| value.parse::<u32>().ok());
+    let listen_fds = listen_fds.and_then(|value| value.parse::<u32>().ok());
+    listen_pid == Some(pid) && listen_fds.map_or(false, |fds| fds >= 1)
+}
+
+/// Takes the first socket passed by systemd, at `fd`, when the process is socket activated. The
+/// env vars are removed, so that child processes don't take the socket in turn.
+pub(crate) fn activated_listener_at(fd: RawFd) -> Option<TcpListener> {
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if !is_activated(
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+        std::process::id(),
+    ) {
+        return None;
+    }
+
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    match listener.local_addr() {
+        Ok(_) => Some(listener),
+        Err(err) => {
+            warn!("Ignoring the socket passed by systemd: {}", err);
+            // The descriptor isn't ours to close.
+            std::mem::forget(listener);
+            None
+        }
+    }
+}
+
+/// Takes the first socket passed by systemd, when the process is socket activated.
+pub(crate) fn activated_listener() -> Option<TcpListener> {
+    activated_listener_at(SD_LISTEN_FDS_START)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{activated_listener_at, is_activated};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn activation() {
+        assert!(is_activated(Some("42"), Some("1"), 42));
+        assert!(is_activated(Some("42"), Some("2"), 42));
+        assert!(!is_activated(Some("43"), Some("1"), 42));
+        assert!(!is_activated(Some("42"), Some("0"), 42));
+        assert!(!is_activated(None, Some("1"), 42));
+        assert!(!is_activated(Some("42"), None, 42));
+    }
+
+    // The env vars are process-global, so every case runs in the same test.
+    #[test]
+    fn takes_the_passed_socket() {
+        let bound = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = bound.local_addr().unwrap();
+        let fd = bound.into_raw_fd();
+
+        std::env::set_var("LISTEN_PID", std::process::id().to_string());
+        std::env::set_var("LISTEN_FDS", "1");
+        let listener = activated_listener_at(fd).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), addr);
+        assert!(std::env::var("LISTEN_PID").is_err());
+        // Not activated twice.
+        assert!(activated_listener_at(fd).is_none());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut buffer = [0; 4];
+        server.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        // A socket that isn't a TCP listener is left alone.
+        let (first, _second) = UnixStream::pair().unwrap();
+        let fd = first.into_raw_fd();
+        std::env::set_var("LISTEN_PID", std::process::id().to_string());
+        std::env::set_var("LISTEN_FDS", "1");
+        assert!(activated_listener_at(fd).is_none());
+        drop(unsafe { UnixStream::from_raw_fd(fd) });
+    }
+}
@@ -0,0 +1,171 @@
+//! Named worker threads counted in the metrics, with `instrumented::thread::spawn`.
+//!
+//! Each thread spawned here is exported under its name:
+//!
+//! * `threads_spawned_total{name}` - The number of threads spawned
+//! * `threads_alive{name}` - The number of threads running, decremented when the thread returns
+//!   or panics
+//! * `thread_lifetime_seconds{name}` - With `Builder::time_lifetime`, the histogram of how long
+//!   the threads ran
+//!
+//! The name is also given to the OS thread, which is the only context the instrumented functions
+//! keep per thread: it's the `thread` label of `label_thread`, and shows up in debuggers and
+//! `top -H`.
+//!
+//! ```rust
+//! let worker = instrumented::thread::spawn("worker", || 40 + 2);
+//! assert_eq!(worker.join().unwrap(), 42);
+//! ```
+use crate::clock::Instant;
+use crate::prometheus::{Histogram, IntGauge};
+use std::io;
+use std::thread::JoinHandle;
+
+lazy_static! {
+    static ref THREADS_SPAWNED: prometheus::IntCounterVec = {
+        let counter_opts =
+            prometheus::Opts::new("threads_spawned_total", "Number of threads spawned");
+        let counter = prometheus::IntCounterVec::new(counter_opts, &["name"]).unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+    static ref THREADS_ALIVE: prometheus::IntGaugeVec = {
+        let gauge_opts = prometheus::Opts::new("threads_alive", "Number of threads running");
+        let gauge = prometheus::IntGaugeVec::new(gauge_opts, &["name"]).unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+    static ref THREAD_LIFETIME: prometheus::HistogramVec = {
+        let histogram_opts = prometheus::HistogramOpts::new(
+            "thread_lifetime_seconds",
+            "Histogram of how long threads ran",
+        )
+        .buckets(vec![
+            0.001, 0.01, 0.1, 1.0, 10.0, 60.0, 600.0, 3600.0, 21600.0, 86400.0,
+        ]);
+        let histogram = prometheus::HistogramVec::new(histogram_opts, &["name"]).unwrap();
+
+        crate::register_builtin(Box::new(histogram.clone()));
+
+        histogram
+    };
+}
+
+#[cfg(feature = "exporter")]
+pub(crate) fn reset() {
+    THREADS_SPAWNED.reset();
+    THREADS_ALIVE.reset();
+    THREAD_LIFETIME.reset();
+}
+
+/// Counts a thread as alive from its spawn until it's dropped, when the thread returns, panics,
+/// or couldn't be spawned.
+struct Alive {
+    alive: IntGauge,
+    lifetime: Option<Histogram>,
+    /// Set once the thread runs, so that a thread that couldn't be spawned isn't timed.
+    start: Option<Instant>,
+}
+
+impl Drop for Alive {
+    fn drop(&mut self) {
+        self.alive.dec();
+        if let (Some(lifetime), Some(start)) = (&self.lifetime, self.start) {
+            lifetime.observe(crate::duration_to_seconds(start.elapsed()));
+        }
+    }
+}
+
+/// Configures an instrumented thread, like `std::thread::Builder`.
+///
+/// ```rust
+/// let worker = instrumented::thread::Builder::new("indexer")
+///     .time_lifetime()
+///     .spawn(|| ())
+///     .unwrap();
+/// worker.join().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Builder {
+    name: String,
+    stack_size: Option<usize>,
+    time_lifetime: bool,
+}
+
+impl Builder {
+    pub fn new(name: &str) -> Self {
+        Builder {
+            name: name.to_string(),
+            stack_size: None,
+            time_lifetime: false,
+        }
+    }
+
+    /// Sets the stack size of the thread, in bytes.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Observes how long the thread ran in `thread_lifetime_seconds`.
+    pub fn time_lifetime(mut self) -> Self {
+        self.time_lifetime = true;
+        self
+    }
+
+    /// Spawns the thread, returning an error if the OS couldn't.
+    pub fn spawn<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let enabled = crate::is_enabled();
+        let mut alive = if enabled {
+            let alive = THREADS_ALIVE.with_label_values(&[&self.name]);
+            alive.inc();
+            Some(Alive {
+                alive,
+                lifetime: if self.time_lifetime {
+                    Some(THREAD_LIFETIME.with_label_values(&[&self.name]))
+                } else {
+                    None
+                },
+                start: None,
+            })
+        } else {
+            None
+        };
+
+        let mut builder = std::thread::Builder::new().name(self.name.clone());
+        if let Some(size) = self.stack_size {
+            builder = builder.stack_size(size);
+        }
+        let handle = builder.spawn(move || {
+            if let Some(alive) = &mut alive {
+                alive.start = Some(Instant::now());
+            }
+            let _alive = alive;
+            f()
+        })?;
+        if enabled {
+            THREADS_SPAWNED.with_label_values(&[&self.name]).inc();
+        }
+        Ok(handle)
+    }
+}
+
+/// Spawns a thread named `name`, counted in `threads_spawned_total` and `threads_alive`.
+///
+/// Panics if the OS can't spawn the thread, like `std::thread::spawn`; use `Builder` to get the
+/// error instead, or to time the lifetime of the thread.
+pub fn spawn<F, T>(name: &str, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Builder::new(name).spawn(f).expect("failed to spawn thread")
+}
@@ -0,0 +1,151 @@
+use crate::prometheus::core::{Collector, Desc};
+use crate::prometheus::proto::MetricFamily;
+use crate::prometheus::{IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts};
+
+lazy_static! {
+    static ref POOL_SIZE: IntGaugeVec = {
+        let gauge_opts = Opts::new("thread_pool_size", "Number of threads in a thread pool");
+        let gauge = IntGaugeVec::new(gauge_opts, &["pool"]).unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+    static ref POOL_QUEUE_DEPTH: IntGaugeVec = {
+        let gauge_opts = Opts::new(
+            "thread_pool_queue_depth",
+            "Number of tasks waiting in the queue of a thread pool",
+        );
+        let gauge = IntGaugeVec::new(gauge_opts, &["pool"]).unwrap();
+
+        crate::register_builtin(Box::new(gauge.clone()));
+
+        gauge
+    };
+    static ref POOL_COMPLETED: IntCounterVec = {
+        let counter_opts = Opts::new(
+            "thread_pool_completed_total",
+            "Number of tasks completed by a thread pool",
+        );
+        let counter = IntCounterVec::new(counter_opts, &["pool"]).unwrap();
+
+        crate::register_builtin(Box::new(counter.clone()));
+
+        counter
+    };
+}
+
+/// Metrics of a named thread pool, exported with a `pool` label.
+///
+/// ```rust
+/// let metrics = instrumented::ThreadPoolMetrics::new("workers");
+/// metrics.set_pool_size(8);
+/// metrics.set_queue_depth(3);
+/// metrics.inc_completed();
+/// ```
+#[derive(Clone)]
+pub struct ThreadPoolMetrics {
+    size: IntGauge,
+    queue_depth: IntGauge,
+    completed: IntCounter,
+}
+
+impl ThreadPoolMetrics {
+    pub fn new(pool: &str) -> Self {
+        ThreadPoolMetrics {
+            size: POOL_SIZE.with_label_values(&[pool]),
+            queue_depth: POOL_QUEUE_DEPTH.with_label_values(&[pool]),
+            completed: POOL_COMPLETED.with_label_values(&[pool]),
+        }
+    }
+
+    /// Sets the number of threads in the pool.
+    pub fn set_pool_size(&self, size: i64) {
+        self.size.set(size);
+    }
+
+    /// Sets the number of tasks waiting to be run by the pool.
+    pub fn set_queue_depth(&self, depth: i64) {
+        self.queue_depth.set(depth);
+    }
+
+    /// Counts a task completed by the pool.
+    pub fn inc_completed(&self) {
+        self.completed.inc();
+    }
+}
+
+/// Exports the number of OS threads of the process as `process_threads_total`, read at gather
+/// time. The series is missing on platforms where it can't be read.
+pub(crate) struct ThreadsCollector {
+    desc: Vec<Desc>,
+    threads: IntGauge,
+}
+
+impl ThreadsCollector {
+    pub(crate) fn new() -> Self {
+        let threads = IntGauge::with_opts(Opts::new(
+            "process_threads_total",
+            "Number of OS threads in the process.",
+        ))
+        .unwrap();
+
+        ThreadsCollector {
+            desc: threads.desc().into_iter().cloned().collect(),
+            threads,
+        }
+    }
+}
+
+impl Collector for ThreadsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.desc.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        match thread_count() {
+            Some(threads) => {
+                self.threads.set(threads as i64);
+                self.threads.collect()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn thread_count() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find(|line| line.starts_with("Threads:"))
+        .and_then(|line| line["Threads:".len()..].trim().parse().ok())
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn thread_count() -> Option<u64> {
+    use std::mem;
+
+    let size = mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+    // Safety: `info` is plain old data, and `size` is its size.
+    let mut info: libc::proc_taskinfo = unsafe { mem::zeroed() };
+    let read = unsafe {
+        libc::proc_pidinfo(
+            libc::getpid(),
+            libc::PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut libc::proc_taskinfo as *mut libc::c_void,
+            size,
+        )
+    };
+    if read == size {
+        Some(info.pti_threadnum as u64)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "ios")))]
+fn thread_count() -> Option<u64> {
+    None
+}
@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::Duration;
+
+const UNSET: u8 = 0;
+const SECONDS: u8 = 1;
+const MILLISECONDS: u8 = 2;
+
+static UNIT: AtomicU8 = AtomicU8::new(UNSET);
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// The unit of the function timing histograms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    /// Durations are observed in seconds, into `_seconds` histograms. The default.
+    Seconds,
+    /// Durations are observed in milliseconds, into `_milliseconds` histograms.
+    Milliseconds,
+}
+
+impl TimeUnit {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            MILLISECONDS => TimeUnit::Milliseconds,
+            _ => TimeUnit::Seconds,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            TimeUnit::Seconds => SECONDS,
+            TimeUnit::Milliseconds => MILLISECONDS,
+        }
+    }
+
+    /// The suffix of the histogram names.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            TimeUnit::Seconds => "seconds",
+            TimeUnit::Milliseconds => "milliseconds",
+        }
+    }
+
+    /// Converts a duration to this unit.
+    pub fn scale(self, d: Duration) -> f64 {
+        match self {
+            TimeUnit::Seconds => crate::duration_to_seconds(d),
+            TimeUnit::Milliseconds => crate::duration_to_seconds(d) * 1e3,
+        }
+    }
+}
+
+/// Selects the unit of the function timing histograms.
+///
+/// The unit defaults to the value of the `METRICS_TIME_UNIT` env var (`seconds` or
+/// `milliseconds`), or seconds. It must be set before the first instrumented function is called,
+/// and changing it afterwards returns an error.
+pub fn set_time_unit(unit: TimeUnit) -> crate::prometheus::Result<()> {
+    if REGISTERED.load(Ordering::SeqCst) && time_unit() != unit {
+        return Err(crate::prometheus::Error::Msg(format!(
+            "the time unit can't be changed to {} once the {} histograms are registered",
+            unit.suffix(),
+            time_unit().suffix(),
+        )));
+    }
+    UNIT.store(unit.to_u8(), Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns the unit of the function timing histograms.
+pub fn time_unit() -> TimeUnit {
+    let unit = UNIT.load(Ordering::Relaxed);
+    if unit != UNSET {
+        return TimeUnit::from_u8(unit);
+    }
+
+    let unit = match std::env::var("METRICS_TIME_UNIT") {
+        Ok(value) => parse(&value).unwrap_or_else(|| {
+            warn!("Unknown METRICS_TIME_UNIT {:?}, using seconds", value);
+            TimeUnit::Seconds
+        }),
+        Err(_) => TimeUnit::Seconds,
+    };
+    match UNIT.compare_exchange(UNSET, unit.to_u8(), Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(_) => unit,
+        Err(current) => TimeUnit::from_u8(current),
+    }
+}
+
+/// Parses a value of `METRICS_TIME_UNIT`.
+pub(crate) fn parse(value: &str) -> Option<TimeUnit> {
+    match value {
+        "milliseconds" | "ms" => Some(TimeUnit::Milliseconds),
+        "seconds" | "s" => Some(TimeUnit::Seconds),
+        _ => None,
+    }
+}
+
+/// The unit selected with `set_time_unit`, if any, without fixing it.
+pub(crate) fn selected() -> Option<TimeUnit> {
+    match UNIT.load(Ordering::Relaxed) {
+        UNSET => None,
+        unit => Some(TimeUnit::from_u8(unit)),
+    }
+}
+
+/// The bucket upper bounds of the timing histograms in `unit`.
+pub(crate) fn buckets(unit: TimeUnit) -> Vec<f64> {
+    let factor = match unit {
+        TimeUnit::Seconds => 1.0,
+        TimeUnit::Milliseconds => 1e3,
+    };
+    crate::prometheus::DEFAULT_BUCKETS
+        .iter()
+        .map(|bucket| bucket * factor)
+        .collect()
+}
+
+/// Builds the options of a timing histogram named `<name>_<unit>`, fixing the time unit.
+pub(crate) fn histogram_opts(name: &str, help: &str) -> crate::prometheus::HistogramOpts {
+    REGISTERED.store(true, Ordering::SeqCst);
+    let unit = time_unit();
+
+    crate::prometheus::HistogramOpts::new(format!("{}_{}", name, unit.suffix()), help.to_string())
+        .buckets(buckets(unit))
+}
+
+/// Builds the options of a timing gauge named `<name>_<unit>`, fixing the time unit.
+pub(crate) fn gauge_opts(name: &str, help: &str) -> crate::prometheus::Opts {
+    REGISTERED.store(true, Ordering::SeqCst);
+    crate::prometheus::Opts::new(
+        format!("{}_{}", name, time_unit().suffix()),
+        help.to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimeUnit;
+    use std::time::Duration;
+
+    #[test]
+    fn scale() {
+        let d = Duration::from_millis(1500);
+        assert_eq!(TimeUnit::Seconds.scale(d), 1.5);
+        assert_eq!(TimeUnit::Milliseconds.scale(d), 1500.0);
+    }
+}
@@ -0,0 +1,52 @@
+//! The values returned by the functions with `return_timing`, with the duration of their call.
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+/// A value returned by a function with `#[instrument(return_timing)]`, with the duration of the
+/// call, as observed in `function_time_seconds`, for callers that need it, e.g. in a response
+/// header, without timing the call a second time.
+///
+/// ```rust
+/// use instrumented::Timed;
+/// use std::time::Duration;
+///
+/// let timed = Timed::new(String::from("body"), Duration::from_millis(3));
+/// assert_eq!(timed.len(), 4);
+/// assert_eq!(timed.duration(), Duration::from_millis(3));
+/// assert_eq!(timed.into_inner(), "body");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Timed<T> {
+    value: T,
+    duration: Duration,
+}
+
+impl<T> Timed<T> {
+    pub fn new(value: T, duration: Duration) -> Self {
+        Timed { value, duration }
+    }
+
+    /// The duration of the call that returned the value.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns the value, without the duration.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Timed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Timed<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
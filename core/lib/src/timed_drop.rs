@@ -0,0 +1,99 @@
+//! Timing the drop of values whose `Drop` does expensive work, such as flushing or syncing a
+//! file, with `timed_drop` or `#[instrument_drop]`.
+use crate::clock::Instant;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+
+/// Times a drop as a call of a function, from its creation until it's dropped. The inflight
+/// gauge is decremented even if the drop panics, as the timer is dropped while unwinding.
+#[doc(hidden)]
+pub struct DropTimer {
+    name: &'static str,
+    ctx: &'static str,
+    krate: &'static str,
+    start: Instant,
+}
+
+impl DropTimer {
+    pub fn start(name: &'static str, ctx: &'static str) -> Self {
+        Self::start_in_crate(name, ctx, "")
+    }
+
+    /// Times the drop of a value of the crate `krate`, for the `crate` label.
+    pub fn start_in_crate(name: &'static str, ctx: &'static str, krate: &'static str) -> Self {
+        crate::inc_called_counter_in_crate(name, ctx, krate);
+        crate::inc_inflight_in_crate(name, ctx, krate);
+        DropTimer {
+            name,
+            ctx,
+            krate,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for DropTimer {
+    fn drop(&mut self) {
+        let elapsed = crate::observe_duration_in_crate(self.name, self.ctx, self.krate, self.start);
+        crate::notify_observers(self.name, self.ctx, elapsed, None);
+        crate::dec_inflight_in_crate(self.name, self.ctx, self.krate);
+    }
+}
+
+/// A value whose drop is timed, see `timed_drop`.
+pub struct TimedDrop<T> {
+    value: ManuallyDrop<T>,
+    name: &'static str,
+    ctx: &'static str,
+}
+
+/// Wraps a value so that its drop, including the drop of its fields, is counted and timed as a
+/// call of the function `name`, in `function_called_total` and `function_time_seconds`.
+///
+/// ```rust
+/// use std::io::{BufWriter, Write};
+///
+/// let mut log = instrumented::timed_drop(BufWriter::new(Vec::new()), "log_flush", "io");
+/// log.write_all(b"entry\n").unwrap();
+/// // Flushed, and timed, here.
+/// drop(log);
+/// ```
+pub fn timed_drop<T>(value: T, name: &'static str, ctx: &'static str) -> TimedDrop<T> {
+    TimedDrop {
+        value: ManuallyDrop::new(value),
+        name,
+        ctx,
+    }
+}
+
+impl<T> TimedDrop<T> {
+    /// Returns the value, whose drop is no longer timed.
+    pub fn into_inner(self) -> T {
+        let this = ManuallyDrop::new(self);
+        // The value is read once, and the wrapper isn't dropped.
+        unsafe { ptr::read(&*this.value) }
+    }
+}
+
+impl<T> Deref for TimedDrop<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for TimedDrop<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> Drop for TimedDrop<T> {
+    fn drop(&mut self) {
+        let _timer = DropTimer::start(self.name, self.ctx);
+        // The value is dropped once, here.
+        unsafe { ManuallyDrop::drop(&mut self.value) }
+    }
+}
@@ -0,0 +1,237 @@
+//! Dry-run validation of the metrics configuration, to catch a misconfigured prefix or label before
+//! dashboards break.
+use crate::labels;
+use crate::time_unit::{self, TimeUnit};
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+/// The labels of the built-in metrics, which default labels can't use.
+const RESERVED_LABELS: &[&str] = &[
+    "type", "name", "ctx", "err", "injected", "thread", "location", "class", "le", "quantile",
+];
+
+/// The crate features enabled at build time.
+const FEATURES: &[(&str, bool)] = &[
+    ("exporter", cfg!(feature = "exporter")),
+    ("systemd", cfg!(feature = "systemd")),
+    ("backend-prometheus", cfg!(feature = "backend-prometheus")),
+    ("backend-metrics", cfg!(feature = "backend-metrics")),
+    ("sentry", cfg!(feature = "sentry")),
+    ("alloc", cfg!(feature = "alloc")),
+    ("jemalloc-metrics", cfg!(feature = "jemalloc-metrics")),
+    ("remote-write", cfg!(feature = "remote-write")),
+    ("graphite", cfg!(feature = "graphite")),
+    ("json", cfg!(feature = "json")),
+    ("csv", cfg!(feature = "csv")),
+    ("ctx-log-level", cfg!(feature = "ctx-log-level")),
+    ("debug-introspection", cfg!(feature = "debug-introspection")),
+];
+
+/// The effective metrics configuration.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct ConfigReport {
+    /// The prefix of the metric names, from `METRICS_PREFIX`.
+    pub prefix: Option<String>,
+    /// The names of the default labels, from `METRICS_LABELS` and the label files.
+    pub label_keys: Vec<String>,
+    /// The unit of the timing histograms, `seconds` or `milliseconds`.
+    pub time_unit: &'static str,
+    /// The address metrics are served at, when validating an exporter `Config`.
+    pub addr: Option<String>,
+    /// The path metrics are served at, when validating an exporter `Config`.
+    pub path: Option<&'static str>,
+    /// The crate features enabled at build time.
+    pub features: Vec<&'static str>,
+}
+
+/// Formats as a one-line summary, e.g. for a startup log.
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let (Some(addr), Some(path)) = (&self.addr, self.path) {
+            write!(f, "http://{}{}, ", addr, path)?;
+        }
+        write!(
+            f,
+            "prefix: {}, labels: [{}], time unit: {}, features: [{}]",
+            self.prefix.as_ref().map_or("none", String::as_str),
+            self.label_keys.join(", "),
+            self.time_unit,
+            self.features.join(", ")
+        )
+    }
+}
+
+/// A configuration value that would break the metrics.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The metrics prefix isn't a valid metric name.
+    InvalidPrefix(String),
+    /// An entry of `METRICS_LABELS` isn't a `label=value` pair.
+    MalformedLabel(String),
+    /// A default label name isn't a valid label name.
+    InvalidLabelName(String),
+    /// A default label name is used by the built-in metrics.
+    ReservedLabelName(String),
+    /// A label file can't be read.
+    UnreadableLabelFile(String),
+    /// `METRICS_TIME_UNIT` isn't `seconds` or `milliseconds`.
+    UnknownTimeUnit(String),
+    /// The bucket upper bounds of the timing histograms aren't finite and strictly increasing.
+    InvalidBuckets(Vec<f64>),
+    /// The exporter address isn't a socket address.
+    InvalidAddr(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::InvalidPrefix(prefix) => {
+                write!(f, "invalid metrics prefix {:?}", prefix)
+            }
+            ConfigError::MalformedLabel(label) => {
+                write!(
+                    f,
+                    "malformed default label {:?}, expected label=value",
+                    label
+                )
+            }
+            ConfigError::InvalidLabelName(name) => write!(f, "invalid label name {:?}", name),
+            ConfigError::ReservedLabelName(name) => {
+                write!(f, "label name {:?} is used by the built-in metrics", name)
+            }
+            ConfigError::UnreadableLabelFile(path) => {
+                write!(f, "label file {:?} can't be read", path)
+            }
+            ConfigError::UnknownTimeUnit(unit) => {
+                write!(
+                    f,
+                    "unknown time unit {:?}, expected seconds or milliseconds",
+                    unit
+                )
+            }
+            ConfigError::InvalidBuckets(buckets) => {
+                write!(
+                    f,
+                    "bucket upper bounds {:?} aren't strictly increasing",
+                    buckets
+                )
+            }
+            ConfigError::InvalidAddr(addr) => write!(f, "invalid address {:?}", addr),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+pub(crate) fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+pub(crate) fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
+fn check_label_name(name: &str) -> Result<(), ConfigError> {
+    if !is_label_name(name) {
+        return Err(ConfigError::InvalidLabelName(name.to_string()));
+    }
+    if RESERVED_LABELS.contains(&name) {
+        return Err(ConfigError::ReservedLabelName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Checks that the label files are readable, and appends their labels to `label_keys`.
+pub(crate) fn check_label_files(
+    files: impl Iterator<Item = Result<(String, PathBuf), String>>,
+    label_keys: &mut Vec<String>,
+) -> Result<(), ConfigError> {
+    for file in files {
+        let (label, path) = file.map_err(ConfigError::MalformedLabel)?;
+        check_label_name(&label)?;
+        if labels::read_label_file(&path).is_err() {
+            return Err(ConfigError::UnreadableLabelFile(path.display().to_string()));
+        }
+        label_keys.push(label);
+    }
+    Ok(())
+}
+
+/// Checks the metrics configuration of the `METRICS_PREFIX`, `METRICS_LABELS`,
+/// `METRICS_LABELS_FILES` and `METRICS_TIME_UNIT` env vars, along with the labels added with
+/// `label_from_file`, without applying it, and returns the effective configuration.
+///
+/// ```rust
+/// let report = instrumented::validate_config().unwrap();
+/// println!("{}", report);
+/// ```
+pub fn validate_config() -> Result<ConfigReport, ConfigError> {
+    let prefix = std::env::var("METRICS_PREFIX").ok();
+    if let Some(prefix) = &prefix {
+        if !is_metric_name(prefix) {
+            return Err(ConfigError::InvalidPrefix(prefix.clone()));
+        }
+    }
+
+    let mut label_keys = vec![];
+    if let Ok(labels) = std::env::var("METRICS_LABELS") {
+        for pair in labels::parse_pairs(&labels) {
+            let (name, _) = pair.map_err(|label| ConfigError::MalformedLabel(label.to_string()))?;
+            check_label_name(name)?;
+            label_keys.push(name.to_string());
+        }
+    }
+    check_label_files(labels::label_files().into_iter(), &mut label_keys)?;
+
+    let unit = match (time_unit::selected(), std::env::var("METRICS_TIME_UNIT")) {
+        (Some(unit), _) => unit,
+        (None, Ok(value)) => time_unit::parse(&value).ok_or(ConfigError::UnknownTimeUnit(value))?,
+        (None, Err(_)) => TimeUnit::Seconds,
+    };
+    let buckets = time_unit::buckets(unit);
+    let increasing = buckets.windows(2).all(|pair| pair[0] < pair[1]);
+    if !increasing || buckets.iter().any(|bucket| !bucket.is_finite()) {
+        return Err(ConfigError::InvalidBuckets(buckets));
+    }
+
+    Ok(ConfigReport {
+        prefix,
+        label_keys,
+        time_unit: unit.suffix(),
+        addr: None,
+        path: None,
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(feature, _)| *feature)
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_label_name, is_metric_name};
+
+    #[test]
+    fn names() {
+        assert!(is_metric_name("myapp"));
+        assert!(is_metric_name("my_app:v2"));
+        assert!(!is_metric_name(""));
+        assert!(!is_metric_name("2app"));
+        assert!(!is_metric_name("my-app"));
+        assert!(is_label_name("region"));
+        assert!(!is_label_name("__region"));
+        assert!(!is_label_name("re:gion"));
+    }
+}
@@ -0,0 +1,118 @@
+//! Instrumenting closures passed as callbacks, e.g. to executors or retry loops, without
+//! extracting named functions.
+use crate::clock::Instant;
+use std::fmt::Debug;
+
+/// The outcome of a wrapped closure: whether it failed, and with which `err` label.
+///
+/// Implemented for `Result`, whose errors are counted in `function_error_total` with their
+/// `Debug` representation as `#[instrument]` does, and for common types that can't fail. Other
+/// return types can implement it with the default method.
+pub trait Outcome {
+    /// Returns the `err` label of a failure, or `None` on success.
+    fn error_label(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<T, E: Debug> Outcome for Result<T, E> {
+    fn error_label(&self) -> Option<String> {
+        self.as_ref().err().map(|err| format!("{:?}", err))
+    }
+}
+
+impl<T> Outcome for Option<T> {}
+impl<T> Outcome for Vec<T> {}
+impl<T: ?Sized> Outcome for Box<T> {}
+
+macro_rules! infallible_outcomes {
+    ($($ty:ty),*) => {
+        $(impl Outcome for $ty {})*
+    };
+}
+
+infallible_outcomes!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    String
+);
+
+/// Counts, times and, if it fails, error-counts a call of a closure, as a call of the function
+/// `name`. The crate of a closure isn't known, so its `crate` label is empty.
+fn call<R: Outcome>(name: &'static str, ctx: &'static str, f: impl FnOnce() -> R) -> R {
+    crate::inc_called_counter_for(name, ctx);
+    crate::inc_inflight_for(name, ctx);
+    let start = Instant::now();
+    let result = f();
+    let elapsed = crate::observe_duration_for(name, ctx, start);
+    match result.error_label() {
+        Some(err) => {
+            crate::notify_observers(name, ctx, elapsed, Some(&err));
+            crate::inc_error_counter_for(name, ctx, err);
+        }
+        None => crate::notify_observers(name, ctx, elapsed, None),
+    }
+    crate::dec_inflight_for(name, ctx);
+    result
+}
+
+/// Wraps a closure so that each of its calls is counted and timed as a call of the function
+/// `name`, and counted as an error when it returns an `Err`. The wrapper captures the closure by
+/// value, and doesn't allocate on the calls that succeed.
+///
+/// ```rust
+/// let parse = instrumented::wrap_fn("parse_port", "config", || "8080".parse::<u16>());
+/// assert_eq!(parse(), Ok(8080));
+/// ```
+///
+/// Closures with arguments can be wrapped by capturing them, or see `wrap_fn_mut` and
+/// `wrap_fn_once` for the closures that aren't `Fn`.
+pub fn wrap_fn<F, R>(name: &'static str, ctx: &'static str, f: F) -> impl Fn() -> R
+where
+    F: Fn() -> R,
+    R: Outcome,
+{
+    move || call(name, ctx, &f)
+}
+
+/// Wraps an `FnMut` closure, like `wrap_fn`.
+///
+/// ```rust
+/// let mut attempts = 0;
+/// let mut connect = instrumented::wrap_fn_mut("connect", "retry", || {
+///     attempts += 1;
+///     if attempts < 3 { Err("refused") } else { Ok(attempts) }
+/// });
+/// while connect().is_err() {}
+/// ```
+pub fn wrap_fn_mut<F, R>(name: &'static str, ctx: &'static str, mut f: F) -> impl FnMut() -> R
+where
+    F: FnMut() -> R,
+    R: Outcome,
+{
+    move || call(name, ctx, &mut f)
+}
+
+/// Wraps an `FnOnce` closure, like `wrap_fn`, e.g. for a task handed to an executor.
+pub fn wrap_fn_once<F, R>(name: &'static str, ctx: &'static str, f: F) -> impl FnOnce() -> R
+where
+    F: FnOnce() -> R,
+    R: Outcome,
+{
+    move || call(name, ctx, f)
+}
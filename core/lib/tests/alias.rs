@@ -0,0 +1,69 @@
+#![cfg(feature = "exporter")]
+
+mod common;
+
+use common::{counter_value, find_metric, histogram_count};
+use instrumented::instrument;
+use std::net::TcpListener;
+
+#[derive(Debug)]
+pub struct InvoiceError;
+
+#[instrument(INFO, ctx = "invoice")]
+fn send(fail: bool) -> Result<(), InvoiceError> {
+    if fail {
+        Err(InvoiceError)
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn exports_the_old_names_until_removed() {
+    assert!(instrumented::Config::new("127.0.0.1:0")
+        .alias_prefix("0payments")
+        .validate()
+        .is_err());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let config = instrumented::Config::new("127.0.0.1:0")
+        .alias_prefix("payments")
+        .alias_ctx("bill", "invoice");
+    instrumented::init_with_config_and_listener(config, listener);
+
+    for fail in &[false, false, true] {
+        let _ = send(*fail);
+    }
+
+    for (family, ctx) in &[
+        ("function_called_total", "bill"),
+        ("payments_function_called_total", "invoice"),
+        ("payments_function_called_total", "bill"),
+    ] {
+        assert_eq!(
+            counter_value(family, &[("name", "send"), ("ctx", ctx)]),
+            counter_value("function_called_total", &[("name", "send"), ("ctx", "invoice")]),
+            "{} {}",
+            family,
+            ctx
+        );
+    }
+    assert_eq!(
+        counter_value("function_called_total", &[("name", "send"), ("ctx", "bill")]),
+        3.0
+    );
+    assert_eq!(
+        counter_value("payments_function_error_total", &[("name", "send"), ("ctx", "bill")]),
+        1.0
+    );
+    let labels = [("name", "send"), ("ctx", "bill")];
+    assert_eq!(histogram_count("function_time_seconds", &labels), 3);
+    assert_eq!(histogram_count("payments_function_time_seconds", &labels), 3);
+
+    instrumented::alias::clear_aliases();
+    assert!(find_metric("payments_function_called_total", &[]).is_none());
+    assert_eq!(
+        counter_value("function_called_total", &[("name", "send"), ("ctx", "bill")]),
+        0.0
+    );
+}
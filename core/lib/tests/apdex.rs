@@ -0,0 +1,52 @@
+mod common;
+
+use instrumented::instrument;
+use std::{thread, time};
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, apdex_t = 0.05)]
+fn apdex_satisfied() {}
+
+#[instrument(INFO, apdex_t = 0.05)]
+fn apdex_tolerating() {
+    thread::sleep(time::Duration::from_millis(100));
+}
+
+#[instrument(INFO, apdex_t = 0.05)]
+fn apdex_frustrated() {
+    thread::sleep(time::Duration::from_millis(300));
+}
+
+#[instrument(INFO, apdex_t = 0.05)]
+fn apdex_fast_error() -> Result<(), MyError> {
+    Err(MyError)
+}
+
+fn apdex_counts(name: &str) -> (f64, f64, f64) {
+    let labels = [("name", name)];
+    (
+        common::counter_value("function_apdex_satisfied_total", &labels),
+        common::counter_value("function_apdex_tolerating_total", &labels),
+        common::counter_value("function_apdex_frustrated_total", &labels),
+    )
+}
+
+#[test]
+fn classifies_by_duration() {
+    apdex_satisfied();
+    apdex_tolerating();
+    apdex_frustrated();
+
+    assert_eq!(apdex_counts("apdex_satisfied"), (1.0, 0.0, 0.0));
+    assert_eq!(apdex_counts("apdex_tolerating"), (0.0, 1.0, 0.0));
+    assert_eq!(apdex_counts("apdex_frustrated"), (0.0, 0.0, 1.0));
+}
+
+#[test]
+fn errors_are_frustrated() {
+    assert!(apdex_fast_error().is_err());
+
+    assert_eq!(apdex_counts("apdex_fast_error"), (0.0, 0.0, 1.0));
+}
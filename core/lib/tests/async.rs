@@ -0,0 +1,104 @@
+mod common;
+
+use instrumented::instrument;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Wake, Waker};
+use std::{thread, time};
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO)]
+async fn async_delayed() {
+    thread::sleep(time::Duration::from_millis(10));
+}
+
+#[instrument(INFO)]
+async fn async_yields_once() -> u32 {
+    common::YieldNow::new().await;
+    42
+}
+
+#[instrument(INFO)]
+async fn async_borrows(s: &str, t: &str) -> usize {
+    s.len() + t.len()
+}
+
+#[instrument(INFO)]
+async fn async_result(fail: bool) -> Result<u32, MyError> {
+    if fail {
+        return Err(MyError);
+    }
+    Ok(1)
+}
+
+#[instrument(INFO)]
+async fn async_never_finishes() {
+    loop {
+        common::YieldNow::new().await;
+    }
+}
+
+#[test]
+fn records_poll_delay_on_saturated_executor() {
+    let hog = Box::pin(async {
+        thread::sleep(time::Duration::from_millis(50));
+    });
+    let delayed = Box::pin(async_delayed());
+    common::run_to_completion(vec![hog, delayed]);
+
+    let labels = [("name", "async_delayed")];
+    assert_eq!(
+        common::histogram_count("function_poll_delay_seconds", &labels),
+        1
+    );
+    assert!(common::histogram_sum("function_poll_delay_seconds", &labels) >= 0.05);
+    // The time spent queued behind the hog is not part of the execution time.
+    let elapsed = common::histogram_sum("function_time_seconds", &labels);
+    assert!((0.01..0.05).contains(&elapsed));
+}
+
+#[test]
+fn counts_polls() {
+    assert_eq!(common::block_on(async_yields_once()), 42);
+
+    let labels = [("name", "async_yields_once")];
+    assert_eq!(common::counter_value("function_polls_total", &labels), 2.0);
+    assert_eq!(common::counter_value("function_called_total", &labels), 1.0);
+}
+
+#[test]
+fn borrows_arguments() {
+    let s = String::from("hello");
+    assert_eq!(common::block_on(async_borrows(&s, "world")), 10);
+}
+
+#[test]
+fn counts_errors() {
+    assert!(common::block_on(async_result(false)).is_ok());
+    assert!(common::block_on(async_result(true)).is_err());
+
+    let labels = [("name", "async_result")];
+    assert_eq!(common::counter_value("function_called_total", &labels), 2.0);
+    assert_eq!(common::counter_value("function_error_total", &labels), 1.0);
+}
+
+#[test]
+fn dropped_future_leaves_no_inflight_call() {
+    let labels = [("name", "async_never_finishes")];
+    let mut never = Box::pin(async_never_finishes());
+    let waker = Waker::from(Arc::new(Noop));
+    let mut cx = Context::from_waker(&waker);
+    assert!(never.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(common::gauge_value("function_calls_inflight_total", &labels), 1.0);
+
+    drop(never);
+    assert_eq!(common::gauge_value("function_calls_inflight_total", &labels), 0.0);
+}
+
+struct Noop;
+
+impl Wake for Noop {
+    fn wake(self: Arc<Self>) {}
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use async_trait::async_trait;
+use instrumented::instrument;
+use std::{thread, time};
+
+#[async_trait]
+trait Sleeper {
+    async fn sleep(&self, millis: u64) -> u64;
+}
+
+struct ThreadSleeper;
+
+#[async_trait]
+impl Sleeper for ThreadSleeper {
+    #[instrument(INFO)]
+    async fn sleep(&self, millis: u64) -> u64 {
+        thread::sleep(time::Duration::from_millis(millis));
+        millis
+    }
+}
+
+#[test]
+fn times_awaited_body() {
+    let sleeper: Box<dyn Sleeper> = Box::new(ThreadSleeper);
+    let future = sleeper.sleep(50);
+    assert_eq!(common::histogram_count("function_time_seconds", &[("name", "sleep")]), 0);
+
+    assert_eq!(common::block_on(future), 50);
+    let labels = [("name", "sleep")];
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 1);
+    assert!(common::histogram_sum("function_time_seconds", &labels) >= 0.05);
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::find_metric;
+
+#[test]
+fn labels_match_the_build() {
+    instrumented::register_build_flags!("tls", "simd");
+
+    let debug_assertions = if cfg!(debug_assertions) {
+        "true"
+    } else {
+        "false"
+    };
+    let flags = find_metric("build_flags_info", &[]).unwrap();
+    let label = |name| {
+        flags
+            .get_label()
+            .iter()
+            .find(|label| label.get_name() == name)
+            .map(|label| label.get_value().to_string())
+    };
+    assert_eq!(label("debug_assertions").as_deref(), Some(debug_assertions));
+    assert_eq!(label("features").as_deref(), Some("simd,tls"));
+    // This crate has no build script passing them through.
+    assert_eq!(label("opt_level").as_deref(), Some("unknown"));
+    assert_eq!(label("target").as_deref(), Some("unknown"));
+    assert_eq!(flags.get_gauge().get_value(), 1.0);
+
+    // Calling it again replaces the series.
+    instrumented::register_build_flags!();
+    let families = instrumented::gather();
+    let family = families
+        .iter()
+        .find(|mf| mf.get_name() == "build_flags_info")
+        .unwrap();
+    assert_eq!(family.get_metric().len(), 1);
+}
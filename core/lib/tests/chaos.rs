@@ -0,0 +1,74 @@
+mod common;
+
+use instrumented::{chaos, instrument};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq)]
+enum PaymentError {
+    Declined,
+    Synthetic,
+}
+
+#[instrument(INFO, chaos)]
+fn charge(decline: bool) -> Result<u32, PaymentError> {
+    if decline {
+        Err(PaymentError::Declined)
+    } else {
+        Ok(42)
+    }
+}
+
+#[instrument(INFO, chaos)]
+fn refund() {}
+
+#[instrument(INFO)]
+fn unaffected() -> Result<u32, PaymentError> {
+    Ok(42)
+}
+
+#[test]
+fn injects_errors() {
+    chaos::inject_error("charge", 1.0, || PaymentError::Synthetic);
+    chaos::inject_error("unaffected", 1.0, || PaymentError::Synthetic);
+    assert_eq!(charge(false), Err(PaymentError::Synthetic));
+    assert_eq!(unaffected(), Ok(42));
+    assert_eq!(charge(true), Err(PaymentError::Synthetic));
+
+    chaos::clear("charge");
+    assert_eq!(charge(false), Ok(42));
+    assert_eq!(charge(true), Err(PaymentError::Declined));
+
+    let labels = |err, injected| [("name", "charge"), ("err", err), ("injected", injected)];
+    assert_eq!(
+        common::counter_value("function_error_total", &labels("Synthetic", "true")),
+        2.0
+    );
+    assert_eq!(
+        common::counter_value("function_error_total", &labels("Declined", "false")),
+        1.0
+    );
+    assert_eq!(
+        common::counter_value("function_error_total", &labels("Declined", "true")),
+        0.0
+    );
+
+    // Errors of another type than the returned one are never injected.
+    chaos::inject_error("charge", 1.0, || "synthetic");
+    assert_eq!(charge(false), Ok(42));
+    chaos::clear("charge");
+}
+
+#[test]
+fn injects_latency() {
+    chaos::inject_latency("refund", Duration::from_millis(50), 1.0);
+    let start = Instant::now();
+    refund();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    chaos::inject_latency("refund", Duration::from_millis(50), 0.0);
+    let start = Instant::now();
+    refund();
+    assert!(start.elapsed() < Duration::from_millis(50));
+
+    chaos::clear("refund");
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use instrumented::instrument;
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub enum Hit<V> {
+    Hit(V),
+    Miss,
+}
+
+#[derive(Debug)]
+pub struct Unavailable;
+
+pub fn classify_hit<V>(value: &Hit<V>) -> &'static str {
+    match value {
+        Hit::Hit(_) => "hit",
+        Hit::Miss => "miss",
+    }
+}
+
+pub struct Cache {
+    entries: HashMap<u32, String>,
+}
+
+impl Cache {
+    #[instrument(INFO, ctx = "classify", classify = "classify_hit")]
+    fn get(&self, key: u32) -> Result<Hit<String>, Unavailable> {
+        Ok(match self.entries.get(&key) {
+            Some(value) => Hit::Hit(value.clone()),
+            None => Hit::Miss,
+        })
+    }
+}
+
+#[instrument(INFO, ctx = "classify", classify = "classify_hit")]
+fn lookup(key: u32) -> Hit<u32> {
+    if key % 2 == 0 {
+        Hit::Hit(key)
+    } else {
+        Hit::Miss
+    }
+}
+
+#[test]
+fn classifies_ok_values() {
+    let cache = Cache {
+        entries: vec![(1, "one".to_string())].into_iter().collect(),
+    };
+    for key in &[1, 1, 2, 1, 3] {
+        cache.get(*key).unwrap();
+    }
+
+    for (class, count) in &[("hit", 3.0), ("miss", 2.0)] {
+        assert_eq!(
+            common::counter_value(
+                "function_result_class_total",
+                &[("name", "get"), ("class", class)]
+            ),
+            *count
+        );
+    }
+}
+
+#[test]
+fn classifies_returned_values() {
+    for key in 0..5 {
+        lookup(key);
+    }
+
+    for (class, count) in &[("hit", 3.0), ("miss", 2.0)] {
+        assert_eq!(
+            common::counter_value(
+                "function_result_class_total",
+                &[("name", "lookup"), ("class", class)]
+            ),
+            *count
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! A logger capturing the log lines, for the tests of the logged messages.
+use std::sync::Mutex;
+
+struct CapturingLogger;
+
+lazy_static::lazy_static! {
+    static ref CAPTURED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        CAPTURED
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger;
+
+/// Installs a logger capturing every log line, for `captured_logs` to return.
+pub fn capture_logs() {
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(log::LevelFilter::Trace);
+}
+
+/// Returns the captured log lines containing `pattern`.
+pub fn captured_logs(pattern: &str) -> Vec<String> {
+    CAPTURED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|line| line.contains(pattern))
+        .cloned()
+        .collect()
+}
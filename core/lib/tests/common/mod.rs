@@ -0,0 +1,128 @@
+#![allow(dead_code)]
+
+use instrumented::prometheus::proto::Metric;
+
+pub mod protobuf;
+#[cfg(feature = "remote-write")]
+pub mod remote_write;
+
+/// Finds the series of `family` whose labels include all of `labels`.
+pub fn find_metric(family: &str, labels: &[(&str, &str)]) -> Option<Metric> {
+    instrumented::gather()
+        .into_iter()
+        .find(|mf| mf.get_name() == family)
+        .and_then(|mf| {
+            mf.get_metric()
+                .iter()
+                .find(|m| {
+                    labels.iter().all(|(name, value)| {
+                        m.get_label()
+                            .iter()
+                            .any(|l| l.get_name() == *name && l.get_value() == *value)
+                    })
+                })
+                .cloned()
+        })
+}
+
+/// Returns the value of a counter series, or 0 if it doesn't exist yet.
+pub fn counter_value(family: &str, labels: &[(&str, &str)]) -> f64 {
+    find_metric(family, labels)
+        .map(|m| m.get_counter().get_value())
+        .unwrap_or(0.0)
+}
+
+/// Returns the value of a gauge series, or 0 if it doesn't exist yet.
+pub fn gauge_value(family: &str, labels: &[(&str, &str)]) -> f64 {
+    find_metric(family, labels)
+        .map(|m| m.get_gauge().get_value())
+        .unwrap_or(0.0)
+}
+
+/// Returns the sample count of a histogram series, or 0 if it doesn't exist yet.
+pub fn histogram_count(family: &str, labels: &[(&str, &str)]) -> u64 {
+    find_metric(family, labels)
+        .map(|m| m.get_histogram().get_sample_count())
+        .unwrap_or(0)
+}
+
+/// Returns the sample sum of a histogram series, or 0 if it doesn't exist yet.
+pub fn histogram_sum(family: &str, labels: &[(&str, &str)]) -> f64 {
+    find_metric(family, labels)
+        .map(|m| m.get_histogram().get_sample_sum())
+        .unwrap_or(0.0)
+}
+
+use std::future::Future;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// A minimal single-threaded executor: polls each task in turn until all of them complete.
+pub fn run_to_completion(mut tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>) {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+    while !tasks.is_empty() {
+        tasks.retain_mut(|task| task.as_mut().poll(&mut cx).is_pending());
+    }
+}
+
+/// Runs a single future to completion on the current thread.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// Returns `Pending` once before completing.
+pub struct YieldNow(bool);
+
+impl YieldNow {
+    pub fn new() -> Self {
+        YieldNow(false)
+    }
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Scrapes `/metrics` from the exporter listening on `addr`, returning the whole response.
+pub fn scrape(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[cfg(feature = "logging")]
+mod logs;
+#[cfg(feature = "logging")]
+#[allow(unused_imports)]
+pub use self::logs::{capture_logs, captured_logs};
@@ -0,0 +1,57 @@
+//! A minimal protobuf reader, for the tests of the remote_write requests and the protobuf
+//! exposition format.
+
+fn varint(buf: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[0];
+        *buf = &buf[1..];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// Returns the fields of a message as `(number, wire type, value)`, with the value of length
+/// delimited fields as their bytes, and of others as their bits.
+pub fn fields(mut buf: &[u8]) -> Vec<(u64, u64, Vec<u8>, u64)> {
+    let mut fields = Vec::new();
+    while !buf.is_empty() {
+        let key = varint(&mut buf);
+        match key & 7 {
+            0 => fields.push((key >> 3, 0, Vec::new(), varint(&mut buf))),
+            1 => {
+                let mut bits = [0; 8];
+                bits.copy_from_slice(&buf[..8]);
+                buf = &buf[8..];
+                fields.push((key >> 3, 1, Vec::new(), u64::from_le_bytes(bits)));
+            }
+            2 => {
+                let len = varint(&mut buf) as usize;
+                fields.push((key >> 3, 2, buf[..len].to_vec(), 0));
+                buf = &buf[len..];
+            }
+            wire => panic!("unexpected wire type {}", wire),
+        }
+    }
+    fields
+}
+
+/// Splits length delimited messages.
+pub fn delimited(mut buf: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    while !buf.is_empty() {
+        let len = varint(&mut buf) as usize;
+        messages.push(buf[..len].to_vec());
+        buf = &buf[len..];
+    }
+    messages
+}
+
+/// Decodes a zigzag encoded `sint32` or `sint64`.
+pub fn zigzag(bits: u64) -> i64 {
+    (bits >> 1) as i64 ^ -((bits & 1) as i64)
+}
@@ -0,0 +1,78 @@
+//! A mock remote_write endpoint.
+use super::protobuf::fields;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+
+/// Accepts a single HTTP request, returning its headers and body.
+pub fn accept_request(listener: &TcpListener) -> (Vec<String>, Vec<u8>) {
+    let (stream, _) = listener.accept().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        headers.push(line);
+    }
+    let length: usize = headers
+        .iter()
+        .find_map(|h| {
+            let (name, value) = h.split_at(h.find(':')?);
+            if name.eq_ignore_ascii_case("content-length") {
+                value[1..].trim().parse().ok()
+            } else {
+                None
+            }
+        })
+        .unwrap();
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    reader
+        .get_mut()
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    (headers, body)
+}
+
+pub struct Series {
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+    pub timestamp: i64,
+}
+
+/// Decodes the series of a `WriteRequest`.
+pub fn decode(request: &[u8]) -> Vec<Series> {
+    fields(request)
+        .into_iter()
+        .filter(|(number, ..)| *number == 1)
+        .map(|(_, _, series, _)| {
+            let mut labels = Vec::new();
+            let mut sample = (0.0, 0);
+            for (number, _, bytes, _) in fields(&series) {
+                if number == 1 {
+                    let label = fields(&bytes);
+                    labels.push((
+                        String::from_utf8(label[0].2.clone()).unwrap(),
+                        String::from_utf8(label[1].2.clone()).unwrap(),
+                    ));
+                } else {
+                    for (number, _, _, bits) in fields(&bytes) {
+                        if number == 1 {
+                            sample.0 = f64::from_bits(bits);
+                        } else {
+                            sample.1 = bits as i64;
+                        }
+                    }
+                }
+            }
+            Series {
+                labels,
+                value: sample.0,
+                timestamp: sample.1,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::scrape;
+use instrumented::Config;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn drops_slow_clients() {
+    let config = Config::new("127.0.0.1:0")
+        .header_read_timeout(Duration::from_millis(300))
+        .request_timeout(Duration::from_secs(1));
+    let exporter = instrumented::init_multi_with_config(config, &["127.0.0.1:0"]);
+    let addr = exporter.addrs()[0];
+
+    // Sends the headers byte by byte, too slowly.
+    let start = Instant::now();
+    let mut slow = TcpStream::connect(addr).unwrap();
+    let mut dropped = false;
+    for byte in b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n".iter() {
+        if slow.write_all(&[*byte]).is_err() {
+            dropped = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert!(scrape(addr).starts_with("HTTP/1.0 200 OK"));
+    }
+    if !dropped {
+        let mut buf = [0; 64];
+        slow.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        dropped = match slow.read(&mut buf) {
+            Ok(read) => read == 0 || !buf.starts_with(b"HTTP/1.1 200"),
+            Err(_) => true,
+        };
+    }
+    assert!(dropped);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(scrape(addr).starts_with("HTTP/1.0 200 OK"));
+}
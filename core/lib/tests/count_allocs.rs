@@ -0,0 +1,83 @@
+#![cfg(feature = "alloc")]
+mod common;
+
+use instrumented::alloc::CountingAllocator;
+use instrumented::instrument;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[instrument(INFO, count_allocs)]
+fn allocate(n: u64) -> u64 {
+    let mut boxes = Vec::with_capacity(n as usize);
+    for i in 0..n {
+        boxes.push(Box::new(i));
+    }
+    boxes.iter().map(|boxed| **boxed).sum()
+}
+
+#[instrument(INFO, count_allocs)]
+fn allocate_nested() -> u64 {
+    let boxed = Box::new(1);
+    *boxed + allocate(3)
+}
+
+#[derive(Debug)]
+struct Limited;
+
+#[instrument(INFO, count_allocs, max_rate = 1, rate_limit_err = "Limited")]
+fn allocate_limited() -> Result<u64, Limited> {
+    Ok(*Box::new(1))
+}
+
+#[instrument(INFO, count_allocs)]
+fn allocate_calling_limited() -> u64 {
+    let boxed = Box::new(1);
+    *boxed + (0..5).filter_map(|_| allocate_limited().ok()).sum::<u64>()
+}
+
+#[test]
+fn counts_allocations() {
+    let labels = [("name", "allocate")];
+    let allocations = common::counter_value("function_allocations_total", &labels);
+    let bytes = common::counter_value("function_allocated_bytes_total", &labels);
+
+    allocate(10);
+
+    // The vector, and ten boxes.
+    assert_eq!(
+        common::counter_value("function_allocations_total", &labels) - allocations,
+        11.0
+    );
+    assert_eq!(
+        common::counter_value("function_allocated_bytes_total", &labels) - bytes,
+        160.0
+    );
+}
+
+#[test]
+fn nested_calls_are_not_counted_twice() {
+    let labels = [("name", "allocate_nested")];
+    let allocations = common::counter_value("function_allocations_total", &labels);
+
+    allocate_nested();
+
+    assert_eq!(
+        common::counter_value("function_allocations_total", &labels) - allocations,
+        1.0
+    );
+}
+
+#[test]
+fn calls_returning_early_are_not_counted_twice() {
+    let labels = [("name", "allocate_calling_limited")];
+    let allocations = common::counter_value("function_allocations_total", &labels);
+
+    // Most of the calls of `allocate_limited` are rejected, returning before being counted.
+    allocate_calling_limited();
+
+    assert_eq!(
+        common::counter_value("function_allocations_total", &labels) - allocations,
+        1.0
+    );
+}
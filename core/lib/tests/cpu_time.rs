@@ -0,0 +1,45 @@
+mod common;
+
+use instrumented::instrument;
+use std::time::{Duration, Instant};
+
+#[instrument(INFO, cpu_time)]
+fn busy(duration: Duration) -> u64 {
+    let start = Instant::now();
+    let mut x = 0u64;
+    while start.elapsed() < duration {
+        x = x.wrapping_add(1);
+    }
+    x
+}
+
+#[instrument(INFO, cpu_time)]
+fn sleepy(duration: Duration) {
+    std::thread::sleep(duration);
+}
+
+#[test]
+fn busy_cpu_time_is_close_to_wall_time() {
+    busy(Duration::from_millis(100));
+
+    let labels = [("name", "busy")];
+    let wall = common::histogram_sum("function_time_seconds", &labels);
+    let cpu = common::histogram_sum("function_cpu_seconds", &labels);
+    assert_eq!(common::histogram_count("function_cpu_seconds", &labels), 1);
+    assert!(
+        cpu > wall * 0.5 && cpu <= wall * 1.1,
+        "cpu {} wall {}",
+        cpu,
+        wall
+    );
+}
+
+#[test]
+fn sleeping_cpu_time_is_near_zero() {
+    sleepy(Duration::from_millis(100));
+
+    let labels = [("name", "sleepy")];
+    let cpu = common::histogram_sum("function_cpu_seconds", &labels);
+    assert_eq!(common::histogram_count("function_cpu_seconds", &labels), 1);
+    assert!(cpu < 0.01, "cpu {}", cpu);
+}
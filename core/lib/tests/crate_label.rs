@@ -0,0 +1,44 @@
+mod common;
+
+use common::counter_value;
+use instrumented::instrument;
+
+#[instrument(INFO, ctx = "api", label_thread, track_last_called)]
+fn handle() -> u32 {
+    42
+}
+
+#[test]
+fn functions_are_labelled_with_their_crate() {
+    // Must be enabled before the function metrics are registered.
+    instrumented::set_crate_label(true).unwrap();
+    assert!(instrumented::crate_label());
+
+    assert_eq!(handle(), 42);
+
+    // The functions of other crates are tested by the `crate-label` crates of the workspace.
+    assert_eq!(
+        counter_value(
+            "function_called_total",
+            &[
+                ("name", "handle"),
+                ("ctx", "api"),
+                ("crate", env!("CARGO_PKG_NAME"))
+            ],
+        ),
+        1.0
+    );
+    assert!(common::find_metric(
+        "function_called_by_thread_total",
+        &[("name", "handle"), ("crate", "instrumented")],
+    )
+    .is_some());
+    assert!(common::find_metric(
+        "function_last_called_timestamp_seconds",
+        &[("name", "handle"), ("crate", "instrumented")],
+    )
+    .is_some());
+
+    assert!(instrumented::set_crate_label(false).is_err());
+    assert!(instrumented::set_crate_label(true).is_ok());
+}
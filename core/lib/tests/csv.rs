@@ -0,0 +1,46 @@
+#![cfg(feature = "csv")]
+
+use instrumented::instrument;
+
+#[instrument(INFO, ctx = "csv,quoted")]
+fn rendered_as_csv() {}
+
+#[test]
+fn renders_csv() {
+    rendered_as_csv();
+    rendered_as_csv();
+
+    let csv = instrumented::render_csv();
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    assert_eq!(
+        reader.headers().unwrap().iter().collect::<Vec<_>>(),
+        vec!["metric", "name", "ctx", "labels", "value"]
+    );
+    let rows: Vec<csv::StringRecord> = reader
+        .records()
+        .map(Result::unwrap)
+        .filter(|row| &row[1] == "rendered_as_csv")
+        .collect();
+    let metric_rows = |metric: &str| {
+        rows.iter()
+            .filter(|row| &row[0] == metric)
+            .collect::<Vec<_>>()
+    };
+
+    let called = metric_rows("function_called_total");
+    assert_eq!(called.len(), 1);
+    assert_eq!(&called[0][2], "csv,quoted");
+    assert_eq!(&called[0][3], "type=func_call");
+    assert_eq!(&called[0][4], "2");
+
+    // A row per bucket, including `+Inf`, plus the sum and count.
+    let buckets = metric_rows("function_time_seconds_bucket");
+    assert_eq!(
+        buckets.len(),
+        instrumented::prometheus::DEFAULT_BUCKETS.len() + 1
+    );
+    assert!(buckets.last().unwrap()[3].starts_with("le=+Inf;"));
+    assert_eq!(&buckets.last().unwrap()[4], "2");
+    assert_eq!(metric_rows("function_time_seconds_sum").len(), 1);
+    assert_eq!(&metric_rows("function_time_seconds_count")[0][4], "2");
+}
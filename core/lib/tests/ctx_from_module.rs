@@ -0,0 +1,68 @@
+use instrumented::{instrument, instrument_mod, list_functions, module_ctx};
+
+mod billing {
+    use instrumented::instrument;
+
+    #[instrument(INFO, ctx_from_module)]
+    pub fn charge() {}
+
+    #[instrument(INFO, ctx = "payments", ctx_from_module)]
+    pub fn refund() {}
+
+    pub mod invoices {
+        use instrumented::instrument;
+
+        #[instrument(INFO, ctx_from_module)]
+        pub fn send() {}
+    }
+}
+
+#[instrument_mod(INFO, ctx_from_module, recursive)]
+mod shipping {
+    pub fn dispatch() {}
+
+    pub mod tracking {
+        pub fn locate() {}
+    }
+}
+
+#[instrument(INFO, ctx_from_module)]
+fn at_root() {}
+
+fn ctx(name: &str) -> Option<String> {
+    list_functions()
+        .into_iter()
+        .find(|function| function.name == name)
+        .map(|function| function.ctx)
+}
+
+#[test]
+fn derives_the_ctx_from_the_module() {
+    billing::charge();
+    billing::refund();
+    billing::invoices::send();
+    shipping::dispatch();
+    shipping::tracking::locate();
+    at_root();
+
+    assert_eq!(ctx("charge"), Some("billing".to_string()));
+    assert_eq!(ctx("send"), Some("invoices".to_string()));
+    assert_eq!(ctx("shipping::dispatch"), Some("shipping".to_string()));
+    assert_eq!(ctx("shipping::tracking::locate"), Some("tracking".to_string()));
+    // At the root of the crate, the name of the test crate.
+    assert_eq!(ctx("at_root"), Some("ctx_from_module".to_string()));
+}
+
+#[test]
+fn an_explicit_ctx_wins() {
+    billing::refund();
+    assert_eq!(ctx("refund"), Some("payments".to_string()));
+}
+
+#[test]
+fn takes_the_last_segment() {
+    assert_eq!(module_ctx("app::billing::invoices"), "invoices");
+    assert_eq!(module_ctx("app"), "app");
+    const CTX: &str = module_ctx(module_path!());
+    assert_eq!(CTX, "ctx_from_module");
+}
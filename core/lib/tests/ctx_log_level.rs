@@ -0,0 +1,43 @@
+#![cfg(feature = "ctx-log-level")]
+
+mod common;
+
+use instrumented::instrument;
+
+#[instrument(ok = "DEBUG", err = "WARN", ctx = "payments")]
+fn charge(fail: bool) -> Result<u32, String> {
+    if fail {
+        Err("declined".to_string())
+    } else {
+        Ok(42)
+    }
+}
+
+#[instrument(ok = "DEBUG", ctx = "search")]
+fn search() -> u32 {
+    7
+}
+
+#[test]
+fn overrides_the_level_of_a_ctx() {
+    common::capture_logs();
+    log::set_max_level(log::LevelFilter::Info);
+
+    charge(false).unwrap();
+    search();
+    assert!(common::captured_logs("charge()").is_empty());
+
+    instrumented::set_ctx_log_level("payments", log::Level::Info);
+    charge(false).unwrap();
+    charge(true).unwrap_err();
+    search();
+    assert_eq!(
+        common::captured_logs("charge()"),
+        vec!["INFO charge() => 42", "WARN charge() => \"declined\""]
+    );
+    assert!(common::captured_logs("search()").is_empty());
+
+    instrumented::clear_ctx_log_level("payments");
+    charge(false).unwrap();
+    assert_eq!(common::captured_logs("charge()").len(), 2);
+}
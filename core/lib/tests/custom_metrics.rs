@@ -0,0 +1,106 @@
+mod common;
+
+#[test]
+fn caches_gauges() {
+    let depth = instrumented::gauge("queue_depth", "Current queue depth").unwrap();
+    let same = instrumented::gauge("queue_depth", "Current queue depth").unwrap();
+    depth.set(7);
+    assert_eq!(same.get(), 7);
+
+    let family = instrumented::gather()
+        .into_iter()
+        .filter(|family| family.get_name() == "queue_depth")
+        .collect::<Vec<_>>();
+    assert_eq!(family.len(), 1);
+    assert_eq!(family[0].get_metric().len(), 1);
+    assert_eq!(common::gauge_value("queue_depth", &[]), 7.0);
+}
+
+#[test]
+fn rejects_conflicting_definitions() {
+    instrumented::counter("jobs_total", "Number of jobs")
+        .unwrap()
+        .inc();
+    let err = instrumented::gauge("jobs_total", "Number of jobs")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("already registered as IntCounter"));
+    assert!(instrumented::counter("jobs_total", "Jobs").is_err());
+    assert!(instrumented::counter_vec("jobs_total", "Number of jobs", &["queue"]).is_err());
+
+    // Built-in metrics can't be redefined either.
+    let err = instrumented::gauge("function_called_total", "Calls")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("reserved"));
+}
+
+#[test]
+fn caches_labeled_metrics() {
+    let jobs = instrumented::counter_vec("labeled_jobs_total", "Jobs", &["queue"]).unwrap();
+    jobs.with_label_values(&["email"]).inc();
+    instrumented::counter_vec("labeled_jobs_total", "Jobs", &["queue"])
+        .unwrap()
+        .with_label_values(&["email"])
+        .inc();
+    assert_eq!(
+        common::counter_value("labeled_jobs_total", &[("queue", "email")]),
+        2.0
+    );
+
+    let buckets = vec![0.1, 1.0];
+    let latency =
+        instrumented::histogram_vec("job_latency", "Latency", &["queue"], buckets.clone()).unwrap();
+    latency.with_label_values(&["email"]).observe(0.5);
+    assert!(instrumented::histogram_vec("job_latency", "Latency", &["queue"], vec![1.0]).is_err());
+    assert_eq!(
+        common::histogram_count("job_latency", &[("queue", "email")]),
+        1
+    );
+}
+
+#[test]
+fn registers_or_gets() {
+    use instrumented::prometheus::{IntCounterVec, IntGauge, Opts};
+
+    let opts = || Opts::new("shared_requests_total", "Requests").const_label("module", "api");
+    let first =
+        instrumented::register_or_get(IntCounterVec::new(opts(), &["method"]).unwrap()).unwrap();
+    let second =
+        instrumented::register_or_get(IntCounterVec::new(opts(), &["method"]).unwrap()).unwrap();
+    first.with_label_values(&["GET"]).inc();
+    second.with_label_values(&["GET"]).inc();
+    assert_eq!(
+        common::counter_value("shared_requests_total", &[("method", "GET")]),
+        2.0
+    );
+
+    let mismatches = vec![
+        IntCounterVec::new(opts(), &["path"]).unwrap(),
+        IntCounterVec::new(
+            Opts::new("shared_requests_total", "Other help").const_label("module", "api"),
+            &["method"],
+        )
+        .unwrap(),
+        IntCounterVec::new(
+            Opts::new("shared_requests_total", "Requests").const_label("module", "web"),
+            &["method"],
+        )
+        .unwrap(),
+    ];
+    for mismatch in mismatches {
+        let err = instrumented::register_or_get(mismatch).err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("already registered as IntCounterVec"));
+    }
+    let gauge = IntGauge::new("shared_requests_total", "Requests").unwrap();
+    assert!(instrumented::register_or_get(gauge).is_err());
+
+    // Metrics registered without it can't be shared.
+    let unmanaged = IntGauge::new("unmanaged_depth", "Depth").unwrap();
+    instrumented::register(Box::new(unmanaged.clone())).unwrap();
+    let err = instrumented::register_or_get(unmanaged).err().unwrap();
+    assert!(err.to_string().contains("couldn't be registered"));
+    assert!(err.to_string().contains("already exists"));
+}
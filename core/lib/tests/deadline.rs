@@ -0,0 +1,147 @@
+mod common;
+
+use common::block_on;
+use instrumented::{instrument, DeadlineExceeded};
+use std::future::Future;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq)]
+pub enum MyError {
+    TimedOut,
+}
+
+impl From<DeadlineExceeded> for MyError {
+    fn from(_: DeadlineExceeded) -> Self {
+        MyError::TimedOut
+    }
+}
+
+/// A future that's pending until `duration` elapsed.
+async fn sleep(duration: Duration) {
+    let until = Instant::now() + duration;
+    std::future::poll_fn(move |cx| {
+        if Instant::now() >= until {
+            Poll::Ready(())
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[instrument(INFO, deadline_ms = 100)]
+async fn in_time() -> u32 {
+    sleep(Duration::from_millis(10)).await;
+    1
+}
+
+#[instrument(INFO, deadline_ms = 50)]
+async fn late() -> u32 {
+    // Pending until the deadline elapsed.
+    std::future::poll_fn(|cx| {
+        if exceeded("late") > 0.0 {
+            Poll::Ready(())
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await;
+    2
+}
+
+#[instrument(INFO, deadline_ms = 50, deadline_cancel)]
+async fn cancelled() -> Result<u32, MyError> {
+    sleep(Duration::from_secs(10)).await;
+    Ok(3)
+}
+
+#[instrument(INFO, deadline_ms = 100, deadline_cancel)]
+async fn not_cancelled() -> Result<u32, MyError> {
+    sleep(Duration::from_millis(10)).await;
+    Ok(4)
+}
+
+#[instrument(INFO, deadline_ms = 50, deadline_cancel)]
+async fn never_woken() -> Result<u32, MyError> {
+    std::future::pending::<()>().await;
+    Ok(5)
+}
+
+#[instrument(INFO, deadline_ms = 100, deadline_cancel)]
+async fn deadline_marker() -> Result<(), MyError> {
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+fn exceeded(name: &str) -> f64 {
+    common::counter_value("function_deadline_exceeded_total", &[("name", name)])
+}
+
+/// Returns once the deadlines of at most 100 ms scheduled so far elapsed, the timer expiring
+/// them in order.
+fn wait_for_deadlines() {
+    assert_eq!(block_on(deadline_marker()), Err(MyError::TimedOut));
+}
+
+/// Sends on a channel when woken.
+struct Notify(SyncSender<()>);
+
+impl Wake for Notify {
+    fn wake(self: Arc<Self>) {
+        let _ = self.0.try_send(());
+    }
+}
+
+#[test]
+fn finishes_in_time() {
+    assert_eq!(block_on(in_time()), 1);
+    wait_for_deadlines();
+    assert_eq!(exceeded("in_time"), 0.0);
+}
+
+#[test]
+fn records_exceeded_deadline_and_continues() {
+    assert_eq!(block_on(late()), 2);
+    assert_eq!(exceeded("late"), 1.0);
+}
+
+#[test]
+fn cancels_at_deadline() {
+    let start = Instant::now();
+    assert_eq!(block_on(cancelled()), Err(MyError::TimedOut));
+    // Cancelled at the deadline, rather than completed by the body.
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(exceeded("cancelled"), 1.0);
+    assert_eq!(
+        common::counter_value("function_error_total", &[("name", "cancelled")]),
+        1.0
+    );
+
+    assert_eq!(block_on(not_cancelled()), Ok(4));
+    wait_for_deadlines();
+    assert_eq!(exceeded("not_cancelled"), 0.0);
+}
+
+#[test]
+fn wakes_pending_call_at_deadline() {
+    let (sender, receiver) = mpsc::sync_channel(1);
+    let waker = Waker::from(Arc::new(Notify(sender)));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(never_woken());
+    assert!(future.as_mut().poll(&mut cx).is_pending());
+
+    // Only the deadline wakes the call, its body never does.
+    receiver
+        .recv_timeout(Duration::from_secs(5))
+        .expect("woken at the deadline");
+    assert_eq!(
+        future.as_mut().poll(&mut cx),
+        Poll::Ready(Err(MyError::TimedOut))
+    );
+    assert_eq!(exceeded("never_woken"), 1.0);
+}
@@ -0,0 +1,56 @@
+#![cfg(feature = "debug-introspection")]
+
+use instrumented::instrument;
+use instrumented::introspection::{records, FunctionRecord};
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, ctx = "introspected", apdex_t = 0.1, track_last_called)]
+fn fetch() -> Result<(), MyError> {
+    Ok(())
+}
+
+#[instrument(TRACE, ctx = "introspected")]
+async fn poll_once() {}
+
+fn find(name: &str) -> &'static FunctionRecord {
+    records()
+        .into_iter()
+        .find(|record| record.name == name && record.ctx == "introspected")
+        .unwrap()
+}
+
+#[test]
+fn records_the_expansion() {
+    if !cfg!(any(target_os = "linux", target_os = "macos", windows)) {
+        return;
+    }
+
+    let fetch = find("fetch");
+    assert_eq!(
+        fetch.flags,
+        &[
+            "calls",
+            "timer",
+            "errors",
+            "log_ok",
+            "log_err",
+            "apdex",
+            "track_last_called"
+        ]
+    );
+    assert_eq!(fetch.crate_name(), "debug_introspection");
+    assert_eq!(fetch.module, "debug_introspection");
+    assert!(
+        fetch.file.ends_with("debug_introspection.rs"),
+        "{}",
+        fetch.file
+    );
+    assert_eq!(fetch.line, 10);
+
+    let poll_once = find("poll_once");
+    assert!(poll_once.has_flag("async"));
+    assert!(!poll_once.has_flag("errors"));
+    assert!(!poll_once.has_flag("apdex"));
+}
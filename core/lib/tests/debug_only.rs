@@ -0,0 +1,48 @@
+mod common;
+
+use instrumented::instrument;
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, debug_only)]
+fn dev_only(x: u32) -> u32 {
+    x + 1
+}
+
+pub struct Cache;
+
+impl Cache {
+    #[instrument(INFO, ctx = "cache", debug_only)]
+    fn lookup(&self, fail: bool) -> Result<u32, MyError> {
+        if fail {
+            Err(MyError)
+        } else {
+            Ok(1)
+        }
+    }
+}
+
+// Both variants must have the same signature.
+const _: fn(u32) -> u32 = dev_only;
+
+#[test]
+fn instrumented_in_debug_builds_only() {
+    assert_eq!(dev_only(1), 2);
+    assert_eq!(Cache.lookup(false).unwrap(), 1);
+    assert!(Cache.lookup(true).is_err());
+
+    let instrumented = cfg!(debug_assertions);
+    assert_eq!(
+        common::find_metric("function_called_total", &[("name", "dev_only")]).is_some(),
+        instrumented
+    );
+    assert_eq!(
+        common::find_metric("function_called_total", &[("name", "lookup")]).is_some(),
+        instrumented
+    );
+    assert_eq!(
+        common::find_metric("function_error_total", &[("name", "lookup")]).is_some(),
+        instrumented
+    );
+}
@@ -0,0 +1,38 @@
+use instrumented::instrument;
+use instrumented::prometheus::{IntCounter, Opts};
+
+#[instrument(INFO)]
+fn still_works() -> u32 {
+    42
+}
+
+#[test]
+fn builtin_registration_survives_collisions() {
+    // Takes the name of a built-in metric, as another copy of the crate sharing the registry would.
+    let conflicting =
+        IntCounter::with_opts(Opts::new("function_called_total", "Conflicting collector")).unwrap();
+    instrumented::register(Box::new(conflicting.clone())).unwrap();
+
+    assert_eq!(still_works(), 42);
+    assert_eq!(still_works(), 42);
+
+    let metrics = instrumented::render_metrics();
+    assert!(metrics.contains("# HELP function_called_total Conflicting collector"));
+    assert!(metrics.contains("function_called_total 0\n"), "{}", metrics);
+    assert!(metrics.contains(&format!(
+        "instrumented_registry_info{{version=\"{}\"}} 1",
+        env!("CARGO_PKG_VERSION")
+    )));
+
+    let errors = instrumented::health();
+    assert!(
+        errors.iter().any(|err| match err {
+            instrumented::InitError::Registration { metrics, .. } => {
+                metrics.iter().any(|m| m == "function_called_total")
+            }
+            instrumented::InitError::InvalidConfig(_) => false,
+        }),
+        "{:?}",
+        errors
+    );
+}
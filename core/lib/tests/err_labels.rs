@@ -0,0 +1,87 @@
+mod common;
+
+use instrumented::instrument;
+
+#[derive(Debug)]
+pub enum FetchError {
+    Timeout,
+    Status(u16),
+}
+
+#[derive(Debug)]
+pub struct DbError {
+    table: &'static str,
+    code: u32,
+}
+
+pub fn fetch_labels(err: &FetchError) -> Vec<(&'static str, String)> {
+    match err {
+        FetchError::Timeout => vec![("kind", "timeout".into()), ("retryable", "true".into())],
+        // In another order, which is fine as long as the names are the same.
+        FetchError::Status(status) => vec![
+            ("retryable", (*status >= 500).to_string()),
+            ("kind", "status".into()),
+        ],
+    }
+}
+
+pub fn db_labels(err: &DbError) -> Vec<(&'static str, String)> {
+    if err.code == 0 {
+        // Not the label names of the first error.
+        return vec![("table", err.table.into())];
+    }
+    vec![("table", err.table.into()), ("code", err.code.to_string())]
+}
+
+#[instrument(INFO, ctx = "err_labels", err_labels = "fetch_labels")]
+fn fetch(status: u16) -> Result<(), FetchError> {
+    match status {
+        0 => Err(FetchError::Timeout),
+        200 => Ok(()),
+        _ => Err(FetchError::Status(status)),
+    }
+}
+
+#[instrument(INFO, ctx = "err_labels", err_labels = "db_labels")]
+fn insert(table: &'static str, code: u32) -> Result<(), DbError> {
+    Err(DbError { table, code })
+}
+
+#[test]
+fn labels_errors_by_their_classifier() {
+    for status in &[0, 200, 503, 0, 404, 503] {
+        let _ = fetch(*status);
+    }
+
+    for (kind, retryable, count) in &[
+        ("timeout", "true", 2.0),
+        ("status", "true", 2.0),
+        ("status", "false", 1.0),
+    ] {
+        assert_eq!(
+            common::counter_value(
+                "function_error_detail_total",
+                &[("name", "fetch"), ("kind", kind), ("retryable", retryable)]
+            ),
+            *count
+        );
+    }
+}
+
+#[test]
+fn ignores_errors_with_other_label_names() {
+    insert("users", 23505).unwrap_err();
+    insert("users", 0).unwrap_err();
+    insert("orders", 23505).unwrap_err();
+
+    let labels = [("name", "insert"), ("ctx", "err_labels")];
+    let count = |table, code| {
+        let mut labels = labels.to_vec();
+        labels.extend(&[("table", table), ("code", code)]);
+        common::counter_value("function_error_detail_total", &labels)
+    };
+    assert_eq!(count("users", "23505"), 1.0);
+    assert_eq!(count("orders", "23505"), 1.0);
+    // All the errors are still counted.
+    assert_eq!(instrumented::stats::errors("insert", "err_labels"), 3);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::counter_value;
+use instrumented::{instrument, InstrumentedErrorClass};
+
+#[derive(Debug, InstrumentedErrorClass)]
+pub enum StoreError {
+    #[instrumented(class = "timeout", retryable)]
+    TimedOut {
+        after_ms: u64,
+    },
+    #[instrumented(class = "conflict", retryable)]
+    Conflict(String),
+    NotFound,
+}
+
+#[instrument(INFO, ctx = "error_class", err_label = "class")]
+fn get(key: &str) -> Result<u32, StoreError> {
+    match key {
+        "slow" => Err(StoreError::TimedOut { after_ms: 500 }),
+        "busy" => Err(StoreError::Conflict(key.to_string())),
+        "found" => Ok(1),
+        _ => Err(StoreError::NotFound),
+    }
+}
+
+#[test]
+fn derived_classes() {
+    let timed_out = StoreError::TimedOut { after_ms: 1 };
+    assert_eq!(timed_out.class(), "timeout");
+    assert!(timed_out.retryable());
+    let conflict = StoreError::Conflict("a".into());
+    assert_eq!(conflict.class(), "conflict");
+    assert!(conflict.retryable());
+    assert_eq!(StoreError::NotFound.class(), "not_found");
+    assert!(!StoreError::NotFound.retryable());
+}
+
+#[test]
+fn errors_are_labelled_by_class() {
+    for key in &["slow", "busy", "found", "missing", "slow", "other"] {
+        let _ = get(key);
+    }
+
+    for (class, retryable, count) in &[
+        ("timeout", "true", 2.0),
+        ("conflict", "true", 1.0),
+        ("not_found", "false", 2.0),
+    ] {
+        let labels = [("name", "get"), ("ctx", "error_class"), ("err", *class)];
+        assert_eq!(
+            counter_value("function_error_total", &labels),
+            *count,
+            "{}",
+            class
+        );
+        let labels = [
+            ("name", "get"),
+            ("ctx", "error_class"),
+            ("class", *class),
+            ("retryable", *retryable),
+        ];
+        assert_eq!(
+            counter_value("function_error_detail_total", &labels),
+            *count,
+            "{}",
+            class
+        );
+    }
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use instrumented::instrument;
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[derive(Debug)]
+pub enum StoreError {
+    NotFound,
+    Corrupted,
+}
+
+#[instrument(INFO, ctx = "ratio")]
+fn sometimes(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(INFO, ctx = "ratio", ignore_err = "NotFound")]
+fn lookup(err: StoreError) -> Result<(), StoreError> {
+    Err(err)
+}
+
+#[test]
+fn error_ratio_of_recent_calls() {
+    assert_eq!(instrumented::error_ratio("sometimes", "ratio"), None);
+
+    for fail in &[false, true, false, false, true, false, false, false] {
+        let _ = sometimes(*fail);
+    }
+    assert_eq!(instrumented::error_ratio("sometimes", "ratio"), Some(0.25));
+    assert_eq!(
+        common::gauge_value(
+            "function_error_ratio",
+            &[("name", "sometimes"), ("ctx", "ratio")]
+        ),
+        0.25
+    );
+    assert_eq!(instrumented::error_ratio("sometimes", "other"), None);
+}
+
+#[test]
+fn ignored_errors_are_successes() {
+    let _ = lookup(StoreError::NotFound);
+    let _ = lookup(StoreError::Corrupted);
+    assert_eq!(instrumented::error_ratio("lookup", "ratio"), Some(0.5));
+}
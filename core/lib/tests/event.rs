@@ -0,0 +1,43 @@
+use std::thread;
+use std::time::Duration;
+
+fn timestamp(metrics: &str, series: &str) -> f64 {
+    metrics
+        .lines()
+        .find(|line| line.starts_with(series))
+        .and_then(|line| line.rsplit(' ').next())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("no {} in {}", series, metrics))
+}
+
+#[test]
+fn later_event_wins() {
+    let series = "event_timestamp_seconds{event=\"failover\",from=\"a\",to=\"b\"}";
+
+    instrumented::event("failover", &[("from", "a"), ("to", "b")]).unwrap();
+    let first = timestamp(&instrumented::render_metrics(), series);
+    thread::sleep(Duration::from_millis(20));
+    instrumented::event("failover", &[("to", "b"), ("from", "a")]).unwrap();
+    let metrics = instrumented::render_metrics();
+    let second = timestamp(&metrics, series);
+
+    assert!(second > first, "{} <= {}", second, first);
+    assert_eq!(
+        metrics.matches("event=\"failover\"").count(),
+        1,
+        "{}",
+        metrics
+    );
+    assert!(first > 1_500_000_000.0);
+}
+
+#[test]
+fn rejects_invalid_labels() {
+    assert_eq!(
+        instrumented::event("reload", &[("bad label", "x")]),
+        Err(instrumented::EventError::InvalidLabelName(
+            "bad label".to_string()
+        ))
+    );
+    assert!(!instrumented::render_metrics().contains("event=\"reload\""));
+}
@@ -0,0 +1,16 @@
+// In its own binary, as filling the events would fail the events of the other tests.
+#[test]
+fn caps_distinct_event_names() {
+    let mut rejected = None;
+    for i in 0..=instrumented::MAX_EVENT_NAMES {
+        if let Err(err) = instrumented::event(&format!("capped_{}", i), &[]) {
+            rejected = Some(err);
+            break;
+        }
+    }
+    assert!(
+        matches!(rejected, Some(instrumented::EventError::TooManyEvents(_))),
+        "{:?}",
+        rejected
+    );
+}
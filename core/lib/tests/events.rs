@@ -0,0 +1,51 @@
+mod common;
+
+use instrumented::events::{self, Outcome};
+use instrumented::instrument;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, ctx = "events")]
+fn published(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn sends_events_to_subscribers() {
+    let before = SystemTime::now();
+    let rx = events::subscribe(16);
+    let small = events::subscribe(1);
+
+    published(false).unwrap();
+    published(true).unwrap_err();
+    published(false).unwrap();
+
+    let received: Vec<_> = rx
+        .try_iter()
+        .filter(|event| event.name == "published")
+        .collect();
+    assert_eq!(received.len(), 3);
+    assert_eq!(received[0].ctx, "events");
+    assert_eq!(received[0].outcome, Outcome::Ok);
+    assert_eq!(received[1].outcome, Outcome::Err("MyError".to_string()));
+    assert!(received[0].started_at >= before);
+    assert!(received[0].duration < Duration::from_secs(1));
+
+    // The second subscriber only buffers the first event.
+    assert_eq!(small.try_iter().count(), 1);
+    assert_eq!(common::counter_value("dropped_events_total", &[]), 2.0);
+
+    // Events are dropped neither for nor because of subscribers that went away.
+    drop(small);
+    published(false).unwrap();
+    let _other = events::subscribe(16);
+    published(false).unwrap();
+    assert_eq!(rx.try_iter().count(), 2);
+    assert_eq!(common::counter_value("dropped_events_total", &[]), 2.0);
+}
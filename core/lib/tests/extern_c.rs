@@ -0,0 +1,49 @@
+mod common;
+
+use instrumented::instrument;
+
+#[instrument(INFO)]
+#[no_mangle]
+pub unsafe extern "C" fn instrumented_read(ptr: *const u32) -> u32 {
+    *ptr
+}
+
+#[instrument(INFO)]
+pub extern "C" fn instrumented_add(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+extern "C" {
+    // Resolves to the `#[no_mangle]` symbol above.
+    #[link_name = "instrumented_read"]
+    fn read_by_symbol(ptr: *const u32) -> u32;
+}
+
+fn type_name_of<T>(_: &T) -> &'static str {
+    std::any::type_name::<T>()
+}
+
+#[test]
+fn extern_c_functions() {
+    let read: unsafe extern "C" fn(*const u32) -> u32 = instrumented_read;
+    let add: extern "C" fn(u32, u32) -> u32 = instrumented_add;
+    assert_eq!(
+        type_name_of(&read),
+        "unsafe extern \"C\" fn(*const u32) -> u32"
+    );
+    assert_eq!(type_name_of(&add), "extern \"C\" fn(u32, u32) -> u32");
+
+    let value = 42;
+    assert_eq!(unsafe { read(&value) }, 42);
+    assert_eq!(unsafe { read_by_symbol(&value) }, 42);
+    assert_eq!(add(1, 2), 3);
+
+    assert_eq!(
+        common::counter_value("function_called_total", &[("name", "instrumented_read")]),
+        2.0
+    );
+    assert_eq!(
+        common::counter_value("function_called_total", &[("name", "instrumented_add")]),
+        1.0
+    );
+}
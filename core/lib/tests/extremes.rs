@@ -0,0 +1,50 @@
+use instrumented::instrument;
+use std::{thread, time};
+
+#[instrument(INFO)]
+fn sleeps(millis: u64) {
+    thread::sleep(time::Duration::from_millis(millis));
+}
+
+/// Scrapes the min and max durations of `sleeps`, if any.
+fn scrape() -> Option<(f64, f64)> {
+    let families = instrumented::gather();
+    let value = |family: &str| {
+        families
+            .iter()
+            .find(|mf| mf.get_name() == family)?
+            .get_metric()
+            .iter()
+            .find(|m| {
+                m.get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "name" && l.get_value() == "sleeps")
+            })
+            .map(|m| m.get_gauge().get_value())
+    };
+    Some((
+        value("function_time_min_seconds")?,
+        value("function_time_max_seconds")?,
+    ))
+}
+
+#[test]
+fn extremes_between_scrapes() {
+    instrumented::set_duration_extremes(true);
+
+    sleeps(1);
+    sleeps(100);
+    sleeps(1);
+    let (min, max) = scrape().unwrap();
+    assert!((0.001..0.05).contains(&min), "min {}", min);
+    assert!((0.1..0.5).contains(&max), "max {}", max);
+
+    // The previous scrape reset the extremes.
+    sleeps(1);
+    let (min, max) = scrape().unwrap();
+    assert!((0.001..0.05).contains(&min), "min {}", min);
+    assert!((0.001..0.05).contains(&max), "max {}", max);
+
+    // Without calls since, the series are left out.
+    assert_eq!(scrape(), None);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::{counter_value, histogram_sum};
+use instrumented::instrument;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// A stand-in for a slow C library call.
+extern "C" fn slow_c_call(millis: u64) -> u64 {
+    sleep(Duration::from_millis(millis));
+    millis
+}
+
+#[instrument(INFO, ctx = "ffi", phases)]
+fn compress() -> u64 {
+    sleep(Duration::from_millis(10));
+    let a = instrumented::ffi_section("deflate", || slow_c_call(30));
+    let b = instrumented::ffi_section("deflate", || slow_c_call(20));
+    a + b
+}
+
+#[instrument(INFO, ctx = "ffi", phases)]
+fn outer() -> u64 {
+    inner() + instrumented::ffi_section("checksum", || slow_c_call(1))
+}
+
+#[instrument(INFO, ctx = "ffi", phases)]
+fn inner() -> u64 {
+    instrumented::ffi_section("checksum", || slow_c_call(1))
+}
+
+#[test]
+fn foreign_calls_are_attributed_to_the_function() {
+    assert_eq!(compress(), 50);
+
+    let labels = [("name", "compress"), ("ctx", "ffi"), ("section", "deflate")];
+    assert_eq!(counter_value("function_ffi_calls_total", &labels), 2.0);
+    let ffi = histogram_sum("function_ffi_seconds", &labels);
+    let total = histogram_sum(
+        "function_time_seconds",
+        &[("name", "compress"), ("ctx", "ffi")],
+    );
+    assert!(ffi >= 0.05, "{}", ffi);
+    assert!(ffi < total, "{} >= {}", ffi, total);
+}
+
+#[test]
+fn innermost_function() {
+    assert_eq!(outer(), 2);
+
+    for name in &["outer", "inner"] {
+        let labels = [("name", *name), ("ctx", "ffi"), ("section", "checksum")];
+        assert_eq!(counter_value("function_ffi_calls_total", &labels), 1.0);
+    }
+
+    // Outside of any instrumented function, the call isn't recorded.
+    assert_eq!(instrumented::ffi_section("orphan", || slow_c_call(0)), 0);
+    assert!(!instrumented::render_metrics().contains("section=\"orphan\""));
+}
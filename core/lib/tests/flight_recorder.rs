@@ -0,0 +1,68 @@
+#![cfg(all(unix, feature = "flight-recorder"))]
+
+use instrumented::flight_recorder::{read_flight_record, FlightOutcome};
+use instrumented::instrument;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug)]
+struct Refused;
+
+#[instrument(INFO, ctx = "payment")]
+fn charge(fail: bool) -> Result<(), Refused> {
+    if fail {
+        Err(Refused)
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(INFO, ctx = "search")]
+fn lookup() {}
+
+#[test]
+fn records_the_last_calls() {
+    let path = std::env::temp_dir().join(format!("instrumented-{}.flight", std::process::id()));
+    let before = SystemTime::now() - Duration::from_secs(1);
+    instrumented::init_with_config(
+        instrumented::Config::new("127.0.0.1:0").flight_recorder(&path, 4),
+    );
+    assert!(instrumented::flight_recorder::init(&path, 4).is_err());
+
+    charge(false).unwrap();
+    charge(true).unwrap_err();
+    lookup();
+
+    let events = read_flight_record(&path).unwrap();
+    let calls: Vec<(u64, &str, &str, FlightOutcome)> = events
+        .iter()
+        .map(|event| (event.seq, &event.name[..], &event.ctx[..], event.outcome))
+        .collect();
+    assert_eq!(
+        calls,
+        vec![
+            (1, "charge", "payment", FlightOutcome::Ok),
+            (2, "charge", "payment", FlightOutcome::Err),
+            (3, "lookup", "search", FlightOutcome::Ok),
+        ]
+    );
+    for event in &events {
+        assert!(event.start >= before && event.start <= SystemTime::now());
+        assert!(event.duration < Duration::from_secs(1));
+    }
+
+    // Only the last 4 calls are kept, and the names are written once.
+    for _ in 0..3 {
+        lookup();
+    }
+    let events = read_flight_record(&path).unwrap();
+    let seqs: Vec<u64> = events.iter().map(|event| event.seq).collect();
+    assert_eq!(seqs, vec![3, 4, 5, 6]);
+    assert!(events.iter().all(|event| event.name == "lookup"));
+
+    let other = path.with_extension("txt");
+    fs::write(&other, b"not a flight record").unwrap();
+    assert!(read_flight_record(&other).is_err());
+    fs::remove_file(&other).unwrap();
+    fs::remove_file(&path).unwrap();
+}
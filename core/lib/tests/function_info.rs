@@ -0,0 +1,46 @@
+use instrumented::instrument;
+
+/// Fetches a user by id.
+///
+/// The id is returned as is.
+#[instrument(INFO, ctx = "users")]
+fn get_user(id: u64) -> u64 {
+    id
+}
+
+#[instrument(INFO, ctx = "users")]
+fn undocumented(id: u64) -> u64 {
+    id
+}
+
+fn info_lines() -> Vec<String> {
+    instrumented::render_metrics()
+        .lines()
+        .filter(|line| line.starts_with("function_info{"))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn exports_the_first_doc_line() {
+    assert_eq!(get_user(7), 7);
+    assert_eq!(undocumented(7), 7);
+    assert!(info_lines().is_empty());
+
+    instrumented::set_function_info(true);
+    if cfg!(any(target_os = "linux", target_os = "macos", windows)) {
+        let lines = info_lines();
+        assert!(
+            lines.contains(
+                &"function_info{ctx=\"users\",help=\"Fetches a user by id\",name=\"get_user\",type=\"func_call\"} 1"
+                    .to_string()
+            ),
+            "{:?}",
+            lines
+        );
+        assert!(!lines.iter().any(|line| line.contains("undocumented")));
+    }
+
+    instrumented::set_function_info(false);
+    assert!(info_lines().is_empty());
+}
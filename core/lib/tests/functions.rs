@@ -0,0 +1,36 @@
+use instrumented::{instrument, list_functions, FunctionStats};
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, ctx = "listed")]
+fn called(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(INFO, ctx = "listed")]
+fn never_called() {}
+
+fn find(name: &str) -> Option<FunctionStats> {
+    list_functions()
+        .into_iter()
+        .find(|function| function.name == name && function.ctx == "listed")
+}
+
+#[test]
+fn lists_registered_functions() {
+    assert!(called(false).is_ok());
+    assert!(called(true).is_err());
+
+    let function = find("called").expect("called is listed");
+    assert_eq!(function.calls, 2);
+    assert_eq!(function.errors, 1);
+
+    let function = find("never_called").expect("never_called is listed");
+    assert_eq!(function.calls, 0);
+    assert_eq!(function.errors, 0);
+}
@@ -0,0 +1,21 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn hooks_run_before_every_scrape() {
+    let scrapes = instrumented::counter("gather_hook_scrapes_total", "Number of scrapes").unwrap();
+    let ran_after_panic = Arc::new(AtomicBool::new(false));
+
+    instrumented::on_gather(Box::new(|| panic!("broken hook")));
+    let counter = scrapes.clone();
+    instrumented::on_gather(Box::new(move || counter.inc()));
+    let ran = ran_after_panic.clone();
+    instrumented::on_gather(Box::new(move || ran.store(true, Ordering::SeqCst)));
+
+    for expected in 1..=3 {
+        let metrics = instrumented::render_metrics();
+        let line = format!("gather_hook_scrapes_total {}\n", expected);
+        assert!(metrics.contains(&line), "{}", metrics);
+    }
+    assert!(ran_after_panic.load(Ordering::SeqCst));
+}
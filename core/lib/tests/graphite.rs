@@ -0,0 +1,43 @@
+#![cfg(feature = "graphite")]
+
+use instrumented::graphite::{init_graphite, GraphiteConfig};
+use instrumented::instrument;
+use std::io::{BufRead, BufReader};
+use std::net::TcpListener;
+use std::time::Duration;
+
+#[instrument(INFO, ctx = "flushed")]
+fn flushed() {}
+
+/// Reads lines from the next connection until one starts with `prefix`.
+fn next_line(listener: &TcpListener, prefix: &str) -> String {
+    let (stream, _) = listener.accept().unwrap();
+    BufReader::new(stream)
+        .lines()
+        .map(Result::unwrap)
+        .find(|line| line.starts_with(prefix))
+        .unwrap()
+}
+
+#[test]
+fn flushes_plaintext_lines_and_reconnects() {
+    flushed();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    init_graphite(
+        GraphiteConfig::new(&listener.local_addr().unwrap().to_string())
+            .prefix("app")
+            .interval(Duration::from_millis(50)),
+    );
+
+    let prefix = "app.function_called_total;ctx=flushed;name=flushed;type=func_call ";
+    let line = next_line(&listener, prefix);
+    let mut fields = line[prefix.len()..].split(' ');
+    assert_eq!(fields.next(), Some("1"));
+    assert!(fields.next().unwrap().parse::<u64>().unwrap() > 1_500_000_000);
+
+    // The first connection was dropped by `next_line`, the exporter connects again.
+    flushed();
+    let line = next_line(&listener, prefix);
+    assert!(line[prefix.len()..].starts_with("2 "), "{}", line);
+}
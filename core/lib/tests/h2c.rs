@@ -0,0 +1,48 @@
+mod common;
+
+use common::protobuf::{delimited, fields};
+use instrumented::Config;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+
+const PROTOBUF: &str =
+    "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+
+#[instrumented::instrument(INFO, ctx = "h2c")]
+fn work() {}
+
+#[test]
+fn serves_http2_and_http1() {
+    work();
+    let exporter =
+        instrumented::init_multi_with_config(Config::new("127.0.0.1:0"), &["127.0.0.1:0"]);
+    let url = format!("http://{}/metrics", exporter.addrs()[0]);
+
+    let h2c = reqwest::Client::builder()
+        .h2_prior_knowledge()
+        .build()
+        .unwrap();
+    let mut response = h2c.get(&url).send().unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    assert!(response.text().unwrap().contains("ctx=\"h2c\""));
+
+    let mut response = reqwest::Client::new().get(&url).send().unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.version(), reqwest::Version::HTTP_11);
+    assert!(response.text().unwrap().contains("ctx=\"h2c\""));
+
+    // The protobuf exposition is negotiated over h2c too.
+    let mut response = h2c.get(&url).header(ACCEPT, PROTOBUF).send().unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    assert_eq!(response.headers()[CONTENT_TYPE], PROTOBUF);
+    let mut exposition = vec![];
+    response.copy_to(&mut exposition).unwrap();
+    let names: Vec<Vec<u8>> = delimited(&exposition)
+        .iter()
+        .flat_map(|family| fields(family))
+        .filter(|field| field.0 == 1)
+        .map(|field| field.2)
+        .collect();
+    assert!(names.contains(&b"function_called_total".to_vec()));
+}
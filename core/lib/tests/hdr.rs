@@ -0,0 +1,84 @@
+#![cfg(feature = "hdr")]
+
+mod common;
+
+use instrumented::instrument;
+use std::time::Duration;
+
+#[instrument(INFO, ctx = "hdr", hdr)]
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn quantile(name: &str, quantile: &str) -> f64 {
+    common::gauge_value(
+        "function_time_hdr_seconds",
+        &[("name", name), ("ctx", "hdr"), ("quantile", quantile)],
+    )
+}
+
+fn count(name: &str) -> f64 {
+    common::counter_value("function_time_hdr_count", &[("name", name), ("ctx", "hdr")])
+}
+
+#[test]
+fn exports_quantiles_within_the_precision() {
+    // 1ns to 10µs, uniformly.
+    for nanos in 1..=10_000 {
+        instrumented::hdr::record("uniform", "hdr", Duration::from_nanos(nanos));
+    }
+
+    assert_eq!(count("uniform"), 10_000.0);
+    for (label, nanos) in &[
+        ("0.5", 5_000.0),
+        ("0.9", 9_000.0),
+        ("0.99", 9_900.0),
+        ("0.999", 9_990.0),
+    ] {
+        let seconds = nanos / 1e9;
+        let exported = quantile("uniform", label);
+        // 3 significant digits, plus the value of a sample either way.
+        assert!(
+            (exported - seconds).abs() <= seconds * 0.001 + 1e-9,
+            "p{} is {}s, not {}s",
+            label,
+            exported,
+            seconds
+        );
+    }
+}
+
+#[test]
+fn records_instrumented_calls() {
+    let a = [1.0; 8];
+    for _ in 0..100 {
+        dot(&a, &a);
+    }
+
+    assert_eq!(count("dot"), 100.0);
+    assert!(quantile("dot", "0.5") > 0.0);
+    assert!(quantile("dot", "0.5") <= quantile("dot", "0.999"));
+    // The calls aren't observed into the bucketed histogram.
+    assert_eq!(
+        common::histogram_count("function_time_seconds", &[("name", "dot")]),
+        0
+    );
+}
+
+#[instrument(INFO, ctx = "hdr", hdr, percentiles)]
+fn sum(a: &[f32]) -> f32 {
+    a.iter().sum()
+}
+
+#[test]
+fn percentiles_are_read_from_the_histogram() {
+    for _ in 0..100 {
+        sum(&[1.0; 8]);
+    }
+
+    let p99 = common::gauge_value(
+        "function_time_p99_seconds",
+        &[("name", "sum"), ("ctx", "hdr")],
+    );
+    assert_eq!(p99, quantile("sum", "0.99"));
+}
@@ -0,0 +1,24 @@
+use instrumented::instrument;
+
+#[instrument(INFO)]
+fn still_works() -> u32 {
+    42
+}
+
+#[test]
+fn invalid_prefix_is_reported_instead_of_panicking() {
+    // Read on first use of the registry, so set before any instrumented call.
+    std::env::set_var("METRICS_PREFIX", "not a valid prefix");
+
+    assert_eq!(still_works(), 42);
+
+    let errors = instrumented::health();
+    assert!(
+        errors
+            .iter()
+            .any(|err| matches!(err, instrumented::InitError::InvalidConfig(_))),
+        "{:?}",
+        errors
+    );
+    assert!(instrumented::render_metrics().contains("function_called_total"));
+}
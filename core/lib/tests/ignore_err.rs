@@ -0,0 +1,40 @@
+mod common;
+
+use instrumented::instrument;
+
+#[derive(Debug)]
+pub enum StoreError {
+    NotFound(String),
+    WouldBlock,
+    Corrupted { offset: u64 },
+}
+
+#[instrument(INFO, ignore_err = "NotFound, WouldBlock")]
+fn lookup(key: &str) -> Result<u32, StoreError> {
+    match key {
+        "missing" => Err(StoreError::NotFound(key.to_string())),
+        "busy" => Err(StoreError::WouldBlock),
+        "bad" => Err(StoreError::Corrupted { offset: 42 }),
+        _ => Ok(1),
+    }
+}
+
+#[test]
+fn skips_matching_errors() {
+    assert!(lookup("missing").is_err());
+    assert!(lookup("busy").is_err());
+    assert!(lookup("bad").is_err());
+    assert!(lookup("present").is_ok());
+
+    let labels = [("name", "lookup")];
+    assert_eq!(common::counter_value("function_called_total", &labels), 4.0);
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 4);
+    assert_eq!(common::counter_value("function_error_total", &labels), 1.0);
+    assert_eq!(
+        common::counter_value(
+            "function_error_total",
+            &[("name", "lookup"), ("err", "Corrupted { offset: 42 }")]
+        ),
+        1.0
+    );
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use instrumented::instrument;
+use std::panic;
+
+#[instrument(INFO)]
+fn make_iter(n: u32) -> impl Iterator<Item = u32> {
+    (0..n).map(|i| i * 2)
+}
+
+#[instrument(INFO)]
+fn borrowed_iter(items: &[u32]) -> impl Iterator<Item = &u32> + '_ {
+    items.iter().rev()
+}
+
+#[instrument(INFO)]
+fn diverges(message: &str) -> ! {
+    panic!("{}", message)
+}
+
+#[test]
+fn impl_trait_values_pass_through() {
+    #[cfg(feature = "logging")]
+    common::capture_logs();
+
+    assert_eq!(make_iter(4).collect::<Vec<_>>(), vec![0, 2, 4, 6]);
+    assert_eq!(
+        borrowed_iter(&[1, 2, 3]).cloned().collect::<Vec<_>>(),
+        vec![3, 2, 1]
+    );
+
+    let labels = [("name", "make_iter")];
+    assert_eq!(common::counter_value("function_called_total", &labels), 1.0);
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 1);
+    assert_eq!(
+        common::gauge_value("function_calls_inflight_total", &labels),
+        0.0
+    );
+    #[cfg(feature = "logging")]
+    assert_eq!(
+        common::captured_logs("make_iter()"),
+        vec!["INFO make_iter() => <impl Trait>".to_string()]
+    );
+}
+
+#[test]
+fn never_returning_functions_are_counted() {
+    assert!(panic::catch_unwind(|| diverges("boom")).is_err());
+
+    let labels = [("name", "diverges")];
+    assert_eq!(common::counter_value("function_called_total", &labels), 1.0);
+    assert!(common::find_metric("function_time_seconds", &labels).is_none());
+}
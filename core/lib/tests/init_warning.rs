@@ -0,0 +1,31 @@
+#![cfg(all(feature = "exporter", feature = "logging"))]
+
+mod common;
+
+use instrumented::instrument;
+
+#[instrument(INFO)]
+fn early() {}
+
+#[instrument(INFO)]
+fn earlier() {}
+
+#[test]
+fn warns_once_about_calls_before_init() {
+    common::capture_logs();
+    assert!(!instrumented::is_initialized());
+
+    for _ in 0..1000 {
+        early();
+        earlier();
+    }
+    let warnings = common::captured_logs("before `instrumented::init`");
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(warnings[0].starts_with("WARN early was called"), "{:?}", warnings);
+
+    let exporter = instrumented::init_multi(&["127.0.0.1:0"]);
+    assert!(exporter.errors().is_empty());
+    assert!(instrumented::is_initialized());
+    early();
+    assert_eq!(common::captured_logs("before `instrumented::init`").len(), 1);
+}
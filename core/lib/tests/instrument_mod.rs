@@ -0,0 +1,55 @@
+use instrumented::{instrument_mod, list_functions};
+
+#[instrument_mod(INFO, ctx = "parser", recursive)]
+mod parser {
+    #[derive(Debug)]
+    pub struct ParseError;
+
+    pub fn tokenize(input: &str) -> Vec<&str> {
+        input.split_whitespace().collect()
+    }
+
+    pub fn parse(input: &str) -> Result<usize, ParseError> {
+        if input.is_empty() {
+            Err(ParseError)
+        } else {
+            Ok(tokenize(input).len())
+        }
+    }
+
+    pub fn validate(tokens: &[&str]) -> bool {
+        !tokens.is_empty() && is_blank("")
+    }
+
+    #[instrument(skip)]
+    pub fn is_blank(input: &str) -> bool {
+        input.trim().is_empty()
+    }
+
+    pub mod literals {
+        pub fn number(token: &str) -> Option<u64> {
+            token.parse().ok()
+        }
+    }
+}
+
+fn calls(name: &str) -> Option<u64> {
+    list_functions()
+        .into_iter()
+        .find(|function| function.name == name && function.ctx == "parser")
+        .map(|function| function.calls)
+}
+
+#[test]
+fn instruments_every_function_of_the_module() {
+    assert!(parser::validate(&parser::tokenize("let x")));
+    assert_eq!(parser::parse("a b").unwrap(), 2);
+    assert!(parser::parse("").is_err());
+    assert_eq!(parser::literals::number("42"), Some(42));
+
+    assert_eq!(calls("parser::tokenize"), Some(2));
+    assert_eq!(calls("parser::parse"), Some(2));
+    assert_eq!(calls("parser::validate"), Some(1));
+    assert_eq!(calls("parser::literals::number"), Some(1));
+    assert_eq!(calls("parser::is_blank"), None);
+}
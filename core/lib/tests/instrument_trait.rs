@@ -0,0 +1,101 @@
+mod common;
+
+use instrumented::instrument_trait;
+use std::collections::HashMap;
+
+#[instrument_trait(INFO, ctx = "storage")]
+trait Store {
+    fn get(&self, key: &str) -> Result<String, String>;
+    fn put(&mut self, key: &str, value: String);
+    #[instrument(skip)]
+    fn size(&self) -> usize;
+}
+
+#[derive(Default)]
+struct MockStore {
+    entries: HashMap<String, String>,
+}
+
+impl Store for MockStore {
+    fn get(&self, key: &str) -> Result<String, String> {
+        self.entries
+            .get(key)
+            .cloned()
+            .ok_or_else(|| format!("{} not found", key))
+    }
+
+    fn put(&mut self, key: &str, value: String) {
+        self.entries.insert(key.to_string(), value);
+    }
+
+    fn size(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[instrument_trait(INFO, ctx = "codec")]
+trait Codec<T> {
+    type Error;
+    const NAME: &'static str;
+
+    fn encode(&self, value: T) -> Vec<u8>;
+}
+
+struct Bytes;
+
+impl Codec<u32> for Bytes {
+    type Error = ();
+    const NAME: &'static str = "bytes";
+
+    fn encode(&self, value: u32) -> Vec<u8> {
+        value.to_be_bytes().to_vec()
+    }
+}
+
+#[test]
+fn counts_calls_per_method() {
+    let mut store: Box<dyn Store> = Box::new(InstrumentedStore::new(MockStore::default()));
+    store.put("a", "1".to_string());
+    assert_eq!(store.get("a"), Ok("1".to_string()));
+    assert!(store.get("b").is_err());
+    assert_eq!(store.size(), 1);
+
+    let called = |name| {
+        common::counter_value(
+            "function_called_total",
+            &[("name", name), ("ctx", "storage")],
+        )
+    };
+    assert_eq!(called("put"), 1.0);
+    assert_eq!(called("get"), 2.0);
+    assert_eq!(called("size"), 0.0);
+    assert_eq!(
+        common::counter_value(
+            "function_error_total",
+            &[("name", "get"), ("ctx", "storage")]
+        ),
+        1.0
+    );
+    assert_eq!(
+        common::histogram_count(
+            "function_time_seconds",
+            &[("name", "get"), ("ctx", "storage")]
+        ),
+        2
+    );
+}
+
+#[test]
+fn delegates_generic_traits() {
+    let codec = InstrumentedCodec::new(Bytes);
+    assert_eq!(codec.encode(1), vec![0, 0, 0, 1]);
+    assert_eq!(<InstrumentedCodec<Bytes> as Codec<u32>>::NAME, "bytes");
+    assert_eq!(codec.into_inner().encode(2), vec![0, 0, 0, 2]);
+    assert_eq!(
+        common::counter_value(
+            "function_called_total",
+            &[("name", "encode"), ("ctx", "codec")]
+        ),
+        1.0
+    );
+}
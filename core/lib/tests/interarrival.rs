@@ -0,0 +1,42 @@
+mod common;
+
+use instrumented::instrument;
+use std::{thread, time};
+
+#[instrument(INFO, interarrival)]
+fn ticked() {}
+
+/// Returns the number of observations in the bucket `(lower, upper]`.
+fn bucket_count(family: &str, labels: &[(&str, &str)], lower: f64, upper: f64) -> u64 {
+    let metric = common::find_metric(family, labels).unwrap();
+    let cumulative = |bound: f64| {
+        metric
+            .get_histogram()
+            .get_bucket()
+            .iter()
+            .find(|bucket| bucket.get_upper_bound() == bound)
+            .unwrap()
+            .get_cumulative_count()
+    };
+    cumulative(upper) - cumulative(lower)
+}
+
+#[test]
+fn observes_time_between_calls() {
+    for _ in 0..4 {
+        ticked();
+        thread::sleep(time::Duration::from_millis(60));
+    }
+
+    let labels = [("name", "ticked")];
+    // The first call isn't observed.
+    assert_eq!(
+        common::histogram_count("function_interarrival_seconds", &labels),
+        3
+    );
+    assert_eq!(
+        bucket_count("function_interarrival_seconds", &labels, 0.05, 0.1),
+        3
+    );
+    assert_eq!(common::counter_value("function_called_total", &labels), 4.0);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use instrumented::instrument;
+use std::{error, fmt, io};
+
+#[instrument(INFO, err_label = "io_kind")]
+fn open(kind: io::ErrorKind) -> io::Result<()> {
+    Err(io::Error::new(kind, "open failed"))
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    Io(io::Error),
+    Invalid,
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl error::Error for AppError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            AppError::Io(err) => Some(err),
+            AppError::Invalid => None,
+        }
+    }
+}
+
+#[instrument(INFO, err_label = "io_kind")]
+fn load(err: AppError) -> Result<(), AppError> {
+    Err(err)
+}
+
+#[test]
+fn labels_by_error_kind() {
+    assert!(open(io::ErrorKind::NotFound).is_err());
+    assert!(open(io::ErrorKind::NotFound).is_err());
+    assert!(open(io::ErrorKind::PermissionDenied).is_err());
+
+    let not_found = [("name", "open"), ("err", "NotFound")];
+    let denied = [("name", "open"), ("err", "PermissionDenied")];
+    assert_eq!(common::counter_value("function_error_total", &not_found), 2.0);
+    assert_eq!(common::counter_value("function_error_total", &denied), 1.0);
+}
+
+#[test]
+fn labels_wrapped_errors_by_kind() {
+    let wrapped = AppError::Io(io::Error::new(io::ErrorKind::TimedOut, "slow"));
+    assert!(load(wrapped).is_err());
+    assert!(load(AppError::Invalid).is_err());
+
+    let timed_out = [("name", "load"), ("err", "TimedOut")];
+    let invalid = [("name", "load"), ("err", "Invalid")];
+    assert_eq!(common::counter_value("function_error_total", &timed_out), 1.0);
+    assert_eq!(common::counter_value("function_error_total", &invalid), 1.0);
+}
@@ -0,0 +1,17 @@
+#![cfg(feature = "jemalloc-metrics")]
+mod common;
+
+#[global_allocator]
+static ALLOCATOR: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+#[test]
+fn exports_allocator_statistics() {
+    instrumented::jemalloc::install().unwrap();
+
+    let large = vec![1u8; 64 * 1024 * 1024];
+    let allocated = common::gauge_value("jemalloc_allocated_bytes", &[]);
+    assert!(allocated >= large.len() as f64, "allocated {}", allocated);
+    assert!(common::gauge_value("jemalloc_resident_bytes", &[]) > 0.0);
+    assert!(common::gauge_value("jemalloc_active_ratio", &[]) >= 1.0);
+    drop(large);
+}
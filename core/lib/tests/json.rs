@@ -0,0 +1,40 @@
+#![cfg(feature = "json")]
+
+use instrumented::instrument;
+
+#[instrument(INFO, ctx = "json")]
+fn rendered_as_json() {}
+
+#[test]
+fn renders_json() {
+    rendered_as_json();
+    rendered_as_json();
+
+    let families: serde_json::Value = serde_json::from_str(&instrumented::render_json()).unwrap();
+    let called = families
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|family| family["name"] == "function_called_total")
+        .unwrap();
+    assert_eq!(called["type"], "counter");
+    let series = called["series"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|series| series["labels"]["name"] == "rendered_as_json")
+        .unwrap();
+    assert_eq!(series["labels"]["ctx"], "json");
+    assert_eq!(series["value"], 2.0);
+
+    let time = families
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|family| family["type"] == "histogram")
+        .unwrap();
+    let series = &time["series"][0];
+    assert!(series["buckets"][0]["le"].is_number());
+    assert!(series["count"].is_u64());
+    assert!(series["sum"].is_number());
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use instrumented::instrument;
+use std::{env, fs};
+
+#[instrument(INFO, ctx = "label_files")]
+fn work() {}
+
+// The labels are read once, so every case runs in the same test.
+#[test]
+fn labels_are_read_from_files() {
+    let dir = env::temp_dir().join(format!("instrumented-label-files-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("pool"), "pool-a\n").unwrap();
+    fs::write(dir.join("zone"), "zone-b\r\n").unwrap();
+    env::set_var(
+        "METRICS_LABELS_FILES",
+        format!(
+            "node_pool={},missing={}",
+            dir.join("pool").display(),
+            dir.join("missing").display()
+        ),
+    );
+    instrumented::label_from_file("zone", dir.join("zone")).unwrap();
+
+    work();
+    assert!(instrumented::label_from_file("late", dir.join("pool")).is_err());
+
+    let families = instrumented::gather();
+    assert!(!families.is_empty());
+    for family in &families {
+        for metric in family.get_metric() {
+            let label = |name: &str| {
+                metric
+                    .get_label()
+                    .iter()
+                    .find(|l| l.get_name() == name)
+                    .map(|l| l.get_value().to_string())
+            };
+            assert_eq!(
+                label("node_pool").as_deref(),
+                Some("pool-a"),
+                "{}",
+                family.get_name()
+            );
+            assert_eq!(
+                label("zone").as_deref(),
+                Some("zone-b"),
+                "{}",
+                family.get_name()
+            );
+            assert_eq!(label("missing"), None);
+        }
+    }
+    assert_eq!(
+        common::counter_value(
+            "function_called_total",
+            &[("name", "work"), ("node_pool", "pool-a")]
+        ),
+        1.0
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::counter_value;
+use instrumented::instrument;
+use std::fmt;
+
+struct RegionDown(&'static str);
+
+impl fmt::Debug for RegionDown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[instrument(INFO)]
+fn call_region(region: &'static str) -> Result<(), RegionDown> {
+    Err(RegionDown(region))
+}
+
+fn event_series(region: &str) -> usize {
+    instrumented::gather()
+        .into_iter()
+        .filter(|family| family.get_name() == "event_timestamp_seconds")
+        .flat_map(|family| family.get_metric().to_vec())
+        .filter(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .any(|label| label.get_name() == "region" && label.get_value() == region)
+        })
+        .count()
+}
+
+#[test]
+fn normalizes_the_dynamic_label_values() {
+    instrumented::set_label_normalizer(|key, value| {
+        if value == "boom" {
+            panic!("can't normalize {}", key);
+        }
+        instrumented::default_label_normalizer(key, value).to_lowercase()
+    });
+
+    call_region("US-East").unwrap_err();
+    call_region(" us-east ").unwrap_err();
+    assert_eq!(
+        counter_value(
+            "function_error_total",
+            &[("name", "call_region"), ("err", "us-east")]
+        ),
+        2.0
+    );
+
+    instrumented::event("failover", &[("region", "US-East")]).unwrap();
+    instrumented::event("failover", &[("region", "us-east")]).unwrap();
+    assert_eq!(event_series("us-east"), 1);
+    assert_eq!(event_series("US-East"), 0);
+
+    // A panicking normalizer keeps the value as is.
+    call_region("boom").unwrap_err();
+    assert_eq!(
+        counter_value(
+            "function_error_total",
+            &[("name", "call_region"), ("err", "boom")]
+        ),
+        1.0
+    );
+    assert_eq!(counter_value("label_normalizer_panics_total", &[]), 1.0);
+
+    instrumented::reset_label_normalizer();
+    call_region("  EU-West ").unwrap_err();
+    assert_eq!(
+        counter_value(
+            "function_error_total",
+            &[("name", "call_region"), ("err", "EU-West")]
+        ),
+        1.0
+    );
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use instrumented::instrument;
+use std::thread;
+
+#[instrument(INFO, label_thread)]
+fn work() {}
+
+#[test]
+fn counts_calls_by_thread() {
+    for name in &["pool-a", "pool b"] {
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(|| {
+                work();
+                work();
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    let family = instrumented::gather()
+        .into_iter()
+        .find(|mf| mf.get_name() == "function_called_by_thread_total")
+        .unwrap();
+    let series = family
+        .get_metric()
+        .iter()
+        .filter(|m| {
+            m.get_label()
+                .iter()
+                .any(|l| l.get_name() == "name" && l.get_value() == "work")
+        })
+        .count();
+    assert_eq!(series, 2);
+    for thread in &["pool-a", "pool_b"] {
+        assert_eq!(
+            common::counter_value(
+                "function_called_by_thread_total",
+                &[("name", "work"), ("thread", thread)]
+            ),
+            2.0
+        );
+    }
+}
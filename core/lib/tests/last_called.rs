@@ -0,0 +1,22 @@
+mod common;
+
+use instrumented::instrument;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[instrument(INFO, ctx = "jobs", track_last_called)]
+fn scheduled_job() {}
+
+#[test]
+fn sets_last_called_timestamp() {
+    scheduled_job();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as f64;
+    let last_called = common::gauge_value(
+        "function_last_called_timestamp_seconds",
+        &[("name", "scheduled_job"), ("ctx", "jobs")],
+    );
+    assert!((now - last_called).abs() <= 1.0, "{} {}", now, last_called);
+}
@@ -0,0 +1,36 @@
+#![cfg(feature = "test-hooks")]
+
+mod common;
+
+use common::gauge_value;
+use instrumented::lifecycle::advance_clock;
+use std::time::Duration;
+
+#[test]
+fn records_the_startup_and_shutdown() {
+    assert!(common::find_metric("process_ready", &[]).is_some());
+    assert_eq!(gauge_value("process_ready", &[]), 0.0);
+
+    // The startup takes a minute on the mocked clock.
+    advance_clock(Duration::from_secs(60));
+    instrumented::mark_ready();
+    assert_eq!(gauge_value("process_ready", &[]), 1.0);
+    let startup = gauge_value("process_startup_seconds", &[]);
+    assert!(startup >= 60.0 && startup < 120.0, "{}", startup);
+
+    // Only the first call records the startup.
+    advance_clock(Duration::from_secs(60));
+    instrumented::mark_ready();
+    assert_eq!(gauge_value("process_startup_seconds", &[]), startup);
+
+    instrumented::mark_stopping();
+    assert_eq!(gauge_value("process_ready", &[]), 0.0);
+    advance_clock(Duration::from_secs(3));
+    instrumented::shutdown(Duration::from_secs(1));
+    let shutdown = gauge_value("process_shutdown_seconds", &[]);
+    assert!(shutdown >= 3.0 && shutdown < 60.0, "{}", shutdown);
+
+    // Marking the process as ready while it stops doesn't make it ready again.
+    instrumented::mark_ready();
+    assert_eq!(gauge_value("process_ready", &[]), 0.0);
+}
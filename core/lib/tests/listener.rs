@@ -0,0 +1,24 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+#[instrumented::instrument(INFO, ctx = "listener")]
+fn work() {}
+
+#[test]
+fn serves_a_pre_bound_listener() {
+    work();
+    // Bound and left in blocking mode by the caller, like a listener handed over by a process
+    // manager.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    instrumented::init_with_listener(listener);
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 200 OK"), "{}", response);
+    assert!(response.contains("ctx=\"listener\""), "{}", response);
+}
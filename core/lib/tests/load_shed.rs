@@ -0,0 +1,78 @@
+mod common;
+
+use common::counter_value;
+use instrumented::instrument;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+enum ApiError {
+    Overloaded,
+}
+
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+#[instrument(INFO, max_inflight = 4, overload_err = "ApiError::Overloaded")]
+fn handle(key: u32) -> Result<u32, ApiError> {
+    let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+    MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(50));
+    RUNNING.fetch_sub(1, Ordering::SeqCst);
+    Ok(key)
+}
+
+#[instrument(INFO, max_inflight = 1, overload_err = "ApiError::Overloaded")]
+async fn handle_async(key: u32) -> Result<u32, ApiError> {
+    Ok(key)
+}
+
+#[test]
+fn sheds_the_calls_over_the_limit() {
+    let barrier = Arc::new(Barrier::new(16));
+    let threads: Vec<_> = (0..16)
+        .map(|key| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                handle(key)
+            })
+        })
+        .collect();
+    let results: Vec<_> = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect();
+    let shed = results
+        .iter()
+        .filter(|result| **result == Err(ApiError::Overloaded))
+        .count();
+
+    assert!(MAX_RUNNING.load(Ordering::SeqCst) <= 4);
+    assert!(shed >= 1, "{:?}", results);
+    let labels = [("name", "handle")];
+    assert_eq!(
+        counter_value("function_load_shed_total", &labels),
+        shed as f64
+    );
+    assert_eq!(
+        counter_value("function_called_total", &labels),
+        (16 - shed) as f64
+    );
+
+    // The slots are released once the calls return.
+    assert_eq!(handle(16), Ok(16));
+}
+
+#[test]
+fn holds_the_slot_until_the_future_completes() {
+    let first = handle_async(1);
+    assert_eq!(
+        common::block_on(handle_async(2)),
+        Err(ApiError::Overloaded)
+    );
+    assert_eq!(common::block_on(first), Ok(1));
+    assert_eq!(common::block_on(handle_async(3)), Ok(3));
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use instrumented::instrument;
+use std::sync::mpsc;
+use std::thread;
+
+#[instrument(INFO, ctx = "local_metrics", local_metrics)]
+fn hot(n: u64) -> u64 {
+    n.wrapping_mul(31)
+}
+
+#[instrument(INFO, ctx = "local_metrics", local_metrics)]
+fn batched() {}
+
+#[test]
+fn every_call_is_observed_across_threads() {
+    const THREADS: u64 = 8;
+    const CALLS: u64 = 10_000;
+
+    let workers = (0..THREADS)
+        .map(|_| {
+            thread::spawn(|| {
+                for n in 0..CALLS {
+                    hot(n);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    // The threads flushed their last partial batch when exiting.
+    let labels = [("name", "hot"), ("ctx", "local_metrics")];
+    assert_eq!(
+        common::histogram_count("function_time_seconds", &labels),
+        THREADS * CALLS
+    );
+    assert_eq!(
+        instrumented::stats::calls("hot", "local_metrics"),
+        THREADS * CALLS
+    );
+}
+
+#[test]
+fn buffers_until_the_batch_is_full() {
+    instrumented::set_local_metrics_batch(4);
+    let labels = [("name", "batched"), ("ctx", "local_metrics")];
+    let (called, wait) = mpsc::channel();
+    let (resume, resumed) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        for _ in 0..3 {
+            batched();
+        }
+        called.send(()).unwrap();
+        resumed.recv().unwrap();
+        batched();
+        called.send(()).unwrap();
+        resumed.recv().unwrap();
+    });
+
+    // Gathering only flushes the buffer of the gathering thread.
+    wait.recv().unwrap();
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 0);
+    resume.send(()).unwrap();
+    wait.recv().unwrap();
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 4);
+    resume.send(()).unwrap();
+    worker.join().unwrap();
+
+    batched();
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 5);
+}
@@ -0,0 +1,35 @@
+use instrumented::instrument;
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, ctx = "located", location)]
+fn run(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn exports_the_location() {
+    // The path given by `file!()` depends on where the crate is built from.
+    instrumented::set_location_prefix(file!().trim_end_matches("tests/location.rs"));
+    run(false).unwrap();
+    run(true).unwrap_err();
+
+    let metrics = instrumented::render_metrics();
+    let line = metrics
+        .lines()
+        .find(|line| line.starts_with("function_location_info{") && line.contains("\"run\""))
+        .expect("function_location_info is exported");
+    assert!(line.contains("ctx=\"located\""), "{}", line);
+    let location = line.split("location=\"").nth(1).unwrap();
+    let location = &location[..location.find('"').unwrap()];
+    let (file, line) = location.split_at(location.rfind(':').unwrap());
+    assert!(file.ends_with("location.rs"), "{}", file);
+    assert_eq!(file, "tests/location.rs");
+    // The line of `fn run`.
+    assert_eq!(line, ":7");
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use instrumented::instrument;
+
+#[derive(Debug)]
+pub struct DependencyDown;
+
+#[instrument(ERROR, log_rate_limit = 5)]
+fn call_dependency() -> Result<(), DependencyDown> {
+    Err(DependencyDown)
+}
+
+#[test]
+fn throttles_error_logs_but_not_metrics() {
+    #[cfg(feature = "logging")]
+    common::capture_logs();
+
+    for _ in 0..1000 {
+        assert!(call_dependency().is_err());
+    }
+
+    #[cfg(feature = "logging")]
+    assert_eq!(
+        common::captured_logs("call_dependency() => DependencyDown").len(),
+        5
+    );
+    assert_eq!(
+        common::counter_value("function_error_total", &[("name", "call_dependency")]),
+        1000.0
+    );
+
+    // The end of the burst is summarized when the metrics are gathered, once.
+    #[cfg(feature = "logging")]
+    {
+        let summaries = common::captured_logs("call_dependency() => suppressed");
+        assert_eq!(
+            summaries,
+            vec!["ERROR call_dependency() => suppressed 995 similar errors".to_string()]
+        );
+        instrumented::gather();
+        assert_eq!(
+            common::captured_logs("call_dependency() => suppressed").len(),
+            1
+        );
+    }
+}
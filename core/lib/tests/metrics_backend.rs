@@ -0,0 +1,86 @@
+#![cfg(feature = "backend-metrics")]
+
+use instrumented::instrument;
+use metrics_util::{DebugValue, DebuggingRecorder, MetricKind};
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, ctx = "facade")]
+fn forwarded(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn forwards_into_the_metrics_facade() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    recorder.install().unwrap();
+
+    assert!(forwarded(false).is_ok());
+    assert!(forwarded(true).is_err());
+
+    let mut seen = Vec::new();
+    for (key, _, _, value) in snapshotter.snapshot() {
+        let (kind, key) = key.into_parts();
+        let mut labels: Vec<(String, String)> = key
+            .labels()
+            .map(|label| (label.key().to_string(), label.value().to_string()))
+            .collect();
+        labels.sort();
+        let value = match value {
+            DebugValue::Counter(value) => value as f64,
+            DebugValue::Gauge(value) => value.into_inner(),
+            DebugValue::Histogram(values) => values.len() as f64,
+        };
+        seen.push((kind, key.name().to_string(), labels, value));
+    }
+
+    let labels = |extra: &[(&str, &str)]| {
+        let mut labels: Vec<(String, String)> = [
+            ("type", "func_call"),
+            ("name", "forwarded"),
+            ("ctx", "facade"),
+        ]
+        .iter()
+        .chain(extra)
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        labels.sort();
+        labels
+    };
+    let find = |kind: MetricKind, name: &str, labels: Vec<(String, String)>| {
+        seen.iter()
+            .find(|(k, n, l, _)| *k == kind && n == name && *l == labels)
+            .map(|(_, _, _, value)| *value)
+    };
+
+    assert_eq!(
+        find(MetricKind::Counter, "function_called_total", labels(&[])),
+        Some(2.0)
+    );
+    assert_eq!(
+        find(
+            MetricKind::Counter,
+            "function_error_total",
+            labels(&[("err", "MyError"), ("injected", "false")])
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        find(MetricKind::Histogram, "function_time_seconds", labels(&[])),
+        Some(2.0)
+    );
+    assert_eq!(
+        find(
+            MetricKind::Gauge,
+            "function_calls_inflight_total",
+            labels(&[])
+        ),
+        Some(0.0)
+    );
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use std::net::TcpListener;
+
+#[instrumented::instrument(INFO, ctx = "multi_listen")]
+fn work() {}
+
+#[test]
+fn serves_every_address() {
+    work();
+    // Hosts without IPv6 can't listen on `[::1]`, which is then an error too.
+    let ipv6 = TcpListener::bind("[::1]:0").is_ok();
+    let exporter = instrumented::init_multi(&["127.0.0.1:0", "[::1]:0", "localhost"]);
+
+    assert_eq!(exporter.addrs().len(), if ipv6 { 2 } else { 1 });
+    assert!(exporter.addrs()[0].is_ipv4());
+    if ipv6 {
+        assert!(exporter.addrs()[1].is_ipv6());
+    }
+    for addr in exporter.addrs() {
+        let response = common::scrape(*addr);
+        assert!(response.starts_with("HTTP/1.0 200 OK"), "{}", response);
+        assert!(response.contains("ctx=\"multi_listen\""), "{}", response);
+    }
+
+    let errors: Vec<_> = exporter
+        .errors()
+        .iter()
+        .map(|error| error.addr.as_str())
+        .collect();
+    if ipv6 {
+        assert_eq!(errors, ["localhost"]);
+    } else {
+        assert_eq!(errors, ["[::1]:0", "localhost"]);
+    }
+}
@@ -0,0 +1,35 @@
+use instrumented::naming::{set_naming_strictness, NamingStrictness};
+use instrumented::prometheus::Result;
+
+fn error<T>(result: Result<T>) -> String {
+    match result {
+        Ok(_) => panic!("registered"),
+        Err(err) => err.to_string(),
+    }
+}
+
+// The strictness is global, so the checks run in one test.
+#[test]
+fn checks_the_names_of_registered_metrics() {
+    // Off by default.
+    assert!(instrumented::counter("unchecked_requests", "Requests").is_ok());
+
+    set_naming_strictness(NamingStrictness::Deny);
+    let err = error(instrumented::counter("requests", "Requests"));
+    assert!(err.contains("counters should end with `_total`"), "{}", err);
+    let err = error(instrumented::gauge("queue_depth_total", "Depth"));
+    assert!(err.contains("only counters"), "{}", err);
+    let err = error(instrumented::histogram(
+        "latency_milliseconds",
+        "Latency",
+        vec![1.0],
+    ));
+    assert!(err.contains("base unit `seconds`"), "{}", err);
+    assert!(instrumented::counter("requests_total", "Requests").is_ok());
+    assert!(instrumented::gauge_vec("pool_connections", "Connections", &["pool"]).is_ok());
+
+    // Denied metrics aren't registered, and can be once the strictness is lowered.
+    set_naming_strictness(NamingStrictness::Warn);
+    assert!(instrumented::counter("requests", "Requests").is_ok());
+    set_naming_strictness(NamingStrictness::Off);
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::protobuf::{delimited, fields, zigzag};
+use instrumented::instrument;
+use instrumented::native_histogram::{set_histogram_mode, HistogramMode};
+
+#[instrument(INFO, ctx = "native", histogram = "native")]
+fn native_only() {}
+
+#[instrument(INFO, ctx = "native", histogram = "both")]
+fn both() {}
+
+#[instrument(INFO, ctx = "native")]
+fn by_default() {}
+
+/// The fields of the histogram of a function in the protobuf exposition.
+struct Scraped {
+    sample_count: u64,
+    classic_buckets: usize,
+    schema: Option<i64>,
+    zero_count: u64,
+    /// The counts of the native buckets, decoded from their deltas.
+    native_buckets: Vec<i64>,
+}
+
+fn scrape(name: &str) -> Option<Scraped> {
+    let exposition = instrumented::render_protobuf();
+    let family = delimited(&exposition).into_iter().find(|family| {
+        fields(family)
+            .iter()
+            .any(|(number, _, value, _)| *number == 1 && value == b"function_time_seconds")
+    })?;
+    let metric = fields(&family)
+        .into_iter()
+        .filter(|(number, ..)| *number == 4)
+        .map(|(_, _, metric, _)| fields(&metric))
+        .find(|metric| {
+            metric.iter().any(|(number, _, label, _)| {
+                *number == 1
+                    && fields(label)
+                        .iter()
+                        .any(|(_, _, value, _)| value == name.as_bytes())
+            })
+        })?;
+    let histogram = metric
+        .iter()
+        .find(|(number, ..)| *number == 7)
+        .map(|(_, _, histogram, _)| fields(histogram))?;
+
+    let mut scraped = Scraped {
+        sample_count: 0,
+        classic_buckets: 0,
+        schema: None,
+        zero_count: 0,
+        native_buckets: Vec::new(),
+    };
+    let mut count = 0;
+    for (number, _, _, bits) in histogram {
+        match number {
+            1 => scraped.sample_count = bits,
+            3 => scraped.classic_buckets += 1,
+            5 => scraped.schema = Some(zigzag(bits)),
+            7 => scraped.zero_count = bits,
+            13 => {
+                count += zigzag(bits);
+                scraped.native_buckets.push(count);
+            }
+            _ => {}
+        }
+    }
+    Some(scraped)
+}
+
+#[test]
+fn exports_native_histograms() {
+    for _ in 0..5 {
+        native_only();
+    }
+    for _ in 0..3 {
+        both();
+    }
+
+    let native = scrape("native_only").unwrap();
+    assert_eq!(native.sample_count, 5);
+    assert_eq!(native.classic_buckets, 0);
+    assert_eq!(native.schema, Some(3));
+    let observed = native.native_buckets.iter().sum::<i64>() + native.zero_count as i64;
+    assert_eq!(observed, 5);
+    assert!(native.native_buckets.iter().all(|count| *count > 0));
+
+    let both = scrape("both").unwrap();
+    assert_eq!(both.sample_count, 3);
+    assert!(both.classic_buckets > 0);
+    assert_eq!(
+        both.native_buckets.iter().sum::<i64>() + both.zero_count as i64,
+        3
+    );
+
+    // The text format has the count of the native only function, without buckets.
+    let text = instrumented::render_metrics();
+    assert!(text
+        .lines()
+        .any(|line| line.starts_with("function_time_seconds_count{")
+            && line.contains("name=\"native_only\"")
+            && line.ends_with(" 5")));
+}
+
+#[test]
+fn selects_the_mode_globally() {
+    by_default();
+    assert_eq!(scrape("by_default").unwrap().schema, None);
+
+    set_histogram_mode(HistogramMode::Native);
+    by_default();
+    set_histogram_mode(HistogramMode::Classic);
+
+    let scraped = scrape("by_default").unwrap();
+    // The classic histogram has the first call, the native one the second.
+    assert_eq!(scraped.sample_count, 1);
+    assert!(scraped.classic_buckets > 0);
+    assert_eq!(
+        scraped.native_buckets.iter().sum::<i64>() + scraped.zero_count as i64,
+        1
+    );
+}
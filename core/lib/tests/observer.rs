@@ -0,0 +1,49 @@
+use instrumented::instrument;
+use instrumented::observer::{self, Completion, Observer, Outcome};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, ctx = "observed")]
+fn observed(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+type Recorded = (&'static str, &'static str, Option<String>);
+
+struct Recorder(Arc<Mutex<Vec<Recorded>>>);
+
+impl Observer for Recorder {
+    fn on_completion(&self, completion: &Completion) {
+        let err = match completion.outcome {
+            Outcome::Ok => None,
+            Outcome::Err(err) => Some(err.to_string()),
+        };
+        self.0
+            .lock()
+            .unwrap()
+            .push((completion.name, completion.ctx, err));
+    }
+}
+
+#[test]
+fn notifies_observers() {
+    let completions = Arc::new(Mutex::new(Vec::new()));
+    observer::add_observer(Box::new(Recorder(completions.clone())));
+
+    assert!(observed(false).is_ok());
+    assert!(observed(true).is_err());
+
+    assert_eq!(
+        *completions.lock().unwrap(),
+        vec![
+            ("observed", "observed", None),
+            ("observed", "observed", Some("MyError".to_string())),
+        ]
+    );
+}
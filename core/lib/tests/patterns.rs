@@ -0,0 +1,69 @@
+mod common;
+
+use instrumented::instrument;
+
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO)]
+fn area((w, h): (u32, u32)) -> u32 {
+    w * h
+}
+
+#[instrument(INFO)]
+fn manhattan(Point { x, y }: Point) -> i32 {
+    x.abs() + y.abs()
+}
+
+#[instrument(INFO)]
+fn deref_sum(&a: &u32, &(b, c): &(u32, u32)) -> u32 {
+    a + b + c
+}
+
+#[instrument(INFO)]
+fn ignores_first(_: u32, second: u32) -> u32 {
+    second
+}
+
+#[instrument(INFO)]
+fn increments(mut n: u32, (mut a, b): (u32, u32)) -> Result<u32, MyError> {
+    n += 1;
+    a += b;
+    Ok(n + a)
+}
+
+#[instrument(INFO)]
+async fn async_area((w, h): (u32, u32), _: &str) -> u32 {
+    w * h
+}
+
+#[test]
+fn pattern_parameters() {
+    assert_eq!(area((3, 4)), 12);
+    assert_eq!(manhattan(Point { x: -2, y: 5 }), 7);
+    assert_eq!(deref_sum(&1, &(2, 3)), 6);
+    assert_eq!(ignores_first(1, 2), 2);
+    assert_eq!(increments(1, (2, 3)).unwrap(), 7);
+    assert_eq!(common::block_on(async_area((5, 6), "ignored")), 30);
+
+    for name in &[
+        "area",
+        "manhattan",
+        "deref_sum",
+        "ignores_first",
+        "increments",
+        "async_area",
+    ] {
+        assert_eq!(
+            common::counter_value("function_called_total", &[("name", name)]),
+            1.0,
+            "{}",
+            name
+        );
+    }
+}
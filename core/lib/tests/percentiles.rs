@@ -0,0 +1,51 @@
+mod common;
+
+use instrumented::instrument;
+
+#[instrument(INFO, ctx = "percentiles", percentiles)]
+fn lookup(key: u32) -> Option<u32> {
+    Some(key)
+}
+
+fn percentile(family: &str, name: &str) -> f64 {
+    common::gauge_value(family, &[("name", name), ("ctx", "percentiles")])
+}
+
+#[test]
+fn p99_of_a_known_distribution() {
+    // Uniformly from a millisecond to a second, in steps of a millisecond.
+    for millis in 1..=1000 {
+        instrumented::percentiles::observe_for(
+            "known_distribution",
+            "percentiles",
+            f64::from(millis) / 1e3,
+        );
+    }
+
+    let p50 = percentile("function_time_p50_seconds", "known_distribution");
+    let p90 = percentile("function_time_p90_seconds", "known_distribution");
+    let p99 = percentile("function_time_p99_seconds", "known_distribution");
+    assert!((0.45..0.55).contains(&p50), "{}", p50);
+    assert!((0.85..0.95).contains(&p90), "{}", p90);
+    assert!((0.98..=1.0).contains(&p99), "{}", p99);
+}
+
+#[test]
+fn only_functions_that_opted_in_are_exported() {
+    assert_eq!(lookup(1), Some(1));
+
+    assert!(common::find_metric(
+        "function_time_p99_seconds",
+        &[("name", "lookup"), ("ctx", "percentiles")]
+    )
+    .is_some());
+    let metrics = instrumented::render_metrics();
+    assert_eq!(
+        metrics
+            .lines()
+            .filter(|line| line.starts_with("function_time_p99_seconds{"))
+            .filter(|line| !line.contains("ctx=\"percentiles\""))
+            .count(),
+        0
+    );
+}
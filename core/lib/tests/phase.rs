@@ -0,0 +1,74 @@
+mod common;
+
+use common::{histogram_count, histogram_sum};
+use instrumented::{instrument, phase};
+use std::thread::sleep;
+use std::time::Duration;
+
+#[instrument(INFO, ctx = "phase", phases)]
+fn import() -> usize {
+    let rows = phase!("parse", {
+        sleep(Duration::from_millis(10));
+        vec![1, 2, 3]
+    });
+    phase!("validate", { sleep(Duration::from_millis(20)) });
+    phase!("write", {
+        sleep(Duration::from_millis(10));
+        phase!("flush", { sleep(Duration::from_millis(20)) });
+        rows.len()
+    })
+}
+
+fn untracked() -> u32 {
+    phase!("untracked", "phase", "compute", { 40 + 2 })
+}
+
+fn phase_sum(name: &str, phase: &str) -> f64 {
+    histogram_sum(
+        "function_phase_seconds",
+        &[("name", name), ("ctx", "phase"), ("phase", phase)],
+    )
+}
+
+#[test]
+fn phases_add_up_to_the_function() {
+    assert_eq!(import(), 3);
+
+    let total = histogram_sum(
+        "function_time_seconds",
+        &[("name", "import"), ("ctx", "phase")],
+    );
+    let phases: f64 = ["parse", "validate", "write"]
+        .iter()
+        .map(|phase| phase_sum("import", phase))
+        .sum();
+    assert!(phases >= 0.06, "{}", phases);
+    assert!(phases <= total, "{} > {}", phases, total);
+    assert!(phases >= total * 0.8, "{} < {}", phases, total);
+
+    // Nested phases are labelled with their path, and counted in their parent too.
+    let flush = phase_sum("import", "write/flush");
+    assert!(flush >= 0.02, "{}", flush);
+    assert!(phase_sum("import", "write") >= flush + 0.01);
+    assert_eq!(phase_sum("import", "flush"), 0.0);
+}
+
+#[test]
+fn explicit_name_and_ctx() {
+    assert_eq!(untracked(), 42);
+    assert_eq!(
+        histogram_count(
+            "function_phase_seconds",
+            &[
+                ("name", "untracked"),
+                ("ctx", "phase"),
+                ("phase", "compute")
+            ],
+        ),
+        1
+    );
+
+    // Without a frame, the block still runs.
+    assert_eq!(phase!("orphan", { 1 }), 1);
+    assert!(!instrumented::render_metrics().contains("phase=\"orphan\""));
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "test-hooks")]
+
+mod common;
+
+use common::counter_value;
+use instrumented::instrument;
+use instrumented::observer::{self, Completion, Observer};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug)]
+pub struct ParseError;
+
+#[instrument(INFO)]
+fn parse(input: &str) -> Result<u32, ParseError> {
+    input.parse().map_err(|_| ParseError)
+}
+
+static OBSERVED: AtomicUsize = AtomicUsize::new(0);
+
+struct Counter;
+
+impl Observer for Counter {
+    fn on_completion(&self, _: &Completion) {
+        OBSERVED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn recovers_from_poisoned_locks() {
+    observer::add_observer(Box::new(Counter));
+    assert_eq!(parse("1").unwrap(), 1);
+    assert_eq!(counter_value("instrumentation_internal_errors_total", &[]), 0.0);
+
+    observer::poison_for_tests();
+
+    assert_eq!(parse("42").unwrap(), 42);
+    assert!(parse("forty-two").is_err());
+    assert_eq!(OBSERVED.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        counter_value("function_called_total", &[("name", "parse")]),
+        3.0
+    );
+    assert!(counter_value("instrumentation_internal_errors_total", &[]) >= 2.0);
+}
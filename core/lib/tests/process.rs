@@ -0,0 +1,12 @@
+#![cfg(unix)]
+mod common;
+
+#[test]
+fn exports_process_metrics() {
+    // Touching a metric initializes the registry, and its process collector.
+    instrumented::inc_called_counter_for("exports_process_metrics", "default");
+
+    let resident = common::gauge_value("process_resident_memory_bytes", &[]);
+    assert!(resident > 1024.0 * 1024.0, "resident {}", resident);
+    assert!(common::gauge_value("process_open_fds", &[]) >= 3.0);
+}
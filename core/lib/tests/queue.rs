@@ -0,0 +1,73 @@
+use instrumented::Stamp;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+fn observed(metrics: &str, suffix: &str) -> f64 {
+    let prefix = format!("queue_wait_seconds_{}{{queue=\"work_queue\"}} ", suffix);
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(prefix.as_str()))
+        .map_or(0.0, |value| value.parse().unwrap())
+}
+
+#[test]
+fn observes_the_queue_wait() {
+    let (tx, rx) = mpsc::channel();
+    instrumented::queue_depth("work_queue").inc();
+    tx.send(Stamp::now()).unwrap();
+
+    let consumer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(30));
+        let stamp: Stamp = rx.recv().unwrap();
+        instrumented::queue_depth("work_queue").dec();
+        stamp.observe_wait("work_queue")
+    });
+    assert_eq!(instrumented::queue_depth("work_queue").get(), 1);
+    let wait = consumer.join().unwrap();
+    assert!(wait >= Duration::from_millis(30), "{:?}", wait);
+
+    let metrics = instrumented::render_metrics();
+    assert_eq!(observed(&metrics, "count"), 1.0, "{}", metrics);
+    assert!(observed(&metrics, "sum") >= 0.03);
+    assert!(metrics.contains("queue_depth{queue=\"work_queue\"} 0\n"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn stamps_survive_serialization() {
+    let stamp = Stamp::now();
+    thread::sleep(Duration::from_millis(20));
+    let json = serde_json::to_string(&stamp).unwrap();
+    let stamp: Stamp = serde_json::from_str(&json).unwrap();
+    // The wall clock has a coarser resolution than `Instant` on some platforms.
+    assert!(
+        stamp.elapsed() >= Duration::from_millis(15),
+        "{:?}",
+        stamp.elapsed()
+    );
+    assert!(stamp.elapsed() < Duration::from_secs(5));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn stamps_from_the_future_are_clamped() {
+    // Stamped by a host whose clock is a minute ahead.
+    let ahead = std::time::SystemTime::now() + Duration::from_secs(60);
+    let nanos = ahead
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    let before = anomalies();
+    let stamp: Stamp = serde_json::from_str(&nanos.to_string()).unwrap();
+    assert!(stamp.elapsed() < Duration::from_secs(1));
+    assert_eq!(anomalies(), before + 1.0);
+}
+
+#[cfg(feature = "serde")]
+fn anomalies() -> f64 {
+    instrumented::gather()
+        .iter()
+        .find(|mf| mf.get_name() == "clock_anomalies_total")
+        .map_or(0.0, |mf| mf.get_metric()[0].get_counter().get_value())
+}
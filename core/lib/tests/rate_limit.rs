@@ -0,0 +1,101 @@
+mod common;
+
+use instrumented::instrument;
+use instrumented::prometheus::proto::MetricFamily;
+
+#[derive(Debug, PartialEq)]
+enum ApiError {
+    TooManyRequests,
+}
+
+#[instrument(INFO, max_rate = 10, rate_limit_err = "ApiError::TooManyRequests")]
+fn lookup(key: u32) -> Result<u32, ApiError> {
+    Ok(key)
+}
+
+#[instrument(INFO, max_rate = 10)]
+fn recorded_only(key: u32) -> u32 {
+    key
+}
+
+#[instrument(INFO, max_rate = 1000, rate_limit_err = "ApiError::TooManyRequests")]
+fn under_limit(key: u32) -> Result<u32, ApiError> {
+    Ok(key)
+}
+
+#[instrument(INFO, max_rate = 10, rate_limit_err = "ApiError::TooManyRequests")]
+async fn lookup_async(key: u32) -> Result<u32, ApiError> {
+    Ok(key)
+}
+
+fn counter(families: &[MetricFamily], family: &str, name: &str) -> f64 {
+    families
+        .iter()
+        .filter(|f| f.get_name() == family)
+        .flat_map(|f| f.get_metric())
+        .filter(|m| m.get_label().iter().any(|l| l.get_value() == name))
+        .map(|m| m.get_counter().get_value())
+        .sum()
+}
+
+#[test]
+fn rejects_the_calls_over_the_limit() {
+    let results: Vec<_> = (0..100).map(lookup).collect();
+    let rejected = results
+        .iter()
+        .filter(|result| **result == Err(ApiError::TooManyRequests))
+        .count();
+    // A second of calls is let through at once, and the bucket refills at 10 calls per second.
+    assert!((85..=90).contains(&rejected), "{}", rejected);
+
+    let families = instrumented::gather();
+    assert_eq!(
+        counter(&families, "function_rate_limited_total", "lookup"),
+        rejected as f64
+    );
+    assert_eq!(
+        counter(&families, "function_called_total", "lookup"),
+        (100 - rejected) as f64
+    );
+    assert_eq!(counter(&families, "function_error_total", "lookup"), 0.0);
+}
+
+#[test]
+fn records_the_calls_over_the_limit() {
+    let sum: u32 = (0..100).map(recorded_only).sum();
+    assert_eq!(sum, 4950);
+
+    let rejected = counter(
+        &instrumented::gather(),
+        "function_rate_limited_total",
+        "recorded_only",
+    );
+    assert!((85.0..=90.0).contains(&rejected), "{}", rejected);
+}
+
+#[test]
+fn leaves_the_calls_under_the_limit_alone() {
+    for key in 0..500 {
+        assert_eq!(under_limit(key), Ok(key));
+    }
+    let families = instrumented::gather();
+    assert_eq!(
+        counter(&families, "function_rate_limited_total", "under_limit"),
+        0.0
+    );
+    assert_eq!(
+        counter(&families, "function_called_total", "under_limit"),
+        500.0
+    );
+}
+
+#[test]
+fn rejects_async_calls_from_their_future() {
+    let futures: Vec<_> = (0..50).map(lookup_async).collect();
+    let rejected = futures
+        .into_iter()
+        .map(common::block_on)
+        .filter(|result| *result == Err(ApiError::TooManyRequests))
+        .count();
+    assert!((35..=40).contains(&rejected), "{}", rejected);
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "rayon-metrics")]
+
+mod common;
+
+use common::{histogram_count, histogram_sum};
+use instrumented::{instrument, phase};
+use rayon::prelude::*;
+use std::thread::sleep;
+use std::time::Duration;
+
+fn squares() -> Vec<u64> {
+    instrumented::rayon::scope_metrics("square", || {
+        (0..32u64)
+            .into_par_iter()
+            .map(|x| {
+                phase!("multiply", {
+                    sleep(Duration::from_millis(2));
+                    x * x
+                })
+            })
+            .collect()
+    })
+}
+
+#[instrument(INFO, ctx = "etl", phases)]
+fn import(lines: &[&str]) -> usize {
+    instrumented::rayon::scope_metrics("parse", || {
+        lines
+            .par_iter()
+            .map(|line| phase!("split", { line.split(',').count() }))
+            .sum()
+    })
+}
+
+#[test]
+fn times_the_stage_and_its_phases() {
+    let squares = squares();
+    assert_eq!(squares.len(), 32);
+    assert_eq!(squares[31], 961);
+
+    let stage = [("stage", "square")];
+    assert_eq!(histogram_count("parallel_stage_seconds", &stage), 1);
+    assert!(histogram_sum("parallel_stage_seconds", &stage) >= 0.002);
+    if cfg!(any(target_os = "linux", target_os = "macos", windows)) {
+        assert_eq!(
+            histogram_count("parallel_stage_busy_seconds", &stage),
+            rayon::current_num_threads() as u64
+        );
+    }
+
+    // The phases run on the workers are labelled with the stage.
+    let labels = [("name", "square"), ("ctx", ""), ("phase", "square/multiply")];
+    assert_eq!(histogram_count("function_phase_seconds", &labels), 32);
+    assert!(histogram_sum("function_phase_seconds", &labels) >= 0.064);
+
+    // Within an instrumented function, they're its phases. The stages of a pool don't overlap, so
+    // this runs after the first one.
+    assert_eq!(import(&["a,b", "c", "d,e,f"]), 6);
+
+    let labels = [("name", "import"), ("ctx", "etl"), ("phase", "parse/split")];
+    assert_eq!(histogram_count("function_phase_seconds", &labels), 3);
+    assert_eq!(
+        histogram_count("parallel_stage_seconds", &[("stage", "parse")]),
+        1
+    );
+}
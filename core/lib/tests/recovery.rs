@@ -0,0 +1,44 @@
+use instrumented::instrument;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Unavailable;
+
+#[instrument(INFO, ctx = "recovery", track_recovery)]
+fn flaky(fail: bool) -> Result<(), Unavailable> {
+    if fail {
+        Err(Unavailable)
+    } else {
+        Ok(())
+    }
+}
+
+fn observed(metrics: &str, suffix: &str) -> f64 {
+    let prefix = format!(
+        "function_recovery_seconds_{}{{ctx=\"recovery\",name=\"flaky\",type=\"func_call\"}} ",
+        suffix
+    );
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(prefix.as_str()))
+        .map_or(0.0, |value| value.parse().unwrap())
+}
+
+#[test]
+fn observes_the_recovery_time() {
+    flaky(false).unwrap();
+    flaky(true).unwrap_err();
+    thread::sleep(Duration::from_millis(20));
+    flaky(true).unwrap_err();
+    thread::sleep(Duration::from_millis(20));
+    flaky(false).unwrap();
+    flaky(false).unwrap();
+    // Never recovers.
+    flaky(true).unwrap_err();
+
+    let metrics = instrumented::render_metrics();
+    assert_eq!(observed(&metrics, "count"), 1.0, "{}", metrics);
+    let sum = observed(&metrics, "sum");
+    assert!((0.04..1.0).contains(&sum), "{}", sum);
+}
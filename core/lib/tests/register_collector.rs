@@ -0,0 +1,45 @@
+mod jobs {
+    use instrumented::prometheus::{IntCounter, IntGauge, Opts};
+
+    instrumented::register_collector! {
+        pub static ref PROCESSED: IntCounter =
+            IntCounter::with_opts(Opts::new("jobs_processed_total", "Number of processed jobs"))
+                .unwrap();
+        pub(crate) static ref RUNNING: IntGauge =
+            IntGauge::with_opts(Opts::new("jobs_running", "Number of running jobs")).unwrap();
+    }
+
+    pub fn process() {
+        RUNNING.inc();
+        PROCESSED.inc();
+        RUNNING.dec();
+    }
+}
+
+mod never_used {
+    use instrumented::prometheus::{Gauge, Opts};
+
+    instrumented::register_collector! {
+        static ref CACHE_RATIO: Gauge =
+            Gauge::with_opts(Opts::new("cache_hit_ratio", "Ratio of cache hits")).unwrap();
+    }
+}
+
+#[test]
+fn declared_collectors_are_scraped() {
+    jobs::process();
+    jobs::process();
+
+    let metrics = instrumented::render_metrics();
+    if cfg!(any(target_os = "linux", target_os = "macos", windows)) {
+        assert!(metrics.contains("jobs_processed_total 2\n"), "{}", metrics);
+        assert!(metrics.contains("jobs_running 0\n"), "{}", metrics);
+        // Registered even though it's never dereferenced.
+        assert!(metrics.contains("cache_hit_ratio 0\n"), "{}", metrics);
+    }
+
+    // Declaring a collector again doesn't register it twice.
+    instrumented::auto_register::declare(|| &*jobs::PROCESSED);
+    let metrics = instrumented::render_metrics();
+    assert_eq!(metrics.matches("# TYPE jobs_processed_total").count(), 1);
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "relabel")]
+
+use instrumented::instrument;
+use instrumented::relabel::RelabelRule;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+#[derive(Debug)]
+enum StoreError {
+    Timeout,
+    NotFound,
+}
+
+#[instrument(INFO, ctx = "store")]
+fn get(err: StoreError) -> Result<(), StoreError> {
+    Err(err)
+}
+
+fn request(addr: SocketAddr, method: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nHost: localhost\r\nAuthorization: Bearer secret\r\n\r\n",
+        method, path
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 200 OK"), "{}", response);
+    response
+}
+
+/// The series of `family` for the `get` function.
+fn series<'a>(metrics: &'a str, family: &str) -> Vec<&'a str> {
+    metrics
+        .lines()
+        .filter(|line| line.starts_with(&format!("{}{{", family)) && line.contains("name=\"get\""))
+        .collect()
+}
+
+#[test]
+fn drops_labels_and_metrics() {
+    for _ in 0..3 {
+        get(StoreError::Timeout).unwrap_err();
+    }
+    for _ in 0..2 {
+        get(StoreError::NotFound).unwrap_err();
+    }
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = instrumented::Config::new("127.0.0.1:0")
+        .bearer_token("secret")
+        .admin(true)
+        .relabel(RelabelRule::drop_label("function_error_.*", "err").unwrap())
+        .relabel(RelabelRule::add_label("cluster", "eu-1").unwrap());
+    instrumented::init_with_config_and_listener(config, listener);
+
+    // The errors of both kinds collapse into a single series.
+    let metrics = request(addr, "GET", "/metrics");
+    let errors = series(&metrics, "function_error_total");
+    assert_eq!(errors.len(), 1, "{}", metrics);
+    assert!(!errors[0].contains("err="), "{}", errors[0]);
+    assert!(errors[0].contains("cluster=\"eu-1\""), "{}", errors[0]);
+    assert!(errors[0].ends_with(" 5"), "{}", errors[0]);
+    assert_eq!(series(&metrics, "function_called_total").len(), 1);
+
+    let rules = request(addr, "POST", "/admin/relabel?rule=drop_metric+function_called_.%2A");
+    assert!(rules.ends_with("OK."));
+    let rules = request(addr, "GET", "/admin/relabel");
+    assert!(rules.ends_with(
+        "drop_label function_error_.* err\nadd_label cluster=eu-1\ndrop_metric function_called_.*\n"
+    ));
+    let metrics = request(addr, "GET", "/metrics");
+    assert!(series(&metrics, "function_called_total").is_empty());
+    assert_eq!(series(&metrics, "function_error_total").len(), 1);
+
+    request(addr, "DELETE", "/admin/relabel");
+    let metrics = request(addr, "GET", "/metrics");
+    assert_eq!(series(&metrics, "function_error_total").len(), 2);
+    assert_eq!(series(&metrics, "function_called_total").len(), 1);
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "remote-write")]
+
+mod common;
+
+use common::remote_write::{accept_request, decode};
+use instrumented::instrument;
+use instrumented::remote_write::{init_remote_write, Auth};
+use std::net::TcpListener;
+use std::time::Duration;
+
+#[instrument(INFO, ctx = "pushed")]
+fn pushed() {}
+
+#[test]
+fn pushes_function_metrics() {
+    pushed();
+    pushed();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/api/v1/write", listener.local_addr().unwrap());
+    init_remote_write(
+        &url,
+        Duration::from_millis(100),
+        Auth::Bearer("secret".into()),
+    )
+    .unwrap();
+
+    let (headers, body) = accept_request(&listener);
+    assert!(headers[0].starts_with("POST /api/v1/write "));
+    for header in &[
+        "authorization: bearer secret",
+        "content-encoding: snappy",
+        "content-type: application/x-protobuf",
+    ] {
+        assert!(
+            headers.iter().any(|h| h.eq_ignore_ascii_case(header)),
+            "{:?}",
+            headers
+        );
+    }
+
+    let request = snap::raw::Decoder::new().decompress_vec(&body).unwrap();
+    let series = decode(&request);
+    let called = series
+        .iter()
+        .find(|series| {
+            series
+                .labels
+                .contains(&("__name__".into(), "function_called_total".into()))
+                && series.labels.contains(&("name".into(), "pushed".into()))
+        })
+        .unwrap();
+    assert_eq!(
+        called.labels,
+        vec![
+            ("__name__".to_string(), "function_called_total".to_string()),
+            ("ctx".to_string(), "pushed".to_string()),
+            ("name".to_string(), "pushed".to_string()),
+            ("type".to_string(), "func_call".to_string()),
+        ]
+    );
+    assert_eq!(called.value, 2.0);
+    assert!(called.timestamp > 1_500_000_000_000);
+
+    let count = series
+        .iter()
+        .find(|series| {
+            series
+                .labels
+                .contains(&("__name__".into(), "function_time_seconds_count".into()))
+                && series.labels.contains(&("name".into(), "pushed".into()))
+        })
+        .unwrap();
+    assert_eq!(count.value, 2.0);
+}
@@ -0,0 +1,19 @@
+use instrumented::instrument;
+
+#[instrument(INFO, ctx = "render")]
+fn rendered() {}
+
+// Runs without the `exporter` feature in CI, to check that metrics can be rendered without the
+// HTTP server.
+#[test]
+fn renders_metrics() {
+    rendered();
+
+    let metrics = instrumented::render_metrics();
+    assert!(metrics.contains("# TYPE function_called_total counter"));
+    assert!(metrics
+        .lines()
+        .any(|line| line.starts_with("function_called_total{")
+            && line.contains("name=\"rendered\"")
+            && line.ends_with(" 1")));
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use instrumented::instrument;
+use instrumented::retry::{instrumented_retry, instrumented_retry_async, RetryPolicy};
+use std::cell::Cell;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+struct Refused;
+
+#[instrument(INFO)]
+fn connect(attempts: &Cell<u32>) -> Result<u32, Refused> {
+    attempts.set(attempts.get() + 1);
+    if attempts.get() < 3 {
+        Err(Refused)
+    } else {
+        Ok(attempts.get())
+    }
+}
+
+#[test]
+fn records_the_attempts_of_an_operation() {
+    let attempts = Cell::new(0);
+    let policy = RetryPolicy::new(5).fixed_backoff(Duration::from_millis(1));
+    let result = instrumented_retry("connect_with_retries", "retry", policy, || {
+        connect(&attempts)
+    });
+    assert_eq!(result, Ok(3));
+
+    assert_eq!(
+        common::histogram_count(
+            "function_retry_attempts",
+            &[("name", "connect_with_retries")]
+        ),
+        1
+    );
+    assert_eq!(
+        common::histogram_sum(
+            "function_retry_attempts",
+            &[("name", "connect_with_retries")]
+        ),
+        3.0
+    );
+    assert_eq!(
+        common::counter_value(
+            "function_retries_total",
+            &[("name", "connect_with_retries")]
+        ),
+        2.0
+    );
+    assert_eq!(
+        common::counter_value(
+            "function_retry_exhausted_total",
+            &[("name", "connect_with_retries")]
+        ),
+        0.0
+    );
+    // The attempts are still calls of the instrumented operation.
+    assert_eq!(
+        common::counter_value("function_called_total", &[("name", "connect")]),
+        3.0
+    );
+    assert_eq!(
+        common::counter_value("function_error_total", &[("name", "connect")]),
+        2.0
+    );
+}
+
+#[test]
+fn records_exhausted_operations() {
+    let result: Result<(), Refused> =
+        instrumented_retry("always_refused", "retry", RetryPolicy::new(4), || {
+            Err(Refused)
+        });
+    assert_eq!(result, Err(Refused));
+
+    assert_eq!(
+        common::histogram_sum("function_retry_attempts", &[("name", "always_refused")]),
+        4.0
+    );
+    assert_eq!(
+        common::counter_value(
+            "function_retry_exhausted_total",
+            &[("name", "always_refused")]
+        ),
+        1.0
+    );
+}
+
+#[test]
+fn retries_async_operations() {
+    let attempts = Cell::new(0);
+    let slept = Cell::new(Duration::from_secs(0));
+    let policy = RetryPolicy::new(5).exponential_backoff(Duration::from_millis(10));
+    let result = common::block_on(instrumented_retry_async(
+        "connect_async",
+        "retry",
+        policy,
+        || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt < 3 {
+                    Err(Refused)
+                } else {
+                    Ok(attempt)
+                }
+            }
+        },
+        |backoff| {
+            slept.set(slept.get() + backoff);
+            async {}
+        },
+    ));
+    assert_eq!(result, Ok(3));
+    assert_eq!(slept.get(), Duration::from_millis(30));
+
+    assert_eq!(
+        common::histogram_count("function_retry_attempts", &[("name", "connect_async")]),
+        1
+    );
+    assert_eq!(
+        common::histogram_sum("function_retry_attempts", &[("name", "connect_async")]),
+        3.0
+    );
+    assert_eq!(
+        common::counter_value(
+            "function_retry_exhausted_total",
+            &[("name", "connect_async")]
+        ),
+        0.0
+    );
+}
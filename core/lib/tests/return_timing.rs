@@ -0,0 +1,60 @@
+use instrumented::prometheus::proto::MetricFamily;
+use instrumented::{instrument, Timed};
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+struct NotFound;
+
+#[instrument(INFO, return_timing)]
+fn render(page: &str) -> String {
+    std::thread::sleep(Duration::from_millis(2));
+    format!("<h1>{}</h1>", page)
+}
+
+#[instrument(INFO, return_timing)]
+fn lookup(key: u32) -> Result<u32, NotFound> {
+    if key == 0 {
+        return Err(NotFound);
+    }
+    Ok(key * 2)
+}
+
+#[instrument(INFO, return_timing)]
+fn touch() {}
+
+fn time_sum(families: &[MetricFamily], name: &str) -> (u64, f64) {
+    let histogram = families
+        .iter()
+        .filter(|f| f.get_name() == "function_time_seconds")
+        .flat_map(|f| f.get_metric())
+        .find(|m| m.get_label().iter().any(|l| l.get_value() == name))
+        .unwrap()
+        .get_histogram();
+    (histogram.get_sample_count(), histogram.get_sample_sum())
+}
+
+#[test]
+fn returns_the_observed_duration() {
+    let page: Timed<String> = render("home");
+    assert_eq!(page.len(), 13);
+    assert!(page.duration() >= Duration::from_millis(2));
+
+    let (count, sum) = time_sum(&instrumented::gather(), "render");
+    assert_eq!(count, 1);
+    assert!((sum - page.duration().as_secs_f64()).abs() < 1e-9);
+    assert_eq!(page.into_inner(), "<h1>home</h1>");
+}
+
+#[test]
+fn wraps_the_ok_value_of_results() {
+    let found = lookup(21).unwrap();
+    assert_eq!(*found, 42);
+    assert_eq!(lookup(0), Err(NotFound));
+
+    let (count, sum) = time_sum(&instrumented::gather(), "lookup");
+    assert_eq!(count, 2);
+    assert!(found.duration().as_secs_f64() <= sum + 1e-9);
+
+    let unit: Timed<()> = touch();
+    assert_eq!(unit.into_inner(), ());
+}
@@ -0,0 +1,42 @@
+use instrumented::instrument;
+use instrumented::prometheus::{Encoder, TextEncoder};
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, ctx = "self_metrics")]
+fn scraped(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+fn gauge(families: &[instrumented::prometheus::proto::MetricFamily], name: &str) -> f64 {
+    families
+        .iter()
+        .find(|mf| mf.get_name() == name)
+        .map(|mf| mf.get_metric()[0].get_gauge().get_value())
+        .unwrap_or_else(|| panic!("{} is exported", name))
+}
+
+#[test]
+fn series_total_matches_scrape() {
+    assert!(scraped(false).is_ok());
+    assert!(scraped(true).is_err());
+
+    let families = instrumented::gather();
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&families, &mut buffer).unwrap();
+    let samples = String::from_utf8(buffer)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .count();
+
+    // The three self-reporting families aren't included in the series count.
+    let series = gauge(&families, "metrics_series_total") as usize;
+    assert_eq!(samples, series + 3);
+    assert!(gauge(&families, "metrics_registered_collectors") >= 4.0);
+}
@@ -0,0 +1,40 @@
+#![cfg(feature = "sentry")]
+
+use instrumented::instrument;
+use instrumented::integrations::sentry::{install, Options};
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, ctx = "payments")]
+fn charge() -> Result<(), MyError> {
+    Err(MyError)
+}
+
+#[instrument(INFO, ctx = "search")]
+fn search() -> Result<(), MyError> {
+    Err(MyError)
+}
+
+#[test]
+fn records_breadcrumbs() {
+    install(Options {
+        ctxs: vec!["payments"],
+        max_per_second: 2,
+    });
+
+    let events = sentry::test::with_captured_events(|| {
+        for _ in 0..5 {
+            assert!(charge().is_err());
+        }
+        assert!(search().is_err());
+        sentry::capture_message("done", sentry::Level::Info);
+    });
+
+    assert_eq!(events.len(), 1);
+    let breadcrumbs = &events[0].breadcrumbs.values;
+    // Filtered by ctx, and rate limited to two per second.
+    assert_eq!(breadcrumbs.len(), 2);
+    assert_eq!(breadcrumbs[0].message.as_deref(), Some("charge() => MyError"));
+    assert_eq!(breadcrumbs[0].data["ctx"], "payments");
+}
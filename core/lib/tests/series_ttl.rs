@@ -0,0 +1,70 @@
+#![cfg(all(feature = "backend-prometheus", feature = "test-hooks"))]
+
+use instrumented::instrument;
+use instrumented::prometheus::proto::MetricFamily;
+use instrumented::series_ttl::{advance_clock, set_series_ttl};
+use std::time::Duration;
+
+// The tenant is only read through `Debug`.
+#[allow(dead_code)]
+#[derive(Debug)]
+enum TenantError {
+    Suspended(u32),
+}
+
+#[instrument(INFO)]
+fn charge(tenant: u32) -> Result<(), TenantError> {
+    Err(TenantError::Suspended(tenant))
+}
+
+fn error_series(families: &[MetricFamily]) -> Vec<String> {
+    families
+        .iter()
+        .filter(|family| family.get_name() == "function_error_total")
+        .flat_map(|family| family.get_metric())
+        .flat_map(|metric| metric.get_label())
+        .filter(|label| label.get_name() == "err")
+        .map(|label| label.get_value().to_string())
+        .collect()
+}
+
+fn calls(families: &[MetricFamily], name: &str) -> f64 {
+    families
+        .iter()
+        .filter(|family| family.get_name() == "function_called_total")
+        .flat_map(|family| family.get_metric())
+        .find(|metric| metric.get_label().iter().any(|l| l.get_value() == name))
+        .map_or(0.0, |metric| metric.get_counter().get_value())
+}
+
+#[test]
+fn evicts_idle_dynamic_series() {
+    set_series_ttl(Some(Duration::from_secs(60)));
+
+    assert!(charge(1).is_err());
+    assert!(charge(2).is_err());
+    let families = instrumented::gather();
+    assert_eq!(
+        error_series(&families),
+        vec!["Suspended(1)", "Suspended(2)"]
+    );
+
+    // Tenant 2 keeps failing, tenant 1 never comes back.
+    advance_clock(Duration::from_secs(40));
+    assert!(charge(2).is_err());
+    advance_clock(Duration::from_secs(40));
+
+    let families = instrumented::gather();
+    assert_eq!(error_series(&families), vec!["Suspended(2)"]);
+    // The series of the function itself aren't evicted.
+    assert_eq!(calls(&families, "charge"), 3.0);
+
+    // A recurring value starts a new series.
+    assert!(charge(1).is_err());
+    let families = instrumented::gather();
+    assert_eq!(
+        error_series(&families),
+        vec!["Suspended(1)", "Suspended(2)"]
+    );
+    set_series_ttl(None);
+}
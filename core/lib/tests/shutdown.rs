@@ -0,0 +1,48 @@
+#![cfg(feature = "remote-write")]
+
+mod common;
+
+use common::remote_write::{accept_request, decode};
+use instrumented::instrument;
+use instrumented::remote_write::{init_remote_write, Auth};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[instrument(INFO)]
+fn before_shutdown() {}
+
+#[test]
+fn pushes_the_last_increments() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/api/v1/write", listener.local_addr().unwrap());
+    // No periodic push happens during the test.
+    init_remote_write(&url, Duration::from_secs(3600), Auth::None).unwrap();
+    let endpoint = thread::spawn(move || accept_request(&listener));
+
+    before_shutdown();
+    before_shutdown();
+    before_shutdown();
+    instrumented::shutdown(Duration::from_secs(5));
+
+    let (headers, body) = endpoint.join().unwrap();
+    assert!(headers[0].starts_with("POST /api/v1/write "));
+    let request = snap::raw::Decoder::new().decompress_vec(&body).unwrap();
+    let called = decode(&request)
+        .into_iter()
+        .find(|series| {
+            series
+                .labels
+                .contains(&("__name__".into(), "function_called_total".into()))
+                && series
+                    .labels
+                    .contains(&("name".into(), "before_shutdown".into()))
+        })
+        .unwrap();
+    assert_eq!(called.value, 3.0);
+
+    // Nothing is pushed again, so this doesn't wait for a connection.
+    let start = Instant::now();
+    instrumented::shutdown(Duration::from_secs(5));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
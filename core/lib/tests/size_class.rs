@@ -0,0 +1,60 @@
+mod common;
+
+use instrumented::instrument;
+
+pub fn bucket_len(len: usize) -> &'static str {
+    if len < 100 {
+        "small"
+    } else {
+        "large"
+    }
+}
+
+#[derive(Debug)]
+pub struct Empty;
+
+#[instrument(INFO, ctx = "size_class", size_class = "bucket_len(items.len())")]
+fn sum(items: Vec<u64>) -> u64 {
+    items.into_iter().sum()
+}
+
+#[instrument(INFO, ctx = "size_class", size_class = "bucket_len(items.len())")]
+fn max(items: Vec<u64>) -> Result<u64, Empty> {
+    items.into_iter().max().ok_or(Empty)
+}
+
+fn count(name: &str, size_class: &str) -> u64 {
+    common::histogram_count(
+        "function_time_by_size_class_seconds",
+        &[
+            ("name", name),
+            ("ctx", "size_class"),
+            ("size_class", size_class),
+        ],
+    )
+}
+
+#[test]
+fn times_calls_by_size_class() {
+    for len in &[1, 10, 1_000, 50] {
+        sum(vec![1; *len]);
+    }
+
+    assert_eq!(count("sum", "small"), 3);
+    assert_eq!(count("sum", "large"), 1);
+    // The histogram without the label still observes every call.
+    assert_eq!(
+        common::histogram_count("function_time_seconds", &[("name", "sum")]),
+        4
+    );
+}
+
+#[test]
+fn times_errors_by_size_class() {
+    max(vec![]).unwrap_err();
+    max(vec![3; 500]).unwrap();
+    max(vec![2; 5]).unwrap();
+
+    assert_eq!(count("max", "small"), 2);
+    assert_eq!(count("max", "large"), 1);
+}
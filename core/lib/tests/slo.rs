@@ -0,0 +1,45 @@
+mod common;
+
+use common::counter_value;
+use instrumented::instrument;
+use std::thread::sleep;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct MyError;
+
+#[instrument(INFO, ctx = "slo", slo_ms = 50)]
+fn checkout(delay_ms: u64, fail: bool) -> Result<(), MyError> {
+    sleep(Duration::from_millis(delay_ms));
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(INFO, ctx = "slo", slo_ms = 50)]
+fn render(delay_ms: u64) {
+    sleep(Duration::from_millis(delay_ms));
+}
+
+fn slo_counts(name: &str) -> (f64, f64) {
+    let labels = [("name", name), ("ctx", "slo")];
+    (
+        counter_value("function_slo_good_total", &labels),
+        counter_value("function_slo_total", &labels),
+    )
+}
+
+#[test]
+fn counts_good_and_total_calls() {
+    // Fast, fast, slow, fast error, slow error.
+    for (delay_ms, fail) in &[(0, false), (1, false), (120, false), (0, true), (120, true)] {
+        let _ = checkout(*delay_ms, *fail);
+    }
+    assert_eq!(slo_counts("checkout"), (2.0, 5.0));
+
+    render(0);
+    render(120);
+    assert_eq!(slo_counts("render"), (1.0, 2.0));
+}
@@ -0,0 +1,60 @@
+use instrumented::instrument;
+use std::time::Duration;
+use std::{fs, thread};
+
+#[derive(Debug)]
+enum QueryError {
+    Timeout,
+}
+
+#[instrument(INFO, ctx = "payment")]
+fn slow_query(fail: bool) -> Result<(), QueryError> {
+    thread::sleep(Duration::from_millis(60));
+    if fail {
+        Err(QueryError::Timeout)
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(INFO, ctx = "payment")]
+fn fast_query() {}
+
+#[test]
+fn logs_slow_calls() {
+    let path = std::env::temp_dir().join(format!("instrumented-slow-{}.log", std::process::id()));
+    let rotated = format!("{}.1", path.display());
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&rotated);
+    instrumented::init_with_config(instrumented::Config::new("127.0.0.1:0").slow_log(
+        &path,
+        Duration::from_millis(50),
+        150,
+    ));
+
+    slow_query(true).unwrap_err();
+    fast_query();
+    slow_query(false).unwrap();
+    instrumented::slow_log::flush();
+
+    let log = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", log);
+    let fields: Vec<&str> = lines[0].split(' ').collect();
+    assert_eq!(fields.len(), 5);
+    assert_eq!(fields[0].len(), "2024-05-01T12:00:00Z".len());
+    assert!(fields[0].ends_with('Z'));
+    assert_eq!(&fields[1..3], &["slow_query", "ctx=payment"]);
+    assert!(fields[3].starts_with("0.") && fields[3].ends_with('s'));
+    assert_eq!(fields[4], "Err(Timeout)");
+    assert!(lines[1].ends_with(" Ok"));
+
+    // The third line doesn't fit in the 150 bytes, and rotates the file.
+    slow_query(false).unwrap();
+    instrumented::slow_log::flush();
+    assert_eq!(fs::read_to_string(&rotated).unwrap(), log);
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&rotated).unwrap();
+}
@@ -0,0 +1,37 @@
+use instrumented::{instrument, stats};
+
+#[derive(Debug)]
+pub struct Overloaded;
+
+#[instrument(INFO, ctx = "stats")]
+fn busy() -> i64 {
+    stats::inflight("busy", "stats")
+}
+
+#[instrument(INFO, ctx = "stats")]
+fn admit(load: u32) -> Result<u32, Overloaded> {
+    if load > 10 {
+        Err(Overloaded)
+    } else {
+        Ok(load)
+    }
+}
+
+#[test]
+fn reads_function_metrics() {
+    assert_eq!(stats::calls("admit", "stats"), 0);
+    assert_eq!(stats::errors("admit", "stats"), 0);
+    assert_eq!(stats::inflight("busy", "stats"), 0);
+
+    assert_eq!(busy(), 1);
+    assert_eq!(stats::inflight("busy", "stats"), 0);
+    assert_eq!(stats::calls("busy", "stats"), 1);
+
+    for load in &[1, 20, 5, 30] {
+        let _ = admit(*load);
+    }
+    assert_eq!(stats::calls("admit", "stats"), 4);
+    assert_eq!(stats::errors("admit", "stats"), 2);
+    // Another ctx is another function.
+    assert_eq!(stats::calls("admit", "default"), 0);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use futures_core::Stream;
+use instrumented::instrument;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[derive(Debug, PartialEq)]
+pub struct MyError;
+
+/// A stream yielding the items of an iterator.
+struct IterStream<I>(I);
+
+impl<I: Iterator + Unpin> Stream for IterStream<I> {
+    type Item = I::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<I::Item>> {
+        Poll::Ready(self.0.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+fn items() -> Vec<Result<u32, MyError>> {
+    vec![Ok(1), Ok(2), Err(MyError), Ok(4), Ok(5)]
+}
+
+#[instrument(INFO, stream)]
+fn five_items() -> impl Stream<Item = Result<u32, MyError>> {
+    IterStream(items().into_iter())
+}
+
+fn collect<S: Stream + Unpin>(mut stream: S) -> Vec<S::Item> {
+    common::block_on(std::future::poll_fn(move |cx| {
+        let mut items = Vec::new();
+        while let Poll::Ready(Some(item)) = Pin::new(&mut stream).poll_next(cx) {
+            items.push(item);
+        }
+        Poll::Ready(items)
+    }))
+}
+
+#[test]
+fn counts_items_and_errors() {
+    let stream = five_items();
+    assert_eq!(stream.size_hint(), (5, Some(5)));
+    assert_eq!(collect(stream), items());
+
+    let labels = [("name", "five_items")];
+    assert_eq!(common::counter_value("function_called_total", &labels), 1.0);
+    assert_eq!(common::counter_value("function_stream_items_total", &labels), 5.0);
+    assert_eq!(common::counter_value("function_error_total", &labels), 1.0);
+    assert_eq!(
+        common::histogram_count("function_stream_first_item_seconds", &labels),
+        1
+    );
+    assert_eq!(
+        common::histogram_count("function_stream_duration_seconds", &labels),
+        1
+    );
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "sync")]
+use instrumented::sync::{Mutex, RwLock};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+fn value(metrics: &str, series: &str) -> f64 {
+    metrics
+        .lines()
+        .find(|line| line.starts_with(series))
+        .and_then(|line| line.rsplit(' ').next())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0.0)
+}
+
+/// Holds the lock from another thread while `acquire` waits for it.
+fn contend<L: Send + Sync + 'static>(lock: Arc<L>, hold: fn(&L, &Barrier), acquire: fn(&L)) {
+    let barrier = Arc::new(Barrier::new(2));
+    let holder = {
+        let lock = lock.clone();
+        let barrier = barrier.clone();
+        thread::spawn(move || hold(&lock, &barrier))
+    };
+    barrier.wait();
+    acquire(&lock);
+    holder.join().unwrap();
+}
+
+#[test]
+fn contended_mutex_records_the_wait() {
+    contend(
+        Arc::new(Mutex::new("contended_mutex", 0)),
+        |lock, barrier| {
+            let _guard = lock.lock();
+            barrier.wait();
+            thread::sleep(Duration::from_millis(50));
+        },
+        |lock| *lock.lock() += 1,
+    );
+
+    let metrics = instrumented::render_metrics();
+    assert_eq!(
+        value(&metrics, "lock_contended_total{name=\"contended_mutex\"}"),
+        1.0,
+        "{}",
+        metrics
+    );
+    assert_eq!(
+        value(
+            &metrics,
+            "lock_wait_seconds_count{name=\"contended_mutex\"}"
+        ),
+        1.0
+    );
+    assert!(value(&metrics, "lock_wait_seconds_sum{name=\"contended_mutex\"}") >= 0.04);
+}
+
+#[test]
+fn contended_rwlock_records_the_wait() {
+    contend(
+        Arc::new(RwLock::new("contended_rwlock", 0)),
+        |lock, barrier| {
+            let _guard = lock.write();
+            barrier.wait();
+            thread::sleep(Duration::from_millis(50));
+        },
+        |lock| assert_eq!(*lock.read(), 0),
+    );
+
+    let metrics = instrumented::render_metrics();
+    assert_eq!(
+        value(&metrics, "lock_contended_total{name=\"contended_rwlock\"}"),
+        1.0,
+        "{}",
+        metrics
+    );
+    assert!(value(&metrics, "lock_wait_seconds_sum{name=\"contended_rwlock\"}") >= 0.04);
+}
+
+#[test]
+fn uncontended_locks_record_nothing() {
+    let mutex = Mutex::new("uncontended_mutex", 0);
+    let rwlock = RwLock::new("uncontended_rwlock", 0);
+    for _ in 0..10_000 {
+        *mutex.lock() += 1;
+        let _first = rwlock.read();
+        let _second = rwlock.read();
+    }
+    *rwlock.write() += 1;
+    assert_eq!(mutex.into_inner(), 10_000);
+
+    let metrics = instrumented::render_metrics();
+    assert!(!metrics.contains("uncontended_mutex"), "{}", metrics);
+    assert!(!metrics.contains("uncontended_rwlock"), "{}", metrics);
+}
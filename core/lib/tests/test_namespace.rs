@@ -0,0 +1,34 @@
+use instrumented::{instrument, list_functions};
+
+#[cfg(test)]
+#[instrument(INFO, ctx = "namespaced", test_namespace)]
+fn seed_fixtures() -> usize {
+    3
+}
+
+#[instrument(INFO, ctx = "namespaced", test_namespace = false)]
+fn shared_helper() {}
+
+fn calls(name: &str) -> Option<u64> {
+    list_functions()
+        .into_iter()
+        .find(|function| function.name == name && function.ctx == "namespaced")
+        .map(|function| function.calls)
+}
+
+#[instrument(INFO, ctx = "namespaced")]
+#[test]
+fn test_helpers_are_namespaced() {
+    assert_eq!(seed_fixtures(), 3);
+    shared_helper();
+
+    assert_eq!(calls("test_seed_fixtures"), Some(1));
+    assert_eq!(calls("seed_fixtures"), None);
+    assert_eq!(calls("shared_helper"), Some(1));
+    // Tests are in the test namespace without the attribute, and are listed before being called
+    // on the platforms registering functions at startup.
+    assert_eq!(calls("test_helpers_are_namespaced"), None);
+    if cfg!(any(target_os = "linux", target_os = "macos", windows)) {
+        assert!(calls("test_test_helpers_are_namespaced").is_some());
+    }
+}
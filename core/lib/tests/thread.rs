@@ -0,0 +1,69 @@
+mod common;
+
+use std::sync::{Arc, Barrier};
+
+fn alive(name: &str) -> f64 {
+    common::gauge_value("threads_alive", &[("name", name)])
+}
+
+fn spawned(name: &str) -> f64 {
+    common::counter_value("threads_spawned_total", &[("name", name)])
+}
+
+#[test]
+fn counts_workers_until_they_exit() {
+    let barrier = Arc::new(Barrier::new(4));
+    let workers = (0..3)
+        .map(|i| {
+            let barrier = barrier.clone();
+            instrumented::thread::spawn("counted", move || {
+                assert_eq!(std::thread::current().name(), Some("counted"));
+                barrier.wait();
+                i
+            })
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(spawned("counted"), 3.0);
+    assert_eq!(alive("counted"), 3.0);
+    barrier.wait();
+    let results = workers
+        .into_iter()
+        .map(|worker| worker.join().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(results, vec![0, 1, 2]);
+    assert_eq!(alive("counted"), 0.0);
+    assert_eq!(spawned("counted"), 3.0);
+}
+
+#[test]
+fn panicking_workers_are_no_longer_alive() {
+    let worker = instrumented::thread::spawn("panicking", || panic!("worker failed"));
+
+    assert!(worker.join().is_err());
+    assert_eq!(alive("panicking"), 0.0);
+    assert_eq!(spawned("panicking"), 1.0);
+}
+
+#[test]
+fn times_the_lifetime_when_asked() {
+    for _ in 0..2 {
+        instrumented::thread::Builder::new("timed")
+            .time_lifetime()
+            .spawn(|| std::thread::sleep(std::time::Duration::from_millis(10)))
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+    instrumented::thread::spawn("untimed", || ())
+        .join()
+        .unwrap();
+
+    assert_eq!(
+        common::histogram_count("thread_lifetime_seconds", &[("name", "timed")]),
+        2
+    );
+    assert!(common::histogram_sum("thread_lifetime_seconds", &[("name", "timed")]) >= 0.02);
+    assert!(common::find_metric("thread_lifetime_seconds", &[("name", "untimed")]).is_none());
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use instrumented::ThreadPoolMetrics;
+
+#[test]
+fn exports_metrics_per_pool() {
+    let io = ThreadPoolMetrics::new("io");
+    let cpu = ThreadPoolMetrics::new("cpu");
+    io.set_pool_size(16);
+    io.set_queue_depth(5);
+    cpu.set_pool_size(4);
+    cpu.inc_completed();
+    cpu.inc_completed();
+
+    assert_eq!(
+        common::gauge_value("thread_pool_size", &[("pool", "io")]),
+        16.0
+    );
+    assert_eq!(
+        common::gauge_value("thread_pool_size", &[("pool", "cpu")]),
+        4.0
+    );
+    assert_eq!(
+        common::gauge_value("thread_pool_queue_depth", &[("pool", "io")]),
+        5.0
+    );
+    assert_eq!(
+        common::counter_value("thread_pool_completed_total", &[("pool", "cpu")]),
+        2.0
+    );
+    assert_eq!(
+        common::counter_value("thread_pool_completed_total", &[("pool", "io")]),
+        0.0
+    );
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn exports_thread_count() {
+    let handle = std::thread::spawn(|| std::thread::sleep(std::time::Duration::from_millis(100)));
+    assert!(common::gauge_value("process_threads_total", &[]) >= 2.0);
+    handle.join().unwrap();
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use instrumented::{instrument, set_time_unit, time_unit, TimeUnit};
+use std::{thread, time::Duration};
+
+#[instrument(INFO)]
+fn sleep_ten_millis() {
+    thread::sleep(Duration::from_millis(10));
+}
+
+#[test]
+fn observes_milliseconds() {
+    set_time_unit(TimeUnit::Milliseconds).unwrap();
+    sleep_ten_millis();
+
+    let labels = [("name", "sleep_ten_millis")];
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 0);
+    assert_eq!(
+        common::histogram_count("function_time_milliseconds", &labels),
+        1
+    );
+    let elapsed = common::histogram_sum("function_time_milliseconds", &labels);
+    assert!((10.0..100.0).contains(&elapsed), "elapsed {}", elapsed);
+
+    // The unit is fixed once the histograms are registered.
+    assert!(set_time_unit(TimeUnit::Milliseconds).is_ok());
+    assert!(set_time_unit(TimeUnit::Seconds).is_err());
+    assert_eq!(time_unit(), TimeUnit::Milliseconds);
+}
@@ -0,0 +1,19 @@
+mod common;
+
+use instrumented::instrument;
+use std::{thread, time::Duration};
+
+#[instrument(INFO)]
+fn sleep_ten_millis() {
+    thread::sleep(Duration::from_millis(10));
+}
+
+#[test]
+fn observes_seconds_by_default() {
+    sleep_ten_millis();
+
+    let labels = [("name", "sleep_ten_millis")];
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 1);
+    let elapsed = common::histogram_sum("function_time_seconds", &labels);
+    assert!((0.01..0.1).contains(&elapsed), "elapsed {}", elapsed);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use instrumented::{instrument_drop, timed_drop};
+use std::panic;
+use std::time::Duration;
+use std::{thread, time};
+
+/// Syncs on drop, slowly.
+struct Journal {
+    sync: Duration,
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        thread::sleep(self.sync);
+    }
+}
+
+struct Segment;
+
+#[instrument_drop(ctx = "io")]
+impl Drop for Segment {
+    fn drop(&mut self) {
+        thread::sleep(time::Duration::from_millis(50));
+    }
+}
+
+struct Poisoned;
+
+#[instrument_drop(name = "poisoned_drop", ctx = "io")]
+impl Drop for Poisoned {
+    fn drop(&mut self) {
+        panic!("unable to sync");
+    }
+}
+
+#[test]
+fn times_wrapped_drops() {
+    let journal = timed_drop(
+        Journal {
+            sync: Duration::from_millis(50),
+        },
+        "journal_drop",
+        "io",
+    );
+    assert_eq!(journal.sync, Duration::from_millis(50));
+    let labels = [("name", "journal_drop"), ("ctx", "io")];
+    assert_eq!(common::counter_value("function_called_total", &labels), 0.0);
+
+    drop(journal);
+    assert_eq!(common::counter_value("function_called_total", &labels), 1.0);
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 1);
+    assert!(common::histogram_sum("function_time_seconds", &labels) >= 0.05);
+    assert_eq!(
+        common::gauge_value("function_calls_inflight_total", &labels),
+        0.0
+    );
+
+    // Unwrapped values aren't timed.
+    let journal = timed_drop(
+        Journal {
+            sync: Duration::from_millis(0),
+        },
+        "journal_drop",
+        "io",
+    );
+    drop(journal.into_inner());
+    assert_eq!(common::counter_value("function_called_total", &labels), 1.0);
+}
+
+#[test]
+fn times_instrumented_drop_impls() {
+    drop(Segment);
+    let labels = [("name", "Segment::drop"), ("ctx", "io")];
+    assert_eq!(common::histogram_count("function_time_seconds", &labels), 1);
+    assert!(common::histogram_sum("function_time_seconds", &labels) >= 0.05);
+}
+
+#[test]
+fn panicking_drops_leave_no_inflight_call() {
+    assert!(panic::catch_unwind(|| drop(Poisoned)).is_err());
+    let labels = [("name", "poisoned_drop"), ("ctx", "io")];
+    assert_eq!(common::counter_value("function_called_total", &labels), 1.0);
+    assert_eq!(
+        common::gauge_value("function_calls_inflight_total", &labels),
+        0.0
+    );
+}
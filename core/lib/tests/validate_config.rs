@@ -0,0 +1,64 @@
+use instrumented::{validate_config, Config, ConfigError};
+use std::env;
+
+// The env vars are process-global, so every case runs in the same test.
+#[test]
+fn validates_the_configuration() {
+    env::remove_var("METRICS_PREFIX");
+    env::remove_var("METRICS_LABELS");
+    env::remove_var("METRICS_TIME_UNIT");
+
+    let report = validate_config().unwrap();
+    assert_eq!(report.prefix, None);
+    assert!(report.label_keys.is_empty());
+    assert_eq!(report.time_unit, "seconds");
+    assert!(report.features.contains(&"exporter"));
+
+    env::set_var("METRICS_PREFIX", "my-app");
+    assert_eq!(
+        validate_config(),
+        Err(ConfigError::InvalidPrefix("my-app".to_string()))
+    );
+    env::set_var("METRICS_PREFIX", "myapp");
+
+    env::set_var("METRICS_LABELS", "app=myapp,prod");
+    assert_eq!(
+        validate_config(),
+        Err(ConfigError::MalformedLabel("prod".to_string()))
+    );
+    env::set_var("METRICS_LABELS", "app=myapp,deploy-env=prod");
+    assert_eq!(
+        validate_config(),
+        Err(ConfigError::InvalidLabelName("deploy-env".to_string()))
+    );
+    env::set_var("METRICS_LABELS", "app=myapp,name=api");
+    assert_eq!(
+        validate_config(),
+        Err(ConfigError::ReservedLabelName("name".to_string()))
+    );
+    env::set_var("METRICS_LABELS", "app=myapp,env=prod");
+
+    env::set_var("METRICS_TIME_UNIT", "minutes");
+    assert_eq!(
+        validate_config(),
+        Err(ConfigError::UnknownTimeUnit("minutes".to_string()))
+    );
+    env::set_var("METRICS_TIME_UNIT", "ms");
+
+    let report = validate_config().unwrap();
+    assert_eq!(report.prefix.as_ref().map(String::as_str), Some("myapp"));
+    assert_eq!(report.label_keys, vec!["app", "env"]);
+    assert_eq!(report.time_unit, "milliseconds");
+    assert!(report
+        .to_string()
+        .starts_with("prefix: myapp, labels: [app, env], time unit: milliseconds, features: ["));
+
+    assert_eq!(
+        Config::new("localhost").validate(),
+        Err(ConfigError::InvalidAddr("localhost".to_string()))
+    );
+    let report = Config::new("127.0.0.1:5000").validate().unwrap();
+    assert!(report
+        .to_string()
+        .starts_with("http://127.0.0.1:5000/metrics, prefix: myapp,"));
+}
@@ -0,0 +1,23 @@
+mod common;
+
+use instrumented::instrument;
+
+pub struct Item;
+
+#[instrument(INFO, ctx = "weight", weight = "items.len() as u64")]
+fn flush(items: &[Item]) -> usize {
+    items.len()
+}
+
+#[test]
+fn counts_the_units_of_work() {
+    flush(&[Item, Item, Item]);
+    flush(&[Item, Item, Item, Item, Item]);
+
+    let labels = [("name", "flush"), ("ctx", "weight")];
+    assert_eq!(
+        common::counter_value("function_work_units_total", &labels),
+        8.0
+    );
+    assert_eq!(common::counter_value("function_called_total", &labels), 2.0);
+}
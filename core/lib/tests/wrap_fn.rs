@@ -0,0 +1,50 @@
+use instrumented::stats::{calls, errors, inflight};
+use instrumented::{wrap_fn, wrap_fn_mut, wrap_fn_once};
+
+#[test]
+fn counts_the_calls_of_an_fn_mut() {
+    let mut count = 0;
+    {
+        let mut increment = wrap_fn_mut("increment", "wrap", || {
+            count += 1;
+            count
+        });
+        for expected in 1..=10 {
+            assert_eq!(increment(), expected);
+        }
+    }
+    assert_eq!(count, 10);
+    assert_eq!(calls("increment", "wrap"), 10);
+    assert_eq!(errors("increment", "wrap"), 0);
+    assert_eq!(inflight("increment", "wrap"), 0);
+}
+
+#[test]
+fn counts_the_errors_of_results() {
+    let check = wrap_fn("check_even", "wrap", {
+        let values = [2, 3, 4, 5, 7];
+        let next = std::cell::Cell::new(0);
+        move || {
+            let value = values[next.get()];
+            next.set(next.get() + 1);
+            if value % 2 == 0 {
+                Ok(value)
+            } else {
+                Err("odd")
+            }
+        }
+    });
+    let ok = (0..5).filter(|_| check().is_ok()).count();
+    assert_eq!(ok, 2);
+    assert_eq!(calls("check_even", "wrap"), 5);
+    assert_eq!(errors("check_even", "wrap"), 3);
+}
+
+#[test]
+fn runs_an_fn_once_once() {
+    let name = String::from("task");
+    let task = wrap_fn_once("run_task", "wrap", move || name);
+    let handle = std::thread::spawn(task);
+    assert_eq!(handle.join().unwrap(), "task");
+    assert_eq!(calls("run_task", "wrap"), 1);
+}
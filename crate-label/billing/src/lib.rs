@@ -0,0 +1,7 @@
+//! A crate of the workspace with a function named like one of `instrumented-shipping`.
+use instrumented::instrument;
+
+#[instrument(INFO, ctx = "api")]
+pub fn handle() -> u32 {
+    1
+}
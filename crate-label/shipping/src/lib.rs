@@ -0,0 +1,8 @@
+//! Checks that the functions of each crate of a workspace get their own `crate` label, with
+//! `instrumented-billing` defining a function of the same name and ctx.
+use instrumented::instrument;
+
+#[instrument(INFO, ctx = "api")]
+pub fn handle() -> u32 {
+    2
+}
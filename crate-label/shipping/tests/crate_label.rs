@@ -0,0 +1,30 @@
+/// Returns the number of calls of the `handle` function defined in `krate`.
+fn handle_calls(krate: &str) -> f64 {
+    let labels = [("name", "handle"), ("ctx", "api"), ("crate", krate)];
+    instrumented::gather()
+        .into_iter()
+        .filter(|mf| mf.get_name() == "function_called_total")
+        .flat_map(|mf| mf.get_metric().to_vec())
+        .find(|m| {
+            labels.iter().all(|(name, value)| {
+                m.get_label()
+                    .iter()
+                    .any(|l| l.get_name() == *name && l.get_value() == *value)
+            })
+        })
+        .map(|m| m.get_counter().get_value())
+        .unwrap_or(0.0)
+}
+
+#[test]
+fn functions_are_labelled_with_their_crate() {
+    // Must be enabled before the function metrics are registered.
+    instrumented::set_crate_label(true).unwrap();
+
+    assert_eq!(instrumented_shipping::handle(), 2);
+    assert_eq!(instrumented_billing::handle(), 1);
+    assert_eq!(instrumented_billing::handle(), 1);
+
+    assert_eq!(handle_calls(env!("CARGO_PKG_NAME")), 1.0);
+    assert_eq!(handle_calls("instrumented-billing"), 2.0);
+}
@@ -0,0 +1,489 @@
+//! Every form of `#[instrument]`, compiled with warnings and clippy's pedantic lints denied, so
+//! that instrumenting a function never breaks a build that denies warnings.
+#![deny(warnings, clippy::pedantic)]
+
+use futures_core::Stream;
+use instrumented::{instrument, instrument_drop, instrument_mod, instrument_trait};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub struct MyError;
+
+impl fmt::Display for MyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("my error")
+    }
+}
+
+impl std::error::Error for MyError {}
+
+#[instrument(INFO)]
+pub fn unit() {}
+
+#[instrument(INFO)]
+#[must_use]
+pub fn value(x: u32) -> u32 {
+    x + 1
+}
+
+#[instrument(INFO)]
+#[must_use]
+pub fn unsized_generic<T: ?Sized + AsRef<str>>(x: &T) -> usize {
+    x.as_ref().len()
+}
+
+#[instrument(INFO)]
+#[must_use]
+pub fn higher_ranked<F>(f: &F) -> usize
+where
+    F: for<'a> Fn(&'a str) -> &'a str,
+{
+    f("key").len()
+}
+
+#[instrument(INFO)]
+#[must_use]
+pub fn const_generic<const N: usize>() -> [u8; N] {
+    [0; N]
+}
+
+#[instrument(INFO)]
+pub async fn async_unsized_generic<T: ?Sized + AsRef<str>, const N: usize>(x: &T) -> usize {
+    x.as_ref().len() * N
+}
+
+#[instrument(INFO)]
+pub fn result(fail: bool) -> Result<u32, MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(1)
+    }
+}
+
+#[instrument(ok = "DEBUG", err = "ERROR", ctx = "forms", fmt = "{:?}")]
+pub fn levels(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(ctx = "quiet")]
+#[must_use]
+pub fn unlogged() -> u32 {
+    1
+}
+
+#[instrument(ctx = "quiet")]
+pub fn unlogged_result(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(INFO, apdex_t = 0.05)]
+pub fn apdex(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(INFO, slo_ms = 200)]
+pub fn slo(fail: bool) -> Result<(), MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(INFO, ignore_err = "NotFound, WouldBlock")]
+pub fn ignore_err(kind: io::ErrorKind) -> io::Result<()> {
+    Err(io::Error::from(kind))
+}
+
+#[instrument(INFO, err_label = "io_kind")]
+pub fn io_kind(kind: io::ErrorKind) -> io::Result<()> {
+    Err(io::Error::from(kind))
+}
+
+#[instrument(ERROR, log_rate_limit = 5)]
+pub fn log_rate_limit() -> Result<(), MyError> {
+    Err(MyError)
+}
+
+#[instrument(INFO, cpu_time)]
+#[must_use]
+pub fn cpu_time(x: u64) -> u64 {
+    (0..x).sum()
+}
+
+#[instrument(INFO, count_allocs)]
+#[must_use]
+pub fn count_allocs(len: usize) -> Vec<u8> {
+    vec![0; len]
+}
+
+#[instrument(INFO)]
+pub async fn async_unit() {}
+
+#[instrument(INFO)]
+pub async fn async_borrows(s: &str, t: &str) -> usize {
+    s.len() + t.len()
+}
+
+#[instrument(INFO)]
+pub async fn async_result(fail: bool) -> Result<u32, MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(1)
+    }
+}
+
+#[instrument(INFO, size_class = "if s.len() < 64 { \"small\" } else { \"large\" }")]
+pub async fn async_size_class(s: &str) -> usize {
+    s.len()
+}
+
+/// # Errors
+///
+/// Fails over 100 calls per second.
+#[instrument(INFO, max_rate = 100, rate_limit_err = "MyError")]
+pub async fn async_rate_limited(x: u32) -> Result<u32, MyError> {
+    Ok(x)
+}
+
+/// # Errors
+///
+/// Fails over 8 calls at once.
+#[instrument(INFO, max_inflight = 8, overload_err = "MyError")]
+pub async fn async_load_shed(x: u32) -> Result<u32, MyError> {
+    Ok(x)
+}
+
+/// The shape `async-trait` gives to `async fn`s.
+#[instrument(INFO)]
+#[must_use]
+pub fn boxed_future(
+    fail: bool,
+) -> Pin<Box<dyn std::future::Future<Output = Result<u32, MyError>> + Send>> {
+    Box::pin(async move {
+        if fail {
+            Err(MyError)
+        } else {
+            Ok(1)
+        }
+    })
+}
+
+pub struct Once<T>(Option<T>);
+
+impl<T: Unpin> Stream for Once<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<T>> {
+        Poll::Ready(self.0.take())
+    }
+}
+
+#[instrument(INFO, stream)]
+pub fn stream() -> impl Stream<Item = u32> {
+    Once(Some(1))
+}
+
+#[instrument(INFO, stream)]
+pub fn result_stream() -> impl Stream<Item = Result<u32, MyError>> {
+    Once(Some(Err(MyError)))
+}
+
+pub struct Service {
+    base: u32,
+}
+
+impl Service {
+    #[instrument(INFO, ctx = "service")]
+    #[must_use]
+    pub fn method(&self, x: u32) -> u32 {
+        self.base + x
+    }
+
+    #[instrument(INFO, ctx = "service")]
+    pub fn method_result(&mut self, fail: bool) -> Result<u32, MyError> {
+        self.base += 1;
+        if fail {
+            Err(MyError)
+        } else {
+            Ok(self.base)
+        }
+    }
+
+    #[instrument(INFO, ctx = "service")]
+    pub async fn async_method(&self) -> u32 {
+        self.base
+    }
+}
+
+#[instrument(INFO)]
+pub fn impl_trait(n: u32) -> impl Iterator<Item = u32> {
+    0..n
+}
+
+#[instrument(INFO)]
+pub fn never(message: &str) -> ! {
+    panic!("{}", message)
+}
+
+#[instrument(INFO)]
+#[must_use]
+pub fn patterns((w, h): (u32, u32), &scale: &u32, _: u32, mut offset: u32) -> u32 {
+    offset += 1;
+    w * h * scale + offset
+}
+
+#[instrument(INFO)]
+pub async fn async_patterns((w, h): (u32, u32), _: &str, mut offset: u32) -> u32 {
+    offset += 1;
+    w * h + offset
+}
+
+/// # Safety
+///
+/// `ptr` must be valid for reads.
+#[instrument(INFO)]
+#[no_mangle]
+pub unsafe extern "C" fn extern_c(ptr: *const u32) -> u32 {
+    *ptr
+}
+
+#[instrument(INFO, debug_only)]
+#[must_use]
+pub fn debug_only(x: u32) -> u32 {
+    x + 1
+}
+
+#[instrument(INFO, interarrival)]
+pub fn interarrival() {}
+
+#[instrument(INFO, track_last_called)]
+pub fn track_last_called() {}
+
+#[instrument(INFO, deadline_ms = 500)]
+pub async fn deadline() -> u32 {
+    1
+}
+
+#[derive(Debug)]
+pub struct TimedOut;
+
+impl From<instrumented::DeadlineExceeded> for TimedOut {
+    fn from(_: instrumented::DeadlineExceeded) -> Self {
+        TimedOut
+    }
+}
+
+#[instrument(INFO, deadline_ms = 500, deadline_cancel)]
+pub async fn deadline_cancel() -> Result<u32, TimedOut> {
+    Ok(1)
+}
+
+#[instrument(INFO, label_thread)]
+pub fn label_thread() {}
+
+#[instrument(INFO, chaos)]
+pub fn chaos(fail: bool) -> Result<u32, MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(1)
+    }
+}
+
+#[instrument(INFO, chaos)]
+pub fn chaos_latency() {}
+
+#[instrument(INFO, location)]
+pub fn location(fail: bool) -> Result<u32, MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(1)
+    }
+}
+
+#[instrument(INFO, location, fmt = "location() returned {:?}")]
+pub async fn location_async() -> u32 {
+    1
+}
+
+#[instrument(INFO, track_recovery)]
+pub fn track_recovery(fail: bool) -> Result<u32, MyError> {
+    if fail {
+        Err(MyError)
+    } else {
+        Ok(1)
+    }
+}
+
+#[instrument(INFO, track_recovery)]
+pub async fn track_recovery_async() -> Result<u32, MyError> {
+    Ok(1)
+}
+
+#[instrument(INFO, test_namespace)]
+pub fn test_namespace() {}
+
+#[instrument(INFO, weight = "items.len() as u64")]
+pub fn weight(items: &[u32]) -> usize {
+    items.len()
+}
+
+#[instrument(INFO, weight = "items.len() as u64")]
+pub async fn weight_async(items: Vec<u32>) -> usize {
+    items.len()
+}
+
+fn classify_empty(value: &str) -> &'static str {
+    if value.is_empty() {
+        "empty"
+    } else {
+        "present"
+    }
+}
+
+#[instrument(INFO, classify = "classify_empty")]
+pub fn classify(x: &'static str) -> &'static str {
+    x
+}
+
+#[instrument(INFO, classify = "classify_empty")]
+pub async fn classify_result_async(x: String) -> Result<String, MyError> {
+    Ok(x)
+}
+
+#[instrument_trait(INFO, ctx = "store")]
+pub trait Store {
+    type Key;
+    fn get(&self, key: &Self::Key) -> Option<String>;
+    fn put(&mut self, key: Self::Key, value: String);
+    #[instrument(skip)]
+    fn size(&self) -> usize;
+}
+
+pub struct Journal;
+
+#[instrument_drop(ctx = "io")]
+impl Drop for Journal {
+    fn drop(&mut self) {}
+}
+
+#[must_use]
+pub fn error_labels(_err: &MyError) -> Vec<(&'static str, String)> {
+    vec![("kind", "mine".to_string())]
+}
+
+/// # Errors
+///
+/// Always fails.
+#[instrument(INFO, err_labels = "error_labels")]
+pub fn labelled() -> Result<(), MyError> {
+    Err(MyError)
+}
+
+#[derive(Debug, instrumented::InstrumentedErrorClass)]
+pub enum ClassifiedError {
+    #[instrumented(class = "timeout", retryable)]
+    TimedOut,
+    Rejected(u16),
+}
+
+/// # Errors
+///
+/// Always fails.
+#[instrument(INFO, err_label = "class")]
+pub fn classified() -> Result<(), ClassifiedError> {
+    Err(ClassifiedError::TimedOut)
+}
+
+#[instrument(INFO, phases)]
+#[must_use]
+pub fn phased(input: &str) -> usize {
+    let words = instrumented::phase!("split", { input.split_whitespace().count() });
+    instrumented::phase!("count", { words + input.len() })
+}
+
+#[instrument(INFO, local_metrics)]
+#[must_use]
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().map(|&b| u32::from(b)).sum()
+}
+
+#[instrument(INFO, histogram = "native")]
+#[must_use]
+pub fn native(x: u64) -> u64 {
+    x.rotate_left(7)
+}
+
+/// # Errors
+///
+/// Fails over 100 calls per second.
+#[instrument(INFO, max_rate = 100, rate_limit_err = "MyError")]
+pub fn rate_limited(x: u32) -> Result<u32, MyError> {
+    Ok(x)
+}
+
+/// # Errors
+///
+/// Fails over 8 calls at once.
+#[instrument(INFO, max_inflight = 8, overload_err = "MyError")]
+pub fn load_shed(x: u32) -> Result<u32, MyError> {
+    Ok(x)
+}
+
+#[instrument(INFO, return_timing)]
+#[must_use]
+pub fn timed(x: u32) -> u32 {
+    x * 2
+}
+
+#[instrument(INFO, main)]
+pub fn run() -> Result<(), MyError> {
+    Ok(())
+}
+
+#[instrument(INFO, percentiles)]
+#[must_use]
+pub fn percentiles(x: u32) -> u32 {
+    x + 1
+}
+
+#[instrument(INFO, ctx_from_module)]
+pub fn module_ctx() {}
+
+#[instrument_mod(INFO, ctx = "module", recursive)]
+pub mod module {
+    pub fn first() {}
+
+    pub fn second(fail: bool) -> Result<u32, super::MyError> {
+        if fail {
+            Err(super::MyError)
+        } else {
+            Ok(1)
+        }
+    }
+
+    #[instrument(skip)]
+    pub fn skipped() {}
+
+    pub mod nested {
+        pub fn third() {}
+    }
+}
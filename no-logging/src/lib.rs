@@ -0,0 +1,39 @@
+//! `#[instrument]` without the `logging` feature, in a crate that doesn't depend on `log`: the log
+//! levels are ignored, and only the metrics are recorded.
+#![deny(warnings)]
+
+use instrumented::instrument;
+use std::io;
+
+#[instrument(INFO)]
+pub fn value(x: u32) -> u32 {
+    x + 1
+}
+
+#[instrument(INFO, err = "ERROR", ctx = "io", err_label = "io_kind")]
+pub fn read(fail: bool) -> io::Result<u32> {
+    if fail {
+        Err(io::Error::new(io::ErrorKind::NotFound, "missing"))
+    } else {
+        Ok(1)
+    }
+}
+
+#[instrument(ERROR, log_rate_limit = 5, fmt = "{:?}")]
+pub fn limited(fail: bool) -> Result<(), String> {
+    if fail {
+        Err("failed".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+#[instrument(ctx = "async")]
+pub async fn awaited(x: u32) -> u32 {
+    x
+}
+
+/// Serves the metrics, which doesn't need `log` either.
+pub fn serve(addr: &str) {
+    instrumented::init(addr);
+}